@@ -1,5 +1,3 @@
-use selectic;
-
 // get text
 fn main() {
     let text = selectic::get_text();
@@ -0,0 +1,102 @@
+//! Per-application capture rules
+//!
+//! Rules are keyed by the platform application identifier (the bundle
+//! identifier on macOS, the `WM_CLASS` class on X11) and consulted before
+//! each capture.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::SelectionMethod;
+
+/// How selections are captured from one application
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppRule {
+    /// Methods to try, in order
+    pub methods: Vec<SelectionMethod>,
+    /// Overrides `SelectionOptions::copy_timeout` for this application
+    pub copy_timeout: Option<Duration>,
+    /// Whether selections may be captured from this application at all
+    pub allow_capture: bool,
+}
+
+impl Default for AppRule {
+    fn default() -> Self {
+        Self {
+            methods: vec![SelectionMethod::Accessibility, SelectionMethod::Clipboard],
+            copy_timeout: None,
+            allow_capture: true,
+        }
+    }
+}
+
+impl AppRule {
+    /// Create a rule with the default behaviour
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the methods to try, in order
+    pub fn methods(mut self, methods: &[SelectionMethod]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Set how long to wait for the simulated copy in this application
+    pub fn copy_timeout(mut self, timeout: Duration) -> Self {
+        self.copy_timeout = Some(timeout);
+        self
+    }
+
+    /// Set whether selections may be captured from this application
+    pub fn allow_capture(mut self, allow: bool) -> Self {
+        self.allow_capture = allow;
+        self
+    }
+}
+
+/// Get the built-in rules for applications known to need special handling
+pub fn default_app_rules() -> HashMap<String, AppRule> {
+    let accessibility_only = AppRule::new().methods(&[SelectionMethod::Accessibility]);
+
+    [
+        // Copying works in terminals, but pollutes the pasteboard history
+        ("com.apple.Terminal", accessibility_only.clone()),
+        ("com.googlecode.iterm2", accessibility_only),
+        // Word's accessibility text loses formatting and list markers, and
+        // it's slow to update the pasteboard
+        (
+            "com.microsoft.Word",
+            AppRule::new()
+                .methods(&[SelectionMethod::Clipboard, SelectionMethod::Accessibility])
+                .copy_timeout(Duration::from_millis(1000)),
+        ),
+    ]
+    .into_iter()
+    .map(|(identifier, rule)| (identifier.to_string(), rule))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SelectionOptions;
+
+    #[test]
+    fn test_app_rule_overrides() {
+        let options = SelectionOptions::new()
+            .app_rule("com.apple.Terminal", AppRule::new().allow_capture(false));
+
+        assert!(
+            !options
+                .rule_for_app(Some("com.apple.Terminal"))
+                .allow_capture
+        );
+        assert_eq!(
+            options.rule_for_app(Some("com.microsoft.Word")).methods[0],
+            SelectionMethod::Clipboard
+        );
+        assert_eq!(options.rule_for_app(None), AppRule::default());
+        assert_eq!(options.app_rules().len(), default_app_rules().len());
+    }
+}
@@ -0,0 +1,69 @@
+//! Standard, padded base64
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard, padded base64
+#[cfg(feature = "json")]
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let mut bytes = [0; 3];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let value = (bits >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(ALPHABET[value as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decode standard, padded base64
+pub(crate) fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let value = |byte: u8| ALPHABET.iter().position(|&letter| letter == byte);
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    for chunk in encoded.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if chunk.len() != 4 || padding > 2 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &byte in &chunk[..4 - padding] {
+            bits = bits << 6 | value(byte)? as u32;
+        }
+        bits <<= 6 * padding;
+        decoded.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode(b"aGk=").unwrap(), b"hi");
+        assert_eq!(decode(b"").unwrap(), b"");
+        assert!(decode(b"aGk").is_none());
+        assert!(decode(b"a===").is_none());
+        assert!(decode(b"a!==").is_none());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_encode() {
+        for data in [&b""[..], b"h", b"hi", b"hello", b"\xff\x00\xfe"] {
+            assert_eq!(decode(encode(data).as_bytes()).unwrap(), data);
+        }
+        assert_eq!(encode(b"hello"), "aGVsbG8=");
+        assert_eq!(encode(b"\xfb\xff"), "+/8=");
+    }
+}
@@ -0,0 +1,219 @@
+//! Runtime probing of what the platform backend can do
+//!
+//! Probing never prompts the user or simulates input, so it's safe to call
+//! e.g. every time a settings window is shown.
+
+use std::fmt;
+
+/// What the platform backend can do right now
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Name of the platform backend
+    pub platform: &'static str,
+    /// Whether the process is allowed to use the accessibility API, if the
+    /// platform requires a permission for it
+    pub accessibility_trusted: Option<bool>,
+    /// Whether secure input (e.g. a focused password field) currently blocks
+    /// simulated keystrokes, if the platform has such a mode
+    pub secure_input: Option<bool>,
+    /// Whether the process may listen to keyboard events, as global hotkeys
+    /// need, if the platform requires a permission for it
+    pub input_monitoring_trusted: Option<bool>,
+    /// Whether a screen reader or other assistive technology is running, if
+    /// the platform can tell
+    pub assistive_technology: Option<bool>,
+    /// Whether other applications may be scripted through Apple Events, if
+    /// the platform has them
+    pub apple_events: Option<bool>,
+    /// Whether the clipboard fallback is enabled with the default options
+    pub clipboard_fallback: Option<bool>,
+    /// Whether keystrokes can be simulated in this session at all, where
+    /// that depends on the environment (Wayland compositors)
+    pub input_injection: Option<bool>,
+    /// Whether the primary selection, which holds the highlighted text, can
+    /// be read, where the platform has one
+    pub primary_selection: Option<bool>,
+    /// Whether a `SelectionWatcher` is told about selection changes, rather
+    /// than polling for them
+    pub selection_events: Option<bool>,
+    /// Whether the focused application can be identified, which
+    /// per-application rules and source metadata rely on
+    pub focused_app: Option<bool>,
+    /// Whether a global hotkey can be registered in this session, where
+    /// that depends on the environment (Wayland desktops) and the `hotkey`
+    /// feature is enabled
+    pub global_hotkeys: Option<bool>,
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "platform: {}", self.platform)?;
+        writeln!(
+            f,
+            "accessibility permission: {}",
+            describe(self.accessibility_trusted, "granted", "not granted")
+        )?;
+        writeln!(
+            f,
+            "secure input: {}",
+            describe(self.secure_input, "active", "inactive")
+        )?;
+        writeln!(
+            f,
+            "input monitoring permission: {}",
+            describe(self.input_monitoring_trusted, "granted", "not granted")
+        )?;
+        writeln!(
+            f,
+            "assistive technology: {}",
+            describe(self.assistive_technology, "running", "not running")
+        )?;
+        writeln!(
+            f,
+            "apple events: {}",
+            describe(self.apple_events, "allowed", "disabled")
+        )?;
+        writeln!(
+            f,
+            "clipboard fallback: {}",
+            describe(self.clipboard_fallback, "enabled", "disabled")
+        )?;
+        writeln!(
+            f,
+            "input injection: {}",
+            describe(self.input_injection, "available", "unavailable")
+        )?;
+        writeln!(
+            f,
+            "primary selection: {}",
+            describe(self.primary_selection, "available", "unavailable")
+        )?;
+        writeln!(
+            f,
+            "selection change events: {}",
+            describe(self.selection_events, "notified", "polled")
+        )?;
+        writeln!(
+            f,
+            "focused application: {}",
+            describe(self.focused_app, "identified", "unknown")
+        )?;
+        writeln!(
+            f,
+            "global hotkeys: {}",
+            describe(self.global_hotkeys, "available", "unavailable")
+        )
+    }
+}
+
+fn describe(value: Option<bool>, yes: &str, no: &str) -> String {
+    match value {
+        Some(true) => yes.to_string(),
+        Some(false) => no.to_string(),
+        None => "n/a".to_string(),
+    }
+}
+
+/// Probe the capabilities of the current platform backend
+pub fn capabilities() -> Capabilities {
+    #[cfg(target_os = "macos")]
+    {
+        crate::macos::capabilities()
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        crate::windows::capabilities()
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    ))]
+    {
+        crate::linux::capabilities()
+    }
+
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "windows",
+        all(unix, not(any(target_os = "ios", target_os = "android")))
+    )))]
+    {
+        Capabilities {
+            platform: "unsupported",
+            ..Default::default()
+        }
+    }
+}
+
+/// Build a human-readable report of the current capabilities, including
+/// hints for fixing anything that prevents selections from being captured
+pub fn diagnose() -> String {
+    let capabilities = capabilities();
+    let mut report = capabilities.to_string();
+
+    if capabilities.accessibility_trusted == Some(false) {
+        report.push_str(
+            "hint: grant Accessibility permission to this application in \
+             System Settings > Privacy & Security > Accessibility\n",
+        );
+    }
+
+    if capabilities.secure_input == Some(true) {
+        report.push_str(
+            "hint: secure input is active, so the clipboard fallback is blocked \
+             until the password field loses focus or the app enabling it quits\n",
+        );
+    }
+
+    if capabilities.input_monitoring_trusted == Some(false) {
+        report.push_str(
+            "hint: global hotkeys need Input Monitoring permission, granted in \
+             System Settings > Privacy & Security > Input Monitoring\n",
+        );
+    }
+
+    if capabilities.clipboard_fallback == Some(false) {
+        report.push_str(
+            "hint: the clipboard fallback is disabled, so only applications \
+             supporting the accessibility API provide selections; enable \
+             `SelectionOptions::synthetic_input` if input events are allowed\n",
+        );
+    }
+
+    if capabilities.input_injection == Some(false) {
+        report.push_str(
+            "hint: this Wayland compositor offers no way to simulate keystrokes, \
+             so the copy fallback can't work; install `ydotool` and start its \
+             daemon, or use a compositor supporting the virtual keyboard protocol\n",
+        );
+    }
+
+    if capabilities.primary_selection == Some(false) {
+        report.push_str(
+            "hint: this Wayland compositor (e.g. GNOME) doesn't let other \
+             applications read the primary selection, so only selections in \
+             XWayland windows are found; enable the `atspi` feature to read \
+             native Wayland applications\n",
+        );
+    }
+
+    if capabilities.focused_app == Some(false) {
+        report.push_str(
+            "hint: Wayland doesn't tell other applications which window is \
+             focused, so per-application rules don't apply\n",
+        );
+    }
+
+    if capabilities.global_hotkeys == Some(false) {
+        report.push_str(
+            "hint: this Wayland desktop has no GlobalShortcuts portal, so \
+             `HotkeyListener` can't work; bind a shortcut in the desktop's \
+             settings that runs your application instead\n",
+        );
+    }
+
+    report
+}
@@ -0,0 +1,128 @@
+//! Text surrounding a selection
+
+/// A selection together with the text immediately before and after it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SelectionContext {
+    /// Text preceding the selection
+    pub before: String,
+    /// The selected text itself
+    pub selected: String,
+    /// Text following the selection
+    pub after: String,
+}
+
+impl SelectionContext {
+    /// Slice the context out of a full text, given the selected range in
+    /// UTF-16 code units as reported by accessibility APIs
+    ///
+    /// `chars` is the number of characters (Unicode scalar values) to keep
+    /// on each side. Offsets are clamped to the text, and an offset falling
+    /// inside a surrogate pair is moved to the start of that character.
+    pub fn from_utf16_range(text: &str, location: usize, length: usize, chars: usize) -> Self {
+        let start = utf16_to_byte_offset(text, location);
+        let end = utf16_to_byte_offset(text, location.saturating_add(length)).max(start);
+
+        SelectionContext {
+            before: last_chars(&text[..start], chars).to_string(),
+            selected: text[start..end].to_string(),
+            after: first_chars(&text[end..], chars).to_string(),
+        }
+    }
+
+    /// Slice the context out of a text, given the selected range in
+    /// characters (Unicode scalar values) as reported by AT-SPI
+    ///
+    /// Offsets are clamped to the text like with `from_utf16_range`.
+    pub fn from_char_range(text: &str, location: usize, length: usize, chars: usize) -> Self {
+        let start = char_to_byte_offset(text, location);
+        let end = char_to_byte_offset(text, location.saturating_add(length)).max(start);
+
+        SelectionContext {
+            before: last_chars(&text[..start], chars).to_string(),
+            selected: text[start..end].to_string(),
+            after: first_chars(&text[end..], chars).to_string(),
+        }
+    }
+}
+
+/// Convert an offset in characters into a byte offset into `text`
+fn char_to_byte_offset(text: &str, char_offset: usize) -> usize {
+    text.char_indices()
+        .nth(char_offset)
+        .map_or(text.len(), |(index, _)| index)
+}
+
+/// Convert an offset in UTF-16 code units into a byte offset into `text`
+pub(crate) fn utf16_to_byte_offset(text: &str, utf16_offset: usize) -> usize {
+    let mut units = 0;
+
+    for (index, ch) in text.char_indices() {
+        units += ch.len_utf16();
+        if units > utf16_offset {
+            return index;
+        }
+    }
+
+    text.len()
+}
+
+/// Get the last `count` characters of a string
+pub(crate) fn last_chars(text: &str, count: usize) -> &str {
+    if count == 0 {
+        return "";
+    }
+
+    match text.char_indices().rev().nth(count - 1) {
+        Some((index, _)) => &text[index..],
+        None => text,
+    }
+}
+
+/// Get the first `count` characters of a string
+pub(crate) fn first_chars(text: &str, count: usize) -> &str {
+    match text.char_indices().nth(count) {
+        Some((index, _)) => &text[..index],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_from_utf16_range() {
+        // "😀" is two UTF-16 units, each CJK character is one
+        let text = "a😀b中文c😀d";
+        let context = SelectionContext::from_utf16_range(text, 4, 2, 2);
+
+        assert_eq!(context.before, "😀b");
+        assert_eq!(context.selected, "中文");
+        assert_eq!(context.after, "c😀");
+    }
+
+    #[test]
+    fn test_context_clamps_offsets() {
+        let context = SelectionContext::from_utf16_range("hello", 3, 100, 10);
+
+        assert_eq!(context.before, "hel");
+        assert_eq!(context.selected, "lo");
+        assert_eq!(context.after, "");
+
+        // An offset inside the surrogate pair snaps to the emoji's start
+        assert_eq!(utf16_to_byte_offset("a😀b", 2), 1);
+    }
+
+    #[test]
+    fn test_context_from_char_range() {
+        let text = "a😀b中文c😀d";
+        let context = SelectionContext::from_char_range(text, 3, 2, 2);
+
+        assert_eq!(context.before, "😀b");
+        assert_eq!(context.selected, "中文");
+        assert_eq!(context.after, "c😀");
+
+        let context = SelectionContext::from_char_range("hello", 3, 100, 10);
+        assert_eq!(context.selected, "lo");
+    }
+}
@@ -1,9 +1,17 @@
 #[cfg(target_os = "macos")]
 use accessibility_ng::Error as AccessibilityErrorNg;
+#[cfg(target_os = "macos")]
+use accessibility_sys_ng::{kAXErrorAPIDisabled, kAXErrorNoValue};
 
+use std::fmt;
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::privacy::redact;
+use crate::SelectionMethod;
+
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum SelectionError {
     #[error("No focused UI element found")]
     NoFocusedElement,
@@ -14,14 +22,64 @@ pub enum SelectionError {
     #[error("Unsupported platform")]
     UnsupportedPlatform,
 
+    #[error(
+        "No display server found: neither WAYLAND_DISPLAY nor DISPLAY is set, or it can't be connected to"
+    )]
+    NoDisplay,
+
+    #[error("Lost the connection to the display server and couldn't reconnect: {0}")]
+    DisplayConnectionLost(String),
+
+    #[error("Sandboxed without access to the display server: {0}")]
+    Sandboxed(String),
+
+    #[error("Selection method not available on this platform: {0}")]
+    MethodUnavailable(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Secure input is active, keystrokes can't be simulated")]
+    SecureField,
+
+    #[error("The focused element is read-only")]
+    ReadOnly,
+
     #[error("Invalid content type: expected {expected}, received {received}")]
     InvalidContentType { expected: String, received: String },
 
     #[error("AppleScript execution failed: {0}")]
     AppleScriptError(String),
 
-    #[error("Accessibility API error: {0}")]
-    AccessibilityError(String),
+    #[error("Accessibility API error: {message}")]
+    AccessibilityError {
+        message: String,
+        /// Platform error code: the AXError on macOS, the HRESULT on Windows
+        code: Option<i32>,
+        /// The platform's own error, where there is one
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+
+    #[error(
+        "{} is a Java application without the Java Access Bridge enabled",
+        redact(.0)
+    )]
+    JavaAccessibilityUnavailable(String),
+
+    #[error(
+        "{} is a Qt application not publishing its widgets to AT-SPI; start it with QT_LINUX_ACCESSIBILITY_ALWAYS_ON=1",
+        redact(.0)
+    )]
+    QtAccessibilityUnavailable(String),
+
+    #[error("The selection is larger than the limit of {limit} bytes")]
+    TooLarge { limit: usize },
+
+    #[error("Timed out after {waited:?} while {phase}")]
+    Timeout {
+        phase: &'static str,
+        waited: Duration,
+    },
 
     #[error("Clipboard error: {0}")]
     ClipboardError(String),
@@ -34,6 +92,181 @@ pub enum SelectionError {
 
     #[error("Selection error: {0}")]
     Other(String),
+
+    #[error("Every selection method failed: {}", describe_failures(.0))]
+    AllMethodsFailed(Vec<(SelectionMethod, SelectionError)>),
+
+    /// A platform API or library failed; `source` is its own error, e.g. a
+    /// `windows::core::Error` or an `arboard::Error`
+    #[error("{message}: {source}")]
+    Platform {
+        message: String,
+        /// What kind of failure this is, see `SelectionError::kind`
+        kind: ErrorKind,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// What kind of failure a `SelectionError` is, e.g. to count failures by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Nothing is selected, or nothing is focused
+    NoSelection,
+    /// The method, platform or display server can't be used here
+    Unavailable,
+    /// A permission is missing or secure input is active
+    PermissionDenied,
+    /// The focused element can't be written to
+    ReadOnly,
+    /// The selection exceeds `SelectionOptions::max_bytes`
+    TooLarge,
+    /// An application or the display server didn't answer in time
+    Timeout,
+    /// Reading or restoring the clipboard failed
+    Clipboard,
+    /// Another application held the clipboard open
+    ClipboardBusy,
+    /// The accessibility API failed
+    Accessibility,
+    /// A script querying another application failed
+    Script,
+    /// The content isn't what was asked for, or isn't valid UTF-8
+    InvalidContent,
+    /// An I/O operation failed, e.g. talking to a helper process
+    Io,
+    /// Anything else, including several methods failing differently
+    Other,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::NoSelection => "no_selection",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::ReadOnly => "read_only",
+            ErrorKind::TooLarge => "too_large",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Clipboard => "clipboard",
+            ErrorKind::ClipboardBusy => "clipboard_busy",
+            ErrorKind::Accessibility => "accessibility",
+            ErrorKind::Script => "script",
+            ErrorKind::InvalidContent => "invalid_content",
+            ErrorKind::Io => "io",
+            ErrorKind::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+impl SelectionError {
+    /// What kind of failure this is
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SelectionError::NoFocusedElement | SelectionError::NoSelectedContent => {
+                ErrorKind::NoSelection
+            }
+            SelectionError::UnsupportedPlatform
+            | SelectionError::NoDisplay
+            | SelectionError::DisplayConnectionLost(_)
+            | SelectionError::Sandboxed(_)
+            | SelectionError::MethodUnavailable(_)
+            | SelectionError::JavaAccessibilityUnavailable(_)
+            | SelectionError::QtAccessibilityUnavailable(_) => ErrorKind::Unavailable,
+            SelectionError::PermissionDenied(_) | SelectionError::SecureField => {
+                ErrorKind::PermissionDenied
+            }
+            SelectionError::ReadOnly => ErrorKind::ReadOnly,
+            SelectionError::TooLarge { .. } => ErrorKind::TooLarge,
+            SelectionError::Timeout { .. } => ErrorKind::Timeout,
+            SelectionError::ClipboardError(_) => ErrorKind::Clipboard,
+            SelectionError::AccessibilityError { .. } => ErrorKind::Accessibility,
+            SelectionError::AppleScriptError(_) => ErrorKind::Script,
+            SelectionError::InvalidContentType { .. } | SelectionError::Utf8Error(_) => {
+                ErrorKind::InvalidContent
+            }
+            SelectionError::IoError(_) => ErrorKind::Io,
+            SelectionError::Other(_) | SelectionError::AllMethodsFailed(_) => ErrorKind::Other,
+            SelectionError::Platform { kind, .. } => *kind,
+        }
+    }
+
+    /// Whether trying again shortly may succeed: another application held
+    /// the clipboard or something didn't answer in time
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SelectionError::AllMethodsFailed(failures) => {
+                failures.iter().any(|(_, err)| err.is_retryable())
+            }
+            err => matches!(err.kind(), ErrorKind::Timeout | ErrorKind::ClipboardBusy),
+        }
+    }
+
+    /// Whether the user can fix this, e.g. by granting a permission or
+    /// enabling accessibility in an application; see the error message
+    pub fn is_user_actionable(&self) -> bool {
+        match self {
+            SelectionError::AllMethodsFailed(failures) => {
+                failures.iter().any(|(_, err)| err.is_user_actionable())
+            }
+            SelectionError::UnsupportedPlatform
+            | SelectionError::Sandboxed(_)
+            | SelectionError::JavaAccessibilityUnavailable(_)
+            | SelectionError::QtAccessibilityUnavailable(_) => true,
+            err => err.kind() == ErrorKind::PermissionDenied,
+        }
+    }
+
+    /// A platform API or library failing with `source`
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    pub(crate) fn platform(
+        kind: ErrorKind,
+        message: &str,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        SelectionError::Platform {
+            message: message.to_string(),
+            kind,
+            source: Box::new(source),
+        }
+    }
+
+    /// Combine the errors of the methods tried for one capture, see
+    /// `Selector::get_selection`
+    pub(crate) fn from_failures(mut failures: Vec<(SelectionMethod, SelectionError)>) -> Self {
+        // Methods that found nothing, or couldn't be used, explain nothing
+        // when another one failed
+        let uninformative = |err: &SelectionError| {
+            matches!(
+                err,
+                SelectionError::NoSelectedContent | SelectionError::MethodUnavailable(_)
+            )
+        };
+        if failures.iter().all(|(_, err)| uninformative(err)) {
+            let nothing_selected = failures
+                .iter()
+                .any(|(_, err)| matches!(err, SelectionError::NoSelectedContent));
+            return match failures.pop() {
+                Some((_, err)) if !nothing_selected => err,
+                _ => SelectionError::NoSelectedContent,
+            };
+        }
+
+        failures.retain(|(_, err)| !uninformative(err));
+        match failures.len() {
+            1 => failures.remove(0).1,
+            _ => SelectionError::AllMethodsFailed(failures),
+        }
+    }
+}
+
+fn describe_failures(failures: &[(SelectionMethod, SelectionError)]) -> String {
+    failures
+        .iter()
+        .map(|(method, err)| format!("{}: {}", method, err))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 impl From<String> for SelectionError {
@@ -50,7 +283,112 @@ impl From<&str> for SelectionError {
 
 #[cfg(target_os = "macos")]
 impl From<AccessibilityErrorNg> for SelectionError {
+    /// Map the AXError codes with a dedicated variant onto it; the others
+    /// keep their code in `AccessibilityError`
+    ///
+    /// `kAXErrorCannotComplete` usually means the application is hanging, but
+    /// only the caller knows how long it waited, so it isn't mapped here.
     fn from(error: AccessibilityErrorNg) -> Self {
-        SelectionError::AccessibilityError(error.to_string())
+        match error {
+            AccessibilityErrorNg::Ax(code) if code == kAXErrorAPIDisabled => {
+                SelectionError::PermissionDenied(
+                    "Accessibility permission has not been granted to this process".to_string(),
+                )
+            }
+            AccessibilityErrorNg::Ax(code) if code == kAXErrorNoValue => {
+                SelectionError::NoSelectedContent
+            }
+            AccessibilityErrorNg::Ax(code) => SelectionError::AccessibilityError {
+                message: error.to_string(),
+                code: Some(code),
+                source: Some(Box::new(error)),
+            },
+            _ => SelectionError::AccessibilityError {
+                message: error.to_string(),
+                code: None,
+                source: Some(Box::new(error)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_platform_source() {
+        let io = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed");
+        let err = SelectionError::platform(ErrorKind::Clipboard, "Failed to read", io);
+        assert_eq!(err.to_string(), "Failed to read: pipe closed");
+        assert_eq!(err.kind(), ErrorKind::Clipboard);
+        let source = err.source().unwrap().downcast_ref::<std::io::Error>();
+        assert_eq!(source.unwrap().kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let err = SelectionError::Timeout {
+            phase: "waiting for an AT-SPI call",
+            waited: Duration::from_millis(500),
+        };
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert_eq!(
+            err.to_string(),
+            "Timed out after 500ms while waiting for an AT-SPI call"
+        );
+    }
+
+    #[test]
+    fn test_retryable_and_user_actionable() {
+        let timeout = SelectionError::Timeout {
+            phase: "taking ownership of CLIPBOARD",
+            waited: Duration::from_millis(500),
+        };
+        let busy = SelectionError::platform(
+            ErrorKind::ClipboardBusy,
+            "Failed to open clipboard",
+            std::io::Error::from(std::io::ErrorKind::WouldBlock),
+        );
+        let malformed =
+            SelectionError::ClipboardError("Unsupported or malformed bitmap".to_string());
+        let lost = SelectionError::DisplayConnectionLost("Connection refused".to_string());
+        let denied = SelectionError::PermissionDenied("no".to_string());
+        for (err, retryable, user_actionable) in [
+            (timeout, true, false),
+            (busy, true, false),
+            (malformed, false, false),
+            (lost, false, false),
+            (denied, false, true),
+            (SelectionError::UnsupportedPlatform, false, true),
+            (SelectionError::NoSelectedContent, false, false),
+            (SelectionError::NoFocusedElement, false, false),
+        ] {
+            assert_eq!(err.is_retryable(), retryable, "{}", err);
+            assert_eq!(err.is_user_actionable(), user_actionable, "{}", err);
+        }
+
+        let all = SelectionError::AllMethodsFailed(vec![
+            (SelectionMethod::Accessibility, SelectionError::SecureField),
+            (
+                SelectionMethod::Clipboard,
+                SelectionError::Timeout {
+                    phase: "waiting for an AT-SPI call",
+                    waited: Duration::from_millis(150),
+                },
+            ),
+        ]);
+        assert!(all.is_retryable());
+        assert!(all.is_user_actionable());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_accessibility_source() {
+        let err = SelectionError::from(AccessibilityErrorNg::NotFound);
+        assert_eq!(err.kind(), ErrorKind::Accessibility);
+        let source = err.source().unwrap().downcast_ref::<AccessibilityErrorNg>();
+        assert!(matches!(source, Some(AccessibilityErrorNg::NotFound)));
     }
 }
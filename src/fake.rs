@@ -0,0 +1,347 @@
+//! An in-memory desktop standing in for the platform, for tests
+//!
+//! With the `fake-platform` feature the free functions (`get_selection`,
+//! `get_text`, ...) and `SelectionWatcher` use this process-local desktop
+//! instead of the real one, so captures can be tested without a desktop
+//! session. The desktop has a focused element with a selection, which the
+//! accessibility API reads if the element exposes it and which a simulated
+//! copy puts on the clipboard, and a clipboard.
+//!
+//! The desktop is shared by the whole process; tests using it should hold
+//! the guard `reset` returns, so they don't run into each other:
+//!
+//! ```
+//! # #[cfg(feature = "fake-platform")]
+//! # {
+//! use selectic::fake;
+//!
+//! let _desktop = fake::reset();
+//! fake::set_selected_text("hello");
+//! assert_eq!(selectic::get_text().unwrap(), "hello");
+//! # }
+//! ```
+
+use log::debug;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::privacy::redact;
+use crate::watcher::{PollingBackend, WatcherBackend};
+use crate::{
+    sort_by_richness, AppInfo, AppRule, Selection, SelectionError, SelectionMethod,
+    SelectionOptions, Selector,
+};
+use crate::{SelectionEvent, SelectionObserver, WatchSource, WatcherOptions};
+
+/// State of the fake desktop
+#[derive(Debug)]
+struct Desktop {
+    /// Representations of the focused element's selection, richest first
+    selected: Vec<Selection>,
+    /// Whether the focused element exposes its selection to the
+    /// accessibility API
+    accessible: bool,
+    /// Whether the focused application copies its selection when sent the
+    /// copy shortcut
+    copyable: bool,
+    /// Representations of what's on the clipboard
+    clipboard: Vec<Selection>,
+    focused_app: Option<AppInfo>,
+    /// How many copies have been simulated
+    copies: usize,
+}
+
+impl Desktop {
+    const fn new() -> Self {
+        Self {
+            selected: Vec::new(),
+            accessible: true,
+            copyable: true,
+            clipboard: Vec::new(),
+            focused_app: None,
+            copies: 0,
+        }
+    }
+}
+
+static DESKTOP: Mutex<Desktop> = Mutex::new(Desktop::new());
+
+/// Held by tests using the fake desktop, see `reset`
+static SESSION: Mutex<()> = Mutex::new(());
+
+fn desktop() -> MutexGuard<'static, Desktop> {
+    DESKTOP.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Exclusive use of the fake desktop, until dropped
+pub struct FakeDesktop {
+    _session: MutexGuard<'static, ()>,
+}
+
+/// Wait for other users of the fake desktop to finish, then reset it: no
+/// selection, an empty clipboard, no focused application, and an
+/// accessible, copyable focused element
+pub fn reset() -> FakeDesktop {
+    let session = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+    *desktop() = Desktop::new();
+    FakeDesktop { _session: session }
+}
+
+/// Select `text` in the focused element; an empty text clears the selection
+pub fn set_selected_text(text: &str) {
+    let selected = if text.is_empty() {
+        Vec::new()
+    } else {
+        vec![Selection::new_text(text.to_string())]
+    };
+    set_selected(selected);
+}
+
+/// Select content available in several representations, e.g. HTML and
+/// plain text
+///
+/// The accessibility API only reads the plain text; a copy puts all of them
+/// on the clipboard.
+pub fn set_selected(selections: Vec<Selection>) {
+    desktop().selected = selections;
+}
+
+/// Whether the focused element exposes its selection to the accessibility
+/// API, as most native text fields do; without it only a copy can read it
+pub fn set_accessible(accessible: bool) {
+    desktop().accessible = accessible;
+}
+
+/// Whether the focused application copies its selection when sent the copy
+/// shortcut
+pub fn set_copyable(copyable: bool) {
+    desktop().copyable = copyable;
+}
+
+/// Put content on the clipboard, as if the user had copied it
+pub fn set_clipboard(selection: Selection) {
+    desktop().clipboard = vec![selection];
+}
+
+/// What's on the clipboard, in its richest representation
+pub fn clipboard() -> Option<Selection> {
+    desktop().clipboard.first().cloned()
+}
+
+/// Set the application owning the focused element, whose `AppRule` is
+/// followed
+pub fn set_focused_app(app: Option<AppInfo>) {
+    desktop().focused_app = app;
+}
+
+/// How many times a copy has been simulated since the last `reset`
+pub fn copy_count() -> usize {
+    desktop().copies
+}
+
+fn total_len(selections: &[Selection]) -> usize {
+    selections
+        .iter()
+        .map(|selection| selection.data.len())
+        .sum()
+}
+
+/// The plain text of the selection, as the accessibility API reads it
+fn selected_text(desktop: &Desktop) -> Option<Selection> {
+    desktop
+        .selected
+        .iter()
+        .find(|selection| selection.as_text().is_some())
+        .cloned()
+}
+
+/// Read the current selection passively, as watchers do, `None` if nothing
+/// is selected or the accessibility API can't read it
+fn passive_selection() -> Option<(Selection, Option<AppInfo>)> {
+    let desktop = desktop();
+    if !desktop.accessible {
+        return None;
+    }
+    let app = desktop.focused_app.clone();
+    selected_text(&desktop).map(|selection| (selection, app))
+}
+
+/// Read the clipboard passively, as watchers do
+fn passive_clipboard() -> Option<(Selection, Option<AppInfo>)> {
+    clipboard().map(|selection| (selection, None))
+}
+
+/// Watch the fake desktop by polling its selection and clipboard
+pub(crate) fn start_watcher(
+    options: &WatcherOptions,
+    sender: Sender<SelectionEvent>,
+) -> Result<Vec<Box<dyn WatcherBackend>>, SelectionError> {
+    let mut backends: Vec<Box<dyn WatcherBackend>> = Vec::new();
+    if options.watch_selection {
+        backends.push(Box::new(PollingBackend::spawn(
+            options,
+            WatchSource::Selection,
+            sender.clone(),
+            passive_selection,
+        )));
+    }
+    if options.watch_clipboard {
+        backends.push(Box::new(PollingBackend::spawn(
+            options,
+            WatchSource::Clipboard,
+            sender,
+            passive_clipboard,
+        )));
+    }
+    Ok(backends)
+}
+
+/// Selector reading the fake desktop
+///
+/// Follows the same `SelectionOptions` as the platform selectors: app
+/// rules, `trust_empty_accessibility`, `synthetic_input`, `max_bytes` and
+/// the options applied to finished selections.
+pub struct FakeSelector {
+    options: SelectionOptions,
+}
+
+impl FakeSelector {
+    /// A selector with the default options, except that the copy is
+    /// simulated in `sandbox` builds too: the fake desktop has no sandbox
+    pub fn new() -> Self {
+        Self::with_options(SelectionOptions::default().synthetic_input(true))
+    }
+
+    pub fn with_options(options: SelectionOptions) -> Self {
+        FakeSelector { options }
+    }
+
+    /// Simulate the copy shortcut and read what it copied with `read`,
+    /// restoring the previous clipboard after
+    fn copy_and_read<T>(
+        &self,
+        read: impl FnOnce(&[Selection]) -> T,
+    ) -> Result<(T, Option<AppInfo>), SelectionError> {
+        if !self.options.synthetic_input {
+            return Err(SelectionError::MethodUnavailable(
+                "simulated keystrokes are disabled".to_string(),
+            ));
+        }
+
+        let mut desktop = desktop();
+        desktop.copies += 1;
+        let app_name = desktop
+            .focused_app
+            .as_ref()
+            .and_then(|app| app.name.clone());
+        debug!(
+            "Simulating a copy in {}",
+            redact(app_name.as_deref().unwrap_or("the focused application"))
+        );
+        if !desktop.copyable || desktop.selected.is_empty() {
+            return Err(SelectionError::NoSelectedContent);
+        }
+
+        let copied = desktop.selected.clone();
+        let previous = std::mem::replace(&mut desktop.clipboard, copied);
+        debug!("Copied {} bytes", redact(total_len(&desktop.clipboard)));
+        let read = read(&desktop.clipboard);
+        if self.options.restore_clipboard {
+            desktop.clipboard = previous;
+        }
+        Ok((read, desktop.focused_app.clone()))
+    }
+
+    /// Cut a selection down to `max_bytes`
+    fn limit(&self, mut selection: Selection) -> Selection {
+        if let Some(max_bytes) = self.options.max_bytes {
+            selection.truncate_text(max_bytes);
+        }
+        selection
+    }
+}
+
+impl Default for FakeSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Selector for FakeSelector {
+    fn capture_rule(&self) -> Result<AppRule, SelectionError> {
+        let focused = desktop().focused_app.clone();
+        Ok(self
+            .options
+            .rule_for_app(focused.as_ref().and_then(|app| app.identifier.as_deref())))
+    }
+
+    fn observer(&self) -> Option<Arc<dyn SelectionObserver>> {
+        self.options.active_observer()
+    }
+
+    fn trusts_empty_accessibility(&self) -> bool {
+        self.options.trust_empty_accessibility
+    }
+
+    fn get_selection_all(&self) -> Result<Vec<Selection>, SelectionError> {
+        let (selections, app) = self.copy_and_read(<[Selection]>::to_vec)?;
+        let mut selections = selections
+            .into_iter()
+            .map(|selection| {
+                let selection = self
+                    .limit(selection)
+                    .with_source_app(app.clone())
+                    .with_method(SelectionMethod::Clipboard)
+                    .left_on_clipboard(!self.options.restore_clipboard);
+                self.options.finish(selection)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        sort_by_richness(&mut selections);
+        Ok(selections)
+    }
+
+    fn get_selection_by_accessibility(&self) -> Result<Selection, SelectionError> {
+        let desktop = desktop();
+        if !desktop.accessible {
+            return Err(SelectionError::MethodUnavailable(
+                "the focused element exposes no selected text".to_string(),
+            ));
+        }
+        let selection = selected_text(&desktop).ok_or(SelectionError::NoSelectedContent)?;
+        let app = desktop.focused_app.clone();
+        drop(desktop);
+        debug!(
+            "Read {} bytes through the accessibility API",
+            redact(selection.data.len())
+        );
+
+        self.options.finish(
+            self.limit(selection)
+                .with_source_app(app)
+                .with_method(SelectionMethod::Accessibility),
+        )
+    }
+
+    fn get_selection_by_clipboard(&self) -> Result<Selection, SelectionError> {
+        let (selection, app) = self.copy_and_read(|copied| {
+            copied
+                .iter()
+                .find(|selection| selection.as_text().is_some())
+                .or(copied.first())
+                .cloned()
+        })?;
+        let selection = selection.ok_or(SelectionError::NoSelectedContent)?;
+
+        self.options.finish(
+            self.limit(selection)
+                .with_source_app(app)
+                .with_method(SelectionMethod::Clipboard)
+                .left_on_clipboard(!self.options.restore_clipboard),
+        )
+    }
+
+    fn set_selected_text(&self, text: &str) -> Result<(), SelectionError> {
+        set_selected_text(text);
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! Screen geometry of a selection
+//!
+//! All coordinates are in logical points (not physical pixels) in global
+//! screen space, with the origin at the top-left corner of the primary
+//! display and the y axis pointing down.
+
+/// A rectangle in screen coordinates
+///
+/// Logical points relative to the top-left corner of the primary display,
+/// with y growing downwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    /// Create a rectangle from its origin and size
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Get the smallest rectangle containing both rectangles
+    pub fn union(&self, other: &Rect) -> Rect {
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Rect::new(left, top, right - left, bottom - top)
+    }
+
+    /// Check whether the rectangle has no area
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+}
+
+/// Where the current selection is displayed on screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionBounds {
+    /// The rectangle enclosing the whole selection
+    pub rect: Rect,
+    /// One rectangle per line of a multi-line selection
+    ///
+    /// Contains just `rect` when the backend can't split the selection into
+    /// lines.
+    pub lines: Vec<Rect>,
+}
+
+impl SelectionBounds {
+    /// Build the bounds from per-line rectangles, or `None` if there are none
+    pub fn from_lines(lines: Vec<Rect>) -> Option<Self> {
+        let rect = lines
+            .iter()
+            .copied()
+            .reduce(|union, line| union.union(&line))?;
+
+        Some(SelectionBounds { rect, lines })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds_from_lines() {
+        let bounds = SelectionBounds::from_lines(vec![
+            Rect::new(100.0, 50.0, 200.0, 20.0),
+            Rect::new(10.0, 70.0, 120.0, 20.0),
+        ])
+        .unwrap();
+
+        assert_eq!(bounds.rect, Rect::new(10.0, 50.0, 290.0, 40.0));
+        assert_eq!(bounds.lines.len(), 2);
+        assert!(SelectionBounds::from_lines(Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+//! Capturing the selection when a global shortcut is pressed
+//!
+//! Enabled by the `hotkey` feature. The platform backend listens for the
+//! shortcut on its own thread and runs a capture each time it's pressed, so
+//! callers don't need to install any keyboard hooks themselves.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use crate::watcher::WatcherBackend;
+use crate::{Selection, SelectionError, SelectionOptions};
+
+/// Modifier keys that must be held for a hotkey
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub control: bool,
+    /// Option on macOS
+    pub alt: bool,
+    pub shift: bool,
+    /// Command on macOS, the Windows key elsewhere
+    pub meta: bool,
+}
+
+impl Modifiers {
+    /// No modifiers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require the Control key
+    pub fn control(mut self) -> Self {
+        self.control = true;
+        self
+    }
+
+    /// Require the Alt (Option) key
+    pub fn alt(mut self) -> Self {
+        self.alt = true;
+        self
+    }
+
+    /// Require the Shift key
+    pub fn shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    /// Require the Meta (Command) key
+    pub fn meta(mut self) -> Self {
+        self.meta = true;
+        self
+    }
+}
+
+/// A global keyboard shortcut
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hotkey {
+    /// Modifiers that must be held, exactly
+    pub modifiers: Modifiers,
+    /// The key, as the ASCII letter, digit or punctuation printed on it in
+    /// the US layout; matched by position, whatever the active layout is
+    pub key: char,
+}
+
+impl Hotkey {
+    /// Create a hotkey from its modifiers and key
+    pub fn new(modifiers: Modifiers, key: char) -> Self {
+        Self {
+            modifiers,
+            key: key.to_ascii_lowercase(),
+        }
+    }
+}
+
+/// Captures the selection every time a hotkey is pressed
+///
+/// On macOS the shortcut still reaches the focused application; on Linux
+/// it's taken from it. The listener is removed when this is dropped.
+pub struct HotkeyListener {
+    receiver: Receiver<Result<Selection, SelectionError>>,
+    backend: Option<Box<dyn WatcherBackend>>,
+}
+
+impl HotkeyListener {
+    /// Start listening for `hotkey`, capturing with `options`
+    pub fn new(hotkey: Hotkey, options: SelectionOptions) -> Result<Self, SelectionError> {
+        let (sender, receiver) = mpsc::channel();
+        let backend = start_backend(hotkey, options, sender)?;
+
+        Ok(Self {
+            receiver,
+            backend: Some(backend),
+        })
+    }
+
+    /// Block until the hotkey is pressed and the capture has finished
+    pub fn recv(&self) -> Option<Result<Selection, SelectionError>> {
+        self.receiver.recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next capture
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Result<Selection, SelectionError>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Get the next capture if one is already waiting
+    pub fn try_recv(&self) -> Option<Result<Selection, SelectionError>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Iterate over captures as they finish
+    pub fn iter(&self) -> impl Iterator<Item = Result<Selection, SelectionError>> + '_ {
+        self.receiver.iter()
+    }
+}
+
+impl Drop for HotkeyListener {
+    fn drop(&mut self) {
+        if let Some(backend) = self.backend.take() {
+            backend.stop();
+        }
+    }
+}
+
+fn start_backend(
+    hotkey: Hotkey,
+    options: SelectionOptions,
+    sender: Sender<Result<Selection, SelectionError>>,
+) -> Result<Box<dyn WatcherBackend>, SelectionError> {
+    #[cfg(target_os = "macos")]
+    {
+        crate::macos::start_hotkey(hotkey, options, sender)
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    ))]
+    {
+        crate::linux::start_hotkey(hotkey, options, sender)
+    }
+
+    #[cfg(not(any(
+        target_os = "macos",
+        all(unix, not(any(target_os = "ios", target_os = "android")))
+    )))]
+    {
+        let _ = (hotkey, options, sender);
+        Err(SelectionError::UnsupportedPlatform)
+    }
+}
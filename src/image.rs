@@ -0,0 +1,251 @@
+//! Recognizing encoded images by their magic bytes, and encoding bitmaps
+//! as PNG
+
+#[cfg(any(
+    feature = "image",
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+use std::borrow::Cow;
+
+#[cfg(any(
+    feature = "image",
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+use crate::{privacy::redact, ErrorKind, SelectionError};
+
+/// Detect the format of encoded image data from its first bytes
+///
+/// Returns the MIME subtype `ContentType::Image` holds: `png`, `jpeg`,
+/// `gif`, `bmp`, `tiff` or `webp`; `None` for anything else.
+pub fn detect_image_format(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some("png"),
+        [0xff, 0xd8, 0xff, ..] => Some("jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("gif"),
+        // The two letters alone are too common, so the file header has to fit
+        [b'B', b'M', header @ ..] if header.len() >= 12 => Some("bmp"),
+        [b'I', b'I', 0x2a, 0x00, ..] | [b'M', b'M', 0x00, 0x2a, ..] => Some("tiff"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        _ => None,
+    }
+}
+
+/// How the color channels of a bitmap relate to its alpha channel
+#[cfg(any(
+    feature = "image",
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alpha {
+    /// Colors are independent of alpha, as PNG stores them
+    Straight,
+    /// Colors are already multiplied by alpha, as in bitmaps drawn by Core
+    /// Graphics
+    Premultiplied,
+}
+
+/// Encode 8-bit RGBA pixels, top row first, as PNG
+///
+/// `stride` is the number of bytes from the start of one row to the next,
+/// at least `width * 4`. Tightly packed straight-alpha pixels are written
+/// as they are; anything else is repacked first.
+#[cfg(any(
+    feature = "image",
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+pub fn encode_png(
+    width: usize,
+    height: usize,
+    stride: usize,
+    pixels: &[u8],
+    alpha: Alpha,
+) -> Result<Vec<u8>, SelectionError> {
+    let row_len = width * 4;
+    let needed = stride * height.saturating_sub(1) + row_len;
+    let (Ok(png_width), Ok(png_height)) = (u32::try_from(width), u32::try_from(height)) else {
+        return Err(SelectionError::ClipboardError(format!(
+            "A {}x{} image is too large to encode",
+            redact(width),
+            redact(height)
+        )));
+    };
+    if width == 0 || height == 0 || stride < row_len || pixels.len() < needed {
+        return Err(SelectionError::ClipboardError(format!(
+            "{} bytes don't hold a {}x{} image with rows {} bytes apart",
+            redact(pixels.len()),
+            redact(width),
+            redact(height),
+            redact(stride)
+        )));
+    }
+
+    let mut rgba = if stride == row_len {
+        Cow::Borrowed(&pixels[..needed])
+    } else {
+        Cow::Owned(
+            pixels
+                .chunks(stride)
+                .take(height)
+                .flat_map(|row| &row[..row_len])
+                .copied()
+                .collect(),
+        )
+    };
+    if alpha == Alpha::Premultiplied {
+        unpremultiply(rgba.to_mut());
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, png_width, png_height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgba))
+        .map_err(|err| {
+            SelectionError::platform(ErrorKind::Clipboard, "Failed to encode the image", err)
+        })?;
+
+    Ok(png)
+}
+
+/// Encode an image read through arboard as PNG
+///
+/// arboard hands out tightly packed RGBA with straight alpha on every
+/// platform, converting macOS's premultiplied bitmaps itself, so the
+/// pixels are encoded without a copy.
+#[cfg(feature = "image")]
+pub fn image_data_to_png(image: &arboard::ImageData) -> Result<Vec<u8>, SelectionError> {
+    encode_png(
+        image.width,
+        image.height,
+        image.width * 4,
+        &image.bytes,
+        Alpha::Straight,
+    )
+}
+
+/// Divide the color channels of RGBA pixels by their alpha
+#[cfg(any(
+    feature = "image",
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+fn unpremultiply(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = u16::from(pixel[3]);
+        if alpha == 0 || alpha == 0xff {
+            continue;
+        }
+        for channel in &mut pixel[..3] {
+            // Rounded, and clamped for colors brighter than their alpha allows
+            let straight = (u16::from(*channel) * 0xff + alpha / 2) / alpha;
+            *channel = straight.min(0xff) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_image_format() {
+        assert_eq!(
+            detect_image_format(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("png")
+        );
+        assert_eq!(
+            detect_image_format(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+            Some("jpeg")
+        );
+        assert_eq!(detect_image_format(b"GIF89a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(
+            detect_image_format(b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0"),
+            Some("bmp")
+        );
+        assert_eq!(detect_image_format(b"MM\0\x2a\0\0\0\x08"), Some("tiff"));
+        assert_eq!(detect_image_format(b"RIFF\x24\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(detect_image_format(b"RIFF\x24\0\0\0WAVEfmt "), None);
+        assert_eq!(detect_image_format(b"BMW"), None);
+        assert_eq!(detect_image_format(b""), None);
+    }
+
+    #[cfg(any(
+        feature = "image",
+        all(
+            unix,
+            not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+        )
+    ))]
+    fn decode_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        (reader.info().width, reader.info().height, pixels)
+    }
+
+    #[cfg(any(
+        feature = "image",
+        all(
+            unix,
+            not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+        )
+    ))]
+    #[test]
+    fn test_encode_png_round_trip() {
+        // Every channel of every pixel differs, so a swapped pair shows up
+        let pixels: Vec<u8> = (0..2 * 3 * 4).map(|i| i * 10).collect();
+        let png = encode_png(2, 3, 8, &pixels, Alpha::Straight).unwrap();
+        assert_eq!(detect_image_format(&png), Some("png"));
+        assert_eq!(decode_png(&png), (2, 3, pixels.clone()));
+
+        // Rows padded to 12 bytes, the last one not
+        let mut padded = Vec::new();
+        for row in pixels.chunks(8) {
+            padded.extend_from_slice(row);
+            padded.extend_from_slice(&[0xee; 4]);
+        }
+        padded.truncate(padded.len() - 4);
+        let png = encode_png(2, 3, 12, &padded, Alpha::Straight).unwrap();
+        assert_eq!(decode_png(&png).2, pixels);
+
+        // Half-transparent red, transparent and opaque pixels
+        let premultiplied = [0x80, 0, 0, 0x80, 0, 0, 0, 0, 0x10, 0x20, 0x30, 0xff];
+        let png = encode_png(3, 1, 12, &premultiplied, Alpha::Premultiplied).unwrap();
+        assert_eq!(
+            decode_png(&png).2,
+            [0xff, 0, 0, 0x80, 0, 0, 0, 0, 0x10, 0x20, 0x30, 0xff]
+        );
+
+        assert!(encode_png(2, 3, 8, &pixels[..20], Alpha::Straight).is_err());
+        assert!(encode_png(2, 3, 4, &pixels, Alpha::Straight).is_err());
+        assert!(encode_png(0, 3, 0, &[], Alpha::Straight).is_err());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_image_data_to_png() {
+        let image = arboard::ImageData {
+            width: 1,
+            height: 2,
+            bytes: vec![1, 2, 3, 4, 5, 6, 7, 8].into(),
+        };
+        let png = image_data_to_png(&image).unwrap();
+        assert_eq!(decode_png(&png), (1, 2, vec![1, 2, 3, 4, 5, 6, 7, 8]));
+    }
+}
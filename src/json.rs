@@ -0,0 +1,714 @@
+//! A stable JSON form of selections, for passing them between processes
+
+use std::path::PathBuf;
+
+use crate::{base64, AppInfo, ContentType, Selection, SelectionError, SelectionMethod};
+
+/// Tag identifying the JSON as a selection
+const FORMAT: &str = "selectic.selection";
+
+/// Version of the shape written
+const VERSION: u32 = 1;
+
+/// Deepest nesting `from_json` parses; the shape needs three levels
+const MAX_DEPTH: usize = 16;
+
+impl Selection {
+    /// Serialize the selection to JSON
+    ///
+    /// The shape, which `from_json` reads back:
+    ///
+    /// ```json
+    /// {
+    ///   "format": "selectic.selection",
+    ///   "version": 1,
+    ///   "kind": "image/png",
+    ///   "type": "image/png",
+    ///   "text": null,
+    ///   "data_base64": "iVBORw0KGgo...",
+    ///   "meta": {
+    ///     "source_app": { "name": "Preview", "identifier": "com.apple.Preview",
+    ///                     "pid": 412, "executable": null, "window_title": null },
+    ///     "method": "clipboard",
+    ///     "ranges": [],
+    ///     "converted_from": null,
+    ///     "original_base64": null,
+    ///     "source_format": null,
+    ///     "possibly_stale": false,
+    ///     "possibly_composing": false,
+    ///     "truncated": false,
+    ///     "retries": 0,
+    ///     "left_on_clipboard": false,
+    ///     "url": null
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// - `kind` is the `ContentType` as it's displayed: `text`, `file`, `files`,
+    ///   `html`, `rtf`, `image/<format>` or `other/<MIME type>`. It's what's read
+    ///   back; `type` is its MIME type, for consumers that only need that.
+    /// - Exactly one of `text` and `data_base64` isn't null. Text, file paths
+    ///   and HTML are inlined as `text` when they're valid UTF-8, the paths of
+    ///   `files` separated by `\u0000`. Everything else, and text that isn't
+    ///   UTF-8, is `data_base64`, in standard padded base64.
+    /// - `method` is `accessibility`, `clipboard` or null, and `converted_from`
+    ///   a `kind` or null. `source_app` is null when unknown.
+    /// - `url` is only written and read with the `url` feature.
+    ///
+    /// Fields may be added without changing `version`; readers ignore fields
+    /// they don't know. Anything else bumps `version`, and `from_json` refuses
+    /// versions newer than its own.
+    pub fn to_json(&self) -> String {
+        let inline = matches!(
+            self.content_type,
+            ContentType::Text | ContentType::File | ContentType::Files | ContentType::Html
+        );
+        let (text, data) = match std::str::from_utf8(&self.data) {
+            Ok(text) if inline => (Value::String(text.to_string()), Value::Null),
+            _ => (Value::Null, Value::String(base64::encode(&self.data))),
+        };
+
+        let mut json = String::new();
+        Value::Object(vec![
+            ("format".to_string(), Value::String(FORMAT.to_string())),
+            ("version".to_string(), Value::Number(f64::from(VERSION))),
+            (
+                "kind".to_string(),
+                Value::String(self.content_type.to_string()),
+            ),
+            (
+                "type".to_string(),
+                Value::String(self.content_type.mime_type()),
+            ),
+            ("text".to_string(), text),
+            ("data_base64".to_string(), data),
+            ("meta".to_string(), self.metadata_value()),
+        ])
+        .write(&mut json);
+        json
+    }
+
+    /// Read a selection serialized with `to_json`
+    ///
+    /// Fails with `SelectionError::Other` on malformed JSON, a missing
+    /// field, or a newer version of the shape.
+    pub fn from_json(json: &str) -> Result<Self, SelectionError> {
+        let mut parser = Parser { json, pos: 0 };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos != json.len() {
+            return Err(invalid("trailing characters after the object"));
+        }
+
+        if value.field("format").as_str("format")? != FORMAT {
+            return Err(invalid("not a selection"));
+        }
+        let version = value.field("version").as_u32("version")?;
+        if version > VERSION {
+            return Err(invalid(&format!(
+                "version {} is newer than the supported {}",
+                version, VERSION
+            )));
+        }
+
+        let content_type = parse_kind(value.field("kind").as_str("kind")?);
+        let data = match (value.field("text"), value.field("data_base64")) {
+            (Value::String(text), Value::Null) => text.clone().into_bytes(),
+            (Value::Null, data) => decode_base64(data, "data_base64")?,
+            _ => return Err(invalid("both text and data_base64 are set")),
+        };
+
+        let mut selection = Selection {
+            content_type,
+            data,
+            metadata: Default::default(),
+        };
+        selection.read_metadata(value.field("meta"))?;
+        Ok(selection)
+    }
+
+    fn metadata_value(&self) -> Value {
+        let metadata = &self.metadata;
+        let source_app = match &metadata.source_app {
+            Some(app) => Value::Object(vec![
+                ("name".to_string(), optional_string(app.name.as_deref())),
+                (
+                    "identifier".to_string(),
+                    optional_string(app.identifier.as_deref()),
+                ),
+                (
+                    "pid".to_string(),
+                    app.pid
+                        .map_or(Value::Null, |pid| Value::Number(f64::from(pid))),
+                ),
+                (
+                    "executable".to_string(),
+                    optional_string(
+                        app.executable
+                            .as_deref()
+                            .map(|path| path.to_string_lossy())
+                            .as_deref(),
+                    ),
+                ),
+                (
+                    "window_title".to_string(),
+                    optional_string(app.window_title.as_deref()),
+                ),
+            ]),
+            None => Value::Null,
+        };
+
+        #[allow(unused_mut)]
+        let mut fields = vec![
+            ("source_app".to_string(), source_app),
+            (
+                "method".to_string(),
+                optional_string(metadata.method.map(|method| method.to_string()).as_deref()),
+            ),
+            (
+                "ranges".to_string(),
+                Value::Array(
+                    metadata
+                        .ranges
+                        .iter()
+                        .map(|range| Value::String(range.clone()))
+                        .collect(),
+                ),
+            ),
+            (
+                "converted_from".to_string(),
+                optional_string(
+                    metadata
+                        .converted_from
+                        .as_ref()
+                        .map(|kind| kind.to_string())
+                        .as_deref(),
+                ),
+            ),
+            (
+                "original_base64".to_string(),
+                optional_string(metadata.original.as_deref().map(base64::encode).as_deref()),
+            ),
+            (
+                "source_format".to_string(),
+                optional_string(metadata.source_format.as_deref()),
+            ),
+            (
+                "possibly_stale".to_string(),
+                Value::Bool(metadata.possibly_stale),
+            ),
+            (
+                "possibly_composing".to_string(),
+                Value::Bool(metadata.possibly_composing),
+            ),
+            ("truncated".to_string(), Value::Bool(metadata.truncated)),
+            (
+                "retries".to_string(),
+                Value::Number(f64::from(metadata.retries)),
+            ),
+            (
+                "left_on_clipboard".to_string(),
+                Value::Bool(metadata.left_on_clipboard),
+            ),
+        ];
+        #[cfg(feature = "url")]
+        fields.push((
+            "url".to_string(),
+            optional_string(metadata.url.as_ref().map(url::Url::as_str)),
+        ));
+        Value::Object(fields)
+    }
+
+    fn read_metadata(&mut self, meta: &Value) -> Result<(), SelectionError> {
+        let metadata = &mut self.metadata;
+        let app = meta.field("source_app");
+        if !matches!(app, Value::Null) {
+            metadata.source_app = Some(AppInfo {
+                name: app.field("name").as_optional_string("name")?,
+                identifier: app.field("identifier").as_optional_string("identifier")?,
+                pid: match app.field("pid") {
+                    Value::Null => None,
+                    pid => Some(pid.as_u32("pid")?),
+                },
+                executable: app
+                    .field("executable")
+                    .as_optional_string("executable")?
+                    .map(PathBuf::from),
+                window_title: app
+                    .field("window_title")
+                    .as_optional_string("window_title")?,
+            });
+        }
+
+        metadata.method = match meta
+            .field("method")
+            .as_optional_string("method")?
+            .as_deref()
+        {
+            None => None,
+            Some("accessibility") => Some(SelectionMethod::Accessibility),
+            Some("clipboard") => Some(SelectionMethod::Clipboard),
+            Some(method) => return Err(invalid(&format!("unknown method {:?}", method))),
+        };
+        metadata.ranges = match meta.field("ranges") {
+            Value::Null => Vec::new(),
+            Value::Array(ranges) => ranges
+                .iter()
+                .map(|range| range.as_str("ranges").map(str::to_string))
+                .collect::<Result<_, _>>()?,
+            _ => return Err(invalid("ranges isn't an array")),
+        };
+        metadata.converted_from = meta
+            .field("converted_from")
+            .as_optional_string("converted_from")?
+            .map(|kind| parse_kind(&kind));
+        metadata.original = match meta.field("original_base64") {
+            Value::Null => None,
+            original => Some(decode_base64(original, "original_base64")?),
+        };
+        metadata.source_format = meta
+            .field("source_format")
+            .as_optional_string("source_format")?;
+        metadata.possibly_stale = meta.field("possibly_stale").as_flag("possibly_stale")?;
+        metadata.possibly_composing = meta
+            .field("possibly_composing")
+            .as_flag("possibly_composing")?;
+        metadata.truncated = meta.field("truncated").as_flag("truncated")?;
+        metadata.retries = match meta.field("retries") {
+            Value::Null => 0,
+            retries => retries.as_u32("retries")?,
+        };
+        metadata.left_on_clipboard = meta
+            .field("left_on_clipboard")
+            .as_flag("left_on_clipboard")?;
+        #[cfg(feature = "url")]
+        {
+            metadata.url = match meta.field("url").as_optional_string("url")? {
+                Some(url) => Some(url::Url::parse(&url).map_err(|_| invalid("url isn't a URL"))?),
+                None => None,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Read a content type back from how it's displayed
+fn parse_kind(kind: &str) -> ContentType {
+    match kind {
+        "text" => ContentType::Text,
+        "file" => ContentType::File,
+        "files" => ContentType::Files,
+        "html" => ContentType::Html,
+        "rtf" => ContentType::Rtf,
+        _ => match (kind.strip_prefix("image/"), kind.strip_prefix("other/")) {
+            (Some(format), _) => ContentType::Image(format.to_string()),
+            (_, Some(format)) => ContentType::Other(format.to_string()),
+            // A kind added later, kept as its MIME type
+            _ => ContentType::Other(kind.to_string()),
+        },
+    }
+}
+
+fn decode_base64(value: &Value, name: &str) -> Result<Vec<u8>, SelectionError> {
+    base64::decode(value.as_str(name)?.as_bytes())
+        .ok_or_else(|| invalid(&format!("{} isn't valid base64", name)))
+}
+
+fn optional_string(string: Option<&str>) -> Value {
+    string.map_or(Value::Null, |string| Value::String(string.to_string()))
+}
+
+fn invalid(reason: &str) -> SelectionError {
+    SelectionError::Other(format!("Invalid selection JSON: {}", reason))
+}
+
+/// A parsed JSON value
+#[derive(Debug, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Fields in the order they were written
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value of an object's field, null if it has none
+    fn field(&self, name: &str) -> &Value {
+        match self {
+            Value::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map_or(&Value::Null, |(_, value)| value),
+            _ => &Value::Null,
+        }
+    }
+
+    fn as_str(&self, name: &str) -> Result<&str, SelectionError> {
+        match self {
+            Value::String(string) => Ok(string),
+            _ => Err(invalid(&format!("{} isn't a string", name))),
+        }
+    }
+
+    fn as_optional_string(&self, name: &str) -> Result<Option<String>, SelectionError> {
+        match self {
+            Value::Null => Ok(None),
+            value => value.as_str(name).map(|string| Some(string.to_string())),
+        }
+    }
+
+    fn as_u32(&self, name: &str) -> Result<u32, SelectionError> {
+        match self {
+            Value::Number(number)
+                if number.fract() == 0.0 && (0.0..=f64::from(u32::MAX)).contains(number) =>
+            {
+                Ok(*number as u32)
+            }
+            _ => Err(invalid(&format!("{} isn't a whole number", name))),
+        }
+    }
+
+    /// A boolean that's false when missing
+    fn as_flag(&self, name: &str) -> Result<bool, SelectionError> {
+        match self {
+            Value::Null => Ok(false),
+            Value::Bool(flag) => Ok(*flag),
+            _ => Err(invalid(&format!("{} isn't a boolean", name))),
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(flag) => out.push_str(if *flag { "true" } else { "false" }),
+            Value::Number(number) => out.push_str(&number.to_string()),
+            Value::String(string) => write_string(string, out),
+            Value::Array(values) => {
+                out.push('[');
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    value.write(out);
+                }
+                out.push(']');
+            }
+            Value::Object(fields) => {
+                out.push('{');
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    write_string(name, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_string(string: &str, out: &mut String) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// A recursive descent parser of JSON text
+struct Parser<'a> {
+    json: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self, depth: usize) -> Result<Value, SelectionError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("nested too deeply"));
+        }
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.string()?;
+                        self.expect(b':')?;
+                        fields.push((name, self.value(depth + 1)?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Object(fields))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.value(depth + 1)?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Array(values))
+            }
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => {
+                for (literal, value) in [
+                    ("null", Value::Null),
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                ] {
+                    if self.json[self.pos..].starts_with(literal) {
+                        self.pos += literal.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.unexpected())
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SelectionError> {
+        if !self.eat(b'"') {
+            return Err(self.unexpected());
+        }
+
+        let mut string = String::new();
+        loop {
+            let rest = &self.json[self.pos..];
+            let Some(end) = rest.find(['"', '\\']) else {
+                return Err(invalid("unterminated string"));
+            };
+            if rest[..end].chars().any(|c| u32::from(c) < 0x20) {
+                return Err(invalid("control character in a string"));
+            }
+            string.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(string);
+            }
+
+            let escaped = self.peek().ok_or_else(|| invalid("unterminated string"))?;
+            self.pos += 1;
+            match escaped {
+                b'"' => string.push('"'),
+                b'\\' => string.push('\\'),
+                b'/' => string.push('/'),
+                b'b' => string.push('\u{8}'),
+                b'f' => string.push('\u{c}'),
+                b'n' => string.push('\n'),
+                b'r' => string.push('\r'),
+                b't' => string.push('\t'),
+                b'u' => {
+                    let unit = self.hex_unit()?;
+                    let c = if (0xd800..0xdc00).contains(&unit) {
+                        // A high surrogate, which must be followed by a low one
+                        if !self.json[self.pos..].starts_with("\\u") {
+                            return Err(invalid("unpaired surrogate"));
+                        }
+                        self.pos += 2;
+                        let low = self.hex_unit()?;
+                        char::decode_utf16([unit, low]).next().and_then(Result::ok)
+                    } else {
+                        char::from_u32(u32::from(unit))
+                    };
+                    string.push(c.ok_or_else(|| invalid("unpaired surrogate"))?);
+                }
+                _ => return Err(invalid("unknown escape in a string")),
+            }
+        }
+    }
+
+    /// The four hex digits of a `\u` escape
+    fn hex_unit(&mut self) -> Result<u16, SelectionError> {
+        let digits = self
+            .json
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .ok_or_else(|| invalid("malformed \\u escape"))?;
+        self.pos += 4;
+        u16::from_str_radix(digits, 16).map_err(|_| invalid("malformed \\u escape"))
+    }
+
+    fn number(&mut self) -> Result<Value, SelectionError> {
+        let rest = &self.json[self.pos..];
+        let len = rest
+            .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+            .unwrap_or(rest.len());
+        let number = rest[..len]
+            .parse()
+            .map_err(|_| invalid("malformed number"))?;
+        self.pos += len;
+        Ok(Value::Number(number))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.json.as_bytes().get(self.pos).copied()
+    }
+
+    /// Skip whitespace and `byte`, returning whether it was there
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), SelectionError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> SelectionError {
+        match self.json[self.pos..].chars().next() {
+            Some(c) => invalid(&format!("unexpected {:?} at byte {}", c, self.pos)),
+            None => invalid("unexpected end"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(selection: &Selection) -> Selection {
+        let json = selection.to_json();
+        let read = Selection::from_json(&json).unwrap();
+        assert_eq!(read.content_type, selection.content_type, "{}", json);
+        assert_eq!(read.data, selection.data, "{}", json);
+        assert_eq!(read.metadata, selection.metadata, "{}", json);
+        read
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let selections = [
+            Selection::new_text("héllo \"wörld\"\n\t\u{1}😀".to_string()),
+            Selection::new_text_bytes(b"caf\xe9".to_vec()),
+            Selection::new_file("/tmp/a file".to_string()),
+            Selection::new_files(vec![PathBuf::from("/a"), PathBuf::from("/b c")]),
+            Selection::new_html("<b>bold</b>".to_string()),
+            Selection::new_rtf(b"{\\rtf1 hi}".to_vec()),
+            Selection::new_image("png", b"\x89PNG\r\n\x1a\n\0".to_vec()),
+            Selection::new_other("application/pdf", b"%PDF-1.7".to_vec()),
+            Selection::new_text(String::new()),
+        ];
+        for selection in &selections {
+            round_trip(selection);
+        }
+
+        let mut selection = Selection::new_text("first\nsecond".to_string())
+            .with_method(SelectionMethod::Clipboard)
+            .with_source_app(Some(AppInfo {
+                name: Some("Editor".to_string()),
+                identifier: Some("org.example.Editor".to_string()),
+                pid: Some(4242),
+                executable: Some(PathBuf::from("/usr/bin/editor")),
+                window_title: None,
+            }));
+        let metadata = &mut selection.metadata;
+        metadata.ranges = vec!["first".to_string(), "second".to_string()];
+        metadata.converted_from = Some(ContentType::Rtf);
+        metadata.original = Some(b"{\\rtf1 first}".to_vec());
+        metadata.source_format = Some("text/plain;charset=utf-8".to_string());
+        metadata.possibly_stale = true;
+        metadata.possibly_composing = true;
+        metadata.truncated = true;
+        metadata.retries = 2;
+        metadata.left_on_clipboard = true;
+        #[cfg(feature = "url")]
+        {
+            metadata.url = url::Url::parse("https://example.com/?q=1").ok();
+        }
+        round_trip(&selection);
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let json = Selection::new_image("png", b"hi".to_vec()).to_json();
+        let url = if cfg!(feature = "url") {
+            ",\"url\":null"
+        } else {
+            ""
+        };
+        assert_eq!(
+            json,
+            format!(
+                "{{\"format\":\"selectic.selection\",\"version\":1,\"kind\":\"image/png\",\
+                 \"type\":\"image/png\",\"text\":null,\"data_base64\":\"aGk=\",\"meta\":{{\
+                 \"source_app\":null,\"method\":null,\"ranges\":[],\"converted_from\":null,\
+                 \"original_base64\":null,\"source_format\":null,\"possibly_stale\":false,\
+                 \"possibly_composing\":false,\"truncated\":false,\"retries\":0,\
+                 \"left_on_clipboard\":false{}}}}}",
+                url
+            )
+        );
+
+        // Invalid UTF-8 falls back to base64, files are NUL-separated text
+        let json = Selection::new_text_bytes(b"caf\xe9".to_vec()).to_json();
+        assert!(json.contains("\"text\":null,\"data_base64\":\"Y2Fm6Q==\""));
+        let json = Selection::new_files(vec![PathBuf::from("/a"), PathBuf::from("/b")]).to_json();
+        assert!(json.contains("\"text\":\"/a\\u0000/b\",\"data_base64\":null"));
+    }
+
+    #[test]
+    fn test_from_json() {
+        // Whitespace, unknown fields, escapes and a missing meta are fine
+        let selection = Selection::from_json(
+            " { \"format\" : \"selectic.selection\", \"version\": 1, \"kind\": \"text\",\n\
+             \"future\": [1, {\"a\": -2.5e3}], \"text\": \"\\u00e9\\ud83d\\ude00\\/\" } ",
+        )
+        .unwrap();
+        assert_eq!(selection.as_text(), Some("é😀/".to_string()));
+
+        for json in [
+            "",
+            "[]",
+            "{\"format\":\"other\",\"version\":1,\"kind\":\"text\",\"text\":\"\"}",
+            "{\"format\":\"selectic.selection\",\"version\":2,\"kind\":\"text\",\"text\":\"\"}",
+            "{\"format\":\"selectic.selection\",\"version\":1,\"kind\":\"text\",\"text\":\"\\ud83d\"}",
+            "{\"format\":\"selectic.selection\",\"version\":1,\"kind\":\"rtf\",\"data_base64\":\"!\"}",
+            "{\"format\":\"selectic.selection\",\"version\":1,\"kind\":\"text\",\"text\":\"\"} x",
+            "{\"format\":\"selectic.selection\",\"version\":1,\"kind\":\"text\",\"text\":\"a\",\"data_base64\":\"\"}",
+        ] {
+            assert!(Selection::from_json(json).is_err(), "{}", json);
+        }
+
+        let nested = "[".repeat(100);
+        assert!(Selection::from_json(&nested).is_err());
+    }
+}
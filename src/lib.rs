@@ -1,7 +1,77 @@
 use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+mod app_rules;
+pub use app_rules::{default_app_rules, AppRule};
+
+#[cfg(any(
+    feature = "json",
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+mod base64;
+
+mod classify;
+pub use classify::{classify_text, TextClass};
+#[cfg(feature = "url")]
+pub use url::Url;
+
+mod capabilities;
+pub use capabilities::{capabilities, diagnose, Capabilities};
+
+mod context;
+pub use context::SelectionContext;
 
 mod error;
-pub use error::SelectionError;
+pub use error::{ErrorKind, SelectionError};
+
+#[cfg(feature = "fake-platform")]
+pub mod fake;
+
+#[cfg(feature = "hotkey")]
+mod hotkey;
+#[cfg(feature = "hotkey")]
+pub use hotkey::{Hotkey, HotkeyListener, Modifiers};
+
+mod geometry;
+pub use geometry::{Rect, SelectionBounds};
+
+mod image;
+pub use image::detect_image_format;
+
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "image")]
+pub use image::{encode_png, image_data_to_png, Alpha};
+
+mod metadata;
+pub use metadata::{AppInfo, SelectionMetadata};
+
+mod observer;
+pub use observer::{set_observer, AttemptOutcome, SelectionObserver};
+
+mod options;
+pub use options::{
+    OversizePolicy, SelectionOptions, SelectionSource, WaylandInjector, DEFAULT_MAX_BYTES,
+};
+
+#[cfg(any(target_os = "macos", all(test, unix)))]
+mod osascript;
+
+mod privacy;
+pub use privacy::{privacy, set_privacy, Privacy, PRIVACY_ENV};
+
+#[cfg(any(target_os = "macos", test))]
+mod rtf;
+
+#[cfg(any(all(unix, not(any(target_os = "ios", target_os = "android"))), test))]
+mod uri;
+
+mod watcher;
+pub use watcher::{SelectionEvent, SelectionWatcher, WatchSource, WatcherOptions};
 
 #[cfg(target_os = "macos")]
 pub mod macos;
@@ -9,7 +79,10 @@ pub mod macos;
 #[cfg(target_os = "windows")]
 pub mod windows;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
 pub mod linux;
 
 /// Represents the type of content that was selected
@@ -19,6 +92,14 @@ pub enum ContentType {
     Text,
     /// File path(s)
     File,
+    /// A list of file paths
+    Files,
+    /// HTML markup
+    Html,
+    /// Rich Text Format document
+    Rtf,
+    /// Encoded image, with the format's MIME subtype (e.g. `png`)
+    Image(String),
     /// Other types of content with format specification
     Other(String),
 }
@@ -28,18 +109,105 @@ impl fmt::Display for ContentType {
         match self {
             ContentType::Text => write!(f, "text"),
             ContentType::File => write!(f, "file"),
+            ContentType::Files => write!(f, "files"),
+            ContentType::Html => write!(f, "html"),
+            ContentType::Rtf => write!(f, "rtf"),
+            ContentType::Image(format) => write!(f, "image/{}", format),
             ContentType::Other(format) => write!(f, "other/{}", format),
         }
     }
 }
 
+impl ContentType {
+    /// Get the MIME type corresponding to this content type
+    pub fn mime_type(&self) -> String {
+        match self {
+            ContentType::Text => "text/plain".to_string(),
+            ContentType::File | ContentType::Files => "text/uri-list".to_string(),
+            ContentType::Html => "text/html".to_string(),
+            ContentType::Rtf => "text/rtf".to_string(),
+            ContentType::Image(format) => format!("image/{}", format),
+            ContentType::Other(format) => format.clone(),
+        }
+    }
+
+    /// Rank of the content type among representations of one selection,
+    /// lowest for the richest: files, then markup, images and plain text last
+    pub(crate) fn richness(&self) -> u8 {
+        match self {
+            ContentType::File | ContentType::Files => 0,
+            ContentType::Html => 1,
+            ContentType::Rtf => 2,
+            ContentType::Image(_) => 3,
+            ContentType::Other(_) => 4,
+            ContentType::Text => 5,
+        }
+    }
+
+    /// Check whether this content type satisfies a MIME pattern
+    ///
+    /// Patterns may be exact (`text/html`), a whole top-level type (`text/*`)
+    /// or `*/*`. Parameters such as `;charset=utf-8` are ignored.
+    pub fn matches_mime(&self, pattern: &str) -> bool {
+        let pattern = normalize_mime(pattern);
+        let mime = self.mime_type();
+
+        if pattern == "*/*" || pattern == mime {
+            return true;
+        }
+
+        if let Some(prefix) = pattern.strip_suffix("/*") {
+            return mime.split('/').next() == Some(prefix);
+        }
+
+        // Common aliases used by different platforms and toolkits
+        matches!(
+            (self, pattern.as_str()),
+            (ContentType::Text, "text" | "utf8_string" | "string")
+                | (ContentType::Rtf, "application/rtf" | "text/richtext")
+        )
+    }
+}
+
+/// A strategy for reading the selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionMethod {
+    /// Query the platform accessibility API, without side effects
+    Accessibility,
+    /// Simulate a copy shortcut and read the clipboard, restoring it after
+    Clipboard,
+}
+
+impl fmt::Display for SelectionMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectionMethod::Accessibility => write!(f, "accessibility"),
+            SelectionMethod::Clipboard => write!(f, "clipboard"),
+        }
+    }
+}
+
+/// Lowercase a MIME type and strip its parameters
+fn normalize_mime(mime: &str) -> String {
+    mime.split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
 /// Structure representing selected content with its type
 #[derive(Debug, Clone)]
 pub struct Selection {
     /// The type of content (text, image, file, etc.)
     pub content_type: ContentType,
     /// The actual content data as bytes
+    ///
+    /// Text is UTF-8, except that on Linux text an owner declared as UTF-8 is
+    /// kept as it was sent even if it isn't valid; see `as_text_lossy`.
     pub data: Vec<u8>,
+    /// Where the selection came from
+    pub metadata: SelectionMetadata,
 }
 
 impl Selection {
@@ -48,6 +216,17 @@ impl Selection {
         Self {
             content_type: ContentType::Text,
             data: text.into_bytes(),
+            metadata: SelectionMetadata::default(),
+        }
+    }
+
+    /// Create a text selection from bytes that should be UTF-8, keeping
+    /// them as they are even if they aren't
+    pub fn new_text_bytes(data: Vec<u8>) -> Self {
+        Self {
+            content_type: ContentType::Text,
+            data,
+            metadata: SelectionMetadata::default(),
         }
     }
 
@@ -56,18 +235,125 @@ impl Selection {
         Self {
             content_type: ContentType::File,
             data: path.into_bytes(),
+            metadata: SelectionMetadata::default(),
+        }
+    }
+
+    /// Create a new selection holding several file paths
+    ///
+    /// The paths are stored NUL-separated in `data`, since NUL can't appear
+    /// in a path on any supported platform.
+    pub fn new_files(paths: Vec<PathBuf>) -> Self {
+        let data = paths
+            .iter()
+            .map(|path| path_to_bytes(path))
+            .collect::<Vec<_>>()
+            .join(&0u8);
+
+        Self {
+            content_type: ContentType::Files,
+            data,
+            metadata: SelectionMetadata::default(),
+        }
+    }
+
+    /// Create a new HTML selection
+    pub fn new_html(html: String) -> Self {
+        Self {
+            content_type: ContentType::Html,
+            data: html.into_bytes(),
+            metadata: SelectionMetadata::default(),
+        }
+    }
+
+    /// Create a new RTF selection from the raw document bytes
+    pub fn new_rtf(data: Vec<u8>) -> Self {
+        Self {
+            content_type: ContentType::Rtf,
+            data,
+            metadata: SelectionMetadata::default(),
         }
     }
 
+    /// Create a new image selection from encoded image bytes
+    ///
+    /// `format` is the MIME subtype of the encoding, e.g. `png`.
+    pub fn new_image(format: &str, data: Vec<u8>) -> Self {
+        Self {
+            content_type: ContentType::Image(format.to_ascii_lowercase()),
+            data,
+            metadata: SelectionMetadata::default(),
+        }
+    }
+
+    /// Create a new image selection from encoded image bytes, taking the
+    /// format from their magic bytes
+    ///
+    /// Data in a format `detect_image_format` doesn't know is typed
+    /// `ContentType::Other("image/unknown")`. Empty data is refused.
+    pub fn new_image_detect(data: Vec<u8>) -> Result<Self, SelectionError> {
+        if data.is_empty() {
+            return Err(SelectionError::InvalidContentType {
+                expected: "image".to_string(),
+                received: "no data".to_string(),
+            });
+        }
+        Ok(match detect_image_format(&data) {
+            Some(format) => Self::new_image(format, data),
+            None => Self::new_other("image/unknown", data),
+        })
+    }
+
     /// Create a new selection with custom type
     pub fn new_other(format: &str, data: Vec<u8>) -> Self {
         Self {
             content_type: ContentType::Other(format.to_string()),
             data,
+            metadata: SelectionMetadata::default(),
+        }
+    }
+
+    /// Attach the application the selection was read from
+    pub fn with_source_app(mut self, app: Option<AppInfo>) -> Self {
+        self.metadata.source_app = app;
+        self
+    }
+
+    /// Record the method the selection was read with
+    pub fn with_method(mut self, method: SelectionMethod) -> Self {
+        self.metadata.method = Some(method);
+        self
+    }
+
+    /// Record whether the selection was left on the clipboard by the copy
+    /// it was read with
+    pub(crate) fn left_on_clipboard(mut self, left: bool) -> Self {
+        self.metadata.left_on_clipboard = left;
+        self
+    }
+
+    /// Shorten text content to at most `max_bytes`, cutting at a character
+    /// boundary and setting `metadata.truncated`
+    ///
+    /// Returns whether anything was cut; other content types are left alone.
+    pub fn truncate_text(&mut self, max_bytes: usize) -> bool {
+        if self.content_type != ContentType::Text || self.data.len() <= max_bytes {
+            return false;
         }
+
+        // Back up to the start of the character straddling the limit
+        let mut end = max_bytes;
+        while end > 0 && (self.data[end] & 0xc0) == 0x80 {
+            end -= 1;
+        }
+        self.data.truncate(end);
+        self.metadata.truncated = true;
+        true
     }
 
     /// Get the content as a UTF-8 string if it's text content
+    ///
+    /// Returns `None` for text that isn't valid UTF-8.
     pub fn as_text(&self) -> Option<String> {
         if let ContentType::Text = self.content_type {
             String::from_utf8(self.data.clone()).ok()
@@ -76,6 +362,15 @@ impl Selection {
         }
     }
 
+    /// Get text content as a string, replacing invalid UTF-8 with U+FFFD
+    pub fn as_text_lossy(&self) -> Option<String> {
+        if let ContentType::Text = self.content_type {
+            Some(String::from_utf8_lossy(&self.data).into_owned())
+        } else {
+            None
+        }
+    }
+
     /// Get the content as a file path if it's file content
     pub fn as_file_path(&self) -> Option<String> {
         if let ContentType::File = self.content_type {
@@ -85,47 +380,388 @@ impl Selection {
         }
     }
 
+    /// Get the content as a list of paths if it's file content
+    pub fn as_files(&self) -> Option<Vec<PathBuf>> {
+        match self.content_type {
+            ContentType::Files => Some(
+                self.data
+                    .split(|byte| *byte == 0)
+                    .filter(|bytes| !bytes.is_empty())
+                    .map(path_from_bytes)
+                    .collect(),
+            ),
+            ContentType::File => self.as_file_path().map(|path| vec![PathBuf::from(path)]),
+            _ => None,
+        }
+    }
+
+    /// Get the content as an HTML string if it's HTML content
+    pub fn as_html(&self) -> Option<String> {
+        if let ContentType::Html = self.content_type {
+            String::from_utf8(self.data.clone()).ok()
+        } else {
+            None
+        }
+    }
+
     /// Check if the selection is empty
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
 }
 
+#[cfg(unix)]
+fn path_to_bytes(path: &std::path::Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &std::path::Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
 /// Trait for retrieving user-selected content across platforms
 pub trait Selector {
     /// Get the currently selected content using the best available method
-    fn get_selection(&self) -> Result<Selection, SelectionError>;
+    ///
+    /// Every backend returns `SelectionError::NoSelectedContent` when nothing
+    /// is selected, rather than an empty selection.
+    ///
+    /// The default implementation tries the methods of `capture_rule` in
+    /// order. An accessibility API reporting nothing selected ends the
+    /// capture if `trusts_empty_accessibility`, rather than simulating a
+    /// copy; otherwise the next method is tried. When every method fails,
+    /// the error is `NoSelectedContent` if none of them found anything to
+    /// read, the one error if only one of them failed for another reason,
+    /// and `AllMethodsFailed` with each method's error otherwise.
+    fn get_selection(&self) -> Result<Selection, SelectionError> {
+        capture(self, AccessibilityRole::Answer, |method| {
+            self.get_selection_by_method(method)
+        })
+    }
+
+    /// The rule `get_selection` follows for the application the selection
+    /// would be read from
+    ///
+    /// The default tries the accessibility API, then the clipboard.
+    /// Backends that can tell the focused application return its rule from
+    /// `SelectionOptions::rule_for_app`.
+    fn capture_rule(&self) -> Result<AppRule, SelectionError> {
+        Ok(AppRule::default())
+    }
+
+    /// The observer `get_selection` reports its attempts to
+    ///
+    /// The default is the one installed with `set_observer`; backends with
+    /// options return `SelectionOptions::observer` in preference.
+    fn observer(&self) -> Option<Arc<dyn SelectionObserver>> {
+        observer::resolve(None)
+    }
+
+    /// Whether `get_selection` stops at an accessibility API reporting
+    /// nothing selected, see `SelectionOptions::trust_empty_accessibility`
+    fn trusts_empty_accessibility(&self) -> bool {
+        true
+    }
+
+    /// Get the current selection through the accessibility API only
+    fn get_selection_by_accessibility(&self) -> Result<Selection, SelectionError> {
+        Err(SelectionError::MethodUnavailable(
+            SelectionMethod::Accessibility.to_string(),
+        ))
+    }
+
+    /// Get the current selection through the clipboard only
+    fn get_selection_by_clipboard(&self) -> Result<Selection, SelectionError> {
+        Err(SelectionError::MethodUnavailable(
+            SelectionMethod::Clipboard.to_string(),
+        ))
+    }
+
+    /// Get the current selection with a specific method, without falling
+    /// back to the other one
+    fn get_selection_by_method(
+        &self,
+        method: SelectionMethod,
+    ) -> Result<Selection, SelectionError> {
+        match method {
+            SelectionMethod::Accessibility => self.get_selection_by_accessibility(),
+            SelectionMethod::Clipboard => self.get_selection_by_clipboard(),
+        }
+    }
+
+    /// Get the currently selected content in the first available format of
+    /// a MIME preference list (most preferred first)
+    ///
+    /// An empty list behaves like `get_selection`. The default implementation
+    /// only checks the result of `get_selection` against the preferences;
+    /// backends that can read richer formats override it.
+    fn get_selection_raw(&self, preferred: &[&str]) -> Result<Selection, SelectionError> {
+        if preferred.is_empty() {
+            return self.get_selection();
+        }
+        pick_preferred(self.get_selection_all()?, preferred)
+    }
+
+    /// Get every representation of the currently selected content, e.g.
+    /// HTML and plain text, richest first
+    ///
+    /// Backends read them all from one capture, simulating at most one
+    /// copy. The default implementation returns the result of
+    /// `get_selection` alone.
+    fn get_selection_all(&self) -> Result<Vec<Selection>, SelectionError> {
+        Ok(vec![self.get_selection()?])
+    }
+
+    /// Get where the current selection is displayed on screen
+    ///
+    /// Coordinates follow the convention documented on [`Rect`]. Backends
+    /// without a way to query geometry return `UnsupportedPlatform`.
+    fn get_selection_bounds(&self) -> Result<SelectionBounds, SelectionError> {
+        Err(SelectionError::UnsupportedPlatform)
+    }
+
+    /// Get the position of the text caret in the focused element
+    ///
+    /// The rect has zero width and the height of the line the caret is on.
+    /// Backends without a way to query geometry return `UnsupportedPlatform`.
+    fn get_caret_position(&self) -> Result<Rect, SelectionError> {
+        Err(SelectionError::UnsupportedPlatform)
+    }
+
+    /// Get the current selection together with up to `chars` characters of
+    /// text on each side of it
+    ///
+    /// Backends that can't read the surrounding text return
+    /// `UnsupportedPlatform`.
+    fn get_selection_context(&self, chars: usize) -> Result<SelectionContext, SelectionError> {
+        let _ = chars;
+        Err(SelectionError::UnsupportedPlatform)
+    }
+
+    /// Replace the current selection with `text`
+    ///
+    /// Backends without a way to write back return `UnsupportedPlatform`.
+    fn set_selected_text(&self, text: &str) -> Result<(), SelectionError> {
+        let _ = text;
+        Err(SelectionError::UnsupportedPlatform)
+    }
 }
 
-/// Main function to get user's current selection
-///
-/// This function automatically creates the appropriate selector
-/// for the current platform and retrieves the selection.
-pub fn get_selection() -> Result<Selection, SelectionError> {
-    #[cfg(target_os = "macos")]
+/// What the accessibility API, which only reads plain text, is used for by
+/// `capture`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccessibilityRole {
+    /// What it reads is the selection
+    Answer,
+    /// What it reads stands in for what the later methods fail to read,
+    /// when the caller wants richer formats; it reporting nothing selected
+    /// still ends the capture
+    StandIn,
+    /// It isn't tried, when the caller wants another format most
+    Skip,
+}
+
+/// Try the methods of the selector's `capture_rule` in order with `read`,
+/// reporting each attempt to its observer; see `Selector::get_selection`
+pub(crate) fn capture<S: Selector + ?Sized, T: observer::Captured>(
+    selector: &S,
+    accessibility: AccessibilityRole,
+    read: impl FnMut(SelectionMethod) -> Result<T, SelectionError>,
+) -> Result<T, SelectionError> {
+    capture_with_rule(selector, selector.capture_rule()?, accessibility, read)
+}
+
+/// `capture` following a rule the caller has already looked up
+pub(crate) fn capture_with_rule<S: Selector + ?Sized, T: observer::Captured>(
+    selector: &S,
+    rule: AppRule,
+    accessibility: AccessibilityRole,
+    mut read: impl FnMut(SelectionMethod) -> Result<T, SelectionError>,
+) -> Result<T, SelectionError> {
+    if !rule.allow_capture {
+        return Err(SelectionError::PermissionDenied(
+            "Capture is disabled for the focused application".to_string(),
+        ));
+    }
+
+    let observer = selector.observer();
+    let observer = observer.as_deref();
+    let mut failures: Vec<(SelectionMethod, SelectionError)> = Vec::new();
+    let mut stand_in = None;
+    for method in rule.methods {
+        let is_accessibility = method == SelectionMethod::Accessibility;
+        if is_accessibility && accessibility == AccessibilityRole::Skip {
+            log::debug!("Skipping the accessibility API, another format is preferred");
+            continue;
+        }
+        if let Some((previous, err)) = failures.last().filter(|_| stand_in.is_none()) {
+            observer::fallback(observer, *previous, method, err);
+        }
+        let err = match observer::attempt(observer, method, || read(method)) {
+            Ok(captured) if is_accessibility && accessibility == AccessibilityRole::StandIn => {
+                stand_in = Some(captured);
+                continue;
+            }
+            Ok(captured) => return Ok(captured),
+            Err(err) => err,
+        };
+        log::debug!("Reading the selection via {} failed: {}", method, err);
+
+        let nothing_selected = matches!(err, SelectionError::NoSelectedContent);
+        if nothing_selected && is_accessibility && selector.trusts_empty_accessibility() {
+            return Err(err);
+        }
+        failures.push((method, err));
+    }
+
+    match stand_in {
+        Some(captured) => Ok(captured),
+        None => Err(SelectionError::from_failures(failures)),
+    }
+}
+
+/// The selector the free functions use: the fake desktop with the
+/// `fake-platform` feature, the current platform's otherwise
+fn default_selector() -> Result<Box<dyn Selector>, SelectionError> {
+    #[cfg(feature = "fake-platform")]
     {
-        let selector = macos::MacOSSelector::new();
-        selector.get_selection()
+        Ok(Box::new(fake::FakeSelector::new()))
     }
 
-    #[cfg(target_os = "windows")]
+    #[cfg(all(not(feature = "fake-platform"), target_os = "macos"))]
     {
-        let selector = windows::WindowsSelector::new();
-        selector.get_selection()
+        Ok(Box::new(macos::MacOSSelector::new()))
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(all(not(feature = "fake-platform"), target_os = "windows"))]
     {
-        let selector = linux::LinuxSelector::new();
-        selector.get_selection()
+        Ok(Box::new(windows::WindowsSelector::new()))
     }
 
-    #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+    #[cfg(all(
+        not(feature = "fake-platform"),
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    ))]
+    {
+        Ok(Box::new(linux::LinuxSelector::new()))
+    }
+
+    #[cfg(not(any(
+        feature = "fake-platform",
+        target_os = "macos",
+        target_os = "windows",
+        all(unix, not(any(target_os = "ios", target_os = "android")))
+    )))]
     {
         Err(SelectionError::UnsupportedPlatform)
     }
 }
 
+/// Main function to get user's current selection
+///
+/// This function automatically creates the appropriate selector
+/// for the current platform and retrieves the selection.
+pub fn get_selection() -> Result<Selection, SelectionError> {
+    default_selector()?.get_selection()
+}
+
+/// Get user's current selection in the first available format of a MIME
+/// preference list, e.g. `get_selection_raw(&["text/html", "text/plain"])`
+pub fn get_selection_raw(preferred: &[&str]) -> Result<Selection, SelectionError> {
+    default_selector()?.get_selection_raw(preferred)
+}
+
+/// Get every representation of user's current selection, richest first
+pub fn get_selection_all() -> Result<Vec<Selection>, SelectionError> {
+    default_selector()?.get_selection_all()
+}
+
+/// Pick the representation in the first format of a MIME preference list
+/// that's among `selections`, as returned by `get_selection_all`
+///
+/// An empty list picks the richest one.
+pub fn pick_preferred(
+    mut selections: Vec<Selection>,
+    preferred: &[&str],
+) -> Result<Selection, SelectionError> {
+    if selections.is_empty() {
+        return Err(SelectionError::NoSelectedContent);
+    }
+    if preferred.is_empty() {
+        return Ok(selections.swap_remove(0));
+    }
+
+    let index = preferred.iter().find_map(|mime| {
+        selections
+            .iter()
+            .position(|selection| selection.content_type.matches_mime(mime))
+    });
+    match index {
+        Some(index) => Ok(selections.swap_remove(index)),
+        None => Err(SelectionError::InvalidContentType {
+            expected: preferred.join(", "),
+            received: selections
+                .iter()
+                .map(|selection| selection.content_type.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }),
+    }
+}
+
+/// Order representations of a selection richest first, see
+/// `ContentType::richness`
+#[cfg(any(
+    target_os = "macos",
+    target_os = "windows",
+    all(unix, not(any(target_os = "ios", target_os = "android"))),
+    feature = "fake-platform"
+))]
+pub(crate) fn sort_by_richness(selections: &mut [Selection]) {
+    selections.sort_by_key(|selection| selection.content_type.richness());
+}
+
+/// Get user's current selection with a specific method, without falling back
+/// to the other one
+pub fn get_selection_by_method(method: SelectionMethod) -> Result<Selection, SelectionError> {
+    default_selector()?.get_selection_by_method(method)
+}
+
+/// Get where the user's current selection is displayed on screen
+pub fn get_selection_bounds() -> Result<SelectionBounds, SelectionError> {
+    default_selector()?.get_selection_bounds()
+}
+
+/// Get the position of the text caret in the focused element
+pub fn get_caret_position() -> Result<Rect, SelectionError> {
+    default_selector()?.get_caret_position()
+}
+
+/// Get the user's current selection together with up to `chars` characters
+/// of surrounding text on each side
+pub fn get_selection_context(chars: usize) -> Result<SelectionContext, SelectionError> {
+    default_selector()?.get_selection_context(chars)
+}
+
+/// Replace the user's current selection with `text`
+pub fn set_selected_text(text: &str) -> Result<(), SelectionError> {
+    default_selector()?.set_selected_text(text)
+}
+
 /// Convenience function to get user's current text selection
 ///
 /// Returns the text as a String if successful, or an error
@@ -164,6 +800,136 @@ mod tests {
         assert!(!selection.is_empty());
     }
 
+    #[test]
+    fn test_selection_files() {
+        let paths = vec![
+            PathBuf::from("/tmp/a file.txt"),
+            PathBuf::from("/tmp/line\nbreak.txt"),
+        ];
+        let selection = Selection::new_files(paths.clone());
+
+        assert_eq!(selection.content_type, ContentType::Files);
+        assert_eq!(selection.as_files(), Some(paths));
+        assert_eq!(selection.as_text(), None);
+    }
+
+    #[test]
+    fn test_content_type_matches_mime() {
+        assert!(ContentType::Text.matches_mime("text/plain;charset=utf-8"));
+        assert!(ContentType::Text.matches_mime("TEXT/PLAIN"));
+        assert!(ContentType::Html.matches_mime("text/*"));
+        assert!(ContentType::Files.matches_mime("*/*"));
+        assert!(ContentType::Rtf.matches_mime("application/rtf"));
+        assert!(!ContentType::Html.matches_mime("text/plain"));
+        assert!(!ContentType::Files.matches_mime("image/*"));
+        assert!(ContentType::Image("png".to_string()).matches_mime("image/*"));
+        assert!(!ContentType::Image("png".to_string()).matches_mime("image/tiff"));
+    }
+
+    #[test]
+    fn test_pick_preferred() {
+        let all = || {
+            vec![
+                Selection::new_html("<b>hi</b>".to_string()),
+                Selection::new_text("hi".to_string()),
+            ]
+        };
+
+        let picked = pick_preferred(all(), &["text/plain", "text/html"]).unwrap();
+        assert_eq!(picked.content_type, ContentType::Text);
+        let picked = pick_preferred(all(), &["image/*", "text/*"]).unwrap();
+        assert_eq!(picked.content_type, ContentType::Html);
+        assert_eq!(
+            pick_preferred(all(), &[]).unwrap().content_type,
+            ContentType::Html
+        );
+        assert!(matches!(
+            pick_preferred(all(), &["image/png"]),
+            Err(SelectionError::InvalidContentType { received, .. }) if received == "html, text"
+        ));
+        assert!(matches!(
+            pick_preferred(Vec::new(), &[]),
+            Err(SelectionError::NoSelectedContent)
+        ));
+
+        let mut selections = all();
+        selections.reverse();
+        selections.push(Selection::new_files(vec![PathBuf::from("/a")]));
+        sort_by_richness(&mut selections);
+        let order: Vec<_> = selections
+            .iter()
+            .map(|s| s.content_type.to_string())
+            .collect();
+        assert_eq!(order, ["files", "html", "text"]);
+    }
+
+    /// A selector whose methods return canned results
+    struct FakeSelector {
+        accessibility: fn() -> Result<Selection, SelectionError>,
+        clipboard: fn() -> Result<Selection, SelectionError>,
+        trust_empty: bool,
+    }
+
+    impl Selector for FakeSelector {
+        fn trusts_empty_accessibility(&self) -> bool {
+            self.trust_empty
+        }
+
+        fn get_selection_by_accessibility(&self) -> Result<Selection, SelectionError> {
+            (self.accessibility)()
+        }
+
+        fn get_selection_by_clipboard(&self) -> Result<Selection, SelectionError> {
+            (self.clipboard)()
+        }
+    }
+
+    #[test]
+    fn test_default_get_selection() {
+        let text = || Ok(Selection::new_text("copied".to_string()));
+        let empty = || Err(SelectionError::NoSelectedContent);
+        let unavailable = || Err(SelectionError::MethodUnavailable("no".to_string()));
+        let failed = || Err(SelectionError::ClipboardError("busy".to_string()));
+        let denied = || Err(SelectionError::PermissionDenied("no".to_string()));
+        let selector = |accessibility, clipboard, trust_empty| FakeSelector {
+            accessibility,
+            clipboard,
+            trust_empty,
+        };
+
+        let selection = selector(unavailable, text, true).get_selection().unwrap();
+        assert_eq!(selection.as_text().as_deref(), Some("copied"));
+        // An empty selection isn't copied, unless that's untrusted
+        assert!(matches!(
+            selector(empty, text, true).get_selection(),
+            Err(SelectionError::NoSelectedContent)
+        ));
+        assert!(selector(empty, text, false).get_selection().is_ok());
+
+        assert!(matches!(
+            selector(unavailable, empty, true).get_selection(),
+            Err(SelectionError::NoSelectedContent)
+        ));
+        assert!(matches!(
+            selector(unavailable, unavailable, true).get_selection(),
+            Err(SelectionError::MethodUnavailable(_))
+        ));
+        assert!(matches!(
+            selector(unavailable, failed, true).get_selection(),
+            Err(SelectionError::ClipboardError(_))
+        ));
+        match selector(denied, failed, true).get_selection() {
+            Err(SelectionError::AllMethodsFailed(failures)) => {
+                let methods: Vec<_> = failures.iter().map(|(method, _)| *method).collect();
+                assert_eq!(
+                    methods,
+                    [SelectionMethod::Accessibility, SelectionMethod::Clipboard]
+                );
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
     // Test the get_text convenience function with mocks
     #[test]
     fn test_get_text_function() {
@@ -206,6 +972,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_truncate_text() {
+        let mut selection = Selection::new_text("a€b".to_string());
+        assert!(selection.truncate_text(3));
+        assert_eq!(selection.as_text().as_deref(), Some("a"));
+        assert!(selection.metadata.truncated);
+
+        let mut selection = Selection::new_text("short".to_string());
+        assert!(!selection.truncate_text(5));
+        assert!(!selection.metadata.truncated);
+
+        let mut selection = Selection::new_rtf(b"{\\rtf1 long}".to_vec());
+        assert!(!selection.truncate_text(2));
+    }
+
     // Test handling of non-UTF8 data
     #[test]
     fn test_non_utf8_data() {
@@ -214,9 +995,15 @@ mod tests {
         let selection = Selection {
             content_type: ContentType::Text,
             data: invalid_utf8,
+            metadata: SelectionMetadata::default(),
         };
 
         // Should return None for as_text since data is not valid UTF-8
         assert_eq!(selection.as_text(), None);
+        assert_eq!(
+            selection.as_text_lossy().as_deref(),
+            Some("\u{0}\u{FFFD}\u{FFFD}\u{FFFD}")
+        );
+        assert_eq!(Selection::new_text_bytes(vec![0xe9]).data, [0xe9]);
     }
 }
@@ -1,15 +1,172 @@
-use crate::{Selection, SelectionError, Selector};
-use std::io::Read;
-use std::time::Duration;
-use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType, Seat};
-use wl_clipboard_rs::utils::is_primary_selection_supported;
+use crate::{
+    capture_with_rule, sort_by_richness, AccessibilityRole, AppInfo, AppRule, Capabilities,
+    ContentType, ErrorKind, Rect, Selection, SelectionBounds, SelectionContext, SelectionError,
+    SelectionMethod, SelectionObserver, SelectionOptions, SelectionSource, Selector,
+};
+use log::{debug, info, warn};
+use std::sync::{Arc, Mutex};
+use std::thread;
+#[cfg(feature = "atspi")]
+use std::time::{Duration, Instant};
+use wl_clipboard_rs::paste::ClipboardType;
+use wl_clipboard_rs::utils::{is_primary_selection_supported, PrimarySelectionCheckError};
 use x11_clipboard::Clipboard;
 
-pub struct LinuxSelector;
+#[cfg(feature = "atspi")]
+mod atspi;
+mod bmp;
+mod command;
+mod copy;
+mod data_control;
+#[cfg(feature = "file-manager")]
+mod file_manager;
+#[cfg(feature = "hotkey")]
+mod hotkey;
+mod inject;
+#[cfg(feature = "klipper")]
+mod klipper;
+mod osc52;
+mod sandbox;
+#[cfg_attr(feature = "fake-platform", allow(dead_code))]
+mod watcher;
+mod wayland;
+mod x11;
+
+#[cfg(feature = "hotkey")]
+pub(crate) use hotkey::start_hotkey;
+use sandbox::Sandbox;
+#[cfg_attr(feature = "fake-platform", allow(unused_imports))]
+pub(crate) use watcher::start_watcher;
+
+/// Display server the selection is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisplayServer {
+    Wayland,
+    X11,
+}
+
+/// How often an open input method composition is checked for being
+/// committed
+#[cfg(feature = "atspi")]
+const COMPOSITION_POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Find the display server of the current session
+///
+/// `WAYLAND_DISPLAY` and `DISPLAY` are what clients actually connect
+/// through, so they're trusted over `XDG_SESSION_TYPE`, which is missing
+/// under ssh -X and in some sandboxed or systemd-user environments. The
+/// session type only decides when both displays are available.
+fn detect_display_server() -> Result<DisplayServer, SelectionError> {
+    display_server_from(
+        display_variable(DisplayServer::Wayland).as_deref(),
+        display_variable(DisplayServer::X11).as_deref(),
+        std::env::var("XDG_SESSION_TYPE").ok().as_deref(),
+    )
+    .ok_or_else(no_display)
+}
+
+/// `NoDisplay`, or why the sandbox hides the display server
+fn no_display() -> SelectionError {
+    Sandbox::detect().map_or(SelectionError::NoDisplay, Sandbox::blocked_error)
+}
+
+/// Map a failure to connect to the display server named in the
+/// environment, which is as good as having none
+pub(crate) fn display_unreachable(err: impl std::fmt::Display) -> SelectionError {
+    debug!("Can't connect to the display server: {}", err);
+    no_display()
+}
+
+/// The other display server, if it can be reached from this session too
+fn other_display_server(server: DisplayServer) -> Option<DisplayServer> {
+    let other = match server {
+        DisplayServer::Wayland => DisplayServer::X11,
+        DisplayServer::X11 => DisplayServer::Wayland,
+    };
+    display_variable(other)
+        .is_some_and(|value| !value.is_empty())
+        .then_some(other)
+}
+
+/// The variable clients connect to a display server through
+///
+/// Inside a Flatpak or snap it's only trusted if its socket exists.
+fn display_variable(server: DisplayServer) -> Option<String> {
+    let (variable, socket_exists): (_, fn(&str) -> bool) = match server {
+        DisplayServer::Wayland => ("WAYLAND_DISPLAY", sandbox::wayland_socket_exists),
+        DisplayServer::X11 => ("DISPLAY", sandbox::x11_socket_exists),
+    };
+    std::env::var(variable)
+        .ok()
+        .filter(|value| Sandbox::detect().is_none() || socket_exists(value))
+}
+
+fn display_server_from(
+    wayland_display: Option<&str>,
+    display: Option<&str>,
+    session_type: Option<&str>,
+) -> Option<DisplayServer> {
+    let wayland = wayland_display.is_some_and(|value| !value.is_empty());
+    let x11 = display.is_some_and(|value| !value.is_empty());
+
+    match (wayland, x11) {
+        // XWayland also sets DISPLAY in Wayland sessions
+        (true, true) if session_type == Some("x11") => Some(DisplayServer::X11),
+        (true, _) => Some(DisplayServer::Wayland),
+        (false, true) => Some(DisplayServer::X11),
+        (false, false) => None,
+    }
+}
+
+/// Linux implementation of the Selector trait, for X11 and Wayland
+///
+/// The BSDs run the same display servers, so they use it too.
+///
+/// The selector is `Send` and `Sync`, so one can be shared between threads
+/// in an `Arc`. Concurrent reads take turns on its X server connection
+/// rather than interleaving their requests on it.
+pub struct LinuxSelector {
+    options: SelectionOptions,
+    /// Connection to the X server, made on first use and remade after it
+    /// breaks
+    x11: Mutex<Option<Clipboard>>,
+}
 
 impl LinuxSelector {
     pub fn new() -> Self {
-        LinuxSelector
+        Self::with_options(SelectionOptions::default())
+    }
+
+    pub fn with_options(options: SelectionOptions) -> Self {
+        LinuxSelector {
+            options,
+            x11: Mutex::new(None),
+        }
+    }
+}
+
+impl LinuxSelector {
+    /// Read the primary selection, or the clipboard if nothing is selected,
+    /// telling which of the two the text came from
+    ///
+    /// Both are read over one X server connection, or on Wayland in one
+    /// round where the data control protocol is spoken directly; each read
+    /// gets `SelectionOptions::selection_timeout` of its own. Neither the
+    /// accessibility API nor the copy fallback is used.
+    pub fn get_text_or_clipboard(&self) -> Result<(Selection, SelectionSource), SelectionError> {
+        let focused = self.focused_app();
+        self.rule_for(focused.as_ref())?;
+
+        let (mut selection, source) = match detect_display_server()? {
+            DisplayServer::X11 => self.get_text_or_clipboard_on_x11()?,
+            DisplayServer::Wayland => self.get_text_or_clipboard_on_wayland()?,
+        };
+
+        if selection.metadata.source_app.is_none() {
+            selection.metadata.source_app = focused;
+        }
+        let selection = require_content(selection.with_method(SelectionMethod::Clipboard))?;
+        Ok((self.options.finish(selection)?, source))
     }
 }
 
@@ -20,64 +177,1135 @@ impl Default for LinuxSelector {
 }
 
 impl Selector for LinuxSelector {
+    /// The focused application's rule is honoured where it can be found
+    /// (X11); the selection's owner is reported as its source when known,
+    /// and the focused application otherwise.
+    ///
+    /// The selection buffer is read as the clipboard method, before the
+    /// copy fallback.
     fn get_selection(&self) -> Result<Selection, SelectionError> {
-        match std::env::var("XDG_SESSION_TYPE") {
-            Ok(session_type) => match session_type.as_str() {
-                "x11" => self.get_selection_on_x11(),
-                "wayland" => self.get_selection_on_wayland(),
-                _ => Err(SelectionError::UnsupportedPlatform),
-            },
-            Err(_) => Err(SelectionError::UnsupportedPlatform),
+        self.capture_text().map(|(selection, _)| selection)
+    }
+
+    fn capture_rule(&self) -> Result<AppRule, SelectionError> {
+        self.rule_for(self.focused_app().as_ref())
+    }
+
+    fn observer(&self) -> Option<Arc<dyn SelectionObserver>> {
+        self.options.active_observer()
+    }
+
+    /// Images and files are read from the regular clipboard, since the
+    /// primary selection only ever holds text; text preferences read the
+    /// selection as `get_selection` does, and HTML from the same selection
+    ///
+    /// On Wayland images in formats other than PNG are returned as they are
+    /// offered, so check the format of `ContentType::Image`.
+    fn get_selection_raw(&self, preferred: &[&str]) -> Result<Selection, SelectionError> {
+        if preferred.is_empty() {
+            return self.get_selection();
+        }
+
+        let image = ContentType::Image("png".to_string());
+        let mut nothing_selected = false;
+        for mime in preferred {
+            let selection = if image.matches_mime(mime) {
+                self.read_content(x11::read_image, wayland::read_image)?
+            } else if ContentType::Files.matches_mime(mime) {
+                self.get_files()?
+            } else if ContentType::Text.matches_mime(mime) {
+                match self.get_selection() {
+                    Ok(selection) => Some(selection),
+                    Err(SelectionError::NoSelectedContent) => {
+                        nothing_selected = true;
+                        None
+                    }
+                    Err(err) => return Err(err),
+                }
+            } else if ContentType::Html.matches_mime(mime) {
+                self.get_html()?
+            } else {
+                None
+            };
+
+            if let Some(selection) = selection.filter(|selection| !selection.is_empty()) {
+                return self.options.finish(selection);
+            }
+        }
+
+        if nothing_selected {
+            return Err(SelectionError::NoSelectedContent);
+        }
+        Err(SelectionError::InvalidContentType {
+            expected: preferred.join(", "),
+            received: "no matching selection target".to_string(),
+        })
+    }
+
+    /// The other representations are read from the selection buffer the
+    /// text came from, e.g. the markup of text selected in a browser. Text
+    /// read with `SelectionOptions::copy_fallback` comes alone, as the
+    /// clipboard is restored right after the copy.
+    fn get_selection_all(&self) -> Result<Vec<Selection>, SelectionError> {
+        let (text, source) = match self.capture_text() {
+            Ok((text, source)) => (Some(text), source),
+            Err(SelectionError::NoSelectedContent) => (None, Some(self.options.selection_source)),
+            Err(err) => return Err(err),
+        };
+
+        let mut selections = Vec::new();
+        if let Some(source) = source {
+            let readers: [(x11::ContentReader, wayland::ContentReader); 3] = [
+                (x11::read_files, wayland::read_files),
+                (x11::read_html, wayland::read_html),
+                (x11::read_image, wayland::read_image),
+            ];
+            for (x11_read, wayland_read) in readers {
+                if let Some(selection) = self
+                    .read_from(source, x11_read, wayland_read)?
+                    .filter(|selection| !selection.is_empty())
+                {
+                    selections.push(self.options.finish(selection)?);
+                }
+            }
+        }
+        selections.extend(text);
+
+        if selections.is_empty() {
+            return Err(SelectionError::NoSelectedContent);
+        }
+        sort_by_richness(&mut selections);
+        Ok(selections)
+    }
+
+    /// Read the selected text of the focused widget over AT-SPI
+    ///
+    /// Needs the `atspi` feature.
+    fn get_selection_by_accessibility(&self) -> Result<Selection, SelectionError> {
+        #[cfg(feature = "atspi")]
+        {
+            let connection = atspi::connect()?;
+            let focused = match atspi::focused_object(&connection) {
+                Err(SelectionError::NoFocusedElement) => {
+                    return Err(self
+                        .unpublished_qt_app()
+                        .unwrap_or(SelectionError::NoFocusedElement))
+                }
+                result => result?,
+            };
+            let mut selection = match atspi::selected_text(&connection, &focused)? {
+                text if text.is_empty() => return Err(SelectionError::NoSelectedContent),
+                text => Selection::new_text(text).with_method(SelectionMethod::Accessibility),
+            };
+            if let Some(max_bytes) = self.options.max_bytes {
+                selection.truncate_text(max_bytes);
+            }
+            self.options.finish(selection)
+        }
+
+        #[cfg(not(feature = "atspi"))]
+        {
+            Err(SelectionError::MethodUnavailable(
+                "AT-SPI support needs the atspi feature".to_string(),
+            ))
+        }
+    }
+
+    /// Read the regular clipboard, which holds what was last copied
+    ///
+    /// With `SelectionOptions::copy_fallback`, Ctrl+C is sent first so the
+    /// clipboard holds the selection, as on the other platforms.
+    fn get_selection_by_clipboard(&self) -> Result<Selection, SelectionError> {
+        let selection = if self.options.copy_fallback {
+            self.copy_selection(&self.options)?
+        } else {
+            require_content(self.read_selection(SelectionSource::Clipboard)?)?
+        };
+        self.options.finish(selection)
+    }
+
+    /// Reads the focused widget's AT-SPI Text interface with the `atspi`
+    /// feature
+    ///
+    /// Where AT-SPI can't provide it, the selection is returned with empty
+    /// context rather than an error.
+    fn get_selection_context(&self, chars: usize) -> Result<SelectionContext, SelectionError> {
+        #[cfg(feature = "atspi")]
+        match atspi::connect().and_then(|connection| {
+            let focused = atspi::focused_object(&connection)?;
+            atspi::selection_context(&connection, &focused, chars)
+        }) {
+            Ok(context) => return Ok(context),
+            Err(err) => debug!("AT-SPI context unavailable, reading the selection: {}", err),
+        }
+        #[cfg(not(feature = "atspi"))]
+        let _ = chars;
+
+        let selection = self.get_selection()?;
+        Ok(SelectionContext {
+            selected: selection.as_text_lossy().unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    /// Writes through the focused widget's AT-SPI EditableText interface
+    /// with the `atspi` feature, replacing exactly the selected range
+    ///
+    /// Widgets without an editable accessible get the text pasted through
+    /// the clipboard with a simulated Ctrl+V instead, and read-only ones
+    /// return `ReadOnly`. A failed write to an editable widget is reported
+    /// rather than pasted, since pasting has different side effects.
+    fn set_selected_text(&self, text: &str) -> Result<(), SelectionError> {
+        #[cfg(feature = "atspi")]
+        match atspi::connect().and_then(|connection| {
+            let focused = atspi::focused_object(&connection)?;
+            atspi::replace_selected_text(&connection, &focused, text)
+        }) {
+            Err(SelectionError::MethodUnavailable(reason)) => {
+                info!("Not writing through AT-SPI ({}), pasting instead", reason)
+            }
+            Err(SelectionError::NoFocusedElement) => {
+                info!("No focused accessible, pasting instead")
+            }
+            result => return result,
+        }
+
+        match detect_display_server()? {
+            DisplayServer::X11 => copy::paste_on_x11(&self.options, text),
+            DisplayServer::Wayland => copy::paste_on_wayland(&self.options, text),
+        }
+    }
+
+    /// Read from the focused widget's AT-SPI Text interface, which needs
+    /// the `atspi` feature
+    ///
+    /// Widgets without the Text interface, as in many terminal emulators,
+    /// return `MethodUnavailable`. On Wayland toolkits may report the rects
+    /// relative to their window, as they can't know where it is.
+    fn get_selection_bounds(&self) -> Result<SelectionBounds, SelectionError> {
+        #[cfg(feature = "atspi")]
+        {
+            let connection = atspi::connect()?;
+            let focused = atspi::focused_object(&connection)?;
+            SelectionBounds::from_lines(atspi::selection_bounds(&connection, &focused)?)
+                .ok_or(SelectionError::NoSelectedContent)
+        }
+
+        #[cfg(not(feature = "atspi"))]
+        {
+            Err(SelectionError::MethodUnavailable(
+                "AT-SPI support needs the atspi feature".to_string(),
+            ))
+        }
+    }
+
+    /// Read from the focused widget's AT-SPI Text interface, as
+    /// `get_selection_bounds` does
+    fn get_caret_position(&self) -> Result<Rect, SelectionError> {
+        #[cfg(feature = "atspi")]
+        {
+            let connection = atspi::connect()?;
+            let focused = atspi::focused_object(&connection)?;
+            atspi::caret_rect(&connection, &focused)?.ok_or_else(|| {
+                SelectionError::MethodUnavailable(
+                    "focused object does not report the caret's position".to_string(),
+                )
+            })
+        }
+
+        #[cfg(not(feature = "atspi"))]
+        {
+            Err(SelectionError::MethodUnavailable(
+                "AT-SPI support needs the atspi feature".to_string(),
+            ))
         }
     }
 }
 
 impl LinuxSelector {
-    fn get_selection_on_x11(&self) -> Result<Selection, SelectionError> {
-        let clipboard = Clipboard::new().map_err(|_| {
-            SelectionError::ClipboardError("Failed to create clipboard".to_string())
+    /// Read the selected text as `get_selection` does, with the selection
+    /// buffer it was read from, `None` if it was copied to the clipboard
+    /// for the copy fallback
+    fn capture_text(&self) -> Result<(Selection, Option<SelectionSource>), SelectionError> {
+        let focused = self.focused_app();
+        let rule = self.rule_for(focused.as_ref())?;
+        self.capture_text_following(rule, focused)
+    }
+
+    /// `capture_text` trying the methods of `rule` in its order
+    fn capture_text_following(
+        &self,
+        rule: AppRule,
+        focused: Option<AppInfo>,
+    ) -> Result<(Selection, Option<SelectionSource>), SelectionError> {
+        let source = self.options.selection_source;
+        let mut options = self.options.clone();
+        if let Some(timeout) = rule.copy_timeout {
+            options.copy_timeout = timeout;
+        }
+
+        // Without the feature AT-SPI isn't tried, rather than reported as
+        // failing on every capture
+        let accessibility = if cfg!(feature = "atspi") {
+            AccessibilityRole::Answer
+        } else {
+            AccessibilityRole::Skip
+        };
+        let mut copied = false;
+        let selection = capture_with_rule(self, rule, accessibility, |method| match method {
+            SelectionMethod::Accessibility => Ok(self
+                .get_selection_by_accessibility()?
+                .with_source_app(focused.clone())),
+            // Reading the selection buffer counts as the clipboard method,
+            // as in the metadata
+            SelectionMethod::Clipboard => {
+                #[cfg(feature = "atspi")]
+                let composing = self.wait_for_composition();
+                #[cfg(not(feature = "atspi"))]
+                let composing = false;
+
+                let mut selection = self.read_selection(source)?;
+                selection.metadata.possibly_composing = composing;
+                if selection.is_empty() && self.options.copy_fallback {
+                    info!("Selection is empty, copying the selection to the clipboard");
+                    copied = true;
+                    let selection = self
+                        .copy_selection(&options)?
+                        .with_source_app(focused.clone());
+                    return self.options.finish(selection);
+                }
+
+                if selection.metadata.source_app.is_none() {
+                    selection.metadata.source_app = focused.clone();
+                }
+                self.options.finish(require_content(selection)?)
+            }
         })?;
-        let primary = clipboard
-            .load(
-                clipboard.getter.atoms.primary,
-                clipboard.getter.atoms.utf8_string,
-                clipboard.getter.atoms.property,
-                Duration::from_millis(100),
-            )
-            .map_err(|_| {
-                SelectionError::ClipboardError("Failed to load clipboard data".to_string())
+        Ok((selection, (!copied).then_some(source)))
+    }
+
+    /// Read the text of a selection buffer on the session's display server
+    ///
+    /// With the `klipper` feature, Klipper is asked for the clipboard when
+    /// that fails; what it returns is flagged as possibly stale.
+    fn read_selection(&self, source: SelectionSource) -> Result<Selection, SelectionError> {
+        let result = self.read_selection_directly(source);
+
+        #[cfg(feature = "klipper")]
+        if let Err(err) = &result {
+            match klipper::clipboard_contents() {
+                Ok(Some(text)) => {
+                    info!(
+                        "Reading the selection failed ({}), using Klipper's clipboard",
+                        err
+                    );
+                    let mut selection =
+                        Selection::new_text(text).with_method(SelectionMethod::Clipboard);
+                    if let Some(max_bytes) = self.options.max_bytes {
+                        selection.truncate_text(max_bytes);
+                    }
+                    selection.metadata.possibly_stale = source == SelectionSource::Primary;
+                    return Ok(selection);
+                }
+                Ok(None) => debug!("Klipper is not running"),
+                Err(klipper_err) => debug!("Klipper fallback failed: {}", klipper_err),
+            }
+        }
+
+        result
+    }
+
+    fn read_selection_directly(
+        &self,
+        source: SelectionSource,
+    ) -> Result<Selection, SelectionError> {
+        let server = match detect_display_server() {
+            Err(SelectionError::NoDisplay) if self.options.osc52 && osc52::is_terminal() => {
+                debug!("No display server, asking the terminal through OSC 52");
+                let mut selection = osc52::read_selection(source, &self.options)?;
+                trim_text(&mut selection, self.options.trim_whitespace);
+                return Ok(selection.with_method(SelectionMethod::Clipboard));
+            }
+            result => result?,
+        };
+        let mut selection = match self.get_selection_on(server, source) {
+            Err(err) if server == DisplayServer::X11 && self.options.x11_command_fallback => {
+                match err {
+                    SelectionError::NoSelectedContent => return Err(err),
+                    err => self.get_selection_by_command(source, err)?,
+                }
+            }
+            result => result?,
+        };
+
+        // XWayland apps only publish their selection on X11, and an X11
+        // session nested in a Wayland compositor may own it on Wayland
+        if selection.is_empty() {
+            if let Some(other) = other_display_server(server) {
+                debug!("Selection on {:?} is empty, trying {:?}", server, other);
+                selection = self.get_selection_on(other, source)?;
+            }
+        }
+
+        Ok(selection.with_method(SelectionMethod::Clipboard))
+    }
+
+    /// Read the selection with `xclip` or `xsel` after the native read
+    /// failed with `err`, which is returned if they fail too
+    fn get_selection_by_command(
+        &self,
+        source: SelectionSource,
+        err: SelectionError,
+    ) -> Result<Selection, SelectionError> {
+        warn!(
+            "Reading the X11 selection failed ({}), trying xclip or xsel",
+            err
+        );
+        match command::read_selection(source, &self.options) {
+            Ok(mut selection) => {
+                trim_text(&mut selection, self.options.trim_whitespace);
+                Ok(selection)
+            }
+            Err(SelectionError::NoSelectedContent) => Ok(Selection::new_text(String::new())),
+            Err(command_err) => {
+                debug!("Command fallback failed: {}", command_err);
+                Err(err)
+            }
+        }
+    }
+
+    /// The rule for the focused application, `PermissionDenied` if it
+    /// disallows capturing
+    fn rule_for(&self, focused: Option<&AppInfo>) -> Result<AppRule, SelectionError> {
+        let rule = self
+            .options
+            .rule_for_app(focused.and_then(|app| app.identifier.as_deref()));
+        if !rule.allow_capture {
+            info!("Capture is disabled for the focused application");
+            return Err(SelectionError::PermissionDenied(
+                "Capture is disabled for the focused application".to_string(),
+            ));
+        }
+        Ok(rule)
+    }
+
+    fn get_text_or_clipboard_on_x11(&self) -> Result<(Selection, SelectionSource), SelectionError> {
+        self.retrying(|| {
+            self.with_x11(|clipboard| {
+                let atoms = &clipboard.getter.atoms;
+                let sources = [
+                    (SelectionSource::Primary, atoms.primary, "PRIMARY"),
+                    (SelectionSource::Clipboard, atoms.clipboard, "CLIPBOARD"),
+                ];
+                let mut text = Selection::new_text(String::new());
+                for (source, selection, name) in sources {
+                    text = x11::read_text(clipboard, selection, name, &self.options)?;
+                    trim_text(&mut text, self.options.trim_whitespace);
+                    if !text.is_empty() {
+                        let owner = x11::selection_owner(&clipboard.getter, selection);
+                        return Ok((text.with_source_app(owner), source));
+                    }
+                }
+                Ok((text, SelectionSource::Clipboard))
+            })
+        })
+    }
+
+    /// Where the compositor's primary selection can't be read, XWayland
+    /// windows' PRIMARY on X11 stands in for it, as in `get_selection`
+    fn get_text_or_clipboard_on_wayland(
+        &self,
+    ) -> Result<(Selection, SelectionSource), SelectionError> {
+        if is_primary_selection_supported().unwrap_or(false) {
+            let (selection, clipboard) = self.retrying(|| {
+                let clipboards = [ClipboardType::Primary, ClipboardType::Regular];
+                wayland::read_first_text(&clipboards, &self.options)
             })?;
+            let source = match clipboard {
+                ClipboardType::Primary => SelectionSource::Primary,
+                ClipboardType::Regular => SelectionSource::Clipboard,
+            };
+            return Ok((selection, source));
+        }
 
-        let result = String::from_utf8_lossy(&primary)
-            .trim_matches('\u{0}')
-            .trim()
-            .to_string();
+        if other_display_server(DisplayServer::Wayland).is_some() {
+            let selection = self.get_selection_on(DisplayServer::X11, SelectionSource::Primary)?;
+            if !selection.is_empty() {
+                return Ok((selection, SelectionSource::Primary));
+            }
+        }
+        let selection =
+            self.get_selection_on(DisplayServer::Wayland, SelectionSource::Clipboard)?;
+        Ok((selection, SelectionSource::Clipboard))
+    }
 
-        Ok(Selection::new_text(result))
+    /// `QtAccessibilityUnavailable` if the focused window, which has no
+    /// focused object on the accessibility bus, belongs to a Qt process
+    ///
+    /// Qt only publishes its widgets while the bus is enabled or with
+    /// `QT_LINUX_ACCESSIBILITY_ALWAYS_ON` set. Windows are only known on X11.
+    #[cfg(feature = "atspi")]
+    fn unpublished_qt_app(&self) -> Option<SelectionError> {
+        let app = self.focused_app()?;
+        if !atspi::is_qt_process(app.pid?) {
+            return None;
+        }
+        let name = app
+            .name
+            .unwrap_or_else(|| "The focused application".to_string());
+        Some(SelectionError::QtAccessibilityUnavailable(name))
     }
 
-    fn get_selection_on_wayland(&self) -> Result<Selection, SelectionError> {
-        if let Ok(support) = is_primary_selection_supported() {
-            if !support {
-                return self.get_selection_on_x11();
+    /// Wait up to `SelectionOptions::composition_wait` for an input method
+    /// composition in the focused widget to be committed, returning whether
+    /// one is still open
+    #[cfg(feature = "atspi")]
+    fn wait_for_composition(&self) -> bool {
+        let Ok(connection) = atspi::connect() else {
+            return false;
+        };
+        let deadline = Instant::now() + self.options.composition_wait;
+        loop {
+            let composing = atspi::focused_object(&connection)
+                .and_then(|focused| atspi::is_composing(&connection, &focused))
+                .unwrap_or(false);
+            if !composing {
+                return false;
             }
+            if Instant::now() >= deadline {
+                debug!("Input method composition still open, reading the selection anyway");
+                return true;
+            }
+            thread::sleep(COMPOSITION_POLL_INTERVAL);
+        }
+    }
+
+    /// Simulate Ctrl+C and read what was copied
+    fn copy_selection(&self, options: &SelectionOptions) -> Result<Selection, SelectionError> {
+        match detect_display_server()? {
+            DisplayServer::X11 => copy::copy_on_x11(options),
+            DisplayServer::Wayland => copy::copy_on_wayland(options),
+        }
+    }
+
+    /// Describe the focused application from the window manager's active
+    /// window
+    ///
+    /// Wayland doesn't tell other clients which window is focused, so this
+    /// is always `None` there.
+    fn focused_app(&self) -> Option<AppInfo> {
+        match detect_display_server() {
+            Ok(DisplayServer::X11) => self
+                .with_x11(|clipboard| Ok(x11::active_window(&clipboard.getter)))
+                .ok()
+                .flatten()
+                .filter(|window| !window.is_empty())
+                .map(x11::WindowInfo::into_app_info),
+            _ => None,
+        }
+    }
+
+    fn get_selection_on(
+        &self,
+        server: DisplayServer,
+        source: SelectionSource,
+    ) -> Result<Selection, SelectionError> {
+        self.retrying(|| match server {
+            DisplayServer::X11 => self.get_selection_on_x11(source),
+            DisplayServer::Wayland => self.get_selection_on_wayland(source),
+        })
+    }
+
+    /// Run `read` again while the selection owner times out, up to
+    /// `SelectionOptions::selection_retries` times, counting the retries in
+    /// the metadata
+    ///
+    /// Only timeouts are retried: a selection without an owner reads as
+    /// empty right away.
+    fn retrying<S: RetriedSelection>(
+        &self,
+        mut read: impl FnMut() -> Result<S, SelectionError>,
+    ) -> Result<S, SelectionError> {
+        let mut backoff = self.options.selection_retry_backoff;
+        let mut retries = 0;
+        loop {
+            match read() {
+                Err(SelectionError::Timeout { phase, .. })
+                    if retries < self.options.selection_retries =>
+                {
+                    debug!("Timed out {}, asking again in {:?}", phase, backoff);
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    retries += 1;
+                }
+                result => {
+                    return result.map(|mut selection| {
+                        selection.set_retries(retries);
+                        selection
+                    })
+                }
+            }
+        }
+    }
+
+    /// Run `read` with the cached X server connection, connecting first if
+    /// there is none
+    ///
+    /// See `with_connection` for how broken connections are replaced.
+    fn with_x11<T>(
+        &self,
+        read: impl Fn(&Clipboard) -> Result<T, SelectionError>,
+    ) -> Result<T, SelectionError> {
+        let mut cached = self.x11.lock().unwrap_or_else(|poisoned| {
+            // A panic mid-read leaves the connection in an unknown state
+            let mut cached = poisoned.into_inner();
+            *cached = None;
+            cached
+        });
+
+        with_connection(
+            &mut cached,
+            || Clipboard::new().map_err(x11::connect_error),
+            x11::is_alive,
+            read,
+        )
+    }
+
+    /// Read the text of a selection on X11, with the application owning it
+    fn get_selection_on_x11(&self, source: SelectionSource) -> Result<Selection, SelectionError> {
+        let (mut text, owner) = self.with_x11(|clipboard| {
+            let (selection, name) = match source {
+                SelectionSource::Primary => (clipboard.getter.atoms.primary, "PRIMARY"),
+                SelectionSource::Clipboard => (clipboard.getter.atoms.clipboard, "CLIPBOARD"),
+            };
+            let text = x11::read_text(clipboard, selection, name, &self.options)?;
+            Ok((text, x11::selection_owner(&clipboard.getter, selection)))
+        })?;
+        trim_text(&mut text, self.options.trim_whitespace);
+
+        Ok(text.with_source_app(owner))
+    }
+
+    /// Read the HTML of the selection `get_selection` reads text from
+    ///
+    /// Browsers offer `text/html` for the primary selection as well as for
+    /// the clipboard.
+    fn get_html(&self) -> Result<Option<Selection>, SelectionError> {
+        self.read_from(
+            self.options.selection_source,
+            x11::read_html,
+            wayland::read_html,
+        )
+    }
+
+    /// Read one kind of content from a selection buffer with the reader for
+    /// the session's display server
+    ///
+    /// Compositors without a primary selection have nothing to read from it.
+    fn read_from(
+        &self,
+        source: SelectionSource,
+        x11_read: x11::ContentReader,
+        wayland_read: wayland::ContentReader,
+    ) -> Result<Option<Selection>, SelectionError> {
+        let server = detect_display_server()?;
+        let selection = self.retrying(|| match server {
+            DisplayServer::X11 => self.with_x11(|clipboard| {
+                let (selection, name) = match source {
+                    SelectionSource::Primary => (clipboard.getter.atoms.primary, "PRIMARY"),
+                    SelectionSource::Clipboard => (clipboard.getter.atoms.clipboard, "CLIPBOARD"),
+                };
+                let content = x11_read(clipboard, selection, name, &self.options)?;
+                Ok(content.map(|content| {
+                    content.with_source_app(x11::selection_owner(&clipboard.getter, selection))
+                }))
+            }),
+            DisplayServer::Wayland => match source {
+                SelectionSource::Primary if is_primary_selection_supported().unwrap_or(false) => {
+                    wayland_read(ClipboardType::Primary, &self.options)
+                }
+                SelectionSource::Primary => Ok(None),
+                SelectionSource::Clipboard => wayland_read(ClipboardType::Regular, &self.options),
+            },
+        })?;
+
+        Ok(selection.map(|selection| selection.with_method(SelectionMethod::Clipboard)))
+    }
+
+    /// Read the selected files
+    ///
+    /// With the `file-manager` feature a focused Dolphin window is told to
+    /// copy its selection over D-Bus. Otherwise the file list on the
+    /// clipboard is read, after simulating Ctrl+C on X11 with
+    /// `SelectionOptions::copy_fallback`.
+    fn get_files(&self) -> Result<Option<Selection>, SelectionError> {
+        #[cfg(feature = "file-manager")]
+        if let Some(result) = self.get_files_from_file_manager() {
+            return result.map(Some);
+        }
+
+        if self.options.copy_fallback
+            && self.options.synthetic_input
+            && detect_display_server()? == DisplayServer::X11
+        {
+            return match copy::copy_on_x11_with(&self.options, inject::post_copy, x11::read_files) {
+                Ok(selection) => Ok(Some(selection)),
+                Err(SelectionError::NoSelectedContent) => Ok(None),
+                Err(err) => Err(err),
+            };
+        }
+
+        self.read_content(x11::read_files, wayland::read_files)
+    }
+
+    /// Ask the file manager owning the active window for its selection,
+    /// `None` if it isn't one that can be asked
+    #[cfg(feature = "file-manager")]
+    fn get_files_from_file_manager(&self) -> Option<Result<Selection, SelectionError>> {
+        if detect_display_server().ok()? != DisplayServer::X11 {
+            return None;
+        }
+        let window = self
+            .with_x11(|clipboard| Ok(x11::active_window(&clipboard.getter)))
+            .ok()??;
+        let manager = file_manager::FileManager::from_class(window.class.as_deref()?)?;
+        let pid = window.pid?;
+
+        match copy::copy_on_x11_with(&self.options, || manager.copy(pid), x11::read_files) {
+            Ok(selection) => Some(Ok(selection)),
+            Err(SelectionError::NoSelectedContent) => Some(Err(SelectionError::NoSelectedContent)),
+            Err(err) => {
+                warn!("Failed to ask {:?} for its selection: {}", manager, err);
+                None
+            }
+        }
+    }
+
+    /// Read the regular clipboard with the reader for the session's display
+    /// server
+    ///
+    /// On Wayland the primary selection is tried too, where the compositor
+    /// supports it.
+    fn read_content(
+        &self,
+        x11_read: x11::ContentReader,
+        wayland_read: wayland::ContentReader,
+    ) -> Result<Option<Selection>, SelectionError> {
+        let server = detect_display_server()?;
+        let selection = self.retrying(|| match server {
+            DisplayServer::X11 => self.with_x11(|clipboard| {
+                let selection = clipboard.getter.atoms.clipboard;
+                let content = x11_read(clipboard, selection, "CLIPBOARD", &self.options)?;
+                Ok(content.map(|content| {
+                    content.with_source_app(x11::selection_owner(&clipboard.getter, selection))
+                }))
+            }),
+            DisplayServer::Wayland => match wayland_read(ClipboardType::Regular, &self.options)? {
+                Some(selection) => Ok(Some(selection)),
+                None if is_primary_selection_supported().unwrap_or(false) => {
+                    wayland_read(ClipboardType::Primary, &self.options)
+                }
+                None => Ok(None),
+            },
+        })?;
+
+        Ok(selection.map(|selection| selection.with_method(SelectionMethod::Clipboard)))
+    }
+
+    fn get_selection_on_wayland(
+        &self,
+        source: SelectionSource,
+    ) -> Result<Selection, SelectionError> {
+        let clipboard = match source {
+            SelectionSource::Primary => match is_primary_selection_supported() {
+                Ok(true) => ClipboardType::Primary,
+                support => return self.get_selection_without_wayland_primary(support),
+            },
+            SelectionSource::Clipboard => ClipboardType::Regular,
+        };
+
+        let mut selection = wayland::read_text(clipboard, &self.options)?;
+        trim_text(&mut selection, self.options.trim_whitespace);
+
+        Ok(selection)
+    }
+
+    /// Read the selection on a compositor whose primary selection can't be
+    /// read, given what the support check returned
+    ///
+    /// XWayland windows still publish their selection on X11 PRIMARY. Failing
+    /// that, compositors with the data control protocol but no primary
+    /// selection offer the regular clipboard, flagged as possibly stale.
+    /// GNOME has neither protocol, so native Wayland applications can't be
+    /// read there at all.
+    fn get_selection_without_wayland_primary(
+        &self,
+        support: Result<bool, PrimarySelectionCheckError>,
+    ) -> Result<Selection, SelectionError> {
+        if other_display_server(DisplayServer::Wayland).is_some() {
+            let selection = self.get_selection_on_x11(SelectionSource::Primary)?;
+            if !selection.is_empty() {
+                return Ok(selection);
+            }
+        }
+
+        match support {
+            Ok(_) | Err(PrimarySelectionCheckError::NoSeats) => {
+                debug!("Wayland primary selection unsupported, reading the clipboard");
+                let mut selection = self.get_selection_on_wayland(SelectionSource::Clipboard)?;
+                selection.metadata.possibly_stale = true;
+                Ok(selection)
+            }
+            Err(PrimarySelectionCheckError::MissingProtocol) => {
+                Err(SelectionError::MethodUnavailable(
+                    "this compositor (e.g. GNOME) lets no other application read the \
+                     selection of native Wayland windows; enable the atspi feature"
+                        .to_string(),
+                ))
+            }
+            Err(
+                err @ (PrimarySelectionCheckError::SocketOpenError(_)
+                | PrimarySelectionCheckError::WaylandConnection(_)),
+            ) => Err(display_unreachable(err)),
+            Err(err) => Err(SelectionError::platform(
+                ErrorKind::Clipboard,
+                "Failed to check the Wayland primary selection",
+                err,
+            )),
+        }
+    }
+}
+
+/// Run `read` with the connection in `cached`, connecting first if there
+/// is none
+///
+/// A connection whose owner timed out is dropped, as the answer could
+/// still arrive later and be taken for the answer to the next request. One
+/// that fails a round trip after an error is dead, e.g. because the X
+/// server restarted or the forwarded display went away: a cached one is
+/// replaced and the read run again right away, and if that reconnection
+/// fails too it's reported as `DisplayConnectionLost`.
+fn with_connection<C, T>(
+    cached: &mut Option<C>,
+    connect: impl Fn() -> Result<C, SelectionError>,
+    is_alive: impl Fn(&C) -> bool,
+    read: impl Fn(&C) -> Result<T, SelectionError>,
+) -> Result<T, SelectionError> {
+    let mut reconnecting = false;
+    loop {
+        let (connection, fresh) = match cached.take() {
+            Some(connection) => (connection, false),
+            None if reconnecting => (
+                connect().map_err(|err| SelectionError::DisplayConnectionLost(err.to_string()))?,
+                true,
+            ),
+            None => (connect()?, true),
+        };
+
+        let result = read(&connection);
+        match &result {
+            Err(SelectionError::Timeout { .. }) => {
+                debug!("Dropping the display connection after a timeout");
+            }
+            Err(err) if !is_alive(&connection) => {
+                if fresh {
+                    debug!("Display connection broke, reconnecting on the next read");
+                } else {
+                    warn!("Display connection was lost ({}), reconnecting", err);
+                    reconnecting = true;
+                    continue;
+                }
+            }
+            _ => *cached = Some(connection),
+        }
+        return result;
+    }
+}
+
+/// A read result whose retries can be recorded
+trait RetriedSelection {
+    fn set_retries(&mut self, retries: u32);
+}
+
+impl RetriedSelection for Selection {
+    fn set_retries(&mut self, retries: u32) {
+        self.metadata.retries = retries;
+    }
+}
+
+impl<T> RetriedSelection for (Selection, T) {
+    fn set_retries(&mut self, retries: u32) {
+        self.0.set_retries(retries);
+    }
+}
+
+impl RetriedSelection for Option<Selection> {
+    fn set_retries(&mut self, retries: u32) {
+        if let Some(selection) = self {
+            selection.set_retries(retries);
+        }
+    }
+}
+
+/// Turn a selection left empty by trimming into `NoSelectedContent`, as
+/// the other platforms report it
+fn require_content(selection: Selection) -> Result<Selection, SelectionError> {
+    if selection.is_empty() {
+        Err(SelectionError::NoSelectedContent)
+    } else {
+        Ok(selection)
+    }
+}
+
+/// Drop the NULs owners leave in text, and the surrounding whitespace
+/// with `trim_whitespace`
+///
+/// Every NUL is removed, not only the padding at the end: some Java
+/// programs put them in the middle of the text, and none mean them as
+/// text. Text that isn't valid UTF-8 only loses ASCII whitespace, so its
+/// bytes aren't changed otherwise.
+fn trim_text(selection: &mut Selection, trim_whitespace: bool) {
+    selection.data.retain(|byte| *byte != 0);
+    if !trim_whitespace {
+        return;
+    }
+
+    let data = &selection.data;
+    let trimmed = match std::str::from_utf8(data) {
+        Ok(text) => text.trim().as_bytes(),
+        Err(_) => {
+            let start = data.iter().position(|byte| !byte.is_ascii_whitespace());
+            let end = data.iter().rposition(|byte| !byte.is_ascii_whitespace());
+            match (start, end) {
+                (Some(start), Some(end)) => &data[start..=end],
+                _ => &[],
+            }
+        }
+    };
+    selection.data = trimmed.to_vec();
+}
+
+/// Decode HTML from a selection into UTF-8
+///
+/// Firefox sends UTF-16, with a byte order mark on some setups, while
+/// Chromium sends UTF-8. Without a mark, UTF-16 is told apart by the zero
+/// byte of the leading ASCII `<`.
+fn decode_html(data: &[u8]) -> String {
+    let utf16 = |data: &[u8], little_endian: bool| {
+        let units: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|pair| {
+                if little_endian {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                }
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+
+    let html = match data {
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        [0xff, 0xfe, rest @ ..] => utf16(rest, true),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, false),
+        [_, 0, ..] => utf16(data, true),
+        [0, _, ..] => utf16(data, false),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    };
+    html.trim_end_matches('\u{0}').to_string()
+}
+
+/// List the names of the Wayland seats, e.g. to offer a choice for
+/// `SelectionOptions::wayland_seat`
+///
+/// Seats of compositors predating seat names are left out.
+pub fn wayland_seats() -> Result<Vec<String>, SelectionError> {
+    data_control::seat_names()
+}
+
+/// Probe the capabilities of the Linux backend
+pub(crate) fn capabilities() -> Capabilities {
+    let options = SelectionOptions::default();
+    let input_injection = match detect_display_server() {
+        Ok(DisplayServer::X11) => Some(true),
+        Ok(DisplayServer::Wayland) => {
+            Some(inject::wayland_injector(options.wayland_injector).is_some())
+        }
+        Err(_) => None,
+    };
+
+    // Primary selection offers come through the data control protocol, so
+    // where it can be read directly it can be watched too
+    let primary_selection = match detect_display_server() {
+        Ok(DisplayServer::X11) => Some(true),
+        Ok(DisplayServer::Wayland) => Some(is_primary_selection_supported().unwrap_or(false)),
+        Err(_) => None,
+    };
+
+    Capabilities {
+        // The BSDs share this backend
+        platform: std::env::consts::OS,
+        input_injection,
+        primary_selection,
+        selection_events: primary_selection,
+        focused_app: match detect_display_server() {
+            Ok(server) => Some(server == DisplayServer::X11),
+            Err(_) => None,
+        },
+        #[cfg(feature = "hotkey")]
+        global_hotkeys: detect_display_server().ok().map(hotkey::is_available),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A connection whose socket can be closed under it
+    struct FakeConnection {
+        open: Cell<bool>,
+    }
+
+    fn read_fake(connection: &FakeConnection) -> Result<&'static str, SelectionError> {
+        if connection.open.get() {
+            Ok("text")
         } else {
-            return self.get_selection_on_x11();
+            Err(SelectionError::ClipboardError("Broken pipe".to_string()))
         }
+    }
 
-        let (mut pipe, _) = get_contents(ClipboardType::Primary, Seat::Unspecified, MimeType::Text)
-            .map_err(|_| {
-                SelectionError::ClipboardError("Failed to get contents from Wayland".to_string())
-            })?;
-        let mut contents = vec![];
-        pipe.read_to_end(&mut contents)
-            .map_err(|_| SelectionError::ClipboardError("Failed to read contents".to_string()))?;
+    #[test]
+    fn test_with_connection_reconnects() {
+        let connects = Cell::new(0);
+        let connect = || {
+            connects.set(connects.get() + 1);
+            Ok(FakeConnection {
+                open: Cell::new(true),
+            })
+        };
+        let is_alive = |connection: &FakeConnection| connection.open.get();
+
+        let mut cached = None;
+        assert_eq!(
+            with_connection(&mut cached, connect, is_alive, read_fake).unwrap(),
+            "text"
+        );
+        assert_eq!(connects.get(), 1);
+
+        // The server goes away between calls
+        cached.as_ref().unwrap().open.set(false);
+        assert_eq!(
+            with_connection(&mut cached, connect, is_alive, read_fake).unwrap(),
+            "text"
+        );
+        assert_eq!(connects.get(), 2);
+        assert!(cached.as_ref().unwrap().open.get());
+
+        cached.as_ref().unwrap().open.set(false);
+        let refused = || Err::<FakeConnection, _>(SelectionError::NoDisplay);
+        assert!(matches!(
+            with_connection(&mut cached, refused, is_alive, read_fake),
+            Err(SelectionError::DisplayConnectionLost(_))
+        ));
+        assert!(cached.is_none());
+    }
+
+    #[test]
+    fn test_selector_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<LinuxSelector>();
+    }
+
+    #[test]
+    fn test_decode_html() {
+        let utf16 = |bom: &[u8], html: &str| {
+            let mut data = bom.to_vec();
+            data.extend(html.encode_utf16().flat_map(u16::to_le_bytes));
+            data
+        };
+
+        assert_eq!(decode_html(&utf16(&[0xff, 0xfe], "<b>é</b>")), "<b>é</b>");
+        assert_eq!(decode_html(&utf16(&[], "<b>é</b>\0")), "<b>é</b>");
+        assert_eq!(decode_html("\u{feff}<b>é</b>".as_bytes()), "<b>é</b>");
+        assert_eq!(decode_html("<b>é</b>".as_bytes()), "<b>é</b>");
+    }
+
+    #[test]
+    fn test_trim_text_keeps_invalid_bytes() {
+        let mut selection = Selection::new_text_bytes(b"\0 caf\xe9 \n".to_vec());
+        trim_text(&mut selection, true);
+        assert_eq!(selection.data, b"caf\xe9");
+
+        let mut selection = Selection::new_text(" \u{a0}text\0".to_string());
+        trim_text(&mut selection, true);
+        assert_eq!(selection.as_text().as_deref(), Some("text"));
+    }
 
-        let contents = String::from_utf8_lossy(&contents)
-            .trim_matches('\u{0}')
-            .trim()
-            .to_string();
+    #[test]
+    fn test_trim_text_removes_every_nul() {
+        let mut selection = Selection::new_text(" two\0 words\n\0\0".to_string());
+        trim_text(&mut selection, false);
+        assert_eq!(selection.as_text().as_deref(), Some(" two words\n"));
+
+        trim_text(&mut selection, true);
+        assert_eq!(selection.as_text().as_deref(), Some("two words"));
+    }
+
+    #[test]
+    fn test_empty_selection_is_no_selected_content() {
+        let mut selection = Selection::new_text(" \n\0".to_string());
+        trim_text(&mut selection, true);
+        assert!(matches!(
+            require_content(selection),
+            Err(SelectionError::NoSelectedContent)
+        ));
+        assert!(require_content(Selection::new_text("a".to_string())).is_ok());
+    }
+
+    #[derive(Default)]
+    struct Attempts(Mutex<Vec<SelectionMethod>>);
+
+    impl SelectionObserver for Attempts {
+        fn on_attempt(&self, method: SelectionMethod) {
+            self.0.lock().unwrap().push(method);
+        }
+    }
+
+    #[test]
+    fn test_accessibility_only_rule_never_copies() {
+        let attempts = Arc::new(Attempts::default());
+        let selector = LinuxSelector::with_options(
+            SelectionOptions::default()
+                .copy_fallback(true)
+                .observer(attempts.clone()),
+        );
+        let rule = AppRule::new().methods(&[SelectionMethod::Accessibility]);
+
+        assert!(selector.capture_text_following(rule, None).is_err());
+        assert!(!attempts
+            .0
+            .lock()
+            .unwrap()
+            .contains(&SelectionMethod::Clipboard));
+    }
 
-        Ok(Selection::new_text(contents))
+    #[test]
+    fn test_display_server_from() {
+        assert_eq!(
+            display_server_from(Some("wayland-0"), Some(":0"), None),
+            Some(DisplayServer::Wayland)
+        );
+        assert_eq!(
+            display_server_from(Some("wayland-0"), Some(":0"), Some("x11")),
+            Some(DisplayServer::X11)
+        );
+        assert_eq!(
+            display_server_from(None, Some("localhost:10.0"), Some("tty")),
+            Some(DisplayServer::X11)
+        );
+        assert_eq!(display_server_from(Some(""), None, Some("wayland")), None);
     }
 }
@@ -0,0 +1,647 @@
+//! Selection through the AT-SPI2 accessibility bus
+//!
+//! GTK and Qt applications publish their widgets on a dedicated D-Bus bus,
+//! whose address is handed out by the session bus. The focused object is
+//! found in the active window of each application, and its selection is read
+//! through the Text interface without touching any selection buffer.
+
+use log::debug;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Once;
+use std::time::Duration;
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::export::serde::{de::DeserializeOwned, Serialize};
+use zbus::zvariant::{DynamicType, OwnedObjectPath, OwnedValue, Type, Value};
+
+use crate::privacy::redact;
+use crate::{Rect, SelectionContext, SelectionError};
+
+/// Service handing out the address of the accessibility bus
+const BUS_LAUNCHER_NAME: &str = "org.a11y.Bus";
+const BUS_LAUNCHER_PATH: &str = "/org/a11y/bus";
+const STATUS_INTERFACE: &str = "org.a11y.Status";
+
+/// The registry's root object, whose children are the applications
+const REGISTRY_NAME: &str = "org.a11y.atspi.Registry";
+const ROOT_PATH: &str = "/org/a11y/atspi/accessible/root";
+
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const ACCESSIBLE_INTERFACE: &str = "org.a11y.atspi.Accessible";
+const TEXT_INTERFACE: &str = "org.a11y.atspi.Text";
+const EDITABLE_TEXT_INTERFACE: &str = "org.a11y.atspi.EditableText";
+
+/// `AtspiCoordType` of coordinates relative to the screen
+const COORD_TYPE_SCREEN: u32 = 0;
+
+/// `AtspiTextBoundaryType` of lines, each starting at its first character
+const BOUNDARY_LINE_START: u32 = 5;
+
+/// Selected ranges spanning more lines than this only get the range's rect
+const MAX_BOUNDS_LINES: usize = 64;
+
+/// `AtspiStateType` values
+const STATE_ACTIVE: u32 = 1;
+const STATE_EDITABLE: u32 = 7;
+const STATE_FOCUSED: u32 = 12;
+const STATE_SHOWING: u32 = 25;
+const STATE_READ_ONLY: u32 = 43;
+
+/// How long a call waits for an application to answer
+///
+/// Calls are answered by the application's main loop, so a hanging
+/// application would otherwise hang the capture with it.
+const CALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most objects visited while looking for the focused one, so huge trees
+/// (spreadsheets, long lists) can't stall the capture
+const MAX_VISITED: usize = 4096;
+
+/// An accessible object: the unique bus name of its application and its path
+type ObjectRef = (String, OwnedObjectPath);
+
+/// Connect to the accessibility bus
+///
+/// The bus is marked enabled first, as an assistive technology would, so
+/// applications only publishing while one is running (Qt) start doing so.
+pub(crate) fn connect() -> Result<Connection, SelectionError> {
+    let session = Connection::session().map_err(unavailable)?;
+    static ENABLED: Once = Once::new();
+    ENABLED.call_once(|| enable_bus(&session));
+    let address: String = session
+        .call_method(
+            Some(BUS_LAUNCHER_NAME),
+            BUS_LAUNCHER_PATH,
+            Some(BUS_LAUNCHER_NAME),
+            "GetAddress",
+            &(),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(unavailable)?;
+
+    Builder::address(address.as_str())
+        .map_err(unavailable)?
+        .method_timeout(CALL_TIMEOUT)
+        .build()
+        .map_err(unavailable)
+}
+
+/// Set the bus launcher's `IsEnabled` property, which toolkits watch to
+/// decide whether to publish their widgets
+///
+/// This lasts for the session, and GNOME stores it as a setting.
+fn enable_bus(session: &Connection) {
+    let enabled = session
+        .call_method(
+            Some(BUS_LAUNCHER_NAME),
+            BUS_LAUNCHER_PATH,
+            Some(PROPERTIES_INTERFACE),
+            "Get",
+            &(STATUS_INTERFACE, "IsEnabled"),
+        )
+        .and_then(|reply| reply.body().deserialize::<OwnedValue>())
+        .ok()
+        .and_then(|value| bool::try_from(value).ok());
+    if enabled != Some(false) {
+        return;
+    }
+
+    debug!("Enabling the accessibility bus");
+    if let Err(err) = session.call_method(
+        Some(BUS_LAUNCHER_NAME),
+        BUS_LAUNCHER_PATH,
+        Some(PROPERTIES_INTERFACE),
+        "Set",
+        &(STATUS_INTERFACE, "IsEnabled", Value::from(true)),
+    ) {
+        debug!("Failed to enable the accessibility bus: {}", err);
+    }
+}
+
+/// Check whether a process runs Qt, from the libraries it has mapped
+pub(crate) fn is_qt_process(pid: u32) -> bool {
+    fs::read_to_string(format!("/proc/{}/maps", pid)).is_ok_and(|maps| maps_qt(&maps))
+}
+
+/// Whether a process's memory map includes Qt's core library
+fn maps_qt(maps: &str) -> bool {
+    maps.lines().any(|line| {
+        line.rsplit('/').next().is_some_and(|file| {
+            file.starts_with("libQt5Core.so") || file.starts_with("libQt6Core.so")
+        })
+    })
+}
+
+/// Find the focused object in the active window of any application
+pub(crate) fn focused_object(connection: &Connection) -> Result<ObjectRef, SelectionError> {
+    let root = (
+        REGISTRY_NAME.to_string(),
+        OwnedObjectPath::try_from(ROOT_PATH).map_err(|err| call_error(err.into()))?,
+    );
+    let mut visited = 0;
+
+    for application in children(connection, &root)? {
+        // Applications that stopped answering mustn't hide the others
+        let windows = match children(connection, &application) {
+            Ok(windows) => windows,
+            Err(err) => {
+                debug!(
+                    "Skipping unresponsive application {}: {}",
+                    application.0, err
+                );
+                continue;
+            }
+        };
+
+        for window in windows {
+            if !has_state(connection, &window, STATE_ACTIVE)? {
+                continue;
+            }
+            if let Some(object) = find_focused(connection, window, &mut visited)? {
+                return Ok(object);
+            }
+        }
+    }
+
+    Err(SelectionError::NoFocusedElement)
+}
+
+/// Depth-first search for the focused object below `object`, including it
+fn find_focused(
+    connection: &Connection,
+    object: ObjectRef,
+    visited: &mut usize,
+) -> Result<Option<ObjectRef>, SelectionError> {
+    *visited += 1;
+    if *visited > MAX_VISITED {
+        return Ok(None);
+    }
+
+    let states = states(connection, &object)?;
+    if states.contains(&STATE_FOCUSED) {
+        return Ok(Some(object));
+    }
+
+    for child in children(connection, &object)? {
+        // Hidden pages (background tabs) can't hold the focus
+        if !has_state(connection, &child, STATE_SHOWING)? {
+            continue;
+        }
+        if let Some(focused) = find_focused(connection, child, visited)? {
+            return Ok(Some(focused));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the selected text of an object through its Text interface
+///
+/// Several selected ranges are joined with newlines.
+pub(crate) fn selected_text(
+    connection: &Connection,
+    object: &ObjectRef,
+) -> Result<String, SelectionError> {
+    require_text(connection, object)?;
+
+    let count: i32 = call(connection, object, TEXT_INTERFACE, "GetNSelections", &())?;
+    let mut ranges = Vec::new();
+    for index in 0..count {
+        let (start, end): (i32, i32) = call(
+            connection,
+            object,
+            TEXT_INTERFACE,
+            "GetSelection",
+            &(index,),
+        )?;
+        if start == end {
+            continue;
+        }
+        let text: String = call(connection, object, TEXT_INTERFACE, "GetText", &(start, end))?;
+        ranges.push(text);
+    }
+
+    Ok(ranges.join("\n"))
+}
+
+/// Read the first selected range of an object with up to `chars`
+/// characters on each side
+///
+/// Only the text around the selection is fetched, clamped to the object's
+/// `CharacterCount`.
+pub(crate) fn selection_context(
+    connection: &Connection,
+    object: &ObjectRef,
+    chars: usize,
+) -> Result<SelectionContext, SelectionError> {
+    require_text(connection, object)?;
+
+    let count: i32 = call(connection, object, TEXT_INTERFACE, "GetNSelections", &())?;
+    if count < 1 {
+        return Err(SelectionError::NoSelectedContent);
+    }
+    let (start, end): (i32, i32) = call(connection, object, TEXT_INTERFACE, "GetSelection", &(0,))?;
+    if start < 0 || start >= end {
+        return Err(SelectionError::NoSelectedContent);
+    }
+
+    let chars = i32::try_from(chars).unwrap_or(i32::MAX);
+    let from = start.saturating_sub(chars).max(0);
+    let mut to = end.saturating_add(chars);
+    // Toolkits not reporting the count clamp `GetText` themselves
+    if let Ok(character_count) = character_count(connection, object) {
+        to = to.min(character_count.max(end));
+    }
+
+    let text: String = call(connection, object, TEXT_INTERFACE, "GetText", &(from, to))?;
+    Ok(SelectionContext::from_char_range(
+        &text,
+        (start - from) as usize,
+        (end - start) as usize,
+        chars as usize,
+    ))
+}
+
+fn character_count(connection: &Connection, object: &ObjectRef) -> Result<i32, SelectionError> {
+    let value: OwnedValue = call(
+        connection,
+        object,
+        PROPERTIES_INTERFACE,
+        "Get",
+        &(TEXT_INTERFACE, "CharacterCount"),
+    )?;
+    i32::try_from(value).map_err(|err| call_error(err.into()))
+}
+
+/// Replace the selected text of an object through its EditableText
+/// interface, or insert at the caret if nothing is selected
+///
+/// Offsets and lengths are counted in characters, as AT-SPI does, not in
+/// bytes. Objects without the interface return `MethodUnavailable`, and
+/// read-only ones `ReadOnly`.
+pub(crate) fn replace_selected_text(
+    connection: &Connection,
+    object: &ObjectRef,
+    text: &str,
+) -> Result<(), SelectionError> {
+    let states = states(connection, object)?;
+    if states.contains(&STATE_READ_ONLY) {
+        return Err(SelectionError::ReadOnly);
+    }
+    if !interfaces(connection, object)?
+        .iter()
+        .any(|interface| interface == EDITABLE_TEXT_INTERFACE)
+        || !states.contains(&STATE_EDITABLE)
+    {
+        return Err(SelectionError::MethodUnavailable(
+            "focused object is not editable through AT-SPI".to_string(),
+        ));
+    }
+
+    let count: i32 = call(connection, object, TEXT_INTERFACE, "GetNSelections", &())?;
+    let (start, end): (i32, i32) = if count > 0 {
+        call(connection, object, TEXT_INTERFACE, "GetSelection", &(0,))?
+    } else {
+        let caret: i32 = call(connection, object, TEXT_INTERFACE, "GetCaretOffset", &())?;
+        (caret, caret)
+    };
+    if start < 0 {
+        return Err(SelectionError::NoSelectedContent);
+    }
+
+    if start != end {
+        let deleted: bool = call(
+            connection,
+            object,
+            EDITABLE_TEXT_INTERFACE,
+            "DeleteText",
+            &(start, end),
+        )?;
+        if !deleted {
+            return Err(refused("deleting the selected text"));
+        }
+    }
+
+    let length = i32::try_from(text.chars().count())
+        .map_err(|_| SelectionError::Other("Replacement text is too long".to_string()))?;
+    let inserted: bool = call(
+        connection,
+        object,
+        EDITABLE_TEXT_INTERFACE,
+        "InsertText",
+        &(start, text, length),
+    )?;
+    if !inserted {
+        return Err(refused("inserting the replacement text"));
+    }
+
+    debug!(
+        "Replaced characters {}..{} through AT-SPI",
+        redact(start),
+        redact(end)
+    );
+    Ok(())
+}
+
+/// Get the screen rects of the selected text of an object, one per line
+///
+/// Toolkits report screen coordinates in logical pixels. On Wayland they
+/// can't know where their windows are, so the rects may be relative to
+/// the window instead.
+pub(crate) fn selection_bounds(
+    connection: &Connection,
+    object: &ObjectRef,
+) -> Result<Vec<Rect>, SelectionError> {
+    require_text(connection, object)?;
+
+    let count: i32 = call(connection, object, TEXT_INTERFACE, "GetNSelections", &())?;
+    let mut lines = Vec::new();
+    for index in 0..count {
+        let (start, end): (i32, i32) = call(
+            connection,
+            object,
+            TEXT_INTERFACE,
+            "GetSelection",
+            &(index,),
+        )?;
+        if start == end {
+            continue;
+        }
+
+        match line_bounds(connection, object, start, end) {
+            Ok(range_lines) if !range_lines.is_empty() => lines.extend(range_lines),
+            _ => lines.extend(range_extents(connection, object, start, end)?),
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Get the screen rect of the caret in an object, `None` if the toolkit
+/// doesn't report one
+///
+/// The caret's rect is that of the character after it, or the right edge
+/// of the one before it at the end of the text.
+pub(crate) fn caret_rect(
+    connection: &Connection,
+    object: &ObjectRef,
+) -> Result<Option<Rect>, SelectionError> {
+    require_text(connection, object)?;
+
+    let offset: i32 = call(connection, object, TEXT_INTERFACE, "GetCaretOffset", &())?;
+    if offset < 0 {
+        return Ok(None);
+    }
+
+    if let Some(rect) = character_extents(connection, object, offset)? {
+        return Ok(Some(Rect::new(rect.x, rect.y, 0.0, rect.height)));
+    }
+    if offset > 0 {
+        if let Some(rect) = character_extents(connection, object, offset - 1)? {
+            return Ok(Some(Rect::new(
+                rect.x + rect.width,
+                rect.y,
+                0.0,
+                rect.height,
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Check whether an input method composition is open at the caret of an
+/// object
+///
+/// Toolkits show the text being composed in the widget, underlined, and
+/// report the underline as a text attribute of the run ending at the caret.
+/// Text the user underlined looks the same, so this may report a
+/// composition where there's none.
+pub(crate) fn is_composing(
+    connection: &Connection,
+    object: &ObjectRef,
+) -> Result<bool, SelectionError> {
+    require_text(connection, object)?;
+
+    let offset: i32 = call(connection, object, TEXT_INTERFACE, "GetCaretOffset", &())?;
+    if offset <= 0 {
+        return Ok(false);
+    }
+    let (attributes, _, end): (HashMap<String, String>, i32, i32) = call(
+        connection,
+        object,
+        TEXT_INTERFACE,
+        "GetAttributes",
+        &(offset - 1,),
+    )?;
+    Ok(end >= offset
+        && attributes
+            .get("underline")
+            .is_some_and(|underline| underline != "none"))
+}
+
+/// Split a range at line boundaries and get the rect of each piece
+fn line_bounds(
+    connection: &Connection,
+    object: &ObjectRef,
+    start: i32,
+    end: i32,
+) -> Result<Vec<Rect>, SelectionError> {
+    let mut lines = Vec::new();
+    let mut offset = start;
+    while offset < end {
+        if lines.len() >= MAX_BOUNDS_LINES {
+            return Ok(Vec::new());
+        }
+
+        let (_, line_start, line_end): (String, i32, i32) = call(
+            connection,
+            object,
+            TEXT_INTERFACE,
+            "GetTextAtOffset",
+            &(offset, BOUNDARY_LINE_START),
+        )?;
+        // Toolkits without line boundaries get the whole range
+        if line_end <= offset {
+            return Ok(Vec::new());
+        }
+
+        let piece_end = line_end.min(end);
+        lines.extend(range_extents(
+            connection,
+            object,
+            line_start.max(offset),
+            piece_end,
+        )?);
+        offset = piece_end;
+    }
+
+    Ok(lines)
+}
+
+fn range_extents(
+    connection: &Connection,
+    object: &ObjectRef,
+    start: i32,
+    end: i32,
+) -> Result<Option<Rect>, SelectionError> {
+    let extents = call(
+        connection,
+        object,
+        TEXT_INTERFACE,
+        "GetRangeExtents",
+        &(start, end, COORD_TYPE_SCREEN),
+    )?;
+    Ok(extents_rect(extents))
+}
+
+fn character_extents(
+    connection: &Connection,
+    object: &ObjectRef,
+    offset: i32,
+) -> Result<Option<Rect>, SelectionError> {
+    let extents = call(
+        connection,
+        object,
+        TEXT_INTERFACE,
+        "GetCharacterExtents",
+        &(offset, COORD_TYPE_SCREEN),
+    )?;
+    Ok(extents_rect(extents))
+}
+
+/// Turn extents into a rect, `None` for the empty extents toolkits report
+/// when they don't know
+fn extents_rect((x, y, width, height): (i32, i32, i32, i32)) -> Option<Rect> {
+    let rect = Rect::new(x.into(), y.into(), width.into(), height.into());
+    (rect.height > 0.0 && rect.width >= 0.0).then_some(rect)
+}
+
+/// Fail unless an object implements the Text interface, which terminal
+/// emulators often don't
+fn require_text(connection: &Connection, object: &ObjectRef) -> Result<(), SelectionError> {
+    if !interfaces(connection, object)?
+        .iter()
+        .any(|interface| interface == TEXT_INTERFACE)
+    {
+        return Err(SelectionError::MethodUnavailable(
+            "focused object does not implement the AT-SPI Text interface".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn interfaces(connection: &Connection, object: &ObjectRef) -> Result<Vec<String>, SelectionError> {
+    call(
+        connection,
+        object,
+        ACCESSIBLE_INTERFACE,
+        "GetInterfaces",
+        &(),
+    )
+}
+
+fn children(connection: &Connection, object: &ObjectRef) -> Result<Vec<ObjectRef>, SelectionError> {
+    call(connection, object, ACCESSIBLE_INTERFACE, "GetChildren", &())
+}
+
+/// Read the states of an object, as `AtspiStateType` values
+fn states(connection: &Connection, object: &ObjectRef) -> Result<Vec<u32>, SelectionError> {
+    // Two 32-bit words, bit n of the set standing for state n
+    let words: Vec<u32> = call(connection, object, ACCESSIBLE_INTERFACE, "GetState", &())?;
+
+    Ok((0..64)
+        .filter(|state| {
+            words
+                .get(*state as usize / 32)
+                .is_some_and(|word| word & (1 << (state % 32)) != 0)
+        })
+        .collect())
+}
+
+fn has_state(
+    connection: &Connection,
+    object: &ObjectRef,
+    state: u32,
+) -> Result<bool, SelectionError> {
+    Ok(states(connection, object)?.contains(&state))
+}
+
+fn call<B, R>(
+    connection: &Connection,
+    (name, path): &ObjectRef,
+    interface: &str,
+    method: &str,
+    body: &B,
+) -> Result<R, SelectionError>
+where
+    B: Serialize + DynamicType,
+    R: DeserializeOwned + Type,
+{
+    connection
+        .call_method(
+            Some(name.as_str()),
+            path.as_ref(),
+            Some(interface),
+            method,
+            body,
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(call_error)
+}
+
+fn refused(action: &str) -> SelectionError {
+    SelectionError::AccessibilityError {
+        message: format!("The application refused {}", action),
+        code: None,
+        source: None,
+    }
+}
+
+fn unavailable(err: zbus::Error) -> SelectionError {
+    SelectionError::MethodUnavailable(format!("AT-SPI bus is not available: {}", err))
+}
+
+fn call_error(err: zbus::Error) -> SelectionError {
+    match err {
+        zbus::Error::InputOutput(io) if io.kind() == std::io::ErrorKind::TimedOut => {
+            SelectionError::Timeout {
+                phase: "waiting for an AT-SPI call",
+                waited: CALL_TIMEOUT,
+            }
+        }
+        err => SelectionError::AccessibilityError {
+            message: err.to_string(),
+            code: None,
+            source: Some(Box::new(err)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extents_rect() {
+        assert_eq!(
+            extents_rect((10, 20, 0, 16)),
+            Some(Rect::new(10.0, 20.0, 0.0, 16.0))
+        );
+        assert_eq!(extents_rect((0, 0, 0, 0)), None);
+        assert_eq!(extents_rect((-1, -1, -1, -1)), None);
+    }
+
+    #[test]
+    fn test_maps_qt() {
+        let maps = "7f1c2a000000-7f1c2a200000 r-xp 00000000 103:02 1234 \
+                    /usr/lib/x86_64-linux-gnu/libQt6Core.so.6.4.2\n\
+                    7f1c2b000000-7f1c2b100000 r-xp 00000000 103:02 5678 \
+                    /usr/lib/libc.so.6\n";
+        assert!(maps_qt(maps));
+        assert!(!maps_qt(
+            "7f1c2b000000-7f1c2b100000 r-xp 0 0:0 0 /usr/lib/libgtk-3.so.0\n"
+        ));
+    }
+}
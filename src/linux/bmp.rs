@@ -0,0 +1,115 @@
+//! Conversion of Windows bitmaps to PNG
+//!
+//! Owners offering images only as `image/bmp` send a whole BMP file.
+//! Uncompressed 24- and 32-bit bitmaps, which is what they write in
+//! practice, are supported.
+
+use crate::image::{encode_png, Alpha};
+use crate::SelectionError;
+
+const FILE_HEADER_LEN: usize = 14;
+const INFO_HEADER_LEN: usize = 40;
+
+/// `biCompression` values
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+/// Convert a BMP file to PNG
+pub(crate) fn to_png(data: &[u8]) -> Result<Vec<u8>, SelectionError> {
+    let (width, height, rgba) = decode(data).ok_or_else(|| {
+        SelectionError::ClipboardError("Unsupported or malformed bitmap".to_string())
+    })?;
+    encode_png(width, height, width * 4, &rgba, Alpha::Straight)
+}
+
+/// Decode a bitmap into its size and RGBA pixels, top row first
+fn decode(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    if data.len() < FILE_HEADER_LEN + INFO_HEADER_LEN || &data[..2] != b"BM" {
+        return None;
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+
+    let pixels_offset = u32_at(10) as usize;
+    let info = FILE_HEADER_LEN;
+    let width = i32::from_le_bytes(u32_at(info + 4).to_le_bytes());
+    let height = i32::from_le_bytes(u32_at(info + 8).to_le_bytes());
+    let bits = u16_at(info + 14);
+    let compression = u32_at(info + 16);
+
+    let supported = matches!(
+        (bits, compression),
+        (24, BI_RGB) | (32, BI_RGB) | (32, BI_BITFIELDS)
+    );
+    if !supported || width <= 0 || height == 0 {
+        return None;
+    }
+
+    // Positive heights are stored bottom-up
+    let bottom_up = height > 0;
+    let width = width as usize;
+    let height = height.unsigned_abs() as usize;
+    let bytes_per_pixel = usize::from(bits / 8);
+    // Rows are padded to 4 bytes
+    let stride = (width * bytes_per_pixel).div_ceil(4) * 4;
+    let pixels = data.get(pixels_offset..pixels_offset.checked_add(stride * height)?)?;
+
+    // 32-bit bitmaps often leave the alpha byte at zero; they're opaque then
+    let has_alpha = bits == 32 && pixels.chunks_exact(4).any(|pixel| pixel[3] != 0);
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in 0..height {
+        let stored = if bottom_up { height - 1 - row } else { row };
+        let line = &pixels[stored * stride..][..width * bytes_per_pixel];
+        for pixel in line.chunks_exact(bytes_per_pixel) {
+            let alpha = if has_alpha { pixel[3] } else { 0xff };
+            rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+        }
+    }
+
+    Some((width, height, rgba))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bmp_to_png() {
+        // 2x2, 24-bit, bottom-up: blue and white below red and green
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&70u32.to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&2i32.to_le_bytes());
+        bmp.extend_from_slice(&2i32.to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&24u16.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+        bmp.extend_from_slice(&[0xff, 0, 0, 0xff, 0xff, 0xff, 0, 0]);
+        bmp.extend_from_slice(&[0, 0, 0xff, 0, 0xff, 0, 0, 0]);
+
+        let png = to_png(&bmp).unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((reader.info().width, reader.info().height), (2, 2));
+        assert_eq!(
+            pixels,
+            [
+                0xff, 0, 0, 0xff, 0, 0xff, 0, 0xff, //
+                0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ]
+        );
+        assert!(to_png(b"BM not really").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! Reading X11 selections through `xclip` or `xsel`
+//!
+//! A last resort for setups that break the native read in ways these tools
+//! cope with (Xpra, old VNC servers, unusual Xwayland builds). They're only
+//! run with `SelectionOptions::x11_command_fallback`.
+
+use log::debug;
+use std::process::{Child, Command, Stdio};
+
+use super::{inject, wayland};
+use crate::privacy::redact;
+use crate::{Selection, SelectionError, SelectionOptions, SelectionSource};
+
+/// Read the text of a selection with whichever of the tools is installed
+pub(crate) fn read_selection(
+    source: SelectionSource,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let (program, args): (&str, &[&str]) = match source {
+        SelectionSource::Primary if inject::in_path("xclip") => {
+            ("xclip", &["-o", "-selection", "primary"])
+        }
+        SelectionSource::Clipboard if inject::in_path("xclip") => {
+            ("xclip", &["-o", "-selection", "clipboard"])
+        }
+        SelectionSource::Primary if inject::in_path("xsel") => ("xsel", &["--output", "--primary"]),
+        SelectionSource::Clipboard if inject::in_path("xsel") => {
+            ("xsel", &["--output", "--clipboard"])
+        }
+        _ => {
+            return Err(SelectionError::MethodUnavailable(
+                "neither xclip nor xsel is installed".to_string(),
+            ))
+        }
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let output = read_output(&mut child, options);
+    if output.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    let output = output?;
+
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            let _ = std::io::Read::read_to_string(&mut pipe, &mut stderr);
+        }
+        return Err(exit_error(program, status.code(), stderr.trim()));
+    }
+    debug!("Read {} bytes with {}", redact(output.len()), program);
+
+    let mut selection = Selection::new_text_bytes(output);
+    if let Some(max_bytes) = options.max_bytes {
+        selection.truncate_text(max_bytes);
+    }
+    Ok(selection)
+}
+
+/// Read the tool's output, giving up if it stalls for `selection_timeout`
+fn read_output(child: &mut Child, options: &SelectionOptions) -> Result<Vec<u8>, SelectionError> {
+    let Some(stdout) = child.stdout.take() else {
+        return Ok(Vec::new());
+    };
+    // One byte more shows whether the text was cut
+    let limit = options.max_bytes.map(|max| max + 1);
+    wayland::read_pipe(stdout, limit, options.selection_timeout)
+}
+
+/// Map a failed run of `program`
+///
+/// Both tools exit with 1 when the selection has no owner, or none that
+/// can convert it to text.
+fn exit_error(program: &str, code: Option<i32>, stderr: &str) -> SelectionError {
+    match code {
+        Some(1) => {
+            debug!("{} found no selection: {}", program, stderr);
+            SelectionError::NoSelectedContent
+        }
+        Some(code) => SelectionError::ClipboardError(format!(
+            "{} failed with exit code {}: {}",
+            program, code, stderr
+        )),
+        None => SelectionError::ClipboardError(format!("{} was killed", program)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_error() {
+        assert!(matches!(
+            exit_error("xclip", Some(1), "Error: target STRING not available"),
+            SelectionError::NoSelectedContent
+        ));
+        assert!(matches!(
+            exit_error("xsel", Some(2), ""),
+            SelectionError::ClipboardError(_)
+        ));
+    }
+}
@@ -0,0 +1,415 @@
+//! Simulated Ctrl+C and Ctrl+V and the regular clipboard
+//!
+//! Some applications (many Electron and Java programs) never set the
+//! primary selection when text is highlighted. For those the copy shortcut
+//! is simulated and the regular clipboard (CLIPBOARD on X11) read instead,
+//! the same way the Windows fallback works, and the previous clipboard text
+//! is put back afterwards unless `SelectionOptions::restore_clipboard` is
+//! off. Writing back to applications without an editable
+//! accessible pastes through the clipboard the same way.
+
+use log::{info, warn};
+use std::io::Read;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use x11_clipboard::Clipboard;
+use x11rb::connection::Connection;
+use x11rb::protocol::xfixes::{self, ConnectionExt as _, SelectionEventMask};
+use x11rb::protocol::xproto::{Atom, ConnectionExt as _, CreateWindowAux, WindowClass};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use super::{inject, wayland, x11};
+use wl_clipboard_rs::copy::{self as wl_copy, Source};
+use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType};
+
+use crate::{ErrorKind, Selection, SelectionError, SelectionMethod, SelectionOptions};
+
+/// Owner of the restored and pasted CLIPBOARD contents
+///
+/// X11 selections are served by their owner, so the clipboard holding the
+/// text has to outlive the capture.
+static RESTORED_OWNER: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+/// Held for the whole of a simulated copy or paste
+///
+/// Two copies at once would each take the other's text for the previous
+/// clipboard and restore it.
+static COPY_LOCK: Mutex<()> = Mutex::new(());
+
+/// How long the focused application gets to read the clipboard after Ctrl+V
+const PASTE_SETTLE: Duration = Duration::from_millis(200);
+
+/// Watches CLIPBOARD ownership changes through XFIXES
+///
+/// An application copying again while it already owns CLIPBOARD keeps the
+/// same owner window, so polling the owner alone would miss the copy.
+struct OwnerWatcher {
+    connection: RustConnection,
+    clipboard: u32,
+}
+
+impl OwnerWatcher {
+    fn new() -> Result<Self, SelectionError> {
+        let (connection, screen) = RustConnection::connect(None).map_err(x11_error)?;
+        let root = connection.setup().roots[screen].root;
+        let window = connection.generate_id().map_err(x11_error)?;
+        connection
+            .create_window(
+                0,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                0,
+                &CreateWindowAux::new(),
+            )
+            .map_err(x11_error)?;
+
+        // The extension has to be initialized before its requests are used
+        connection
+            .xfixes_query_version(5, 0)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        let clipboard = connection
+            .intern_atom(false, b"CLIPBOARD")
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?
+            .atom;
+        connection
+            .xfixes_select_selection_input(
+                window,
+                clipboard,
+                SelectionEventMask::SET_SELECTION_OWNER,
+            )
+            .map_err(x11_error)?;
+        connection.flush().map_err(x11_error)?;
+
+        Ok(Self {
+            connection,
+            clipboard,
+        })
+    }
+
+    /// Check whether CLIPBOARD got a new owner since the last check
+    fn changed(&self) -> bool {
+        let mut changed = false;
+        while let Ok(Some(event)) = self.connection.poll_for_event() {
+            if let Event::XfixesSelectionNotify(xfixes::SelectionNotifyEvent {
+                selection, ..
+            }) = event
+            {
+                changed |= selection == self.clipboard;
+            }
+        }
+        changed
+    }
+
+    /// Wait for CLIPBOARD to get a new owner, up to `timeout`
+    fn wait_for_change(&self, timeout: Duration, interval: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            if self.changed() {
+                return true;
+            }
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(interval);
+        }
+    }
+}
+
+/// Copy the selection with a simulated Ctrl+C and read it from CLIPBOARD
+pub(crate) fn copy_on_x11(options: &SelectionOptions) -> Result<Selection, SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+    copy_on_x11_with(options, inject::post_copy, read_copied_text)
+}
+
+/// Have the focused application copy its selection with `copy` and read
+/// CLIPBOARD with `read`, restoring the previous clipboard text
+pub(crate) fn copy_on_x11_with(
+    options: &SelectionOptions,
+    copy: impl FnOnce() -> Result<(), SelectionError>,
+    read: x11::ContentReader,
+) -> Result<Selection, SelectionError> {
+    let _copying = COPY_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let clipboard = Clipboard::new().map_err(x11::connect_error)?;
+    let previous = if options.restore_clipboard {
+        load_clipboard(&clipboard, options).ok()
+    } else {
+        None
+    };
+
+    let watcher = OwnerWatcher::new()?;
+    // Events from before the copy aren't ours
+    watcher.changed();
+
+    copy()?;
+
+    // Without a new owner nothing was copied; what's there is the user's
+    // previous clipboard, not the selection
+    if !watcher.wait_for_change(options.copy_timeout, options.copy_poll_interval) {
+        warn!("CLIPBOARD did not change after the copy, assuming nothing is selected");
+        return Err(SelectionError::NoSelectedContent);
+    }
+
+    let copied = read(
+        &clipboard,
+        clipboard.getter.atoms.clipboard,
+        "CLIPBOARD",
+        options,
+    );
+
+    // Restore unless told not to, but never clobber a copy the user made
+    // since ours
+    if !options.restore_clipboard {
+        info!("Leaving the copied content on CLIPBOARD");
+    } else if watcher.changed() {
+        info!("CLIPBOARD changed again during the capture, not restoring it");
+    } else if let Some(previous) = previous {
+        restore_clipboard(previous);
+    }
+
+    copied?
+        .map(|selection| {
+            selection
+                .with_method(SelectionMethod::Clipboard)
+                .left_on_clipboard(!options.restore_clipboard)
+        })
+        .ok_or(SelectionError::NoSelectedContent)
+}
+
+/// Copy the selection with a simulated Ctrl+C and read it from the regular
+/// Wayland clipboard
+///
+/// Needs a compositor supporting the data control protocol, like reading
+/// the primary selection does, and one of the injectors.
+pub(crate) fn copy_on_wayland(options: &SelectionOptions) -> Result<Selection, SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+    let injector = inject::wayland_injector(options.wayland_injector).ok_or_else(|| {
+        SelectionError::MethodUnavailable(
+            "no way to simulate keystrokes on this Wayland compositor".to_string(),
+        )
+    })?;
+
+    let _copying = COPY_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    // Read even without `restore_clipboard`, as it's the only way to tell
+    // the copy happened
+    let previous = load_wayland_clipboard(options).ok();
+    inject::post_wayland_copy(injector)?;
+
+    // Clients only learn about clipboard changes by reading it, so a copy of
+    // the same text as before can't be told apart from no copy at all
+    let start = Instant::now();
+    let copied = loop {
+        match load_wayland_clipboard(options) {
+            Ok(contents) if Some(&contents) != previous.as_ref() => break contents,
+            _ if start.elapsed() >= options.copy_timeout => {
+                warn!("Clipboard did not change after Ctrl+C, assuming nothing is selected");
+                return Err(SelectionError::NoSelectedContent);
+            }
+            _ => thread::sleep(options.copy_poll_interval),
+        }
+    };
+
+    // Restore unless told not to, but never clobber a copy the user made
+    // since ours
+    if !options.restore_clipboard {
+        info!("Leaving the copied content on the clipboard");
+    } else if load_wayland_clipboard(options).ok().as_ref() != Some(&copied) {
+        info!("Clipboard changed again during the capture, not restoring it");
+    } else if let Some(previous) = previous {
+        if let Err(err) = store_wayland_clipboard(options, previous) {
+            warn!("Failed to restore the clipboard: {}", err);
+        }
+    }
+
+    text_selection(copied, options)
+        .map(|selection| selection.left_on_clipboard(!options.restore_clipboard))
+}
+
+/// Paste `text` with a simulated Ctrl+V through CLIPBOARD, restoring the
+/// previous clipboard text
+pub(crate) fn paste_on_x11(options: &SelectionOptions, text: &str) -> Result<(), SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+
+    let _copying = COPY_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let clipboard = Clipboard::new().map_err(x11::connect_error)?;
+    let previous = load_clipboard(&clipboard, options).ok();
+
+    let watcher = OwnerWatcher::new()?;
+    watcher.changed();
+    store_clipboard(text.as_bytes().to_vec())?;
+    // Pasting before the X server has made us the owner would paste the
+    // previous clipboard
+    if !watcher.wait_for_change(options.copy_timeout, options.copy_poll_interval) {
+        return Err(SelectionError::Timeout {
+            phase: "taking ownership of CLIPBOARD",
+            waited: options.copy_timeout,
+        });
+    }
+
+    let result = inject::post_paste();
+    // The application reads CLIPBOARD asynchronously when handling Ctrl+V
+    thread::sleep(PASTE_SETTLE);
+
+    if watcher.changed() {
+        info!("CLIPBOARD changed during the paste, not restoring it");
+    } else if let Some(previous) = previous {
+        restore_clipboard(previous);
+    }
+
+    result
+}
+
+/// Paste `text` with a simulated Ctrl+V through the regular Wayland
+/// clipboard, restoring the previous clipboard text
+pub(crate) fn paste_on_wayland(
+    options: &SelectionOptions,
+    text: &str,
+) -> Result<(), SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+    let injector = inject::wayland_injector(options.wayland_injector).ok_or_else(|| {
+        SelectionError::MethodUnavailable(
+            "no way to simulate keystrokes on this Wayland compositor".to_string(),
+        )
+    })?;
+
+    let _copying = COPY_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let previous = load_wayland_clipboard(options).ok();
+    store_wayland_clipboard(options, text.as_bytes().to_vec())?;
+
+    let result = inject::post_wayland_paste(injector);
+    // The application reads the clipboard asynchronously when handling Ctrl+V
+    thread::sleep(PASTE_SETTLE);
+
+    if load_wayland_clipboard(options).ok().as_deref() != Some(text.as_bytes()) {
+        info!("Clipboard changed during the paste, not restoring it");
+    } else if let Some(previous) = previous {
+        if let Err(err) = store_wayland_clipboard(options, previous) {
+            warn!("Failed to restore the clipboard: {}", err);
+        }
+    }
+
+    result
+}
+
+fn store_wayland_clipboard(
+    options: &SelectionOptions,
+    contents: Vec<u8>,
+) -> Result<(), SelectionError> {
+    let mut copy_options = wl_copy::Options::new();
+    copy_options.clipboard(wl_copy::ClipboardType::Regular);
+    if let Some(seat) = &options.wayland_seat {
+        copy_options.seat(wl_copy::Seat::Specific(seat.clone()));
+    }
+    copy_options
+        .copy(
+            Source::Bytes(contents.into_boxed_slice()),
+            wl_copy::MimeType::Text,
+        )
+        .map_err(|err| {
+            SelectionError::platform(ErrorKind::Clipboard, "Failed to write the clipboard", err)
+        })
+}
+
+fn load_wayland_clipboard(options: &SelectionOptions) -> Result<Vec<u8>, SelectionError> {
+    let seat = wayland::seat(options);
+    let (mut pipe, _) = get_contents(ClipboardType::Regular, seat, MimeType::Text)
+        .map_err(wayland::wayland_error)?;
+    let mut contents = Vec::new();
+    pipe.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// The copied text, cut down to `max_bytes`
+///
+/// The clipboard is read in full all the same, to be restored.
+fn text_selection(
+    copied: Vec<u8>,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let text = String::from_utf8_lossy(&copied).into_owned();
+    if text.is_empty() {
+        return Err(SelectionError::NoSelectedContent);
+    }
+
+    let mut selection = Selection::new_text(text);
+    if let Some(max_bytes) = options.max_bytes {
+        selection.truncate_text(max_bytes);
+    }
+    Ok(selection.with_method(SelectionMethod::Clipboard))
+}
+
+/// Read copied text, `None` if there is none
+fn read_copied_text(
+    clipboard: &Clipboard,
+    selection: Atom,
+    name: &str,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    let text = x11::read_text(clipboard, selection, name, options)?;
+    Ok((!text.is_empty()).then_some(text))
+}
+
+fn synthetic_input_disabled() -> SelectionError {
+    SelectionError::MethodUnavailable("simulated keystrokes are disabled".to_string())
+}
+
+/// Read the clipboard text to restore, in full since a cut-down copy would
+/// lose the rest of it
+fn load_clipboard(
+    clipboard: &Clipboard,
+    options: &SelectionOptions,
+) -> Result<Vec<u8>, SelectionError> {
+    let selection = clipboard.getter.atoms.clipboard;
+    let options = SelectionOptions {
+        max_bytes: None,
+        ..options.clone()
+    };
+    x11::read_text(clipboard, selection, "CLIPBOARD", &options).map(|text| text.data)
+}
+
+fn restore_clipboard(previous: Vec<u8>) {
+    if let Err(err) = store_clipboard(previous) {
+        warn!("Failed to restore CLIPBOARD: {}", err);
+    }
+}
+
+/// Own CLIPBOARD with `contents`, served for as long as the process runs
+fn store_clipboard(contents: Vec<u8>) -> Result<(), SelectionError> {
+    let mut owner = RESTORED_OWNER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    let clipboard = match owner.take() {
+        Some(clipboard) => clipboard,
+        None => Clipboard::new().map_err(x11::connect_error)?,
+    };
+    let atoms = &clipboard.setter.atoms;
+    let stored = clipboard
+        .store(atoms.clipboard, atoms.utf8_string, contents)
+        .map_err(x11_error);
+    *owner = Some(clipboard);
+    stored
+}
+
+fn x11_error(err: impl std::error::Error + Send + Sync + 'static) -> SelectionError {
+    SelectionError::platform(ErrorKind::Clipboard, "X11 error", err)
+}
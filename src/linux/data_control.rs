@@ -0,0 +1,299 @@
+//! Reading Wayland selections through the data control protocol directly
+//!
+//! wl-clipboard-rs fails on some compositors, e.g. ones announcing seats
+//! it can't match up or a protocol version it doesn't expect. This second
+//! path binds `zwlr_data_control_manager_v1` at whatever version the
+//! compositor offers and reads from the first seat that has a selection.
+//!
+//! `zwp_primary_selection_device_manager_v1` isn't used: it only hands the
+//! selection to clients with keyboard focus, which a library without a
+//! window never has.
+
+use std::collections::HashMap;
+use std::os::fd::AsFd;
+use std::time::Duration;
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalList, GlobalListContents};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::protocol::wl_seat::{self, WlSeat};
+use wayland_client::{event_created_child, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::{
+    self, ZwlrDataControlDeviceV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::{
+    self, ZwlrDataControlManagerV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::{
+    self, ZwlrDataControlOfferV1,
+};
+use wl_clipboard_rs::paste::ClipboardType;
+
+use super::wayland::{read_pipe, SelectionReader};
+use crate::{ErrorKind, SelectionError};
+
+/// Manager version that added the primary selection
+const PRIMARY_SELECTION_VERSION: u32 = 2;
+
+/// Selections of every seat, as they were when connecting
+pub(crate) struct DataControl {
+    connection: Connection,
+    /// How long an owner may go without sending anything
+    timeout: Duration,
+    /// The only seat to read from, by name
+    seat: Option<String>,
+    state: State,
+    primary_selection: bool,
+}
+
+#[derive(Default)]
+struct State {
+    /// Selections by seat, in the order the seats were announced
+    seats: Vec<SeatSelections>,
+    /// MIME types of offers not yet made a selection
+    offers: HashMap<ObjectId, Vec<String>>,
+}
+
+#[derive(Default)]
+struct SeatSelections {
+    /// Name the compositor gave the seat, from version 2 on
+    name: Option<String>,
+    regular: Option<Offer>,
+    primary: Option<Offer>,
+}
+
+struct Offer {
+    offer: ZwlrDataControlOfferV1,
+    mime_types: Vec<String>,
+}
+
+impl DataControl {
+    /// Connect to the compositor and take a snapshot of its selections,
+    /// those of `seat` only if it's given
+    pub(crate) fn connect(timeout: Duration, seat: Option<&str>) -> Result<Self, SelectionError> {
+        let connection = Connection::connect_to_env().map_err(super::display_unreachable)?;
+        let (globals, mut queue) =
+            registry_queue_init::<State>(&connection).map_err(wayland_error)?;
+        let handle = queue.handle();
+
+        let manager: ZwlrDataControlManagerV1 = globals
+            .bind(&handle, 1..=PRIMARY_SELECTION_VERSION, ())
+            .map_err(wayland_error)?;
+        let primary_selection = manager.version() >= PRIMARY_SELECTION_VERSION;
+
+        let mut state = State::default();
+        let seats = bind_seats(&globals, &handle, &mut state)?;
+        for (index, seat) in seats.iter().enumerate() {
+            manager.get_data_device(seat, &handle, index);
+        }
+
+        // Seats announce their names, and devices the current selections,
+        // right away
+        queue.roundtrip(&mut state).map_err(wayland_error)?;
+
+        Ok(Self {
+            connection,
+            timeout,
+            seat: seat.map(str::to_string),
+            state,
+            primary_selection,
+        })
+    }
+
+    fn selection(&self, clipboard: ClipboardType) -> Result<Option<&Offer>, SelectionError> {
+        if clipboard == ClipboardType::Primary && !self.primary_selection {
+            return Err(SelectionError::MethodUnavailable(
+                "the compositor's data control protocol has no primary selection".to_string(),
+            ));
+        }
+
+        Ok(self
+            .state
+            .seats
+            .iter()
+            .filter(|seat| self.seat.is_none() || seat.name == self.seat)
+            .find_map(|seat| match clipboard {
+                ClipboardType::Regular => seat.regular.as_ref(),
+                ClipboardType::Primary => seat.primary.as_ref(),
+            }))
+    }
+}
+
+impl SelectionReader for DataControl {
+    fn offered_types(
+        &self,
+        clipboard: ClipboardType,
+    ) -> Result<Option<Vec<String>>, SelectionError> {
+        Ok(self
+            .selection(clipboard)?
+            .map(|selection| selection.mime_types.clone()))
+    }
+
+    fn receive(
+        &self,
+        clipboard: ClipboardType,
+        mime: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<u8>, SelectionError> {
+        let Some(selection) = self.selection(clipboard)? else {
+            return Ok(Vec::new());
+        };
+
+        let (reader, writer) = std::io::pipe()?;
+        selection.offer.receive(mime.to_string(), writer.as_fd());
+        self.connection.flush().map_err(wayland_error)?;
+        // The owner writes into its own copy; ours would keep the pipe open
+        drop(writer);
+
+        read_pipe(reader, limit, self.timeout)
+    }
+}
+
+/// List the names of the compositor's seats
+pub(crate) fn seat_names() -> Result<Vec<String>, SelectionError> {
+    let connection = Connection::connect_to_env().map_err(super::display_unreachable)?;
+    let (globals, mut queue) = registry_queue_init::<State>(&connection).map_err(wayland_error)?;
+
+    let mut state = State::default();
+    bind_seats(&globals, &queue.handle(), &mut state)?;
+    queue.roundtrip(&mut state).map_err(wayland_error)?;
+
+    Ok(state
+        .seats
+        .into_iter()
+        .filter_map(|seat| seat.name)
+        .collect())
+}
+
+/// Bind every seat the compositor announced
+///
+/// Seats are told apart by the order they were announced in, so seats
+/// without a name work too.
+fn bind_seats(
+    globals: &GlobalList,
+    handle: &QueueHandle<State>,
+    state: &mut State,
+) -> Result<Vec<WlSeat>, SelectionError> {
+    let seats: Vec<(u32, u32)> = globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|global| global.interface == WlSeat::interface().name)
+            .map(|global| (global.name, global.version))
+            .collect()
+    });
+    if seats.is_empty() {
+        return Err(SelectionError::ClipboardError(
+            "The compositor announced no seats".to_string(),
+        ));
+    }
+
+    Ok(seats
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, version))| {
+            state.seats.push(SeatSelections::default());
+            globals.registry().bind(
+                name,
+                version.min(WlSeat::interface().version),
+                handle,
+                index,
+            )
+        })
+        .collect())
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _state: &mut Self,
+        _registry: &WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlSeat, usize> for State {
+    fn event(
+        state: &mut Self,
+        _seat: &WlSeat,
+        event: wl_seat::Event,
+        index: &usize,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Name { name } = event {
+            state.seats[*index].name = Some(name);
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _manager: &ZwlrDataControlManagerV1,
+        _event: zwlr_data_control_manager_v1::Event,
+        _data: &(),
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        _device: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        seat: &usize,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+    ) {
+        use zwlr_data_control_device_v1::Event;
+
+        let mut take = |offer: Option<ZwlrDataControlOfferV1>| {
+            offer.map(|offer| Offer {
+                mime_types: state.offers.remove(&offer.id()).unwrap_or_default(),
+                offer,
+            })
+        };
+        match event {
+            Event::DataOffer { id } => {
+                state.offers.insert(id.id(), Vec::new());
+            }
+            Event::Selection { id } => {
+                let offer = take(id);
+                state.seats[*seat].regular = offer;
+            }
+            Event::PrimarySelection { id } => {
+                let offer = take(id);
+                state.seats[*seat].primary = offer;
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        offer: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        _data: &(),
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            if let Some(mime_types) = state.offers.get_mut(&offer.id()) {
+                mime_types.push(mime_type);
+            }
+        }
+    }
+}
+
+fn wayland_error(err: impl std::error::Error + Send + Sync + 'static) -> SelectionError {
+    SelectionError::platform(ErrorKind::Clipboard, "Wayland error", err)
+}
@@ -0,0 +1,52 @@
+//! Selected files of file managers, without simulated keystrokes
+//!
+//! None of the common file managers publishes its selection over D-Bus,
+//! but KDE applications export their menu actions there, so Dolphin can be
+//! told to copy its selected items without a keystroke reaching it.
+//! Nautilus and Nemo export nothing comparable and are read through the
+//! clipboard like any other application.
+
+use zbus::blocking::Connection;
+
+use crate::SelectionError;
+
+/// Path of the Copy action of Dolphin's first window
+const DOLPHIN_COPY_ACTION: &str = "/dolphin/Dolphin_1/actions/edit_copy";
+const ACTION_INTERFACE: &str = "org.qtproject.Qt.QAction";
+
+/// A file manager whose selection can be copied over D-Bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileManager {
+    Dolphin,
+}
+
+impl FileManager {
+    /// Find the file manager with the window class `class`
+    pub(crate) fn from_class(class: &str) -> Option<Self> {
+        class
+            .eq_ignore_ascii_case("dolphin")
+            .then_some(FileManager::Dolphin)
+    }
+
+    /// Make the instance with process id `pid` copy its selected items
+    pub(crate) fn copy(self, pid: u32) -> Result<(), SelectionError> {
+        let error = |err: zbus::Error| {
+            SelectionError::ClipboardError(format!("Failed to ask {:?} to copy: {}", self, err))
+        };
+
+        let connection = Connection::session().map_err(error)?;
+        let (name, action) = match self {
+            FileManager::Dolphin => (format!("org.kde.dolphin-{}", pid), DOLPHIN_COPY_ACTION),
+        };
+        connection
+            .call_method(
+                Some(name.as_str()),
+                action,
+                Some(ACTION_INTERFACE),
+                "trigger",
+                &(),
+            )
+            .map_err(error)?;
+        Ok(())
+    }
+}
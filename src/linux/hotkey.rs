@@ -0,0 +1,591 @@
+//! Global hotkey through a key grab on X11 and the GlobalShortcuts portal
+//! on Wayland
+//!
+//! On X11 the chord is grabbed on the root window, so the X server hands it
+//! to the listener instead of the focused window. Wayland gives clients no
+//! way to see keys meant for others; desktops implementing
+//! `org.freedesktop.portal.GlobalShortcuts` (KDE, GNOME 48 and later,
+//! Hyprland) bind the shortcut on the application's behalf, after asking
+//! the user to confirm it.
+//!
+//! Either way the listener only forwards presses to a second thread running
+//! the captures. Captures never simulate input: the shortcut's modifiers
+//! may still be held, and a simulated Ctrl+C would reach the application
+//! as a different chord.
+
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use x11rb::connection::Connection as _;
+use x11rb::protocol::xkb::{self, ConnectionExt as _, PerClientFlag};
+use x11rb::protocol::xproto::{
+    AtomEnum, ClientMessageEvent, ConnectionExt as _, CreateWindowAux, EventMask, GrabMode,
+    ModMask, Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::export::serde::Serialize;
+use zbus::message::Type as MessageType;
+use zbus::zvariant::{DynamicType, ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::MatchRule;
+
+use super::{detect_display_server, DisplayServer, LinuxSelector};
+use crate::hotkey::{Hotkey, Modifiers};
+use crate::watcher::WatcherBackend;
+use crate::{ErrorKind, Selection, SelectionError, SelectionOptions, Selector};
+
+/// Keys by the character on them in the US layout, with their evdev code
+/// and the name of their keysym
+const KEYS: [(char, u8, &str); 48] = [
+    ('1', 2, "1"),
+    ('2', 3, "2"),
+    ('3', 4, "3"),
+    ('4', 5, "4"),
+    ('5', 6, "5"),
+    ('6', 7, "6"),
+    ('7', 8, "7"),
+    ('8', 9, "8"),
+    ('9', 10, "9"),
+    ('0', 11, "0"),
+    ('-', 12, "minus"),
+    ('=', 13, "equal"),
+    ('q', 16, "q"),
+    ('w', 17, "w"),
+    ('e', 18, "e"),
+    ('r', 19, "r"),
+    ('t', 20, "t"),
+    ('y', 21, "y"),
+    ('u', 22, "u"),
+    ('i', 23, "i"),
+    ('o', 24, "o"),
+    ('p', 25, "p"),
+    ('[', 26, "bracketleft"),
+    (']', 27, "bracketright"),
+    ('a', 30, "a"),
+    ('s', 31, "s"),
+    ('d', 32, "d"),
+    ('f', 33, "f"),
+    ('g', 34, "g"),
+    ('h', 35, "h"),
+    ('j', 36, "j"),
+    ('k', 37, "k"),
+    ('l', 38, "l"),
+    (';', 39, "semicolon"),
+    ('\'', 40, "apostrophe"),
+    ('`', 41, "grave"),
+    ('\\', 43, "backslash"),
+    ('z', 44, "z"),
+    ('x', 45, "x"),
+    ('c', 46, "c"),
+    ('v', 47, "v"),
+    ('b', 48, "b"),
+    ('n', 49, "n"),
+    ('m', 50, "m"),
+    (',', 51, "comma"),
+    ('.', 52, "period"),
+    ('/', 53, "slash"),
+    (' ', 57, "space"),
+];
+
+/// X keycodes are evdev codes shifted by this
+const X11_KEYCODE_OFFSET: u8 = 8;
+
+/// Lock modifiers a grab has to ignore: Caps Lock and, usually, Num Lock
+const IGNORED_MODIFIERS: [u16; 4] = [0, 1 << 1, 1 << 4, (1 << 1) | (1 << 4)];
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const GLOBAL_SHORTCUTS_INTERFACE: &str = "org.freedesktop.portal.GlobalShortcuts";
+const REQUEST_INTERFACE: &str = "org.freedesktop.portal.Request";
+const SESSION_INTERFACE: &str = "org.freedesktop.portal.Session";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// ID of the one shortcut bound in the portal session
+const SHORTCUT_ID: &str = "capture-selection";
+
+/// Start capturing the selection whenever `hotkey` is pressed
+pub(crate) fn start_hotkey(
+    hotkey: Hotkey,
+    options: SelectionOptions,
+    sender: Sender<Result<Selection, SelectionError>>,
+) -> Result<Box<dyn WatcherBackend>, SelectionError> {
+    let (_, code, keysym) = KEYS
+        .iter()
+        .find(|(key, _, _)| *key == hotkey.key)
+        .copied()
+        .ok_or_else(|| SelectionError::Other(format!("Unsupported hotkey key {:?}", hotkey.key)))?;
+
+    let (trigger, triggered) = mpsc::channel::<()>();
+    let listener = match detect_display_server()? {
+        DisplayServer::X11 => {
+            GrabBackend::spawn(code + X11_KEYCODE_OFFSET, hotkey.modifiers, trigger)
+                .map(|backend| Box::new(backend) as Box<dyn WatcherBackend>)
+        }
+        DisplayServer::Wayland => {
+            PortalBackend::spawn(&portal_trigger(hotkey.modifiers, keysym), trigger)
+                .map(|backend| Box::new(backend) as Box<dyn WatcherBackend>)
+        }
+    }?;
+
+    let capture_thread = thread::spawn(move || {
+        let selector = LinuxSelector::with_options(SelectionOptions {
+            copy_fallback: false,
+            synthetic_input: false,
+            ..options
+        });
+        for () in triggered {
+            if sender.send(selector.get_selection()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Box::new(HotkeyBackend {
+        listener: Some(listener),
+        capture_thread: Some(capture_thread),
+    }))
+}
+
+/// Whether a global hotkey can be registered in this session
+pub(crate) fn is_available(server: DisplayServer) -> bool {
+    match server {
+        DisplayServer::X11 => true,
+        DisplayServer::Wayland => Connection::session()
+            .and_then(|connection| {
+                connection.call_method(
+                    Some(PORTAL_NAME),
+                    PORTAL_PATH,
+                    Some(PROPERTIES_INTERFACE),
+                    "Get",
+                    &(GLOBAL_SHORTCUTS_INTERFACE, "version"),
+                )
+            })
+            .is_ok(),
+    }
+}
+
+/// The listener thread and the capture thread it triggers
+struct HotkeyBackend {
+    listener: Option<Box<dyn WatcherBackend>>,
+    capture_thread: Option<JoinHandle<()>>,
+}
+
+impl WatcherBackend for HotkeyBackend {
+    fn stop(mut self: Box<Self>) {
+        if let Some(listener) = self.listener.take() {
+            listener.stop();
+        }
+        // The capture thread ends once the listener and its trigger are gone
+        if let Some(handle) = self.capture_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Event loop thread of a passive key grab on the root window
+struct GrabBackend {
+    connection: Arc<RustConnection>,
+    /// Window the thread is woken up through
+    window: Window,
+    stopping: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl GrabBackend {
+    fn spawn(
+        keycode: u8,
+        modifiers: Modifiers,
+        trigger: Sender<()>,
+    ) -> Result<Self, SelectionError> {
+        let (connection, screen) = RustConnection::connect(None).map_err(x11_error)?;
+        let root = connection.setup().roots[screen].root;
+        let window = connection.generate_id().map_err(x11_error)?;
+        connection
+            .create_window(
+                0,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                0,
+                &CreateWindowAux::new(),
+            )
+            .map_err(x11_error)?;
+
+        // Without detectable autorepeat a held key sends a release before
+        // every repeated press, and each would look like a new press
+        let detectable_autorepeat = connection
+            .xkb_use_extension(1, 0)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?
+            .supported
+            && connection
+                .xkb_per_client_flags(
+                    xkb::ID::USE_CORE_KBD.into(),
+                    PerClientFlag::DETECTABLE_AUTO_REPEAT,
+                    PerClientFlag::DETECTABLE_AUTO_REPEAT,
+                    0u32.into(),
+                    0u32.into(),
+                    0u32.into(),
+                )
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?
+                .supported
+                .contains(PerClientFlag::DETECTABLE_AUTO_REPEAT);
+        if !detectable_autorepeat {
+            debug!("No detectable autorepeat, holding the hotkey captures repeatedly");
+        }
+
+        let mask = modifier_mask(modifiers);
+        for ignored in IGNORED_MODIFIERS {
+            connection
+                .grab_key(
+                    false,
+                    root,
+                    mask | ModMask::from(ignored),
+                    keycode,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )
+                .map_err(x11_error)?
+                .check()
+                .map_err(|err| {
+                    SelectionError::Other(format!(
+                        "The hotkey is already taken by another application: {}",
+                        err
+                    ))
+                })?;
+        }
+        connection.flush().map_err(x11_error)?;
+        info!("Listening for the hotkey");
+
+        let connection = Arc::new(connection);
+        let stopping = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let connection = Arc::clone(&connection);
+            let stopping = Arc::clone(&stopping);
+            thread::spawn(move || {
+                let mut held = false;
+                loop {
+                    let event = match connection.wait_for_event() {
+                        Ok(event) => event,
+                        Err(err) => return warn!("Hotkey grab lost the X server: {}", err),
+                    };
+                    if stopping.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    match event {
+                        // Other keys pressed while the grab is active come
+                        // here too
+                        Event::KeyPress(event) if event.detail == keycode => {
+                            if !held && trigger.send(()).is_err() {
+                                break;
+                            }
+                            held = detectable_autorepeat;
+                        }
+                        Event::KeyRelease(event) if event.detail == keycode => held = false,
+                        _ => {}
+                    }
+                }
+                debug!("Hotkey grab removed");
+            })
+        };
+
+        Ok(Self {
+            connection,
+            window,
+            stopping,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl WatcherBackend for GrabBackend {
+    fn stop(mut self: Box<Self>) {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // The thread blocks waiting for events, so send it one to wake it up
+        let wake_up = ClientMessageEvent::new(32, self.window, AtomEnum::NONE, [0; 5]);
+        let _ = self
+            .connection
+            .send_event(false, self.window, EventMask::NO_EVENT, wake_up);
+        let _ = self.connection.flush();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        // Closing the connection releases the grabs
+    }
+}
+
+fn modifier_mask(modifiers: Modifiers) -> ModMask {
+    [
+        (modifiers.control, ModMask::CONTROL),
+        (modifiers.alt, ModMask::M1),
+        (modifiers.shift, ModMask::SHIFT),
+        (modifiers.meta, ModMask::M4),
+    ]
+    .into_iter()
+    .filter(|(held, _)| *held)
+    .fold(ModMask::from(0u16), |mask, (_, modifier)| mask | modifier)
+}
+
+fn x11_error(err: impl std::error::Error + Send + Sync + 'static) -> SelectionError {
+    SelectionError::platform(ErrorKind::Clipboard, "X11 error", err)
+}
+
+/// Thread receiving activations of a shortcut bound through the portal
+struct PortalBackend {
+    connection: Connection,
+    session: OwnedObjectPath,
+    stopping: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PortalBackend {
+    /// Bind `trigger` in a new portal session
+    ///
+    /// Blocks while the desktop asks the user to confirm the shortcut; the
+    /// user may pick a different one there.
+    fn spawn(trigger_chord: &str, trigger: Sender<()>) -> Result<Self, SelectionError> {
+        let connection = Connection::session().map_err(portal_error)?;
+
+        let session_token = token();
+        let results = request(
+            &connection,
+            "CreateSession",
+            &(HashMap::from([
+                ("handle_token", Value::from(token())),
+                ("session_handle_token", Value::from(session_token.as_str())),
+            ]),),
+        )?;
+        let session = results
+            .get("session_handle")
+            .and_then(|handle| <&str>::try_from(handle).ok())
+            .and_then(|handle| OwnedObjectPath::try_from(handle).ok())
+            .ok_or_else(|| {
+                SelectionError::Other("The portal created no shortcuts session".to_string())
+            })?;
+
+        // Subscribe before binding, so no activation can be missed
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface(GLOBAL_SHORTCUTS_INTERFACE)
+            .and_then(|rule| rule.member("Activated"))
+            .map_err(portal_error)?
+            .build();
+        let activations =
+            MessageIterator::for_match_rule(rule, &connection, None).map_err(portal_error)?;
+
+        let shortcut = (
+            SHORTCUT_ID,
+            HashMap::from([
+                ("description", Value::from("Capture the selection")),
+                ("preferred_trigger", Value::from(trigger_chord)),
+            ]),
+        );
+        let results = request(
+            &connection,
+            "BindShortcuts",
+            &(
+                &session,
+                vec![shortcut],
+                "",
+                HashMap::from([("handle_token", Value::from(token()))]),
+            ),
+        )?;
+        debug!("Portal bound the hotkey: {:?}", results.get("shortcuts"));
+        info!("Listening for the hotkey");
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stopping = Arc::clone(&stopping);
+            let session = session.clone();
+            thread::spawn(move || {
+                for message in activations {
+                    if stopping.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(message) = message else { continue };
+                    let activated: Result<
+                        (OwnedObjectPath, String, u64, HashMap<String, OwnedValue>),
+                        _,
+                    > = message.body().deserialize();
+                    match activated {
+                        Ok((activated, id, _, _)) if activated == session && id == SHORTCUT_ID => {
+                            if trigger.send(()).is_err() {
+                                break;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(err) => debug!("Ignoring a malformed activation: {}", err),
+                    }
+                }
+                debug!("Portal hotkey listener stopped");
+            })
+        };
+
+        Ok(Self {
+            connection,
+            session,
+            stopping,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl WatcherBackend for PortalBackend {
+    fn stop(mut self: Box<Self>) {
+        self.stopping.store(true, Ordering::SeqCst);
+        let _ = self.connection.call_method(
+            Some(PORTAL_NAME),
+            &self.session,
+            Some(SESSION_INTERFACE),
+            "Close",
+            &(),
+        );
+
+        // The thread blocks waiting for activations, so send it one, to
+        // this connection only, to wake it up
+        if let Some(name) = self.connection.unique_name() {
+            let wake_up = (&self.session, "", 0u64, HashMap::<&str, Value>::new());
+            let _ = self.connection.emit_signal(
+                Some(name.as_str()),
+                PORTAL_PATH,
+                GLOBAL_SHORTCUTS_INTERFACE,
+                "Activated",
+                &wake_up,
+            );
+        }
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Call a portal method answering through a request object, and wait for
+/// its results
+///
+/// The body's last member holds the options, including the handle token
+/// the request object's path is made from.
+fn request<B>(
+    connection: &Connection,
+    method: &str,
+    body: &B,
+) -> Result<HashMap<String, OwnedValue>, SelectionError>
+where
+    B: Serialize + DynamicType,
+{
+    // The request's path is only known from the reply, which may come after
+    // the response, so listen to every request of this connection
+    let sender = connection
+        .unique_name()
+        .map(|name| name.trim_start_matches(':').replace('.', "_"))
+        .unwrap_or_default();
+    let requests = format!("{}/request/{}", PORTAL_PATH, sender);
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(REQUEST_INTERFACE)
+        .and_then(|rule| rule.member("Response"))
+        .and_then(|rule| rule.path_namespace(requests.as_str()))
+        .map_err(portal_error)?
+        .build();
+    let mut responses =
+        MessageIterator::for_match_rule(rule, connection, None).map_err(portal_error)?;
+
+    let handle: OwnedObjectPath = connection
+        .call_method(
+            Some(PORTAL_NAME),
+            PORTAL_PATH,
+            Some(GLOBAL_SHORTCUTS_INTERFACE),
+            method,
+            body,
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(portal_error)?;
+
+    loop {
+        let message = responses
+            .next()
+            .ok_or_else(|| SelectionError::Other("The portal didn't answer".to_string()))?
+            .map_err(portal_error)?;
+        if message.header().path().map(ObjectPath::as_str) != Some(handle.as_str()) {
+            continue;
+        }
+
+        let (response, results): (u32, HashMap<String, OwnedValue>) =
+            message.body().deserialize().map_err(portal_error)?;
+        return match response {
+            0 => Ok(results),
+            1 => Err(SelectionError::PermissionDenied(
+                "The hotkey was declined".to_string(),
+            )),
+            _ => Err(SelectionError::Other(format!(
+                "The GlobalShortcuts portal failed to {}",
+                method
+            ))),
+        };
+    }
+}
+
+/// A handle token unique within the process
+fn token() -> String {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    format!("selectic_{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The trigger in the shortcut format of the XDG specification, e.g.
+/// `CTRL+SHIFT+c`
+fn portal_trigger(modifiers: Modifiers, keysym: &str) -> String {
+    [
+        (modifiers.control, "CTRL"),
+        (modifiers.alt, "ALT"),
+        (modifiers.shift, "SHIFT"),
+        (modifiers.meta, "LOGO"),
+    ]
+    .into_iter()
+    .filter(|(held, _)| *held)
+    .map(|(_, name)| name)
+    .chain([keysym])
+    .collect::<Vec<_>>()
+    .join("+")
+}
+
+fn portal_error(err: zbus::Error) -> SelectionError {
+    match err {
+        zbus::Error::MethodError(name, ..)
+            if name.as_str() == "org.freedesktop.DBus.Error.UnknownMethod"
+                || name.as_str() == "org.freedesktop.DBus.Error.UnknownInterface"
+                || name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown" =>
+        {
+            SelectionError::MethodUnavailable(
+                "this desktop has no GlobalShortcuts portal for Wayland hotkeys".to_string(),
+            )
+        }
+        err => SelectionError::platform(ErrorKind::Other, "GlobalShortcuts portal error", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portal_trigger() {
+        let modifiers = Modifiers::new().control().shift();
+        assert_eq!(portal_trigger(modifiers, "c"), "CTRL+SHIFT+c");
+        assert_eq!(portal_trigger(Modifiers::new(), "comma"), "comma");
+    }
+}
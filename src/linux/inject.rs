@@ -0,0 +1,185 @@
+//! Simulated copy and paste shortcuts on Linux
+//!
+//! X11 takes keystrokes through XTEST. Wayland has no portable way to inject
+//! them: wlroots compositors offer a virtual keyboard protocol, used directly
+//! with the `virtual-keyboard` feature, and `wtype` (the same protocol) or
+//! `ydotool` (writing to uinput) may be installed.
+
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use log::debug;
+use std::path::Path;
+use std::process::Command;
+#[cfg(feature = "virtual-keyboard")]
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+#[cfg(feature = "virtual-keyboard")]
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+#[cfg(feature = "virtual-keyboard")]
+use wayland_client::{Connection, Dispatch, QueueHandle};
+
+use crate::{SelectionError, WaylandInjector};
+
+/// Global announced by compositors supporting the virtual keyboard protocol
+#[cfg(feature = "virtual-keyboard")]
+const VIRTUAL_KEYBOARD_MANAGER: &str = "zwp_virtual_keyboard_manager_v1";
+
+/// Linux input event codes (`KEY_LEFTCTRL`, `KEY_C`, `KEY_V`) used by
+/// ydotool
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_C: u16 = 46;
+const KEY_V: u16 = 47;
+
+/// Find the Wayland injector to use for `preference`, if it's available
+pub(crate) fn wayland_injector(preference: WaylandInjector) -> Option<WaylandInjector> {
+    let available = |injector| match injector {
+        WaylandInjector::VirtualKeyboard => has_virtual_keyboard(),
+        WaylandInjector::Wtype => in_path("wtype"),
+        WaylandInjector::Ydotool => in_path("ydotool"),
+        WaylandInjector::Auto => false,
+    };
+
+    match preference {
+        WaylandInjector::Auto => [
+            WaylandInjector::VirtualKeyboard,
+            WaylandInjector::Wtype,
+            WaylandInjector::Ydotool,
+        ]
+        .into_iter()
+        .find(|injector| available(*injector)),
+        injector => available(injector).then_some(injector),
+    }
+}
+
+/// Post Ctrl+C through `injector` on Wayland
+pub(crate) fn post_wayland_copy(injector: WaylandInjector) -> Result<(), SelectionError> {
+    post_wayland_chord(injector, 'c', KEY_C)
+}
+
+/// Post Ctrl+V through `injector` on Wayland
+pub(crate) fn post_wayland_paste(injector: WaylandInjector) -> Result<(), SelectionError> {
+    post_wayland_chord(injector, 'v', KEY_V)
+}
+
+/// Post Ctrl+C with enigo, through XTEST or the virtual keyboard protocol
+pub(crate) fn post_copy() -> Result<(), SelectionError> {
+    post_chord('c')
+}
+
+/// Post Ctrl+V with enigo, as `post_copy` does
+pub(crate) fn post_paste() -> Result<(), SelectionError> {
+    post_chord('v')
+}
+
+/// Post Ctrl and `key`, whose input event code is `code`
+fn post_wayland_chord(
+    injector: WaylandInjector,
+    key: char,
+    code: u16,
+) -> Result<(), SelectionError> {
+    match injector {
+        WaylandInjector::Wtype => run("wtype", &["-M", "ctrl", &key.to_string(), "-m", "ctrl"]),
+        WaylandInjector::Ydotool => {
+            let sequence = [
+                format!("{}:1", KEY_LEFTCTRL),
+                format!("{}:1", code),
+                format!("{}:0", code),
+                format!("{}:0", KEY_LEFTCTRL),
+            ];
+            let mut args = vec!["key"];
+            args.extend(sequence.iter().map(String::as_str));
+            run("ydotool", &args)
+        }
+        WaylandInjector::VirtualKeyboard | WaylandInjector::Auto => post_chord(key),
+    }
+}
+
+fn post_chord(key: char) -> Result<(), SelectionError> {
+    let mut enigo = Enigo::new(&Settings::default()).map_err(|err| {
+        SelectionError::Other(format!("Failed to create Enigo instance: {}", err))
+    })?;
+    let chord = format!("Ctrl+{}", key.to_ascii_uppercase());
+    let key_error = |err| SelectionError::Other(format!("Failed to simulate {}: {}", chord, err));
+
+    // Modifiers still held from the shortcut that triggered the capture
+    // would turn the shortcut into another chord
+    for modifier in [Key::Shift, Key::Alt, Key::Meta] {
+        enigo.key(modifier, Direction::Release).map_err(key_error)?;
+    }
+
+    enigo
+        .key(Key::Control, Direction::Press)
+        .map_err(key_error)?;
+    let result = enigo.key(Key::Unicode(key), Direction::Click);
+    // Never leave Ctrl held down
+    enigo
+        .key(Key::Control, Direction::Release)
+        .map_err(key_error)?;
+    result.map_err(key_error)?;
+
+    debug!("Posted {}", chord);
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), SelectionError> {
+    let status = Command::new(program).args(args).status()?;
+    if !status.success() {
+        return Err(SelectionError::Other(format!(
+            "{} failed with {}",
+            program, status
+        )));
+    }
+    debug!("Posted a shortcut with {}", program);
+    Ok(())
+}
+
+pub(crate) fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| is_executable(&dir.join(program)))
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Registry state for listing the compositor's globals
+#[cfg(feature = "virtual-keyboard")]
+struct Globals;
+
+#[cfg(feature = "virtual-keyboard")]
+impl Dispatch<WlRegistry, GlobalListContents> for Globals {
+    fn event(
+        _state: &mut Self,
+        _registry: &WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+/// Check whether the compositor supports the virtual keyboard protocol
+///
+/// GNOME doesn't; wlroots-based compositors (Sway, Hyprland) do.
+#[cfg(feature = "virtual-keyboard")]
+fn has_virtual_keyboard() -> bool {
+    let Ok(connection) = Connection::connect_to_env() else {
+        return false;
+    };
+    let Ok((globals, _queue)) = registry_queue_init::<Globals>(&connection) else {
+        return false;
+    };
+
+    globals.contents().with_list(|list| {
+        list.iter()
+            .any(|global| global.interface == VIRTUAL_KEYBOARD_MANAGER)
+    })
+}
+
+#[cfg(not(feature = "virtual-keyboard"))]
+fn has_virtual_keyboard() -> bool {
+    false
+}
@@ -0,0 +1,57 @@
+//! The clipboard through KDE's Klipper over D-Bus
+//!
+//! Klipper serves the clipboard to anything on the session bus, which keeps
+//! working where the display protocols can't be used directly, such as from
+//! background services without a display connection on Plasma.
+
+use zbus::blocking::Connection;
+
+use crate::{ErrorKind, SelectionError};
+
+const KLIPPER_NAME: &str = "org.kde.klipper";
+const KLIPPER_PATH: &str = "/klipper";
+const KLIPPER_INTERFACE: &str = "org.kde.klipper.klipper";
+
+const DBUS_NAME: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+
+/// Read the current clipboard text from Klipper
+///
+/// Returns `None` without trying when Klipper isn't on the session bus.
+pub(crate) fn clipboard_contents() -> Result<Option<String>, SelectionError> {
+    let connection = Connection::session().map_err(dbus_error)?;
+
+    let running: bool = connection
+        .call_method(
+            Some(DBUS_NAME),
+            DBUS_PATH,
+            Some(DBUS_NAME),
+            "NameHasOwner",
+            &(KLIPPER_NAME,),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(dbus_error)?;
+    if !running {
+        return Ok(None);
+    }
+
+    connection
+        .call_method(
+            Some(KLIPPER_NAME),
+            KLIPPER_PATH,
+            Some(KLIPPER_INTERFACE),
+            "getClipboardContents",
+            &(),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map(Some)
+        .map_err(dbus_error)
+}
+
+fn dbus_error(err: zbus::Error) -> SelectionError {
+    SelectionError::platform(
+        ErrorKind::Clipboard,
+        "Failed to ask Klipper for the clipboard",
+        err,
+    )
+}
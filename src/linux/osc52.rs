@@ -0,0 +1,176 @@
+//! Reading selections from the terminal through OSC 52
+//!
+//! Over SSH there's no display server, but terminal emulators answering
+//! the `OSC 52` query send their clipboard, and some (kitty, foot) the
+//! primary selection, back as base64 on the terminal's input. Most only
+//! answer once reading is allowed in their settings, and say nothing
+//! otherwise. Only used with `SelectionOptions::osc52`.
+
+use log::debug;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::time::Duration;
+
+use super::wayland::wait_readable;
+use crate::base64;
+use crate::privacy::redact;
+use crate::{Selection, SelectionError, SelectionOptions, SelectionSource};
+
+/// Bytes the reply's payload is framed by, besides its terminator
+const REPLY_PREFIX: &[u8] = b"\x1b]52;";
+
+/// Whether stdin and stdout are a terminal that could be asked
+pub(crate) fn is_terminal() -> bool {
+    // SAFETY: isatty only inspects the descriptors
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Ask the terminal for a selection and decode its reply
+pub(crate) fn read_selection(
+    source: SelectionSource,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let selection = match source {
+        SelectionSource::Primary => 'p',
+        SelectionSource::Clipboard => 'c',
+    };
+
+    let stdin = io::stdin();
+    let _raw = RawMode::enable(stdin.as_fd().as_raw_fd())?;
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;{};?\x07", selection)?;
+    stdout.flush()?;
+
+    // Base64 takes four bytes for every three
+    let limit = options.max_bytes.map(|max| (max + 1).div_ceil(3) * 4);
+    let reply = read_reply(stdin.lock(), limit, options.selection_timeout)?;
+    let mut text = Selection::new_text_bytes(decode_reply(&reply)?);
+    debug!("Read {} bytes through OSC 52", redact(text.data.len()));
+
+    if let Some(max_bytes) = options.max_bytes {
+        text.truncate_text(max_bytes);
+    }
+    Ok(text)
+}
+
+/// Read the reply up to its terminator, BEL or ST, keeping at most `limit`
+/// bytes of its payload
+///
+/// The rest is read all the same, lest it reach the program as input.
+fn read_reply(
+    mut input: impl Read + AsFd,
+    limit: Option<usize>,
+    timeout: Duration,
+) -> Result<Vec<u8>, SelectionError> {
+    let limit = limit.map_or(usize::MAX, |limit| limit + REPLY_PREFIX.len() + 2);
+    let mut reply = Vec::new();
+    let mut byte = [0];
+    let mut escaped = false;
+
+    loop {
+        if !wait_readable(input.as_fd(), timeout)? {
+            return Err(SelectionError::PermissionDenied(
+                "The terminal didn't answer the OSC 52 query; most terminals only let \
+                 programs read the clipboard once it's allowed in their settings (e.g. \
+                 kitty's clipboard_control, xterm's allowWindowOps)"
+                    .to_string(),
+            ));
+        }
+        match input.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+
+        match byte[0] {
+            b'\x07' => break,
+            b'\\' if escaped => {
+                if reply.last() == Some(&b'\x1b') {
+                    reply.pop();
+                }
+                break;
+            }
+            byte => {
+                escaped = byte == b'\x1b';
+                if reply.len() < limit {
+                    reply.push(byte);
+                }
+            }
+        }
+    }
+    Ok(reply)
+}
+
+/// Decode the payload of a reply such as `ESC ] 52 ; p ; aGk=`
+///
+/// A cut-off payload is decoded as far as it goes.
+fn decode_reply(reply: &[u8]) -> Result<Vec<u8>, SelectionError> {
+    let malformed =
+        || SelectionError::ClipboardError("The terminal's OSC 52 reply is malformed".to_string());
+    let payload = reply
+        .strip_prefix(REPLY_PREFIX)
+        .and_then(|rest| {
+            let separator = rest.iter().position(|&byte| byte == b';')?;
+            Some(&rest[separator + 1..])
+        })
+        .ok_or_else(malformed)?;
+    // Some terminals answer a refused query with the query itself
+    if payload == b"?" {
+        return Ok(Vec::new());
+    }
+
+    let complete = payload.len() - payload.len() % 4;
+    base64::decode(&payload[..complete]).ok_or_else(malformed)
+}
+
+/// The terminal switched to passing input through unechoed and unbuffered,
+/// until this is dropped
+struct RawMode {
+    fd: libc::c_int,
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable(fd: libc::c_int) -> Result<Self, SelectionError> {
+        // SAFETY: termios is plain data, filled in by tcgetattr
+        let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+        // SAFETY: `original` is a valid termios to write to
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid termios
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: `original` is the termios tcgetattr returned
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_reply() {
+        assert_eq!(decode_reply(b"\x1b]52;p;aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_reply(b"\x1b]52;c;aGk=").unwrap(), b"hi");
+        // Cut off by the size limit
+        assert_eq!(decode_reply(b"\x1b]52;c;aGVsbG8gd29").unwrap(), b"hello ");
+        assert!(decode_reply(b"\x1b]52;c;?").unwrap().is_empty());
+        assert!(decode_reply(b"\x1b]52;c;").unwrap().is_empty());
+        assert!(decode_reply(b"garbage").is_err());
+    }
+}
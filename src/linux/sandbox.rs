@@ -0,0 +1,95 @@
+//! Detection of Flatpak and Snap sandboxes
+//!
+//! Sandboxes pass the display environment variables through even when they
+//! don't expose the sockets behind them, so inside one the sockets are
+//! checked instead. The clipboard portal only serves applications in the
+//! foreground so far; once it lets background applications read the
+//! selection, it fits in where `blocked_error` is returned.
+
+use std::path::{Path, PathBuf};
+
+use crate::SelectionError;
+
+/// A sandbox the process runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sandbox {
+    Flatpak,
+    Snap,
+}
+
+impl Sandbox {
+    /// The sandbox the process runs in, if any
+    pub(crate) fn detect() -> Option<Self> {
+        if Path::new("/.flatpak-info").exists() {
+            Some(Sandbox::Flatpak)
+        } else if std::env::var_os("SNAP").is_some() {
+            Some(Sandbox::Snap)
+        } else {
+            None
+        }
+    }
+
+    /// Error for a sandbox exposing no display server, telling which
+    /// permissions are missing
+    pub(crate) fn blocked_error(self) -> SelectionError {
+        SelectionError::Sandboxed(
+            match self {
+                Sandbox::Flatpak => {
+                    "the Flatpak has no display socket; add --socket=wayland and \
+                     --socket=fallback-x11 to its finish-args"
+                }
+                Sandbox::Snap => {
+                    "the snap has no display socket; connect its wayland and x11 \
+                     interfaces (snap connect <snap>:wayland, <snap>:x11)"
+                }
+            }
+            .to_string(),
+        )
+    }
+}
+
+/// Whether the socket of the Wayland display `name` exists
+pub(crate) fn wayland_socket_exists(name: &str) -> bool {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return path.exists();
+    }
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .is_some_and(|runtime_dir| Path::new(&runtime_dir).join(path).exists())
+}
+
+/// Whether the X11 display `display` can be reached
+///
+/// Displays on another host are reached over TCP and assumed reachable.
+pub(crate) fn x11_socket_exists(display: &str) -> bool {
+    x11_socket_path(display).is_none_or(|path| path.exists())
+}
+
+/// Path of the local socket of an X11 display such as `:1.0`, `None` for a
+/// display on another host
+fn x11_socket_path(display: &str) -> Option<PathBuf> {
+    let (host, rest) = display.rsplit_once(':')?;
+    if !host.is_empty() && host != "unix" {
+        return None;
+    }
+    let number = rest.split('.').next().unwrap_or(rest);
+    Some(PathBuf::from(format!("/tmp/.X11-unix/X{}", number)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x11_socket_path() {
+        assert_eq!(
+            x11_socket_path(":1.0"),
+            Some(PathBuf::from("/tmp/.X11-unix/X1"))
+        );
+        assert_eq!(
+            x11_socket_path("unix:0"),
+            Some(PathBuf::from("/tmp/.X11-unix/X0"))
+        );
+        assert_eq!(x11_socket_path("localhost:10.0"), None);
+    }
+}
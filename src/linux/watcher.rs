@@ -0,0 +1,543 @@
+//! Selection watchers driven by owner-change events
+//!
+//! On X11 the watcher thread asks the server for `XFixesSelectionNotify`
+//! events on PRIMARY (and CLIPBOARD when watching the clipboard), so it
+//! sleeps until an owner changes instead of reading the selection on every
+//! poll. Applications re-assert PRIMARY as a drag-selection grows, so reads
+//! wait until no owner change came in for the debounce period.
+//!
+//! On Wayland the data control protocol (wlroots compositors such as Sway,
+//! Hyprland and river) announces every new offer to clients without focus.
+//! GNOME and KDE don't offer it for the primary selection, so there, and on
+//! X servers without XFIXES, the selection is polled instead;
+//! `Capabilities::selection_events` tells which one is used.
+
+use log::{debug, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::protocol::xfixes::{self, ConnectionExt as _, SelectionEventMask};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ClientMessageEvent, ConnectionExt as _, CreateWindowAux, EventMask, Window,
+    WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use wl_clipboard_rs::paste::Seat;
+use wl_clipboard_rs::watch::{self, CancelHandle, ClipboardEvent, Watcher};
+
+use super::{detect_display_server, trim_text, wayland, x11, DisplayServer, LinuxSelector};
+use crate::watcher::{Debouncer, PollingBackend, WatchSource, WatcherBackend, WatcherOptions};
+use crate::{
+    ContentType, ErrorKind, Selection, SelectionError, SelectionEvent, SelectionMethod,
+    SelectionOptions, SelectionSource, Selector,
+};
+
+/// How often pending events are checked while waiting out the debounce
+const DEBOUNCE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Start the watcher backends for Linux
+///
+/// On X11 selection and clipboard changes are reported from XFIXES events,
+/// and on Wayland from data control offers. Where those aren't available the
+/// primary selection is polled, which is a passive read.
+pub(crate) fn start_watcher(
+    options: &WatcherOptions,
+    sender: Sender<SelectionEvent>,
+) -> Result<Vec<Box<dyn WatcherBackend>>, SelectionError> {
+    let mut backends: Vec<Box<dyn WatcherBackend>> = Vec::new();
+    let mut poll_selection = options.watch_selection;
+
+    match detect_display_server() {
+        Ok(DisplayServer::X11) if options.watch_selection || options.watch_clipboard => {
+            match XfixesBackend::spawn(options, sender.clone()) {
+                Ok(backend) => {
+                    backends.push(Box::new(backend));
+                    poll_selection = false;
+                }
+                Err(err) => warn!("XFIXES watcher unavailable, polling instead: {}", err),
+            }
+        }
+        Ok(DisplayServer::Wayland) => {
+            if options.watch_selection {
+                match DataControlBackend::spawn(options, WatchSource::Selection, sender.clone()) {
+                    Ok(backend) => {
+                        backends.push(Box::new(backend));
+                        poll_selection = false;
+                    }
+                    Err(err) => debug!("Primary selection offers unavailable, polling: {}", err),
+                }
+            }
+            if options.watch_clipboard {
+                match DataControlBackend::spawn(options, WatchSource::Clipboard, sender.clone()) {
+                    Ok(backend) => backends.push(Box::new(backend)),
+                    Err(err) => warn!("Clipboard changes can't be watched: {}", err),
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if poll_selection {
+        let selector = LinuxSelector::new();
+        backends.push(Box::new(PollingBackend::spawn(
+            options,
+            WatchSource::Selection,
+            sender,
+            move || {
+                selector
+                    .get_selection()
+                    .ok()
+                    .map(|selection| (selection, None))
+            },
+        )));
+    }
+
+    Ok(backends)
+}
+
+/// Event loop thread receiving selection owner changes
+struct XfixesBackend {
+    connection: Arc<RustConnection>,
+    window: Window,
+    stopping: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// A watched selection and what has been seen of it
+struct Watched {
+    atom: Atom,
+    source: WatchSource,
+    /// When the owner last changed, while a read is due
+    changed: Option<Instant>,
+    debouncer: Debouncer,
+}
+
+impl XfixesBackend {
+    fn spawn(
+        options: &WatcherOptions,
+        sender: Sender<SelectionEvent>,
+    ) -> Result<Self, SelectionError> {
+        let (connection, window, watched) = listen(options)?;
+        let connection = Arc::new(connection);
+        let stopping = Arc::new(AtomicBool::new(false));
+        let debounce = options.debounce;
+        let preferred = options.preferred.clone();
+
+        let handle = {
+            let connection = Arc::clone(&connection);
+            let stopping = Arc::clone(&stopping);
+            thread::spawn(move || {
+                EventLoop {
+                    connection: &connection,
+                    stopping: &stopping,
+                    selector: LinuxSelector::new(),
+                    sender,
+                    watched,
+                    debounce,
+                    preferred,
+                }
+                .run();
+                debug!("XFIXES watcher stopped");
+            })
+        };
+
+        Ok(Self {
+            connection,
+            window,
+            stopping,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl WatcherBackend for XfixesBackend {
+    fn stop(mut self: Box<Self>) {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // The thread blocks waiting for events, so send it one to wake it up
+        let wake_up = ClientMessageEvent::new(32, self.window, AtomEnum::NONE, [0; 5]);
+        let _ = self
+            .connection
+            .send_event(false, self.window, EventMask::NO_EVENT, wake_up);
+        let _ = self.connection.flush();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Connect to the X server and select owner-change events on the watched
+/// selections
+fn listen(
+    options: &WatcherOptions,
+) -> Result<(RustConnection, Window, Vec<Watched>), SelectionError> {
+    let (connection, screen) = RustConnection::connect(None).map_err(x11_error)?;
+    let root = connection.setup().roots[screen].root;
+    let window = connection.generate_id().map_err(x11_error)?;
+    connection
+        .create_window(
+            0,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .map_err(x11_error)?;
+
+    // The extension has to be initialized before its requests are used
+    connection
+        .xfixes_query_version(5, 0)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+
+    let mut watched = Vec::new();
+    if options.watch_selection {
+        watched.push((Atom::from(AtomEnum::PRIMARY), WatchSource::Selection));
+    }
+    if options.watch_clipboard {
+        let clipboard = connection
+            .intern_atom(false, b"CLIPBOARD")
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?
+            .atom;
+        watched.push((clipboard, WatchSource::Clipboard));
+    }
+
+    let mask = SelectionEventMask::SET_SELECTION_OWNER
+        | SelectionEventMask::SELECTION_WINDOW_DESTROY
+        | SelectionEventMask::SELECTION_CLIENT_CLOSE;
+    for (atom, _) in &watched {
+        connection
+            .xfixes_select_selection_input(window, *atom, mask)
+            .map_err(x11_error)?;
+    }
+    connection.flush().map_err(x11_error)?;
+
+    let watched = watched
+        .into_iter()
+        .map(|(atom, source)| Watched {
+            atom,
+            source,
+            changed: None,
+            // Duplicates are filtered here; owner changes do the debouncing
+            debouncer: Debouncer::new(Duration::ZERO),
+        })
+        .collect();
+
+    Ok((connection, window, watched))
+}
+
+/// State of the watcher thread
+struct EventLoop<'a> {
+    connection: &'a RustConnection,
+    stopping: &'a AtomicBool,
+    selector: LinuxSelector,
+    sender: Sender<SelectionEvent>,
+    watched: Vec<Watched>,
+    debounce: Duration,
+    preferred: Vec<String>,
+}
+
+impl EventLoop<'_> {
+    /// Handle events until the backend is stopped or the receiver is gone
+    fn run(&mut self) {
+        loop {
+            // Block while no read is due, otherwise poll until one is
+            let result = if self.watched.iter().any(|watched| watched.changed.is_some()) {
+                self.connection.poll_for_event()
+            } else {
+                self.connection.wait_for_event().map(Some)
+            };
+            let event = match result {
+                Ok(event) => event,
+                Err(err) => return warn!("XFIXES watcher lost the X server: {}", err),
+            };
+
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+
+            match event {
+                Some(Event::XfixesSelectionNotify(xfixes::SelectionNotifyEvent {
+                    selection,
+                    ..
+                })) => {
+                    let now = Instant::now();
+                    for watched in &mut self.watched {
+                        if watched.atom == selection {
+                            watched.changed = Some(now);
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    if !self.report_settled() {
+                        return;
+                    }
+                    thread::sleep(DEBOUNCE_POLL_INTERVAL);
+                }
+            }
+        }
+    }
+
+    /// Read and report the selections whose owner stopped changing,
+    /// returning `false` once the receiver is gone
+    fn report_settled(&mut self) -> bool {
+        let now = Instant::now();
+        for index in 0..self.watched.len() {
+            let watched = &self.watched[index];
+            let settled = watched
+                .changed
+                .is_some_and(|changed| now.duration_since(changed) >= self.debounce);
+            if !settled {
+                continue;
+            }
+
+            let source = watched.source;
+            let selection = match source {
+                WatchSource::Selection => self.selector.read_selection(SelectionSource::Primary),
+                WatchSource::Clipboard => read_clipboard(&self.selector, &self.preferred),
+            };
+            let current = match selection {
+                Ok(selection) => {
+                    let app = selection.metadata.source_app.clone();
+                    Some((selection, app))
+                }
+                Err(err) => {
+                    debug!(
+                        "Reading the {:?} after an owner change failed: {}",
+                        source, err
+                    );
+                    None
+                }
+            };
+
+            let watched = &mut self.watched[index];
+            watched.changed = None;
+            if let Some((selection, app)) = watched.debouncer.observe(current, now) {
+                let event = SelectionEvent {
+                    selection,
+                    source,
+                    app,
+                };
+                if self.sender.send(event).is_err() {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Read the regular clipboard in the first of the preferred formats it has
+fn read_clipboard(
+    selector: &LinuxSelector,
+    preferred: &[String],
+) -> Result<Selection, SelectionError> {
+    let image = ContentType::Image("png".to_string());
+    for mime in preferred {
+        let selection = if image.matches_mime(mime) {
+            selector.read_content(x11::read_image, wayland::read_image)?
+        } else if ContentType::Files.matches_mime(mime) {
+            selector.read_content(x11::read_files, wayland::read_files)?
+        } else if ContentType::Text.matches_mime(mime) {
+            Some(selector.read_selection(SelectionSource::Clipboard)?)
+        } else {
+            None
+        };
+
+        if let Some(selection) = selection.filter(|selection| !selection.is_empty()) {
+            return Ok(selection);
+        }
+    }
+    Err(SelectionError::NoSelectedContent)
+}
+
+/// Threads receiving data control offers on a Wayland selection
+///
+/// An offer can't be read any more once the next one replaced it, so one
+/// thread reads every offer as it arrives, and a second reports the last of
+/// them once no new offer came in for the debounce period.
+struct DataControlBackend {
+    cancel: CancelHandle,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl DataControlBackend {
+    fn spawn(
+        options: &WatcherOptions,
+        source: WatchSource,
+        sender: Sender<SelectionEvent>,
+    ) -> Result<Self, SelectionError> {
+        let (clipboard, preferred) = match source {
+            WatchSource::Selection => (watch::ClipboardType::Primary, vec!["text/plain".into()]),
+            WatchSource::Clipboard => (watch::ClipboardType::Regular, options.preferred.clone()),
+        };
+        let (ready, started) = mpsc::channel();
+        let (changes, changed) = mpsc::channel();
+
+        let reader = thread::spawn(move || {
+            // Fails without the protocol, or without primary selection support
+            let mut watcher = match Watcher::new(clipboard, Seat::Unspecified) {
+                Ok(watcher) => watcher,
+                Err(err) => {
+                    let _ = ready.send(Err(wayland::wayland_error(err)));
+                    return;
+                }
+            };
+            let _ = ready.send(Ok(watcher.cancel_handle()));
+
+            let options = SelectionOptions::default();
+            loop {
+                let selection = match watcher.next_event() {
+                    Ok(Some(ClipboardEvent::Changed {
+                        mime_types,
+                        mut offer,
+                        ..
+                    })) => read_offer(&mime_types, &preferred, &options, |mime, limit| {
+                        let pipe = offer.receive(mime).map_err(wayland::wayland_error)?;
+                        wayland::read_pipe(pipe, limit, options.selection_timeout)
+                    }),
+                    Ok(Some(ClipboardEvent::Cleared { .. })) => None,
+                    // Cancelled
+                    Ok(None) => break,
+                    Err(err) => {
+                        warn!("Wayland watcher lost the compositor: {}", err);
+                        break;
+                    }
+                };
+                if changes.send(selection).is_err() {
+                    break;
+                }
+            }
+            debug!("Wayland {:?} watcher stopped", source);
+        });
+
+        let cancel = match started.recv() {
+            Ok(Ok(cancel)) => cancel,
+            Ok(Err(err)) => {
+                let _ = reader.join();
+                return Err(err);
+            }
+            Err(_) => {
+                return Err(SelectionError::Other(
+                    "Wayland watcher thread exited unexpectedly".to_string(),
+                ))
+            }
+        };
+
+        let debounce = options.debounce;
+        let reporter = thread::spawn(move || report_settled(changed, debounce, source, sender));
+
+        Ok(Self {
+            cancel,
+            handles: vec![reader, reporter],
+        })
+    }
+}
+
+impl WatcherBackend for DataControlBackend {
+    fn stop(mut self: Box<Self>) {
+        // The reporter ends once the reader is gone
+        self.cancel.cancel();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Report the last of the selections read from offers once no new one came
+/// in for `debounce`
+fn report_settled(
+    changed: Receiver<Option<Selection>>,
+    debounce: Duration,
+    source: WatchSource,
+    sender: Sender<SelectionEvent>,
+) {
+    // Duplicates are filtered here; the offers do the debouncing
+    let mut debouncer = Debouncer::new(Duration::ZERO);
+    let mut pending: Option<(Option<Selection>, Instant)> = None;
+
+    loop {
+        let received = match &pending {
+            Some((_, since)) => changed.recv_timeout(debounce.saturating_sub(since.elapsed())),
+            None => changed.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(selection) => pending = Some((selection, Instant::now())),
+            Err(RecvTimeoutError::Timeout) => {
+                let Some((selection, _)) = pending.take() else {
+                    continue;
+                };
+                let current = selection.map(|selection| (selection, None));
+                if let Some((selection, app)) = debouncer.observe(current, Instant::now()) {
+                    let event = SelectionEvent {
+                        selection,
+                        source,
+                        app,
+                    };
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Read an offer in the first of the preferred formats it has
+///
+/// The formats are picked the same way as when reading the selection.
+fn read_offer(
+    offered: &[String],
+    preferred: &[String],
+    options: &SelectionOptions,
+    mut receive: impl FnMut(&str, Option<usize>) -> Result<Vec<u8>, SelectionError>,
+) -> Option<Selection> {
+    let image = ContentType::Image("png".to_string());
+    for mime in preferred {
+        let selection = if image.matches_mime(mime) {
+            wayland::image_from_offer(offered, options, &mut receive)
+        } else if ContentType::Files.matches_mime(mime) {
+            wayland::files_from_offer(offered, options, &mut receive)
+        } else if ContentType::Text.matches_mime(mime) {
+            wayland::text_from_offer(offered, options, &mut receive).map(|selection| {
+                selection.map(|mut selection| {
+                    trim_text(&mut selection, options.trim_whitespace);
+                    selection
+                })
+            })
+        } else {
+            Ok(None)
+        };
+
+        match selection {
+            Ok(Some(selection)) if !selection.is_empty() => {
+                return Some(selection.with_method(SelectionMethod::Clipboard))
+            }
+            Ok(_) => {}
+            Err(err) => debug!("Reading the offer as {} failed: {}", mime, err),
+        }
+    }
+    None
+}
+
+fn x11_error(err: impl std::error::Error + Send + Sync + 'static) -> SelectionError {
+    SelectionError::platform(ErrorKind::Clipboard, "X11 error", err)
+}
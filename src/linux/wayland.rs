@@ -0,0 +1,607 @@
+//! Reading Wayland selections
+//!
+//! Owners list the MIME types they offer and the reader picks one.
+//! wl-clipboard-rs's own text pick misses owners offering only unusual
+//! spellings, such as `UTF8_STRING` from XWayland bridges or types with
+//! stray whitespace, so the text type is chosen here.
+
+use log::debug;
+use std::io::{self, Read};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::Duration;
+use wl_clipboard_rs::paste::{self, get_contents, get_mime_types_ordered, ClipboardType, Seat};
+
+use super::data_control::DataControl;
+use crate::privacy::redact;
+use crate::uri::{parse_file_list, FILE_LIST_TYPES};
+use crate::{ErrorKind, Selection, SelectionError, SelectionOptions};
+
+/// A reader of one kind of content from a selection, `None` if the owner
+/// doesn't offer it
+pub(crate) type ContentReader =
+    fn(ClipboardType, &SelectionOptions) -> Result<Option<Selection>, SelectionError>;
+
+/// Text MIME types by preference, compared after normalizing
+const TEXT_MIME_TYPES: [&str; 6] = [
+    "text/plain;charset=utf-8",
+    "utf8_string",
+    "text/plain",
+    "text",
+    "string",
+    "text/plain;charset=iso-8859-1",
+];
+
+/// A way of reading Wayland selections
+///
+/// wl-clipboard-rs is tried first and the data control protocol spoken
+/// directly if it fails, see `read_with_fallback`.
+pub(crate) trait SelectionReader {
+    /// List the MIME types offered for a selection, `None` if it has no owner
+    fn offered_types(
+        &self,
+        clipboard: ClipboardType,
+    ) -> Result<Option<Vec<String>>, SelectionError>;
+
+    /// Read a selection as `mime`, up to `limit` bytes
+    fn receive(
+        &self,
+        clipboard: ClipboardType,
+        mime: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<u8>, SelectionError>;
+}
+
+/// Selections read through wl-clipboard-rs
+struct WlClipboard<'a> {
+    /// How long the owner may go without sending anything
+    timeout: Duration,
+    seat: Seat<'a>,
+}
+
+impl SelectionReader for WlClipboard<'_> {
+    fn offered_types(
+        &self,
+        clipboard: ClipboardType,
+    ) -> Result<Option<Vec<String>>, SelectionError> {
+        match get_mime_types_ordered(clipboard, self.seat) {
+            Ok(offered) => Ok(Some(offered)),
+            Err(paste::Error::ClipboardEmpty | paste::Error::NoSeats) => Ok(None),
+            Err(err) => Err(wayland_error(err)),
+        }
+    }
+
+    fn receive(
+        &self,
+        clipboard: ClipboardType,
+        mime: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<u8>, SelectionError> {
+        let (pipe, _) = get_contents(clipboard, self.seat, paste::MimeType::Specific(mime))
+            .map_err(wayland_error)?;
+
+        read_pipe(pipe, limit, self.timeout)
+    }
+}
+
+/// The seat `SelectionOptions::wayland_seat` names, or any seat
+pub(crate) fn seat(options: &SelectionOptions) -> Seat<'_> {
+    options
+        .wayland_seat
+        .as_deref()
+        .map_or(Seat::Unspecified, Seat::Specific)
+}
+
+/// Read through wl-clipboard-rs, retrying through the data control
+/// protocol directly if that fails
+///
+/// The first error is kept if the compositor has no data control protocol
+/// either, as it's the one explaining why.
+fn read_with_fallback<T>(
+    options: &SelectionOptions,
+    read: impl Fn(&dyn SelectionReader) -> Result<T, SelectionError>,
+) -> Result<T, SelectionError> {
+    let timeout = options.selection_timeout;
+    let seat = seat(options);
+    let err = match read(&WlClipboard { timeout, seat }) {
+        Ok(result) => return Ok(result),
+        Err(err) => err,
+    };
+    debug!(
+        "wl-clipboard-rs failed ({}), trying data control directly",
+        err
+    );
+
+    match DataControl::connect(timeout, options.wayland_seat.as_deref()) {
+        Ok(data_control) => read(&data_control),
+        Err(direct_err) => {
+            debug!("Data control is unavailable too: {}", direct_err);
+            Err(err)
+        }
+    }
+}
+
+/// Read the text of a selection, recording the MIME type it was read as
+///
+/// An empty selection is an empty text rather than an error.
+pub(crate) fn read_text(
+    clipboard: ClipboardType,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    read_with_fallback(options, |reader| {
+        text_from_reader(reader, clipboard, options)
+    })
+}
+
+/// Read the text of the first of `clipboards` that isn't empty once
+/// trimmed, through one reader, with the one it was read from
+///
+/// The text of the last one is returned, empty, if all of them are.
+pub(crate) fn read_first_text(
+    clipboards: &[ClipboardType],
+    options: &SelectionOptions,
+) -> Result<(Selection, ClipboardType), SelectionError> {
+    read_with_fallback(options, |reader| {
+        let mut text = (Selection::new_text(String::new()), ClipboardType::Regular);
+        for &clipboard in clipboards {
+            let mut selection = text_from_reader(reader, clipboard, options)?;
+            super::trim_text(&mut selection, options.trim_whitespace);
+            text = (selection, clipboard);
+            if !text.0.is_empty() {
+                break;
+            }
+        }
+        Ok(text)
+    })
+}
+
+/// Read an image from a selection
+///
+/// PNG is preferred; owners offering only other formats (`image/jpeg`,
+/// `image/bmp`) get them passed through unconverted, so the format in the
+/// content type has to be checked. Images larger than
+/// `SelectionOptions::max_bytes` are refused rather than cut short.
+pub(crate) fn read_image(
+    clipboard: ClipboardType,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    read_with_fallback(options, |reader| match reader.offered_types(clipboard)? {
+        Some(offered) => image_from_offer(&offered, options, |mime, limit| {
+            reader.receive(clipboard, mime, limit)
+        }),
+        None => Ok(None),
+    })
+}
+
+/// Read a list of files from a selection
+///
+/// File managers offer `text/uri-list`, and Nautilus and Caja also their own
+/// format, parsed the same way as on X11. A list longer than
+/// `SelectionOptions::max_bytes` is cut after its last whole entry.
+pub(crate) fn read_files(
+    clipboard: ClipboardType,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    read_with_fallback(options, |reader| match reader.offered_types(clipboard)? {
+        Some(offered) => files_from_offer(&offered, options, |mime, limit| {
+            reader.receive(clipboard, mime, limit)
+        }),
+        None => Ok(None),
+    })
+}
+
+/// Read the HTML of a selection, e.g. the primary selection in a browser
+///
+/// Markup longer than `SelectionOptions::max_bytes` is cut short and
+/// flagged as truncated.
+pub(crate) fn read_html(
+    clipboard: ClipboardType,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    read_with_fallback(options, |reader| match reader.offered_types(clipboard)? {
+        Some(offered) => html_from_offer(&offered, options, |mime, limit| {
+            reader.receive(clipboard, mime, limit)
+        }),
+        None => Ok(None),
+    })
+}
+
+fn text_from_reader(
+    reader: &dyn SelectionReader,
+    clipboard: ClipboardType,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let selection = match reader.offered_types(clipboard)? {
+        Some(offered) => text_from_offer(&offered, options, |mime, limit| {
+            reader.receive(clipboard, mime, limit)
+        })?,
+        None => None,
+    };
+    Ok(selection.unwrap_or_else(|| Selection::new_text(String::new())))
+}
+
+/// Read the text of an offer with the given MIME types
+///
+/// `receive` reads the offer as a MIME type, up to a number of bytes. Text
+/// longer than `SelectionOptions::max_bytes` is cut short and flagged as
+/// truncated.
+pub(crate) fn text_from_offer(
+    offered: &[String],
+    options: &SelectionOptions,
+    mut receive: impl FnMut(&str, Option<usize>) -> Result<Vec<u8>, SelectionError>,
+) -> Result<Option<Selection>, SelectionError> {
+    let Some(mime) = choose_text_mime(offered) else {
+        debug!("No text type among the offered {:?}", offered);
+        return Ok(None);
+    };
+    debug!("Reading the selection as {:?}", mime);
+
+    // Anything but Latin-1 is kept as it was sent, even if it isn't UTF-8
+    // One byte more shows whether the text was cut
+    let contents = receive(mime, options.max_bytes.map(|max| max + 1))?;
+    let mut selection = if is_latin1(mime) {
+        Selection::new_text(contents.iter().map(|byte| char::from(*byte)).collect())
+    } else {
+        Selection::new_text_bytes(contents)
+    };
+    // Latin-1 grows when decoded, so the decoded text is cut
+    if let Some(max_bytes) = options.max_bytes {
+        selection.truncate_text(max_bytes);
+    }
+    selection.metadata.source_format = Some(mime.to_string());
+    Ok(Some(selection))
+}
+
+/// Read an image from an offer with the given MIME types
+pub(crate) fn image_from_offer(
+    offered: &[String],
+    options: &SelectionOptions,
+    mut receive: impl FnMut(&str, Option<usize>) -> Result<Vec<u8>, SelectionError>,
+) -> Result<Option<Selection>, SelectionError> {
+    let Some(mime) = offered
+        .iter()
+        .find(|mime| normalize(mime) == "image/png")
+        .or_else(|| {
+            offered
+                .iter()
+                .find(|mime| normalize(mime).starts_with("image/"))
+        })
+    else {
+        return Ok(None);
+    };
+
+    let data = receive(mime, options.max_bytes.map(|max| max + 1))?;
+    if options.max_bytes.is_some_and(|max| data.len() > max) {
+        return Err(options.too_large());
+    }
+    if data.is_empty() {
+        return Ok(None);
+    }
+    debug!("Read {} bytes of {}", redact(data.len()), mime);
+
+    // The offered type is only what the owner claims the data is
+    let mut selection = Selection::new_image_detect(data)?;
+    selection.metadata.source_format = Some(mime.to_string());
+    Ok(Some(selection))
+}
+
+/// Read HTML from an offer with the given MIME types
+pub(crate) fn html_from_offer(
+    offered: &[String],
+    options: &SelectionOptions,
+    mut receive: impl FnMut(&str, Option<usize>) -> Result<Vec<u8>, SelectionError>,
+) -> Result<Option<Selection>, SelectionError> {
+    let Some(mime) = offered
+        .iter()
+        .find(|mime| normalize(mime).starts_with("text/html"))
+    else {
+        return Ok(None);
+    };
+
+    let data = receive(mime, options.max_bytes.map(|max| max + 1))?;
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let truncated = options.max_bytes.is_some_and(|max| data.len() > max);
+    let data = match options.max_bytes {
+        Some(max) if truncated => &data[..max],
+        _ => &data[..],
+    };
+    debug!("Read {} bytes of {}", redact(data.len()), mime);
+
+    let mut selection = Selection::new_html(super::decode_html(data));
+    selection.metadata.source_format = Some(mime.to_string());
+    selection.metadata.truncated = truncated;
+    Ok(Some(selection))
+}
+
+/// Read a list of files from an offer with the given MIME types
+pub(crate) fn files_from_offer(
+    offered: &[String],
+    options: &SelectionOptions,
+    mut receive: impl FnMut(&str, Option<usize>) -> Result<Vec<u8>, SelectionError>,
+) -> Result<Option<Selection>, SelectionError> {
+    for list_type in FILE_LIST_TYPES {
+        let Some(mime) = offered.iter().find(|mime| normalize(mime) == list_type) else {
+            continue;
+        };
+
+        let mut list = receive(mime, options.max_bytes.map(|max| max + 1))?;
+        let truncated = options.max_bytes.is_some_and(|max| list.len() > max);
+        if truncated {
+            let whole = list.iter().rposition(|byte| *byte == b'\n').unwrap_or(0);
+            list.truncate(whole);
+        }
+
+        let paths = parse_file_list(list_type, &String::from_utf8_lossy(&list));
+        if !paths.is_empty() {
+            debug!("Read {} files as {}", redact(paths.len()), mime);
+            let mut selection = Selection::new_files(paths);
+            selection.metadata.truncated = truncated;
+            return Ok(Some(selection));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read what an owner writes into a pipe, up to `limit` bytes
+///
+/// Owners that send nothing for `timeout` are given up on, so a hanging
+/// application can't hang the capture with it.
+pub(crate) fn read_pipe(
+    mut pipe: impl Read + AsFd,
+    limit: Option<usize>,
+    timeout: Duration,
+) -> Result<Vec<u8>, SelectionError> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut contents = Vec::new();
+    let mut buffer = [0; 8192];
+
+    while contents.len() < limit {
+        if !wait_readable(pipe.as_fd(), timeout)? {
+            return Err(SelectionError::Timeout {
+                phase: "waiting for the owner of the Wayland selection to send it",
+                waited: timeout,
+            });
+        }
+
+        let room = buffer.len().min(limit - contents.len());
+        match pipe.read(&mut buffer[..room]) {
+            Ok(0) => break,
+            Ok(read) => contents.extend_from_slice(&buffer[..read]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Wait for `fd` to have data or be closed, `false` after `timeout`
+pub(crate) fn wait_readable(fd: BorrowedFd, timeout: Duration) -> Result<bool, SelectionError> {
+    let mut poll_fd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+
+    loop {
+        // SAFETY: `poll_fd` is a single valid pollfd for the call's duration
+        match unsafe { libc::poll(&mut poll_fd, 1, millis) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error().into()),
+            ready => return Ok(ready > 0),
+        }
+    }
+}
+
+/// Lowercase a MIME type and drop its whitespace
+fn normalize(mime: &str) -> String {
+    mime.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Pick the best text type among the offered ones, as offered
+///
+/// Types are compared lowercased and without whitespace, so that
+/// `text/plain; charset=UTF-8\r\n` still counts as UTF-8 text.
+fn choose_text_mime(offered: &[String]) -> Option<&str> {
+    let normalized: Vec<String> = offered.iter().map(|mime| normalize(mime)).collect();
+
+    TEXT_MIME_TYPES
+        .iter()
+        .find_map(|preferred| normalized.iter().position(|mime| mime == preferred))
+        // Any other text/plain variant is better than nothing
+        .or_else(|| {
+            normalized
+                .iter()
+                .position(|mime| mime.starts_with("text/plain"))
+        })
+        .map(|index| offered[index].as_str())
+}
+
+/// Whether text offered as `mime` is Latin-1 rather than UTF-8
+fn is_latin1(mime: &str) -> bool {
+    let mime = mime.trim().to_ascii_lowercase();
+    mime == "string" || mime.ends_with("charset=iso-8859-1")
+}
+
+/// Map a failure to read a Wayland selection, telling an unreachable
+/// compositor and an empty selection apart
+pub(crate) fn wayland_error(err: paste::Error) -> SelectionError {
+    match err {
+        paste::Error::SocketOpenError(_) | paste::Error::WaylandConnection(_) => {
+            super::display_unreachable(err)
+        }
+        paste::Error::NoSeats | paste::Error::ClipboardEmpty | paste::Error::NoMimeType => {
+            SelectionError::NoSelectedContent
+        }
+        paste::Error::MissingProtocol { .. } | paste::Error::PrimarySelectionUnsupported => {
+            SelectionError::MethodUnavailable(err.to_string())
+        }
+        err => SelectionError::platform(
+            ErrorKind::Clipboard,
+            "Failed to read the Wayland selection",
+            err,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OversizePolicy;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// A selection owner serving fixed contents
+    struct FakeReader {
+        contents: Option<HashMap<&'static str, &'static [u8]>>,
+    }
+
+    impl SelectionReader for FakeReader {
+        fn offered_types(
+            &self,
+            _clipboard: ClipboardType,
+        ) -> Result<Option<Vec<String>>, SelectionError> {
+            Ok(self
+                .contents
+                .as_ref()
+                .map(|contents| contents.keys().map(|mime| mime.to_string()).collect()))
+        }
+
+        fn receive(
+            &self,
+            _clipboard: ClipboardType,
+            mime: &str,
+            limit: Option<usize>,
+        ) -> Result<Vec<u8>, SelectionError> {
+            let data = self.contents.as_ref().unwrap()[mime];
+            Ok(data[..limit.unwrap_or(data.len()).min(data.len())].to_vec())
+        }
+    }
+
+    #[test]
+    fn test_choose_text_mime() {
+        let offered = |types: &[&str]| {
+            types
+                .iter()
+                .map(|mime| mime.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            choose_text_mime(&offered(&["text/html", "text/plain", "UTF8_STRING"])),
+            Some("UTF8_STRING")
+        );
+        assert_eq!(
+            choose_text_mime(&offered(&["text/plain;charset=utf-8\r\n"])),
+            Some("text/plain;charset=utf-8\r\n")
+        );
+        assert_eq!(
+            choose_text_mime(&offered(&["text/plain;charset=UTF-16"])),
+            Some("text/plain;charset=UTF-16")
+        );
+        assert_eq!(choose_text_mime(&offered(&["image/png"])), None);
+    }
+
+    #[test]
+    fn test_text_from_reader() {
+        let options = SelectionOptions::default();
+        let reader = FakeReader {
+            contents: Some(HashMap::from([
+                ("text/html", b"<b>caf\xc3\xa9</b>" as &[u8]),
+                ("STRING", b"caf\xe9"),
+            ])),
+        };
+        let selection = text_from_reader(&reader, ClipboardType::Primary, &options).unwrap();
+        assert_eq!(selection.as_text(), Some("café".to_string()));
+        assert_eq!(selection.metadata.source_format.as_deref(), Some("STRING"));
+
+        let reader = FakeReader { contents: None };
+        let selection = text_from_reader(&reader, ClipboardType::Primary, &options).unwrap();
+        assert_eq!(selection.as_text(), Some(String::new()));
+    }
+
+    #[test]
+    fn test_offer_size_limit() {
+        let offered = |mime: &str| vec![mime.to_string()];
+        let receive = |data: &'static [u8]| {
+            move |_: &str, limit: Option<usize>| {
+                Ok(data[..limit.unwrap_or(data.len()).min(data.len())].to_vec())
+            }
+        };
+        let options = SelectionOptions::default().max_bytes(5);
+
+        let text = text_from_offer(
+            &offered("UTF8_STRING"),
+            &options,
+            receive(b"ca\xc3\xa9\xc3\xa9"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(text.as_text(), Some("caé".to_string()));
+        assert!(text.metadata.truncated);
+        assert!(matches!(
+            options.clone().oversize(OversizePolicy::Error).finish(text),
+            Err(SelectionError::TooLarge { limit: 5 })
+        ));
+
+        let files = files_from_offer(
+            &offered("text/uri-list"),
+            &options.clone().max_bytes(20),
+            receive(b"file:///a\r\nfile:///bcd\r\n"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(files.as_files(), Some(vec![PathBuf::from("/a")]));
+        assert!(files.metadata.truncated);
+
+        assert!(matches!(
+            image_from_offer(&offered("image/png"), &options, receive(b"\x89PNG\r\n")),
+            Err(SelectionError::TooLarge { limit: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_wayland_error() {
+        assert!(matches!(
+            wayland_error(paste::Error::ClipboardEmpty),
+            SelectionError::NoSelectedContent
+        ));
+        assert!(matches!(
+            wayland_error(paste::Error::MissingProtocol {
+                name: "zwlr_data_control_manager_v1",
+                version: 1
+            }),
+            SelectionError::MethodUnavailable(_)
+        ));
+        assert!(matches!(
+            wayland_error(paste::Error::SeatNotFound),
+            SelectionError::Platform {
+                kind: ErrorKind::Clipboard,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_read_pipe_times_out() {
+        use std::io::Write;
+
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        writer.write_all(b"text").unwrap();
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(
+            read_pipe(&reader, None, timeout),
+            Err(SelectionError::Timeout { .. })
+        ));
+
+        writer.write_all(b"text").unwrap();
+        drop(writer);
+        assert_eq!(read_pipe(&reader, Some(2), timeout).unwrap(), b"te");
+    }
+}
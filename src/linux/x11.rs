@@ -0,0 +1,1065 @@
+//! Reading X11 selections
+//!
+//! Owners convert a selection to the target the reader asks for. Almost all
+//! of them offer `UTF8_STRING`, which is asked for directly; old Motif and Tk
+//! programs only offer the legacy encodings, found through `TARGETS`.
+//! Images and HTML are found the same way.
+
+use log::debug;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use x11_clipboard::{Clipboard, Context};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, GetPropertyReply, Property,
+    Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::CURRENT_TIME;
+
+use super::bmp;
+use crate::privacy::redact;
+use crate::uri::{parse_file_list, FILE_LIST_TYPES};
+use crate::{AppInfo, ErrorKind, Selection, SelectionError, SelectionOptions};
+
+/// Image targets by preference; bitmaps are converted to PNG
+const PNG_TARGET: &str = "image/png";
+const IMAGE_TARGETS: [&str; 2] = [PNG_TARGET, "image/bmp"];
+
+/// Target browsers offer the selection's markup as
+const HTML_TARGET: &str = "text/html";
+
+/// How often the connection is checked for the owner's answer
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Text targets by preference, with the encoding of their data
+const TEXT_TARGETS: [(&str, Encoding); 4] = [
+    ("UTF8_STRING", Encoding::Utf8),
+    ("COMPOUND_TEXT", Encoding::CompoundText),
+    ("STRING", Encoding::Latin1),
+    // The owner picks the encoding; in practice ASCII or compound text
+    ("TEXT", Encoding::CompoundText),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    CompoundText,
+    Latin1,
+}
+
+/// Read the text of a selection, e.g. PRIMARY
+///
+/// Text longer than `SelectionOptions::max_bytes` is cut short and flagged
+/// as truncated.
+pub(crate) fn read_text(
+    clipboard: &Clipboard,
+    selection: Atom,
+    name: &str,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let context = &clipboard.getter;
+    let atoms = &context.atoms;
+    let timeout = options.selection_timeout;
+    let limit = options.max_bytes;
+
+    match convert(context, selection, atoms.utf8_string, name, timeout, limit) {
+        Ok(Some(converted)) if !converted.data.is_empty() => {
+            return Ok(text_selection(context, converted, Encoding::Utf8, limit));
+        }
+        // A slow owner won't get faster for another target
+        Err(err @ SelectionError::Timeout { .. }) => return Err(err),
+        Ok(_) => debug!("{} is empty as UTF8_STRING, asking for its targets", name),
+        Err(err) => debug!(
+            "{} can't be read as UTF8_STRING ({}), asking for its targets",
+            name, err
+        ),
+    }
+
+    let offered = read_targets(context, selection, name, timeout)?;
+    for (target_name, encoding) in TEXT_TARGETS {
+        let target = atom(context, target_name, name, timeout)?;
+        if !offered.contains(&target) {
+            continue;
+        }
+
+        if let Some(converted) = convert(context, selection, target, name, timeout, limit)? {
+            if !converted.data.is_empty() {
+                debug!("Read {} as {}", name, target_name);
+                return Ok(text_selection(context, converted, encoding, limit));
+            }
+        }
+    }
+
+    Ok(Selection::new_text(String::new()))
+}
+
+/// Decode converted text, cutting it down to `max_bytes`
+///
+/// UTF-8 is kept as the owner sent it, even if it isn't valid; the legacy
+/// encodings are converted to UTF-8.
+fn text_selection(
+    context: &Context,
+    converted: Converted,
+    requested: Encoding,
+    max_bytes: Option<usize>,
+) -> Selection {
+    let mut data = match encoding_of(context, converted.kind, requested) {
+        Encoding::Utf8 => converted.data,
+        encoding => decode(&converted.data, encoding).into_bytes(),
+    };
+    if converted.truncated {
+        // The cut may have split the last character
+        if let Err(err) = std::str::from_utf8(&data) {
+            if err.error_len().is_none() {
+                data.truncate(err.valid_up_to());
+            }
+        }
+    }
+
+    let mut selection = Selection::new_text_bytes(data);
+    // Latin-1 and compound text can grow when decoded
+    if let Some(max_bytes) = max_bytes {
+        selection.truncate_text(max_bytes);
+    }
+    selection.metadata.truncated |= converted.truncated;
+    selection
+}
+
+/// A reader of one kind of content from a selection, `None` if the owner
+/// doesn't offer it
+pub(crate) type ContentReader =
+    fn(&Clipboard, Atom, &str, &SelectionOptions) -> Result<Option<Selection>, SelectionError>;
+
+/// Read an image from a selection, e.g. CLIPBOARD, as PNG
+///
+/// `image/png` is taken as it is; owners only offering `image/bmp` (Wine
+/// and some Java programs) get their bitmap converted. Returns `None` if
+/// neither is offered. Images larger than `SelectionOptions::max_bytes` are
+/// refused rather than cut short.
+pub(crate) fn read_image(
+    clipboard: &Clipboard,
+    selection: Atom,
+    name: &str,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    let context = &clipboard.getter;
+    let timeout = options.selection_timeout;
+    let offered = read_targets(context, selection, name, timeout)?;
+
+    for target_name in IMAGE_TARGETS {
+        let target = atom(context, target_name, name, timeout)?;
+        if !offered.contains(&target) {
+            continue;
+        }
+
+        let converted = convert(context, selection, target, name, timeout, options.max_bytes)?;
+        let Some(Converted {
+            data, truncated, ..
+        }) = converted
+        else {
+            continue;
+        };
+        if truncated {
+            return Err(options.too_large());
+        }
+        if data.is_empty() {
+            continue;
+        }
+        debug!(
+            "Read {} bytes of {} from {}",
+            redact(data.len()),
+            target_name,
+            name
+        );
+
+        if target_name == PNG_TARGET {
+            return Selection::new_image_detect(data).map(Some);
+        }
+        return Ok(Some(Selection::new_image("png", bmp::to_png(&data)?)));
+    }
+
+    Ok(None)
+}
+
+/// Read a list of files from a selection, e.g. CLIPBOARD
+///
+/// File managers offer `text/uri-list`, and Nautilus and Caja also their own
+/// format, which starts with whether the files were cut or copied. Returns
+/// `None` if neither is offered. A list longer than
+/// `SelectionOptions::max_bytes` is cut after its last whole entry.
+pub(crate) fn read_files(
+    clipboard: &Clipboard,
+    selection: Atom,
+    name: &str,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    let context = &clipboard.getter;
+    let timeout = options.selection_timeout;
+    let offered = read_targets(context, selection, name, timeout)?;
+
+    for target_name in FILE_LIST_TYPES {
+        let target = atom(context, target_name, name, timeout)?;
+        if !offered.contains(&target) {
+            continue;
+        }
+
+        let converted = convert(context, selection, target, name, timeout, options.max_bytes)?;
+        let Some(Converted {
+            mut data,
+            truncated,
+            ..
+        }) = converted
+        else {
+            continue;
+        };
+        if truncated {
+            let whole = data.iter().rposition(|byte| *byte == b'\n').unwrap_or(0);
+            data.truncate(whole);
+        }
+
+        let list = String::from_utf8_lossy(&data);
+        let paths = parse_file_list(target_name, &list);
+        if !paths.is_empty() {
+            debug!(
+                "Read {} files as {} from {}",
+                redact(paths.len()),
+                target_name,
+                name
+            );
+            let mut selection = Selection::new_files(paths);
+            selection.metadata.truncated = truncated;
+            return Ok(Some(selection));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the HTML of a selection, e.g. PRIMARY in a browser
+///
+/// Firefox sends UTF-16 and Chromium UTF-8; both come out as UTF-8. Returns
+/// `None` if the owner doesn't offer `text/html`. Markup longer than
+/// `SelectionOptions::max_bytes` is cut short and flagged as truncated.
+pub(crate) fn read_html(
+    clipboard: &Clipboard,
+    selection: Atom,
+    name: &str,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    let context = &clipboard.getter;
+    let timeout = options.selection_timeout;
+    let offered = read_targets(context, selection, name, timeout)?;
+    let target = atom(context, HTML_TARGET, name, timeout)?;
+    if !offered.contains(&target) {
+        return Ok(None);
+    }
+
+    let converted = convert(context, selection, target, name, timeout, options.max_bytes)?;
+    let Some(converted) = converted.filter(|converted| !converted.data.is_empty()) else {
+        return Ok(None);
+    };
+    debug!(
+        "Read {} bytes of HTML from {}",
+        redact(converted.data.len()),
+        name
+    );
+
+    let mut selection = Selection::new_html(super::decode_html(&converted.data));
+    selection.metadata.source_format = Some(HTML_TARGET.to_string());
+    selection.metadata.truncated = converted.truncated;
+    Ok(Some(selection))
+}
+
+/// List the targets the owner of a selection can convert it to
+fn read_targets(
+    context: &Context,
+    selection: Atom,
+    name: &str,
+    timeout: Duration,
+) -> Result<Vec<Atom>, SelectionError> {
+    let targets = context.atoms.targets;
+    Ok(convert(context, selection, targets, name, timeout, None)?
+        .map(|converted| parse_atoms(&converted.data))
+        .unwrap_or_default())
+}
+
+/// What the owner of a selection converted it to
+struct Converted {
+    /// Type the owner gave the data
+    kind: Atom,
+    data: Vec<u8>,
+    /// Whether the data was cut off at the limit
+    truncated: bool,
+}
+
+/// Data of a transfer, kept up to a limit
+#[derive(Debug, Default)]
+struct Incoming {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl Incoming {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Add a chunk, returning whether there's room for more
+    fn push(&mut self, chunk: &[u8]) -> bool {
+        let room = self
+            .limit
+            .map_or(usize::MAX, |limit| limit.saturating_sub(self.data.len()));
+        if chunk.len() > room {
+            self.data.extend_from_slice(&chunk[..room]);
+            self.truncated = true;
+            return false;
+        }
+        self.data.extend_from_slice(chunk);
+        true
+    }
+
+    fn into_converted(self, kind: Atom) -> Converted {
+        Converted {
+            kind,
+            data: self.data,
+            truncated: self.truncated,
+        }
+    }
+}
+
+/// Ask the owner of a selection to convert it to `target` and read the
+/// result, keeping up to `limit` bytes
+///
+/// Returns `None` if the owner refused the conversion or there is no owner.
+/// Large values arrive through INCR, in chunks, and `timeout` applies to
+/// each chunk rather than to the whole transfer. x11-clipboard's own loader
+/// rejects replies whose type isn't the target, which is what owners send
+/// for `TARGETS` (typed `ATOM`) and `TEXT`.
+///
+/// Each conversion gets a window of its own. Destroying it is the only way
+/// to make an owner give up an INCR transfer cut off at the limit, and
+/// chunks still on their way can't be taken for a later conversion's.
+fn convert(
+    context: &Context,
+    selection: Atom,
+    target: Atom,
+    name: &str,
+    timeout: Duration,
+    limit: Option<usize>,
+) -> Result<Option<Converted>, SelectionError> {
+    let connection = &context.connection;
+
+    let root = connection.setup().roots[context.screen].root;
+    let window = connection
+        .generate_id()
+        .map_err(|err| read_error(name, err))?;
+    connection
+        .create_window(
+            0,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )
+        .map_err(|err| read_error(name, err))?;
+
+    let result = receive(context, window, selection, target, name, timeout, limit);
+
+    let _ = connection.destroy_window(window);
+    let _ = connection.flush();
+    result
+}
+
+/// Convert a selection into a property of `window` and read it
+fn receive(
+    context: &Context,
+    window: Window,
+    selection: Atom,
+    target: Atom,
+    name: &str,
+    timeout: Duration,
+    limit: Option<usize>,
+) -> Result<Option<Converted>, SelectionError> {
+    let connection = &context.connection;
+    let property = context.atoms.property;
+
+    connection
+        .convert_selection(window, selection, target, property, CURRENT_TIME)
+        .map_err(|err| read_error(name, err))?;
+    connection.flush().map_err(|err| read_error(name, err))?;
+
+    let transfer = Transfer {
+        window,
+        selection,
+        property,
+        incr: context.atoms.incr,
+        name,
+    };
+    transfer.run(
+        timeout,
+        limit,
+        || {
+            connection
+                .poll_for_event()
+                .map_err(|err| read_error(name, err))
+        },
+        || read_property(context, window),
+    )
+}
+
+/// A conversion `receive` waits for the owner's answer to
+struct Transfer<'a> {
+    window: Window,
+    selection: Atom,
+    property: Atom,
+    incr: Atom,
+    name: &'a str,
+}
+
+impl Transfer<'_> {
+    /// Handle the events `next_event` polls for until the answer is read
+    /// with `read`, following INCR transfers chunk by chunk
+    fn run(
+        &self,
+        timeout: Duration,
+        limit: Option<usize>,
+        mut next_event: impl FnMut() -> Result<Option<Event>, SelectionError>,
+        mut read: impl FnMut() -> Result<GetPropertyReply, SelectionError>,
+    ) -> Result<Option<Converted>, SelectionError> {
+        let mut incoming = Incoming::new(limit);
+        let mut incremental = false;
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let Some(event) = next_event()? else {
+                if Instant::now() >= deadline {
+                    return Err(timeout_error(self.name, timeout));
+                }
+                thread::sleep(EVENT_POLL_INTERVAL);
+                continue;
+            };
+
+            let reply = match event {
+                Event::SelectionNotify(event)
+                    if event.requestor == self.window
+                        && event.selection == self.selection
+                        && !incremental =>
+                {
+                    if event.property == u32::from(AtomEnum::NONE) {
+                        return Ok(None);
+                    }
+                    read()?
+                }
+                Event::PropertyNotify(event)
+                    if incremental
+                        && event.window == self.window
+                        && event.atom == self.property
+                        && event.state == Property::NEW_VALUE =>
+                {
+                    read()?
+                }
+                _ => continue,
+            };
+
+            if !incremental && reply.type_ == self.incr {
+                // Deleting the property, done by reading it, starts the transfer
+                debug!("{} is sent incrementally", self.name);
+                incremental = true;
+            } else if !incremental {
+                incoming.push(&reply.value);
+                return Ok(Some(incoming.into_converted(reply.type_)));
+            } else if reply.value.is_empty() {
+                // A zero-length chunk ends the transfer
+                return Ok(Some(incoming.into_converted(reply.type_)));
+            } else if !incoming.push(&reply.value) {
+                debug!(
+                    "Stopped reading {} at {} bytes",
+                    self.name,
+                    redact(incoming.data.len())
+                );
+                return Ok(Some(incoming.into_converted(reply.type_)));
+            }
+            deadline = Instant::now() + timeout;
+        }
+    }
+}
+
+/// Read and delete the property a selection is converted into
+fn read_property(context: &Context, window: Window) -> Result<GetPropertyReply, SelectionError> {
+    context
+        .connection
+        .get_property(
+            true,
+            window,
+            context.atoms.property,
+            AtomEnum::ANY,
+            0,
+            u32::MAX,
+        )
+        .map_err(|err| read_error("the selection property", err))?
+        .reply()
+        .map_err(|err| read_error("the selection property", err))
+}
+
+fn atom(
+    context: &Context,
+    atom_name: &str,
+    name: &str,
+    timeout: Duration,
+) -> Result<Atom, SelectionError> {
+    context
+        .get_atom(atom_name)
+        .map_err(|err| x11_load_error(err, name, timeout))
+}
+
+/// Encoding of text converted to `kind`, `requested` if the type isn't one
+/// of the text targets
+fn encoding_of(context: &Context, kind: Atom, requested: Encoding) -> Encoding {
+    TEXT_TARGETS
+        .iter()
+        .find(|(target_name, _)| context.get_atom(target_name).is_ok_and(|atom| atom == kind))
+        .map_or(requested, |(_, encoding)| *encoding)
+}
+
+fn timeout_error(selection: &str, timeout: Duration) -> SelectionError {
+    debug!("The owner of {} didn't send it in time", selection);
+    SelectionError::Timeout {
+        phase: "waiting for the owner of an X11 selection to send it",
+        waited: timeout,
+    }
+}
+
+/// Check whether a connection still works with a round trip to the server
+pub(crate) fn is_alive(clipboard: &Clipboard) -> bool {
+    clipboard
+        .getter
+        .connection
+        .get_input_focus()
+        .is_ok_and(|cookie| cookie.reply().is_ok())
+}
+
+/// What a window tells about the application that created it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WindowInfo {
+    /// Class part of `WM_CLASS`, e.g. `dolphin`
+    pub(crate) class: Option<String>,
+    /// Process id from `_NET_WM_PID`
+    pub(crate) pid: Option<u32>,
+    /// Title from `_NET_WM_NAME` or `WM_NAME`
+    pub(crate) title: Option<String>,
+}
+
+impl WindowInfo {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.class.is_none() && self.pid.is_none()
+    }
+
+    /// Describe the application, resolving its pid through `/proc`
+    pub(crate) fn into_app_info(self) -> AppInfo {
+        let executable = self
+            .pid
+            .and_then(|pid| fs::read_link(format!("/proc/{}/exe", pid)).ok());
+        let name = executable
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .or_else(|| self.class.clone());
+
+        AppInfo {
+            name,
+            identifier: self.class,
+            pid: self.pid,
+            executable,
+            window_title: self.title,
+        }
+    }
+}
+
+/// Find the active window through the EWMH `_NET_ACTIVE_WINDOW` property
+///
+/// Returns `None` if the window manager doesn't set it or no window is
+/// active. Minimal window managers may set none of the window's properties
+/// either.
+pub(crate) fn active_window(context: &Context) -> Option<WindowInfo> {
+    let root = context.connection.setup().roots.get(context.screen)?.root;
+    let window = property(context, root, "_NET_ACTIVE_WINDOW", AtomEnum::WINDOW)?
+        .value32()?
+        .next()
+        .filter(|window| *window != 0)?;
+
+    Some(window_info(context, window))
+}
+
+/// Describe the application owning `selection`
+///
+/// Toolkits often own selections with a hidden window of their own, which
+/// is then followed to its `WM_CLIENT_LEADER`. Returns `None` if there is no
+/// owner or it doesn't say which application it belongs to.
+pub(crate) fn selection_owner(context: &Context, selection: Atom) -> Option<AppInfo> {
+    let owner = context
+        .connection
+        .get_selection_owner(selection)
+        .ok()?
+        .reply()
+        .ok()?
+        .owner;
+    if owner == x11rb::NONE {
+        return None;
+    }
+
+    let mut info = window_info(context, owner);
+    if info.is_empty() {
+        let leader = property(context, owner, "WM_CLIENT_LEADER", AtomEnum::WINDOW)
+            .and_then(|reply| reply.value32()?.next())
+            .filter(|leader| *leader != 0 && *leader != owner)?;
+        info = window_info(context, leader);
+    }
+
+    (!info.is_empty()).then(|| info.into_app_info())
+}
+
+fn window_info(context: &Context, window: Window) -> WindowInfo {
+    let class = property(context, window, "WM_CLASS", AtomEnum::STRING)
+        .and_then(|reply| parse_wm_class(&reply.value));
+    let pid = property(context, window, "_NET_WM_PID", AtomEnum::CARDINAL)
+        .and_then(|reply| reply.value32()?.next());
+    let title = property(context, window, "_NET_WM_NAME", AtomEnum::ANY)
+        .filter(|reply| !reply.value.is_empty())
+        .map(|reply| String::from_utf8_lossy(&reply.value).into_owned())
+        .or_else(|| {
+            let reply = property(context, window, "WM_NAME", AtomEnum::ANY)?;
+            let encoding = encoding_of(context, reply.type_, Encoding::Latin1);
+            Some(decode(&reply.value, encoding))
+        })
+        .filter(|title| !title.is_empty());
+
+    WindowInfo { class, pid, title }
+}
+
+/// Read a window property of up to 4 KiB, `None` if it isn't set
+fn property(
+    context: &Context,
+    window: Window,
+    name: &str,
+    kind: AtomEnum,
+) -> Option<GetPropertyReply> {
+    let atom = context.get_atom(name).ok()?;
+    context
+        .connection
+        .get_property(false, window, atom, kind, 0, 1024)
+        .ok()?
+        .reply()
+        .ok()
+        .filter(|reply| reply.type_ != x11rb::NONE)
+}
+
+/// Get the class from `WM_CLASS`, which holds the instance and class names
+/// as two NUL-terminated strings
+fn parse_wm_class(data: &[u8]) -> Option<String> {
+    let class = data.split(|byte| *byte == 0).nth(1)?;
+    (!class.is_empty()).then(|| String::from_utf8_lossy(class).into_owned())
+}
+
+/// Map a failed request while reading `name`
+fn read_error(name: &str, err: impl std::error::Error + Send + Sync + 'static) -> SelectionError {
+    SelectionError::platform(
+        ErrorKind::Clipboard,
+        &format!("Failed to read {}", name),
+        err,
+    )
+}
+
+/// Map a failure to connect to the X server, telling an unreachable one
+/// apart
+pub(crate) fn connect_error(err: x11_clipboard::error::Error) -> SelectionError {
+    match err {
+        x11_clipboard::error::Error::XcbConnect(_) => super::display_unreachable(err),
+        err => SelectionError::platform(
+            ErrorKind::Clipboard,
+            "Failed to connect to the X server",
+            err,
+        ),
+    }
+}
+
+/// Map a failure to read an X11 selection, telling a slow owner apart
+fn x11_load_error(
+    err: x11_clipboard::error::Error,
+    selection: &str,
+    timeout: Duration,
+) -> SelectionError {
+    match err {
+        x11_clipboard::error::Error::Timeout => timeout_error(selection, timeout),
+        err => read_error(selection, err),
+    }
+}
+
+/// Parse a property of format 32, such as the `TARGETS` list
+///
+/// x11rb sets up connections in the client's byte order.
+fn parse_atoms(data: &[u8]) -> Vec<Atom> {
+    data.chunks_exact(4)
+        .map(|atom| Atom::from_ne_bytes([atom[0], atom[1], atom[2], atom[3]]))
+        .collect()
+}
+
+fn decode(data: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+        Encoding::Latin1 => data.iter().map(|byte| char::from(*byte)).collect(),
+        Encoding::CompoundText => decode_compound_text(data),
+    }
+}
+
+/// Character set designated to one half of the compound text code space
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Charset {
+    Ascii,
+    Latin1Upper,
+    /// A set that isn't decoded; its characters become U+FFFD
+    Unsupported,
+}
+
+/// Decode compound text (ISO 2022 with ASCII and Latin-1 designated at the
+/// start), as written by Xlib's `XmbTextListToTextProperty`
+///
+/// ASCII, Latin-1 and UTF-8 segments (`ESC % G`), which cover what owners
+/// send in practice, are decoded; characters of other designated sets are
+/// replaced by U+FFFD.
+fn decode_compound_text(data: &[u8]) -> String {
+    let mut text = String::new();
+    let mut left = Charset::Ascii;
+    let mut right = Charset::Latin1Upper;
+
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        if byte == 0x1b {
+            match (data.get(i + 1), data.get(i + 2)) {
+                // UTF-8 segment, up to `ESC % @`
+                (Some(b'%'), Some(b'G')) => {
+                    let start = i + 3;
+                    let end = data[start..]
+                        .windows(3)
+                        .position(|window| window == b"\x1b%@")
+                        .map_or(data.len(), |offset| start + offset);
+                    text.push_str(&String::from_utf8_lossy(&data[start..end]));
+                    i = (end + 3).min(data.len());
+                }
+                // 94-character set into the left half
+                (Some(b'('), Some(&set)) => {
+                    left = if set == b'B' {
+                        Charset::Ascii
+                    } else {
+                        Charset::Unsupported
+                    };
+                    i += 3;
+                }
+                // 96-character set into the right half
+                (Some(b'-'), Some(&set)) => {
+                    right = if set == b'A' {
+                        Charset::Latin1Upper
+                    } else {
+                        Charset::Unsupported
+                    };
+                    i += 3;
+                }
+                // Multi-byte sets (`ESC $ ( F`, `ESC $ ) F`, ...) and
+                // anything else: skip the final byte of the sequence
+                _ => {
+                    let end = data[i + 1..]
+                        .iter()
+                        .position(|byte| (0x30..=0x7e).contains(byte))
+                        .map_or(data.len(), |offset| i + 2 + offset);
+                    left = Charset::Unsupported;
+                    right = Charset::Unsupported;
+                    i = end;
+                }
+            }
+            continue;
+        }
+
+        let charset = match byte {
+            0x21..=0x7e => left,
+            0xa0..=0xff => right,
+            // Control characters, space and newline are always themselves
+            _ => Charset::Ascii,
+        };
+        text.push(match charset {
+            Charset::Ascii | Charset::Latin1Upper => char::from(byte),
+            Charset::Unsupported => char::REPLACEMENT_CHARACTER,
+        });
+        i += 1;
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_read_error_source() {
+        let err = x11_load_error(
+            x11_clipboard::error::Error::XcbConnection(
+                x11rb::errors::ConnectionError::UnknownError,
+            ),
+            "PRIMARY",
+            Duration::from_millis(100),
+        );
+        assert_eq!(err.kind(), ErrorKind::Clipboard);
+        // Down to the x11rb error the clipboard crate wrapped
+        let source = err.source().unwrap();
+        assert!(source.is::<x11_clipboard::error::Error>());
+        let connection = source.source().unwrap();
+        assert!(connection.is::<x11rb::errors::ConnectionError>());
+    }
+
+    #[test]
+    fn test_decode_compound_text() {
+        assert_eq!(decode_compound_text(b"caf\xe9 au lait"), "café au lait");
+        assert_eq!(
+            decode_compound_text(b"na\x1b%G\xc3\xafve \xe2\x82\xac\x1b%@ \xe9"),
+            "naïve € é"
+        );
+        assert_eq!(
+            decode_compound_text(b"\x1b$(B\x30\x21\x1b(B ok"),
+            "\u{fffd}\u{fffd} ok"
+        );
+    }
+
+    #[test]
+    fn test_parse_wm_class() {
+        assert_eq!(
+            parse_wm_class(b"dolphin\0dolphin\0").as_deref(),
+            Some("dolphin")
+        );
+        assert_eq!(
+            parse_wm_class(b"org.gnome.Nautilus\0Org.gnome.Nautilus\0").as_deref(),
+            Some("Org.gnome.Nautilus")
+        );
+        assert_eq!(parse_wm_class(b"broken"), None);
+    }
+
+    #[test]
+    fn test_incoming_stops_at_the_limit() {
+        let mut incoming = Incoming::new(Some(5));
+        assert!(incoming.push(b"abc"));
+        assert!(incoming.push(b"de"));
+        assert!(!incoming.truncated);
+
+        // Full, so a further chunk means the data was longer
+        assert!(!incoming.push(b"fgh"));
+        assert!(incoming.truncated);
+        assert_eq!(incoming.data, b"abcde");
+
+        let mut incoming = Incoming::new(None);
+        assert!(incoming.push(&[0; 4096]));
+        assert_eq!(incoming.into_converted(x11rb::NONE).data.len(), 4096);
+    }
+
+    const WINDOW: Window = 1;
+    const PRIMARY: Atom = 2;
+    const PROPERTY: Atom = 3;
+    const INCR: Atom = 4;
+    const UTF8_STRING: Atom = 5;
+
+    /// What the owner does in a scripted transfer, `delay` after the
+    /// previous step
+    struct Step {
+        delay: Duration,
+        event: Event,
+        reply: Option<GetPropertyReply>,
+    }
+
+    fn notify(delay_ms: u64, kind: Atom) -> Step {
+        Step {
+            delay: Duration::from_millis(delay_ms),
+            event: Event::SelectionNotify(x11rb::protocol::xproto::SelectionNotifyEvent {
+                requestor: WINDOW,
+                selection: PRIMARY,
+                property: PROPERTY,
+                ..Default::default()
+            }),
+            reply: Some(GetPropertyReply {
+                type_: kind,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn chunk(delay_ms: u64, value: &[u8]) -> Step {
+        Step {
+            delay: Duration::from_millis(delay_ms),
+            event: Event::PropertyNotify(x11rb::protocol::xproto::PropertyNotifyEvent {
+                window: WINDOW,
+                atom: PROPERTY,
+                state: Property::NEW_VALUE,
+                ..Default::default()
+            }),
+            reply: Some(GetPropertyReply {
+                type_: UTF8_STRING,
+                value: value.to_vec(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Run a transfer against a script, returning its result and how many
+    /// steps were left unread
+    fn run_script(
+        steps: Vec<Step>,
+        timeout: Duration,
+        limit: Option<usize>,
+    ) -> (Result<Option<Converted>, SelectionError>, usize) {
+        let transfer = Transfer {
+            window: WINDOW,
+            selection: PRIMARY,
+            property: PROPERTY,
+            incr: INCR,
+            name: "PRIMARY",
+        };
+        let mut steps = std::collections::VecDeque::from(steps);
+        let replies = std::cell::RefCell::new(std::collections::VecDeque::new());
+        let mut due = Instant::now() + steps.front().map_or(Duration::ZERO, |step| step.delay);
+        let result = transfer.run(
+            timeout,
+            limit,
+            || {
+                if steps.is_empty() || Instant::now() < due {
+                    return Ok(None);
+                }
+                let step = steps.pop_front().unwrap();
+                replies.borrow_mut().extend(step.reply);
+                due = Instant::now() + steps.front().map_or(Duration::ZERO, |step| step.delay);
+                Ok(Some(step.event))
+            },
+            || {
+                Ok(replies
+                    .borrow_mut()
+                    .pop_front()
+                    .expect("read without an answer"))
+            },
+        );
+        (result, steps.len())
+    }
+
+    #[test]
+    fn test_transfer_reads_incr_chunks() {
+        let deleted = Step {
+            reply: None,
+            event: Event::PropertyNotify(x11rb::protocol::xproto::PropertyNotifyEvent {
+                window: WINDOW,
+                atom: PROPERTY,
+                state: Property::DELETE,
+                ..Default::default()
+            }),
+            ..chunk(0, b"")
+        };
+        let script = vec![
+            notify(0, INCR),
+            chunk(0, b"abc"),
+            deleted,
+            chunk(0, b"de"),
+            chunk(0, b""),
+        ];
+        let (result, left) = run_script(script, Duration::from_millis(100), None);
+        let converted = result.unwrap().unwrap();
+        assert_eq!(converted.data, b"abcde");
+        assert_eq!(converted.kind, UTF8_STRING);
+        assert!(!converted.truncated);
+        assert_eq!(left, 0);
+
+        // Without INCR the first answer is the whole value
+        let script = vec![Step {
+            reply: Some(GetPropertyReply {
+                type_: UTF8_STRING,
+                value: b"whole".to_vec(),
+                ..Default::default()
+            }),
+            ..notify(0, UTF8_STRING)
+        }];
+        let (result, _) = run_script(script, Duration::from_millis(100), None);
+        assert_eq!(result.unwrap().unwrap().data, b"whole");
+
+        let refused = Step {
+            event: Event::SelectionNotify(x11rb::protocol::xproto::SelectionNotifyEvent {
+                requestor: WINDOW,
+                selection: PRIMARY,
+                property: x11rb::NONE,
+                ..Default::default()
+            }),
+            ..notify(0, UTF8_STRING)
+        };
+        let (result, _) = run_script(vec![refused], Duration::from_millis(100), None);
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[test]
+    fn test_transfer_waits_for_each_chunk() {
+        // Slower in total than the timeout, but each chunk is in time
+        let script = vec![
+            notify(0, INCR),
+            chunk(60, b"a"),
+            chunk(60, b"b"),
+            chunk(60, b"c"),
+            chunk(60, b""),
+        ];
+        let (result, _) = run_script(script, Duration::from_millis(150), None);
+        assert_eq!(result.unwrap().unwrap().data, b"abc");
+
+        let script = vec![notify(0, INCR), chunk(0, b"a"), chunk(400, b"")];
+        let (result, left) = run_script(script, Duration::from_millis(150), None);
+        assert!(matches!(result, Err(SelectionError::Timeout { .. })));
+        assert_eq!(left, 1);
+    }
+
+    #[test]
+    fn test_transfer_stops_at_the_limit() {
+        let script = vec![
+            notify(0, INCR),
+            chunk(0, b"abc"),
+            chunk(0, b"def"),
+            chunk(0, b"ghi"),
+            chunk(0, b""),
+        ];
+        let (result, left) = run_script(script, Duration::from_millis(100), Some(4));
+        let converted = result.unwrap().unwrap();
+        assert_eq!(converted.data, b"abcd");
+        assert!(converted.truncated);
+        // The rest is left for destroying the window to cancel
+        assert_eq!(left, 2);
+    }
+
+    #[test]
+    fn test_window_info_resolves_the_executable() {
+        let info = WindowInfo {
+            class: Some("Selectic".to_string()),
+            pid: Some(std::process::id()),
+            title: None,
+        };
+        let app = info.into_app_info();
+
+        assert_eq!(app.executable, std::env::current_exe().ok());
+        assert_eq!(app.identifier.as_deref(), Some("Selectic"));
+        assert_ne!(app.name.as_deref(), Some("Selectic"));
+    }
+
+    #[test]
+    fn test_decode_latin1() {
+        assert_eq!(decode(b"\xe9t\xe9", Encoding::Latin1), "été");
+    }
+}
@@ -1,20 +1,75 @@
 //! macOS implementation for the selection library
 
 use accessibility_ng::{AXAttribute, AXUIElement};
-use accessibility_sys_ng::{kAXFocusedUIElementAttribute, kAXSelectedTextAttribute};
+use accessibility_sys_ng::{
+    kAXErrorAttributeUnsupported, kAXErrorCannotComplete, kAXErrorNoValue,
+    kAXSelectedTextAttribute, kAXTrustedCheckOptionPrompt, AXIsProcessTrusted,
+    AXIsProcessTrustedWithOptions,
+};
+use core_foundation::base::{CFRange, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
 use core_foundation::string::CFString;
-use log::{error, info};
-use std::process::Command;
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{Selection, SelectionError, Selector};
+use crate::context;
+use crate::privacy::redact;
+use crate::{
+    capture, pick_preferred, sort_by_richness, AccessibilityRole, AppInfo, AppRule, Capabilities,
+    ContentType, Rect, Selection, SelectionBounds, SelectionContext, SelectionError,
+    SelectionMethod, SelectionObserver, SelectionOptions, Selector,
+};
+
+mod app;
+mod ax;
+mod electron;
+mod finder;
+#[cfg(feature = "hotkey")]
+mod hotkey;
+#[cfg(feature = "hotkey")]
+pub(crate) use hotkey::start_hotkey;
+mod java;
+mod pasteboard;
+#[cfg_attr(feature = "fake-platform", allow(dead_code))]
+mod watcher;
+#[cfg_attr(feature = "fake-platform", allow(unused_imports))]
+pub(crate) use watcher::start_watcher;
+
+const TEXT_MIME: &str = "text/plain";
+
+/// Role of windows, which is where focus ends up when an app exposes no
+/// focusable elements
+const WINDOW_ROLE: &str = "AXWindow";
+const FILES_MIME: &str = "text/uri-list";
+const IMAGE_MIME: &str = "image/*";
+
+/// How long the focused application gets to read the pasteboard after Cmd+V,
+/// unless the pasteboard changes first
+const PASTE_SETTLE: Duration = Duration::from_millis(200);
+
+/// Values longer than this, in UTF-16 code units, are never copied as a whole
+/// when reading the selection context
+const MAX_CONTEXT_VALUE_LENGTH: isize = 100_000;
+
+/// Selections spanning more lines than this only get the union rect
+const MAX_BOUNDS_LINES: isize = 64;
 
 /// macOS implementation of the Selector trait
-pub struct MacOSSelector;
+pub struct MacOSSelector {
+    options: SelectionOptions,
+}
 
 impl MacOSSelector {
     /// Create a new macOS selector
     pub fn new() -> Self {
-        MacOSSelector
+        Self::with_options(SelectionOptions::default())
+    }
+
+    /// Create a new macOS selector with custom options
+    pub fn with_options(options: SelectionOptions) -> Self {
+        MacOSSelector { options }
     }
 }
 
@@ -25,29 +80,183 @@ impl Default for MacOSSelector {
 }
 
 impl Selector for MacOSSelector {
-    /// Get user selection using the best available method for macOS
-    fn get_selection(&self) -> Result<Selection, SelectionError> {
-        // Try accessibility API first
-        match get_selection_by_accessibility() {
-            Ok(selection) if !selection.is_empty() => {
-                info!("Retrieved selection via macOS accessibility API");
-                Ok(selection)
+    /// The frontmost application's rule; both methods need the
+    /// accessibility permission, so it's checked first
+    fn capture_rule(&self) -> Result<AppRule, SelectionError> {
+        prepare_capture(&self.options).map(|(_, rule, _)| rule)
+    }
+
+    fn observer(&self) -> Option<Arc<dyn SelectionObserver>> {
+        self.options.active_observer()
+    }
+
+    fn trusts_empty_accessibility(&self) -> bool {
+        self.options.trust_empty_accessibility
+    }
+
+    /// The accessibility API only provides plain text, so it's only tried
+    /// when plain text is what the caller wants most
+    fn get_selection_raw(&self, preferred: &[&str]) -> Result<Selection, SelectionError> {
+        let accessibility = match preferred.first() {
+            None => return self.get_selection(),
+            Some(mime) if ContentType::Text.matches_mime(mime) => AccessibilityRole::Answer,
+            Some(_) => AccessibilityRole::Skip,
+        };
+        capture(self, accessibility, |method| match method {
+            SelectionMethod::Accessibility => {
+                pick_preferred(vec![self.get_selection_by_accessibility()?], preferred)
             }
-            Ok(_) => {
-                info!("Selection via macOS accessibility API is empty");
-                // Fall back to clipboard method
-                get_selection_by_clipboard()
+            SelectionMethod::Clipboard => {
+                let (options, _, frontmost) = prepare_capture(&self.options)?;
+                self.options
+                    .finish(get_selection_fallback(&options, preferred, frontmost)?)
             }
-            Err(err) => {
-                error!(
-                    "Error getting selection via macOS accessibility API: {}",
-                    err
-                );
-                // Fall back to clipboard method
-                get_selection_by_clipboard()
+        })
+    }
+
+    /// The representations come from one Cmd+C; the accessibility API's
+    /// plain text stands in when copying fails
+    fn get_selection_all(&self) -> Result<Vec<Selection>, SelectionError> {
+        capture(self, AccessibilityRole::StandIn, |method| match method {
+            SelectionMethod::Accessibility => Ok(vec![self.get_selection_by_accessibility()?]),
+            SelectionMethod::Clipboard => get_selection_all_by_clipboard(&self.options)?
+                .into_iter()
+                .map(|selection| self.options.finish(selection))
+                .collect(),
+        })
+    }
+
+    /// `NoSelectedContent` means the focused element reported an empty
+    /// selection; elements the selection can't be read from at all, such
+    /// as ones without a selected text attribute, are `MethodUnavailable`
+    fn get_selection_by_accessibility(&self) -> Result<Selection, SelectionError> {
+        match get_selection_by_accessibility(&self.options) {
+            Ok(selection) if selection.is_empty() => Err(SelectionError::NoSelectedContent),
+            Ok(selection) => {
+                info!("Retrieved selection via macOS accessibility API");
+                self.options.finish(selection)
             }
+            Err(SelectionError::NoSelectedContent) => Err(SelectionError::MethodUnavailable(
+                "the focused element exposes no selected text".to_string(),
+            )),
+            Err(err) => Err(err),
         }
     }
+
+    /// Finder is asked for its selected files instead of being sent Cmd+C
+    fn get_selection_by_clipboard(&self) -> Result<Selection, SelectionError> {
+        // Posted keystrokes are silently dropped without the permission
+        let (options, _, frontmost) = prepare_capture(&self.options)?;
+        self.options
+            .finish(get_selection_fallback(&options, &[], frontmost)?)
+    }
+
+    fn get_selection_bounds(&self) -> Result<SelectionBounds, SelectionError> {
+        get_selection_bounds(&self.options)
+    }
+
+    fn get_caret_position(&self) -> Result<Rect, SelectionError> {
+        get_caret_position(&self.options)
+    }
+
+    fn get_selection_context(&self, chars: usize) -> Result<SelectionContext, SelectionError> {
+        get_selection_context(&self.options, chars)
+    }
+
+    fn set_selected_text(&self, text: &str) -> Result<(), SelectionError> {
+        set_selected_text(&self.options, text)
+    }
+}
+
+/// Check what a capture needs and look up the frontmost application's
+/// rule, returning the options adjusted by it
+fn prepare_capture(
+    options: &SelectionOptions,
+) -> Result<(SelectionOptions, AppRule, Option<AppInfo>), SelectionError> {
+    // Both methods need the permission: without it AX queries fail and
+    // posted keystrokes are silently dropped
+    if !is_accessibility_trusted() {
+        return Err(accessibility_permission_denied());
+    }
+
+    let frontmost = app::frontmost_app_info();
+    let rule = options.rule_for_app(frontmost.as_ref().and_then(|app| app.identifier.as_deref()));
+    if !rule.allow_capture {
+        info!("Capture is disabled for the frontmost application");
+        return Err(SelectionError::PermissionDenied(
+            "Capture is disabled for the frontmost application".to_string(),
+        ));
+    }
+
+    let mut options = options.clone();
+    if let Some(timeout) = rule.copy_timeout {
+        options.copy_timeout = timeout;
+    }
+    Ok((options, rule, frontmost))
+}
+
+/// Get every representation of the selection, richest first, from one Cmd+C
+///
+/// Finder is asked for its selected files instead.
+fn get_selection_all_by_clipboard(
+    options: &SelectionOptions,
+) -> Result<Vec<Selection>, SelectionError> {
+    let (options, _, frontmost) = prepare_capture(options)?;
+
+    if !cfg!(feature = "sandbox")
+        && frontmost.as_ref().and_then(|app| app.identifier.as_deref())
+            == Some(finder::FINDER_BUNDLE_ID)
+    {
+        match finder::selected_files(options.script_timeout) {
+            Ok(files) if files.is_empty() => return Err(SelectionError::NoSelectedContent),
+            Ok(files) => return Ok(vec![Selection::new_files(files).with_source_app(frontmost)]),
+            Err(err) => warn!("Failed to ask Finder for its selection: {}", err),
+        }
+    }
+
+    let (selections, source_app) = copy_and_read(&options, || pasteboard::read_all(&options))?;
+    let mut selections: Vec<Selection> = selections
+        .into_iter()
+        .map(|selection| {
+            selection
+                .with_source_app(source_app.clone())
+                .with_method(SelectionMethod::Clipboard)
+                .left_on_clipboard(!options.restore_clipboard)
+        })
+        .collect();
+    sort_by_richness(&mut selections);
+    Ok(selections)
+}
+
+/// Get the selection when the accessibility API didn't provide one
+///
+/// Finder is asked for its selected files directly instead of faking Cmd+C;
+/// everything else goes through the clipboard.
+fn get_selection_fallback(
+    options: &SelectionOptions,
+    preferred: &[&str],
+    frontmost: Option<AppInfo>,
+) -> Result<Selection, SelectionError> {
+    let files_wanted = preferred.is_empty()
+        || preferred
+            .iter()
+            .any(|mime| ContentType::Files.matches_mime(mime));
+
+    // Scripting Finder needs the Apple Events entitlement in the sandbox
+    if files_wanted
+        && !cfg!(feature = "sandbox")
+        && frontmost.as_ref().and_then(|app| app.identifier.as_deref())
+            == Some(finder::FINDER_BUNDLE_ID)
+    {
+        match finder::selected_files(options.script_timeout) {
+            Ok(files) if files.is_empty() => return Err(SelectionError::NoSelectedContent),
+            Ok(files) => return Ok(Selection::new_files(files).with_source_app(frontmost)),
+            Err(err) => warn!("Failed to ask Finder for its selection: {}", err),
+        }
+    }
+
+    // Fall back to clipboard method
+    get_selection_by_clipboard(options, preferred)
 }
 
 /// Get selected text from macOS using the best available method
@@ -66,86 +275,688 @@ pub fn get_macos_text() -> Result<String, SelectionError> {
     }
 }
 
+/// Check whether the process has been granted Accessibility permission
+///
+/// With `prompt` set, macOS shows the system dialog asking the user to grant
+/// the permission if it's missing. The return value reflects the state at the
+/// time of the call, not the user's answer to the dialog.
+pub fn request_accessibility_permission(prompt: bool) -> bool {
+    let key = unsafe { CFString::wrap_under_get_rule(kAXTrustedCheckOptionPrompt) };
+    let options =
+        CFDictionary::from_CFType_pairs(&[(key.as_CFType(), CFBoolean::from(prompt).as_CFType())]);
+
+    unsafe { AXIsProcessTrustedWithOptions(options.as_concrete_TypeRef()) }
+}
+
+/// Check the Accessibility permission without prompting
+fn is_accessibility_trusted() -> bool {
+    unsafe { AXIsProcessTrusted() }
+}
+
+fn accessibility_permission_denied() -> SelectionError {
+    SelectionError::PermissionDenied(
+        "Accessibility permission has not been granted to this process".to_string(),
+    )
+}
+
+/// Convert an accessibility error from `action` into a `SelectionError`
+///
+/// An application that didn't answer within the messaging timeout is
+/// reported as `Timeout`; see the `From` conversion for the other codes.
+fn ax_error(
+    err: accessibility_ng::Error,
+    action: &'static str,
+    options: &SelectionOptions,
+) -> SelectionError {
+    match err {
+        accessibility_ng::Error::Ax(code) if code == kAXErrorCannotComplete => {
+            warn!("The application isn't responding to accessibility requests");
+            SelectionError::Timeout {
+                phase: action,
+                waited: options.accessibility_timeout,
+            }
+        }
+        err => {
+            debug!("Accessibility error while {}: {}", action, err);
+            err.into()
+        }
+    }
+}
+
+fn synthetic_input_disabled() -> SelectionError {
+    SelectionError::MethodUnavailable("simulated keystrokes are disabled".to_string())
+}
+
+#[link(name = "Carbon", kind = "framework")]
+extern "C" {
+    fn IsSecureEventInputEnabled() -> u8;
+}
+
+/// Check whether secure event input is on
+///
+/// Password fields and some apps (password managers, terminals with secure
+/// keyboard entry) enable it; simulated keystrokes are then ignored, and
+/// posting them can stall.
+fn is_secure_input_enabled() -> bool {
+    unsafe { IsSecureEventInputEnabled() != 0 }
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGPreflightListenEventAccess() -> bool;
+}
+
+/// Check whether the process may listen to keyboard events, which the
+/// hotkey's event tap needs (Input Monitoring)
+fn is_input_monitoring_trusted() -> bool {
+    unsafe { CGPreflightListenEventAccess() }
+}
+
+/// Probe the capabilities of the macOS backend
+pub(crate) fn capabilities() -> Capabilities {
+    Capabilities {
+        platform: "macos",
+        accessibility_trusted: Some(is_accessibility_trusted()),
+        secure_input: Some(is_secure_input_enabled()),
+        input_monitoring_trusted: Some(is_input_monitoring_trusted()),
+        assistive_technology: Some(app::assistive_technology_active()),
+        apple_events: Some(!cfg!(feature = "sandbox")),
+        clipboard_fallback: Some(SelectionOptions::default().synthetic_input),
+        input_injection: None,
+        primary_selection: None,
+        selection_events: Some(true),
+        focused_app: Some(true),
+        global_hotkeys: None,
+    }
+}
+
+/// Find the focused element to read the selection from
+///
+/// The system-wide lookup fails for some apps (Electron before accessibility
+/// is enabled, some Java apps, apps with odd focus handling) or returns an
+/// element without any selection attributes, while asking the frontmost
+/// application directly often works.
+///
+/// Accessibility calls block until the application answers, so they're
+/// limited to `accessibility_timeout`; an application that's hanging fails
+/// with `Timeout`.
+fn find_focused_element(options: &SelectionOptions) -> Result<AXUIElement, SelectionError> {
+    let system_wide = AXUIElement::system_wide();
+    // Set on the system-wide element, the timeout applies to all elements
+    ax::set_messaging_timeout(&system_wide, options.accessibility_timeout);
+
+    let system_focused = ax::focused_element(&system_wide);
+
+    if let Ok(element) = &system_focused {
+        if ax::exposes_selection(element) {
+            return Ok(element.clone());
+        }
+    }
+
+    let app_focused = match app::frontmost_pid() {
+        Some(pid) => {
+            let application = AXUIElement::application(pid);
+            ax::set_messaging_timeout(&application, options.accessibility_timeout);
+            ax::focused_element(&application)
+        }
+        None => Err(accessibility_ng::Error::NotFound),
+    };
+
+    match (app_focused, system_focused) {
+        (Ok(element), _) => {
+            info!("Using the focused element of the frontmost application");
+            Ok(element)
+        }
+        (Err(_), Ok(element)) => Ok(element),
+        (Err(accessibility_ng::Error::Ax(code)), _)
+            if code == kAXErrorNoValue || code == kAXErrorAttributeUnsupported =>
+        {
+            Err(SelectionError::NoFocusedElement)
+        }
+        (Err(accessibility_ng::Error::Ax(code)), _) => Err(ax_error(
+            accessibility_ng::Error::Ax(code),
+            "reading the focused element",
+            options,
+        )),
+        (Err(_), Err(_)) => Err(SelectionError::NoFocusedElement),
+    }
+}
+
 /// Get user selection using macOS Accessibility API
-fn get_selection_by_accessibility() -> Result<Selection, SelectionError> {
-    let system_element = AXUIElement::system_wide();
+fn get_selection_by_accessibility(options: &SelectionOptions) -> Result<Selection, SelectionError> {
+    if !is_accessibility_trusted() {
+        return Err(accessibility_permission_denied());
+    }
+
+    let frontmost = app::frontmost_app_info();
+
+    // Chromium-based apps have no accessibility tree until it's requested
+    let newly_enabled = frontmost
+        .as_ref()
+        .filter(|app| electron::is_chromium_based(app))
+        .and_then(|app| app.pid)
+        .and_then(|pid| i32::try_from(pid).ok())
+        .is_some_and(electron::enable_accessibility);
+
+    let selection = read_focused_selection(options, frontmost.as_ref());
+    if newly_enabled && !matches!(&selection, Ok(selection) if !selection.is_empty()) {
+        debug!("Retrying after enabling the accessibility tree");
+        std::thread::sleep(electron::TREE_BUILD_DELAY);
+        return read_focused_selection(options, frontmost.as_ref());
+    }
+
+    selection
+}
+
+/// Read the selected text of the focused element
+fn read_focused_selection(
+    options: &SelectionOptions,
+    frontmost: Option<&AppInfo>,
+) -> Result<Selection, SelectionError> {
+    // Without the Java Access Bridge, Swing apps expose nothing beyond their
+    // windows. JetBrains IDEs and others ship their own accessibility support,
+    // so Java apps are only reported once the regular lookup came up empty.
+    let java_app = frontmost.filter(|app| java::is_java_app(app));
+    let java_error = |app: &AppInfo| {
+        SelectionError::JavaAccessibilityUnavailable(
+            app.name
+                .clone()
+                .unwrap_or_else(|| "The application".to_string()),
+        )
+    };
+
+    let focused_element = match (find_focused_element(options), java_app) {
+        (Ok(element), Some(app)) if ax::role(&element).as_deref() == Some(WINDOW_ROLE) => {
+            return Err(java_error(app))
+        }
+        (Ok(element), _) => element,
+        (Err(SelectionError::NoFocusedElement), Some(app)) => return Err(java_error(app)),
+        (Err(err), _) => return Err(err),
+    };
+
+    // Label the selection with the app owning the element that was read, so
+    // a fast app switch can't mislabel it
+    let source_app = focused_element.pid().ok().and_then(app::app_info);
 
-    // Get focused UI element - fixing the type conversion issues
-    let focused_element_result = system_element.attribute(&AXAttribute::new(
-        &CFString::from_static_string(kAXFocusedUIElementAttribute),
-    ));
+    read_selected_text(options, &focused_element).map(|selection| {
+        selection
+            .with_source_app(source_app)
+            .with_method(SelectionMethod::Accessibility)
+    })
+}
 
-    if focused_element_result.is_err() {
-        return Err(SelectionError::NoFocusedElement);
+/// Read a selection made of several ranges, joined by the range separator
+///
+/// Returns `None` when there's at most one range, which is read the usual way.
+fn read_discontiguous_selection(
+    options: &SelectionOptions,
+    focused_element: &AXUIElement,
+) -> Option<Selection> {
+    // Editors with multiple cursors report their empty ranges too
+    let ranges: Vec<CFRange> = ax::selected_text_ranges(focused_element)?
+        .into_iter()
+        .filter(|range| range.length > 0)
+        .collect();
+    if ranges.len() < 2 {
+        return None;
     }
 
-    let element_value = focused_element_result.unwrap();
-    let focused_element = match element_value.downcast_into::<AXUIElement>() {
-        Some(element) => element,
-        None => return Err(SelectionError::NoFocusedElement),
+    let texts: Vec<String> = ranges
+        .into_iter()
+        .map(|range| ax::string_for_range(focused_element, range))
+        .collect::<Option<_>>()?;
+    debug!("Read a selection of {} ranges", redact(texts.len()));
+
+    let mut selection = Selection::new_text(texts.join(&options.range_separator));
+    selection.metadata.ranges = texts;
+    Some(selection)
+}
+
+/// Read the selected text of a focused element
+fn read_selected_text(
+    options: &SelectionOptions,
+    focused_element: &AXUIElement,
+) -> Result<Selection, SelectionError> {
+    let mut selection = match read_discontiguous_selection(options, focused_element) {
+        Some(selection) => selection,
+        None => match read_selection_prefix(options, focused_element) {
+            Some(selection) => return Ok(selection),
+            None => read_single_selection(options, focused_element)?,
+        },
     };
 
-    // Get selected text from focused element
-    let selected_text_result = focused_element.attribute(&AXAttribute::new(
+    if let Some(max_bytes) = options.max_bytes {
+        selection.truncate_text(max_bytes);
+    }
+    Ok(selection)
+}
+
+/// Read only the start of a selection too long for `max_bytes`
+///
+/// Reading the selected text attribute of a select-all in a huge document
+/// copies all of it through the accessibility API, which can take seconds.
+fn read_selection_prefix(
+    options: &SelectionOptions,
+    focused_element: &AXUIElement,
+) -> Option<Selection> {
+    let max_bytes = options.max_bytes?;
+    let range = ax::selected_text_range(focused_element)?;
+
+    // Every UTF-16 code unit takes at least one byte of UTF-8
+    let max_units = isize::try_from(max_bytes).ok()?;
+    if range.length <= max_units {
+        return None;
+    }
+
+    let text = ax::string_for_range(focused_element, CFRange::init(range.location, max_units))?;
+    debug!(
+        "Selection of {} UTF-16 code units exceeds the limit, read only its start",
+        redact(range.length)
+    );
+
+    let mut selection = Selection::new_text(text);
+    selection.truncate_text(max_bytes);
+    selection.metadata.truncated = true;
+    Some(selection)
+}
+
+/// Read a selection made of a single range
+fn read_single_selection(
+    options: &SelectionOptions,
+    focused_element: &AXUIElement,
+) -> Result<Selection, SelectionError> {
+    // Get selected text from focused element. Elements without the attribute
+    // are read the other ways below; anything else is a real failure.
+    let selected_text = match focused_element.attribute(&AXAttribute::new(
         &CFString::from_static_string(kAXSelectedTextAttribute),
-    ));
+    )) {
+        Ok(value) => value
+            .downcast_into::<CFString>()
+            .map(|text| text.to_string()),
+        Err(accessibility_ng::Error::Ax(code))
+            if code == kAXErrorNoValue || code == kAXErrorAttributeUnsupported =>
+        {
+            None
+        }
+        Err(err) => return Err(ax_error(err, "reading the selected text", options)),
+    };
 
-    if selected_text_result.is_err() {
-        return Err(SelectionError::NoSelectedContent);
+    if let Some(text) = selected_text.filter(|text| !text.is_empty()) {
+        return Ok(Selection::new_text(text));
+    }
+
+    // WebKit only exposes the selection through text markers on the web area
+    if let Some(web_area) = ax::enclosing_web_area(focused_element) {
+        return match ax::text_marker_selected_text(&web_area) {
+            Some(text) => Ok(Selection::new_text(text)),
+            None => Err(SelectionError::NoSelectedContent),
+        };
+    }
+
+    // PDF views keep their selection as text markers too, without a role
+    // to recognize them by
+    if let Some(text) = ax::enclosing_text_marker_element(focused_element)
+        .and_then(|element| ax::text_marker_selected_text(&element))
+        .filter(|text| !text.is_empty())
+    {
+        return Ok(Selection::new_text(text));
+    }
+
+    // Some apps only expose the selected range, so fetch the string for it
+    match ax::selected_text_range(focused_element) {
+        Some(range) if range.length == 0 => Ok(Selection::new_text(String::new())),
+        Some(range) => match ax::string_for_range(focused_element, range) {
+            Some(text) => Ok(Selection::new_text(text)),
+            None => Err(SelectionError::NoSelectedContent),
+        },
+        None => Err(SelectionError::NoSelectedContent),
     }
+}
+
+/// Get the screen bounds of the selection in the focused element
+fn get_selection_bounds(options: &SelectionOptions) -> Result<SelectionBounds, SelectionError> {
+    if !is_accessibility_trusted() {
+        return Err(accessibility_permission_denied());
+    }
+
+    let focused_element = find_focused_element(options)?;
 
-    let text_value = selected_text_result.unwrap();
-    let selected_text = match text_value.downcast_into::<CFString>() {
-        Some(text) => text,
-        None => return Err(SelectionError::NoSelectedContent),
+    if let Some(web_area) = ax::enclosing_web_area(&focused_element) {
+        return ax::web_area_selection_bounds(&web_area)
+            .filter(|rect| !rect.is_empty())
+            .and_then(|rect| SelectionBounds::from_lines(vec![rect]))
+            .ok_or(SelectionError::NoSelectedContent);
+    }
+
+    let range = ax::selected_text_range(&focused_element)
+        .filter(|range| range.length > 0)
+        .ok_or(SelectionError::NoSelectedContent)?;
+
+    let lines = line_bounds(&focused_element, range).unwrap_or_default();
+    let lines = if lines.is_empty() {
+        ax::bounds_for_range(&focused_element, range)
+            .filter(|rect| !rect.is_empty())
+            .into_iter()
+            .collect()
+    } else {
+        lines
     };
 
-    Ok(Selection::new_text(selected_text.to_string()))
+    SelectionBounds::from_lines(lines).ok_or(SelectionError::NoSelectedContent)
 }
 
-/// Get user selection using macOS clipboard
-fn get_selection_by_clipboard() -> Result<Selection, SelectionError> {
-    const APPLE_SCRIPT: &str = r#"
-use AppleScript version "2.4"
-use scripting additions
-use framework "Foundation"
-use framework "AppKit"
+/// Split a range at line boundaries and get the bounds of each piece
+///
+/// Uses the element's own line layout, so soft-wrapped lines are split too.
+fn line_bounds(element: &AXUIElement, range: CFRange) -> Option<Vec<Rect>> {
+    let end = range.location + range.length;
+    let first_line = ax::line_for_index(element, range.location)?;
+    let last_line = ax::line_for_index(element, end - 1)?;
 
-set initialClipboard to the clipboard
+    if last_line < first_line || last_line - first_line >= MAX_BOUNDS_LINES {
+        return None;
+    }
 
-tell application "System Events"
-    keystroke "c" using {command down}
-end tell
-delay 0.1
+    let mut lines = Vec::new();
+    for line in first_line..=last_line {
+        let line_range = ax::range_for_line(element, line)?;
+        let start = line_range.location.max(range.location);
+        let stop = (line_range.location + line_range.length).min(end);
+        if stop <= start {
+            continue;
+        }
 
-set copiedText to the clipboard
+        if let Some(rect) = ax::bounds_for_range(element, CFRange::init(start, stop - start)) {
+            if !rect.is_empty() {
+                lines.push(rect);
+            }
+        }
+    }
 
-if copiedText is not initialClipboard and copiedText is not "" then
-    set the clipboard to initialClipboard
-end if
+    Some(lines)
+}
 
-copiedText
-"#;
+/// Get the caret position in the focused element
+///
+/// Many elements report an empty rect for a zero-length range, so the range
+/// is then widened by one character and the matching edge of its bounds used.
+fn get_caret_position(options: &SelectionOptions) -> Result<Rect, SelectionError> {
+    if !is_accessibility_trusted() {
+        return Err(accessibility_permission_denied());
+    }
 
-    let output = Command::new("osascript")
-        .arg("-e")
-        .arg(APPLE_SCRIPT)
-        .output()?;
+    let focused_element = find_focused_element(options)?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(SelectionError::AppleScriptError(stderr.to_string()));
+    if let Some(web_area) = ax::enclosing_web_area(&focused_element) {
+        return ax::web_area_selection_bounds(&web_area)
+            .filter(|rect| rect.height > 0.0)
+            .map(|rect| Rect::new(rect.x, rect.y, 0.0, rect.height))
+            .ok_or(SelectionError::NoFocusedElement);
     }
 
-    let content = String::from_utf8(output.stdout)?;
+    let range =
+        ax::selected_text_range(&focused_element).ok_or(SelectionError::NoFocusedElement)?;
+    let caret = CFRange::init(range.location, 0);
 
-    // Check if we got a file path
-    if content.starts_with("[FILE]") {
-        let file_path = content.trim_start_matches("[FILE]").to_string();
-        Ok(Selection::new_file(file_path))
+    if let Some(rect) =
+        ax::bounds_for_range(&focused_element, caret).filter(|rect| rect.height > 0.0)
+    {
+        return Ok(Rect::new(rect.x, rect.y, 0.0, rect.height));
     }
-    // Otherwise, assume it's text
-    else {
-        Ok(Selection::new_text(content))
+
+    // Use the leading edge of the next character, or the trailing edge of the
+    // previous one when the caret is at the end of the text
+    let next = ax::bounds_for_range(&focused_element, CFRange::init(range.location, 1))
+        .filter(|rect| rect.height > 0.0)
+        .map(|rect| Rect::new(rect.x, rect.y, 0.0, rect.height));
+    let previous = || {
+        (range.location > 0)
+            .then(|| ax::bounds_for_range(&focused_element, CFRange::init(range.location - 1, 1)))
+            .flatten()
+            .filter(|rect| rect.height > 0.0)
+            .map(|rect| Rect::new(rect.x + rect.width, rect.y, 0.0, rect.height))
+    };
+
+    next.or_else(previous)
+        .ok_or(SelectionError::NoFocusedElement)
+}
+
+/// Get the selection of the focused element with the text around it
+fn get_selection_context(
+    options: &SelectionOptions,
+    chars: usize,
+) -> Result<SelectionContext, SelectionError> {
+    if !is_accessibility_trusted() {
+        return Err(accessibility_permission_denied());
+    }
+
+    let focused_element = find_focused_element(options)?;
+    let range =
+        ax::selected_text_range(&focused_element).ok_or(SelectionError::NoSelectedContent)?;
+    let length = ax::number_of_characters(&focused_element);
+
+    if length.is_none_or(|length| length <= MAX_CONTEXT_VALUE_LENGTH) {
+        if let Some(text) = ax::value(&focused_element) {
+            return Ok(SelectionContext::from_utf16_range(
+                &text,
+                usize::try_from(range.location).unwrap_or(0),
+                usize::try_from(range.length).unwrap_or(0),
+                chars,
+            ));
+        }
+    }
+
+    read_context_window(&focused_element, range, length, chars)
+}
+
+/// Fetch only the text around the selection instead of the whole value
+///
+/// Each character is at most two UTF-16 code units, so a window of
+/// `2 * chars + 1` units always holds `chars` complete characters even when
+/// its edge splits a surrogate pair; the partial character is trimmed off.
+fn read_context_window(
+    element: &AXUIElement,
+    range: CFRange,
+    length: Option<isize>,
+    chars: usize,
+) -> Result<SelectionContext, SelectionError> {
+    let window = isize::try_from(chars)
+        .unwrap_or(isize::MAX / 4)
+        .saturating_mul(2)
+        .saturating_add(1);
+    let end = range.location + range.length;
+
+    let selected = if range.length > 0 {
+        ax::string_for_range(element, range).ok_or(SelectionError::NoSelectedContent)?
+    } else {
+        String::new()
+    };
+
+    let before_start = (range.location - window).max(0);
+    let before = ax::string_for_range(
+        element,
+        CFRange::init(before_start, range.location - before_start),
+    )
+    .unwrap_or_default();
+
+    let after_length = match length {
+        Some(length) => window.min(length - end).max(0),
+        None => window,
+    };
+    let after = ax::string_for_range(element, CFRange::init(end, after_length)).unwrap_or_default();
+
+    Ok(SelectionContext {
+        before: context::last_chars(&before, chars).to_string(),
+        selected,
+        after: context::first_chars(&after, chars).to_string(),
+    })
+}
+
+/// Replace the selection in the focused element with `text`
+///
+/// Writes `kAXSelectedTextAttribute` directly when the element allows it,
+/// which replaces exactly the selected range without touching the
+/// pasteboard. Only elements that don't expose a settable attribute get the
+/// Cmd+V fallback; a failed write to a settable attribute is reported instead,
+/// since pasting has different side effects.
+fn set_selected_text(options: &SelectionOptions, text: &str) -> Result<(), SelectionError> {
+    if !is_accessibility_trusted() {
+        return Err(accessibility_permission_denied());
+    }
+
+    let focused_element = find_focused_element(options)?;
+    let attribute = AXAttribute::new(&CFString::from_static_string(kAXSelectedTextAttribute));
+
+    let settable = match focused_element.is_settable(&attribute) {
+        Ok(settable) => settable,
+        Err(accessibility_ng::Error::Ax(code)) if code == kAXErrorAttributeUnsupported => false,
+        Err(err) => {
+            return Err(ax_error(
+                err,
+                "checking whether the selected text is settable",
+                options,
+            ))
+        }
+    };
+
+    if !settable {
+        info!("Selected text attribute is not settable, pasting instead");
+        return paste_text(options, text);
+    }
+
+    focused_element
+        .set_attribute(&attribute, CFString::new(text).as_CFType())
+        .map_err(|err| ax_error(err, "setting the selected text", options))
+}
+
+/// Paste `text` into the focused application through the pasteboard
+fn paste_text(options: &SelectionOptions, text: &str) -> Result<(), SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+    if is_secure_input_enabled() {
+        return Err(SelectionError::SecureField);
+    }
+
+    let snapshot = pasteboard::snapshot();
+
+    pasteboard::write_string(text, options.mark_transient);
+    let written_count = pasteboard::change_count();
+    let result = pasteboard::post_paste();
+
+    // The application reads the pasteboard asynchronously when handling
+    // Cmd+V, which leaves the change count alone; stop waiting early once
+    // something else writes to the pasteboard, as the snapshot then isn't
+    // put back anyway
+    if result.is_ok()
+        && pasteboard::wait_for_change(written_count, PASTE_SETTLE, options.copy_poll_interval)
+    {
+        debug!("Pasteboard changed while pasting");
+    }
+    pasteboard::restore_unless_changed(&snapshot, written_count, options.mark_transient);
+
+    result
+}
+
+/// Get user selection using macOS clipboard
+///
+/// Reads the copied content in the first available format of `preferred`,
+/// see `copy_and_read`.
+fn get_selection_by_clipboard(
+    options: &SelectionOptions,
+    preferred: &[&str],
+) -> Result<Selection, SelectionError> {
+    let (selection, source_app) = copy_and_read(options, || {
+        // Without a preference, text wins over file URLs and images but a
+        // copy that only produced those still returns them
+        if preferred.is_empty() {
+            pasteboard::negotiate(&[TEXT_MIME, FILES_MIME, IMAGE_MIME], options)
+        } else {
+            pasteboard::negotiate(preferred, options)
+        }
+    })
+    .map_err(|err| match err {
+        // Without a preference, a copy that produced nothing readable just
+        // means nothing usable was selected
+        SelectionError::InvalidContentType { .. } if preferred.is_empty() => {
+            SelectionError::NoSelectedContent
+        }
+        err => err,
+    })?;
+
+    Ok(selection
+        .with_source_app(source_app)
+        .with_method(SelectionMethod::Clipboard)
+        .left_on_clipboard(!options.restore_clipboard))
+}
+
+/// Post Cmd+C to the focused application and read the pasteboard with
+/// `read`, together with the application the keystroke went to
+///
+/// Waits for the pasteboard change count to increment and restores the
+/// previous pasteboard contents afterwards, unless something else was
+/// copied in the meantime or `restore_clipboard` is off. Returns
+/// `NoSelectedContent` if the copy didn't change the pasteboard.
+fn copy_and_read<T>(
+    options: &SelectionOptions,
+    read: impl FnOnce() -> Result<T, SelectionError>,
+) -> Result<(T, Option<AppInfo>), SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+
+    if is_secure_input_enabled() {
+        warn!("Secure input is active, not simulating Cmd+C");
+        return Err(SelectionError::SecureField);
+    }
+
+    if options.require_text_focus
+        && !find_focused_element(options).is_ok_and(|element| ax::holds_selection(&element))
+    {
+        info!("Focused element holds no selection, not simulating Cmd+C");
+        return Err(SelectionError::NoSelectedContent);
+    }
+
+    // The frontmost app is the one receiving the keystroke
+    let source_app = app::frontmost_app_info();
+    let snapshot = options.restore_clipboard.then(pasteboard::snapshot);
+    let initial_count = pasteboard::change_count();
+
+    pasteboard::post_copy()?;
+
+    // An unchanged pasteboard means nothing was copied; what's on it is the
+    // user's previous clipboard, not the selection
+    if !pasteboard::wait_for_change(
+        initial_count,
+        options.copy_timeout,
+        options.copy_poll_interval,
+    ) {
+        warn!("Pasteboard did not change after Cmd+C, assuming nothing is selected");
+        return Err(SelectionError::NoSelectedContent);
+    }
+
+    // Anything copied after our copy landed belongs to someone else
+    let copied_count = pasteboard::change_count();
+    let read = read();
+    match snapshot {
+        Some(snapshot) => {
+            pasteboard::restore_unless_changed(&snapshot, copied_count, options.mark_transient);
+        }
+        None => debug!("Leaving the copied content on the pasteboard"),
+    }
+
+    Ok((read?, source_app))
+}
+
+/// Read the current pasteboard contents without simulating a copy
+///
+/// Types are negotiated in the order of the MIME preference list, the same
+/// way the clipboard fallback of `get_selection_raw` does it, within the
+/// default `SelectionOptions::max_bytes`.
+pub fn read_clipboard(preferred: &[&str]) -> Result<Selection, SelectionError> {
+    let options = SelectionOptions::default();
+    if preferred.is_empty() {
+        return pasteboard::negotiate(&[TEXT_MIME], &options);
     }
+    pasteboard::negotiate(preferred, &options)
 }
@@ -0,0 +1,53 @@
+//! Information about running applications
+
+use objc2::rc::Retained;
+use objc2_app_kit::{NSRunningApplication, NSWorkspace};
+use std::path::PathBuf;
+
+use crate::AppInfo;
+
+/// Get the application that currently owns the menu bar and keyboard focus
+pub(crate) fn frontmost_application() -> Option<Retained<NSRunningApplication>> {
+    NSWorkspace::sharedWorkspace().frontmostApplication()
+}
+
+/// Get the process id of the frontmost application
+pub(crate) fn frontmost_pid() -> Option<i32> {
+    frontmost_application()
+        .map(|app| app.processIdentifier())
+        .filter(|pid| *pid > 0)
+}
+
+/// Check whether VoiceOver or Switch Control is running
+///
+/// Both query the accessibility API themselves, and heavy use of it by
+/// another process makes them noticeably less responsive.
+pub(crate) fn assistive_technology_active() -> bool {
+    let workspace = NSWorkspace::sharedWorkspace();
+    workspace.isVoiceOverEnabled() || workspace.isSwitchControlEnabled()
+}
+
+/// Describe the running application with the given process id
+///
+/// Only uses NSRunningApplication, which needs no extra permissions.
+pub(crate) fn app_info(pid: i32) -> Option<AppInfo> {
+    NSRunningApplication::runningApplicationWithProcessIdentifier(pid).map(|app| describe(&app))
+}
+
+/// Describe the frontmost application
+pub(crate) fn frontmost_app_info() -> Option<AppInfo> {
+    frontmost_application().map(|app| describe(&app))
+}
+
+fn describe(app: &NSRunningApplication) -> AppInfo {
+    AppInfo {
+        name: app.localizedName().map(|name| name.to_string()),
+        identifier: app.bundleIdentifier().map(|id| id.to_string()),
+        pid: u32::try_from(app.processIdentifier()).ok(),
+        executable: app
+            .executableURL()
+            .and_then(|url| url.path())
+            .map(|path| PathBuf::from(path.to_string())),
+        window_title: None,
+    }
+}
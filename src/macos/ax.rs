@@ -0,0 +1,309 @@
+//! Helpers for accessibility attributes not covered by accessibility-ng
+
+use accessibility_ng::{AXAttribute, AXUIElement, AXValue, Error};
+use accessibility_sys_ng::{
+    kAXBoundsForRangeParameterizedAttribute, kAXFocusedUIElementAttribute,
+    kAXLineForIndexParameterizedAttribute, kAXNumberOfCharactersAttribute, kAXParentAttribute,
+    kAXRangeForLineParameterizedAttribute, kAXRoleAttribute, kAXSelectedTextAttribute,
+    kAXSelectedTextRangeAttribute, kAXSelectedTextRangesAttribute,
+    kAXStringForRangeParameterizedAttribute, kAXValueAttribute,
+};
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFRange, CFType, CFTypeRef, TCFType};
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_graphics::geometry::CGRect;
+use log::debug;
+use std::time::Duration;
+
+use crate::Rect;
+
+/// Role of WebKit content areas (Safari, Mail, WKWebView)
+const WEB_AREA_ROLE: &str = "AXWebArea";
+
+/// Selection as an opaque text marker range, exposed by WebKit web areas
+/// and PDFKit views
+const SELECTED_TEXT_MARKER_RANGE_ATTRIBUTE: &str = "AXSelectedTextMarkerRange";
+
+/// Parameterized attribute returning the string for a text marker range
+const STRING_FOR_TEXT_MARKER_RANGE_ATTRIBUTE: &str = "AXStringForTextMarkerRange";
+
+/// Parameterized attribute returning the bounds of a text marker range
+const BOUNDS_FOR_TEXT_MARKER_RANGE_ATTRIBUTE: &str = "AXBoundsForTextMarkerRange";
+
+/// Roles of elements that can hold a selection worth copying
+const SELECTABLE_ROLES: [&str; 9] = [
+    "AXTextField",
+    "AXTextArea",
+    "AXComboBox",
+    "AXStaticText",
+    WEB_AREA_ROLE,
+    "AXTable",
+    "AXOutline",
+    "AXList",
+    "AXBrowser",
+];
+
+/// How far up the element tree to look for an enclosing web area
+const MAX_PARENT_DEPTH: usize = 32;
+
+/// Build an untyped attribute from one of the `kAX...Attribute` names
+pub(crate) fn attribute(name: &'static str) -> AXAttribute<CFType> {
+    AXAttribute::new(&CFString::from_static_string(name))
+}
+
+/// Read the selected range of a text element, in UTF-16 code units
+pub(crate) fn selected_text_range(element: &AXUIElement) -> Option<CFRange> {
+    element
+        .attribute(&attribute(kAXSelectedTextRangeAttribute))
+        .ok()?
+        .downcast_into::<AXValue>()?
+        .get_value::<CFRange>()
+        .ok()
+}
+
+/// Read all selected ranges of a text element supporting multiple
+/// selections, in UTF-16 code units
+pub(crate) fn selected_text_ranges(element: &AXUIElement) -> Option<Vec<CFRange>> {
+    let ranges = element
+        .attribute(&attribute(kAXSelectedTextRangesAttribute))
+        .ok()?
+        .downcast_into::<CFArray>()?;
+
+    ranges
+        .iter()
+        .map(|value| {
+            unsafe { CFType::wrap_under_get_rule(*value as CFTypeRef) }
+                .downcast_into::<AXValue>()?
+                .get_value::<CFRange>()
+                .ok()
+        })
+        .collect()
+}
+
+/// Fetch the string for a range of a text element
+///
+/// The range is passed through to the application untouched, so it's in
+/// UTF-16 code units; the returned CFString is converted to UTF-8 as a whole.
+pub(crate) fn string_for_range(element: &AXUIElement, range: CFRange) -> Option<String> {
+    let parameter = AXValue::from_CFRange(range).ok()?;
+
+    element
+        .parameterized_attribute(
+            &attribute(kAXStringForRangeParameterizedAttribute),
+            &parameter,
+        )
+        .ok()?
+        .downcast_into::<CFString>()
+        .map(|string| string.to_string())
+}
+
+/// Read the full text of a text element
+pub(crate) fn value(element: &AXUIElement) -> Option<String> {
+    element
+        .attribute(&attribute(kAXValueAttribute))
+        .ok()?
+        .downcast_into::<CFString>()
+        .map(|value| value.to_string())
+}
+
+/// Read the length of a text element's value, in UTF-16 code units
+pub(crate) fn number_of_characters(element: &AXUIElement) -> Option<isize> {
+    element
+        .attribute(&attribute(kAXNumberOfCharactersAttribute))
+        .ok()?
+        .downcast_into::<CFNumber>()?
+        .to_i64()
+        .and_then(|count| isize::try_from(count).ok())
+}
+
+/// Get the screen bounds of a range of a text element
+///
+/// The accessibility API already reports global coordinates with the origin
+/// at the top-left of the main screen, which is the crate's convention.
+pub(crate) fn bounds_for_range(element: &AXUIElement, range: CFRange) -> Option<Rect> {
+    let parameter = AXValue::from_CFRange(range).ok()?;
+
+    element
+        .parameterized_attribute(
+            &attribute(kAXBoundsForRangeParameterizedAttribute),
+            &parameter,
+        )
+        .ok()
+        .and_then(|value| rect_from_value(&value))
+}
+
+/// Get the line number containing a character index
+pub(crate) fn line_for_index(element: &AXUIElement, index: isize) -> Option<isize> {
+    let parameter = CFNumber::from(i64::try_from(index).ok()?);
+
+    element
+        .parameterized_attribute(
+            &attribute(kAXLineForIndexParameterizedAttribute),
+            &parameter,
+        )
+        .ok()?
+        .downcast_into::<CFNumber>()?
+        .to_i64()
+        .and_then(|line| isize::try_from(line).ok())
+}
+
+/// Get the character range of a line
+pub(crate) fn range_for_line(element: &AXUIElement, line: isize) -> Option<CFRange> {
+    let parameter = CFNumber::from(i64::try_from(line).ok()?);
+
+    element
+        .parameterized_attribute(
+            &attribute(kAXRangeForLineParameterizedAttribute),
+            &parameter,
+        )
+        .ok()?
+        .downcast_into::<AXValue>()?
+        .get_value::<CFRange>()
+        .ok()
+}
+
+fn rect_from_value(value: &CFType) -> Option<Rect> {
+    let rect = value
+        .clone()
+        .downcast_into::<AXValue>()?
+        .get_value::<CGRect>()
+        .ok()?;
+
+    Some(Rect::new(
+        rect.origin.x,
+        rect.origin.y,
+        rect.size.width,
+        rect.size.height,
+    ))
+}
+
+/// Read the role of an element, e.g. `AXTextArea`
+pub(crate) fn role(element: &AXUIElement) -> Option<String> {
+    element
+        .attribute(&attribute(kAXRoleAttribute))
+        .ok()?
+        .downcast_into::<CFString>()
+        .map(|role| role.to_string())
+}
+
+fn parent(element: &AXUIElement) -> Option<AXUIElement> {
+    element
+        .attribute(&attribute(kAXParentAttribute))
+        .ok()?
+        .downcast_into::<AXUIElement>()
+}
+
+/// Find the web area containing an element, including the element itself
+///
+/// In WebKit views focus usually sits on an element inside the page, while
+/// the text marker attributes live on the web area.
+pub(crate) fn enclosing_web_area(element: &AXUIElement) -> Option<AXUIElement> {
+    let mut current = element.clone();
+
+    for _ in 0..MAX_PARENT_DEPTH {
+        if role(&current).as_deref() == Some(WEB_AREA_ROLE) {
+            return Some(current);
+        }
+        current = parent(&current)?;
+    }
+
+    None
+}
+
+/// Find the closest element exposing a text marker selection, including the
+/// element itself
+///
+/// PDF views (Preview, Skim, PDFKit in general) leave the selected text
+/// attribute empty and keep the selection as text markers, either on the
+/// focused element or on the view around the focused page.
+pub(crate) fn enclosing_text_marker_element(element: &AXUIElement) -> Option<AXUIElement> {
+    let mut current = element.clone();
+
+    for _ in 0..MAX_PARENT_DEPTH {
+        if has_attribute(&current, SELECTED_TEXT_MARKER_RANGE_ATTRIBUTE) {
+            return Some(current);
+        }
+        current = parent(&current)?;
+    }
+
+    None
+}
+
+fn has_attribute(element: &AXUIElement, name: &str) -> bool {
+    element
+        .attribute_names()
+        .is_ok_and(|names| names.iter().any(|candidate| candidate.to_string() == name))
+}
+
+/// Read the selected text of an element through its text marker range
+pub(crate) fn text_marker_selected_text(element: &AXUIElement) -> Option<String> {
+    let marker_range = element
+        .attribute(&attribute(SELECTED_TEXT_MARKER_RANGE_ATTRIBUTE))
+        .ok()?;
+
+    element
+        .parameterized_attribute(
+            &attribute(STRING_FOR_TEXT_MARKER_RANGE_ATTRIBUTE),
+            &marker_range,
+        )
+        .ok()?
+        .downcast_into::<CFString>()
+        .map(|string| string.to_string())
+}
+
+/// Get the screen bounds of a web area's selection through its text markers
+pub(crate) fn web_area_selection_bounds(web_area: &AXUIElement) -> Option<Rect> {
+    let marker_range = web_area
+        .attribute(&attribute(SELECTED_TEXT_MARKER_RANGE_ATTRIBUTE))
+        .ok()?;
+
+    web_area
+        .parameterized_attribute(
+            &attribute(BOUNDS_FOR_TEXT_MARKER_RANGE_ATTRIBUTE),
+            &marker_range,
+        )
+        .ok()
+        .and_then(|value| rect_from_value(&value))
+}
+
+/// Read the focused element of an application or the system-wide element
+pub(crate) fn focused_element(element: &AXUIElement) -> Result<AXUIElement, Error> {
+    element
+        .attribute(&attribute(kAXFocusedUIElementAttribute))?
+        .downcast_into::<AXUIElement>()
+        .ok_or(Error::NotFound)
+}
+
+/// Limit how long calls on an element wait for its application to answer
+pub(crate) fn set_messaging_timeout(element: &AXUIElement, timeout: Duration) {
+    if let Err(err) = element.set_messaging_timeout(timeout.as_secs_f32()) {
+        debug!("Failed to set the accessibility messaging timeout: {}", err);
+    }
+}
+
+/// Check whether an element exposes any attribute the selection can be read from
+pub(crate) fn exposes_selection(element: &AXUIElement) -> bool {
+    let names = match element.attribute_names() {
+        Ok(names) => names,
+        Err(_) => return false,
+    };
+
+    let has_attribute = names.iter().any(|name| {
+        let name = name.to_string();
+        name == kAXSelectedTextAttribute
+            || name == kAXSelectedTextRangeAttribute
+            || name == SELECTED_TEXT_MARKER_RANGE_ATTRIBUTE
+    });
+
+    has_attribute || enclosing_web_area(element).is_some()
+}
+
+/// Check whether copying from an element could produce anything
+///
+/// Used to avoid sending Cmd+C to buttons and canvases, which makes many
+/// apps beep.
+pub(crate) fn holds_selection(element: &AXUIElement) -> bool {
+    role(element).is_some_and(|role| SELECTABLE_ROLES.contains(&role.as_str()))
+        || exposes_selection(element)
+}
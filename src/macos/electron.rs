@@ -0,0 +1,90 @@
+//! Turning on the accessibility tree of Electron and Chromium apps
+//!
+//! Chromium only builds its accessibility tree once an assistive technology
+//! asks for it. Setting `AXManualAccessibility` on the application element is
+//! the supported way to ask without enabling VoiceOver-specific behaviour.
+
+use accessibility_ng::AXUIElement;
+use core_foundation::base::TCFType;
+use core_foundation::boolean::CFBoolean;
+use log::{debug, info};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::ax;
+use crate::AppInfo;
+
+const MANUAL_ACCESSIBILITY_ATTRIBUTE: &str = "AXManualAccessibility";
+
+/// How long the app gets to build its accessibility tree after enabling it
+pub(crate) const TREE_BUILD_DELAY: Duration = Duration::from_millis(150);
+
+/// Bundle identifiers of Chromium-based apps that don't ship the Electron
+/// framework under its usual name
+const CHROMIUM_BUNDLE_IDS: [&str; 6] = [
+    "com.google.Chrome",
+    "com.microsoft.edgemac",
+    "com.brave.Browser",
+    "com.vivaldi.Vivaldi",
+    "com.operasoftware.Opera",
+    "org.chromium.Chromium",
+];
+
+/// Processes that already had their accessibility tree enabled
+static ENABLED_PIDS: Mutex<Option<HashSet<i32>>> = Mutex::new(None);
+
+/// Check whether an app is built on Electron or Chromium
+pub(crate) fn is_chromium_based(app: &AppInfo) -> bool {
+    if app
+        .identifier
+        .as_deref()
+        .is_some_and(|id| CHROMIUM_BUNDLE_IDS.contains(&id))
+    {
+        return true;
+    }
+
+    // The executable lives in `Foo.app/Contents/MacOS`
+    app.executable
+        .as_deref()
+        .and_then(Path::parent)
+        .and_then(Path::parent)
+        .is_some_and(|contents| {
+            contents
+                .join("Frameworks/Electron Framework.framework")
+                .exists()
+        })
+}
+
+/// Enable the accessibility tree of a Chromium-based app, once per process
+///
+/// Returns true if it was enabled just now, in which case the tree may still
+/// be under construction.
+pub(crate) fn enable_accessibility(pid: i32) -> bool {
+    {
+        let mut enabled = ENABLED_PIDS.lock().unwrap_or_else(|err| err.into_inner());
+        // Also remember failures, so an app rejecting the attribute isn't
+        // asked again on every capture
+        if !enabled.get_or_insert_with(HashSet::new).insert(pid) {
+            return false;
+        }
+    }
+
+    let application = AXUIElement::application(pid);
+    match application.set_attribute(
+        &ax::attribute(MANUAL_ACCESSIBILITY_ATTRIBUTE),
+        CFBoolean::true_value().as_CFType(),
+    ) {
+        Ok(()) => {
+            info!("Enabled the accessibility tree of process {}", pid);
+            thread::sleep(TREE_BUILD_DELAY);
+            true
+        }
+        Err(err) => {
+            debug!("Failed to set {}: {}", MANUAL_ACCESSIBILITY_ATTRIBUTE, err);
+            false
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Reading Finder's selected files through Apple Events
+
+use log::warn;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::osascript;
+use crate::privacy::redact;
+use crate::uri::file_url_to_path;
+use crate::SelectionError;
+
+/// Bundle identifier of Finder
+pub(crate) const FINDER_BUNDLE_ID: &str = "com.apple.finder";
+
+/// Collects the URL of every selected item, one per line
+///
+/// URLs are percent-encoded, so quotes and newlines in file names can't break
+/// the output apart. `selection` also covers items selected on the desktop.
+const SELECTION_SCRIPT: [&str; 8] = [
+    "set urls to {}",
+    "tell application \"Finder\"",
+    "repeat with theItem in (get selection)",
+    "set end of urls to URL of theItem",
+    "end repeat",
+    "end tell",
+    "set AppleScript's text item delimiters to linefeed",
+    "return urls as text",
+];
+
+/// Ask Finder for the paths of its selected items
+///
+/// Needs the Automation permission for Finder, which macOS asks the user for
+/// on first use. Fails with `Timeout` if Finder doesn't answer in time.
+pub(crate) fn selected_files(timeout: Duration) -> Result<Vec<PathBuf>, SelectionError> {
+    let output = osascript::run(&SELECTION_SCRIPT, "Finder", timeout)?;
+    if output.lossy {
+        warn!("Finder returned item URLs that aren't valid UTF-8");
+    }
+
+    let paths = output
+        .text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|url| {
+            let path = file_url_to_path(url);
+            if path.is_none() {
+                warn!("Ignoring unexpected Finder item URL: {}", redact(url));
+            }
+            path
+        })
+        .collect();
+
+    Ok(paths)
+}
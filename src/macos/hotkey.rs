@@ -0,0 +1,239 @@
+//! Global hotkey through a listen-only event tap
+//!
+//! The tap lives on its own run loop thread and only forwards presses to a
+//! second thread running the captures, so a slow capture never stalls the
+//! system's event delivery.
+
+use core_foundation::base::TCFType;
+use core_foundation::mach_port::{CFMachPortInvalidate, CFMachPortRef};
+use core_foundation::runloop::{kCFRunLoopCommonModes, CFRunLoop};
+use core_graphics::event::{
+    CGEventFlags, CGEventTap, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement,
+    CGEventType, CGKeyCode, EventField,
+};
+use log::{debug, info};
+use std::cell::Cell;
+use std::ptr;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use super::{is_input_monitoring_trusted, MacOSSelector};
+use crate::hotkey::{Hotkey, Modifiers};
+use crate::watcher::WatcherBackend;
+use crate::{Selection, SelectionError, SelectionOptions, Selector};
+
+/// Virtual key codes of the ANSI keyboard (kVK_ANSI_*), by the character on
+/// the key in the US layout
+const KEY_CODES: [(char, CGKeyCode); 48] = [
+    ('a', 0),
+    ('s', 1),
+    ('d', 2),
+    ('f', 3),
+    ('h', 4),
+    ('g', 5),
+    ('z', 6),
+    ('x', 7),
+    ('c', 8),
+    ('v', 9),
+    ('b', 11),
+    ('q', 12),
+    ('w', 13),
+    ('e', 14),
+    ('r', 15),
+    ('y', 16),
+    ('t', 17),
+    ('1', 18),
+    ('2', 19),
+    ('3', 20),
+    ('4', 21),
+    ('6', 22),
+    ('5', 23),
+    ('=', 24),
+    ('9', 25),
+    ('7', 26),
+    ('-', 27),
+    ('8', 28),
+    ('0', 29),
+    (']', 30),
+    ('o', 31),
+    ('u', 32),
+    ('[', 33),
+    ('i', 34),
+    ('p', 35),
+    ('l', 37),
+    ('j', 38),
+    ('\'', 39),
+    ('k', 40),
+    (';', 41),
+    ('\\', 42),
+    (',', 43),
+    ('/', 44),
+    ('n', 45),
+    ('m', 46),
+    ('.', 47),
+    (' ', 49),
+    ('`', 50),
+];
+
+/// Modifier flags compared against the hotkey; others (Caps Lock, Fn) are
+/// ignored
+const MODIFIER_MASK: u64 = CGEventFlags::CGEventFlagControl.bits()
+    | CGEventFlags::CGEventFlagAlternate.bits()
+    | CGEventFlags::CGEventFlagShift.bits()
+    | CGEventFlags::CGEventFlagCommand.bits();
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventTapEnable(tap: CFMachPortRef, enable: bool);
+}
+
+thread_local! {
+    /// Port of the tap on this thread, to re-enable it when macOS disables it
+    static TAP_PORT: Cell<CFMachPortRef> = const { Cell::new(ptr::null_mut()) };
+}
+
+/// Event tap thread and the capture thread it triggers
+struct HotkeyBackend {
+    run_loop: CFRunLoop,
+    tap_thread: Option<JoinHandle<()>>,
+    capture_thread: Option<JoinHandle<()>>,
+}
+
+/// Start capturing the selection whenever `hotkey` is pressed
+pub(crate) fn start_hotkey(
+    hotkey: Hotkey,
+    options: SelectionOptions,
+    sender: Sender<Result<Selection, SelectionError>>,
+) -> Result<Box<dyn WatcherBackend>, SelectionError> {
+    let key_code = KEY_CODES
+        .iter()
+        .find(|(key, _)| *key == hotkey.key)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| SelectionError::Other(format!("Unsupported hotkey key {:?}", hotkey.key)))?;
+
+    if !is_input_monitoring_trusted() {
+        return Err(SelectionError::PermissionDenied(
+            "Input Monitoring permission has not been granted to this process".to_string(),
+        ));
+    }
+
+    let (trigger, triggered) = mpsc::channel::<()>();
+    let capture_thread = thread::spawn(move || {
+        let selector = MacOSSelector::with_options(options);
+        for () in triggered {
+            if sender.send(selector.get_selection()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let flags = modifier_flags(hotkey.modifiers);
+    let (ready, started) = mpsc::channel();
+    let tap_thread = thread::spawn(move || {
+        let tap = CGEventTap::new(
+            CGEventTapLocation::Session,
+            CGEventTapPlacement::HeadInsertEventTap,
+            CGEventTapOptions::ListenOnly,
+            vec![CGEventType::KeyDown],
+            move |_proxy, event_type, event| {
+                match event_type {
+                    CGEventType::TapDisabledByTimeout | CGEventType::TapDisabledByUserInput => {
+                        debug!("Event tap was disabled, enabling it again");
+                        TAP_PORT.with(|port| unsafe { CGEventTapEnable(port.get(), true) });
+                    }
+                    CGEventType::KeyDown => {
+                        let pressed = event
+                            .get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE)
+                            == i64::from(key_code)
+                            && event.get_flags().bits() & MODIFIER_MASK == flags
+                            // Holding the shortcut shouldn't capture repeatedly
+                            && event
+                                .get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT)
+                                == 0;
+                        if pressed {
+                            let _ = trigger.send(());
+                        }
+                    }
+                    _ => {}
+                }
+                None
+            },
+        );
+
+        let tap = match tap {
+            Ok(tap) => tap,
+            Err(()) => {
+                let _ = ready.send(Err(SelectionError::PermissionDenied(
+                    "Failed to create the event tap for the hotkey".to_string(),
+                )));
+                return;
+            }
+        };
+        let source = match tap.mach_port.create_runloop_source(0) {
+            Ok(source) => source,
+            Err(()) => {
+                let _ = ready.send(Err(SelectionError::Other(
+                    "Failed to create a run loop source for the event tap".to_string(),
+                )));
+                return;
+            }
+        };
+
+        let run_loop = CFRunLoop::get_current();
+        unsafe { run_loop.add_source(&source, kCFRunLoopCommonModes) };
+        TAP_PORT.with(|port| port.set(tap.mach_port.as_concrete_TypeRef()));
+        tap.enable();
+        let _ = ready.send(Ok(run_loop.clone()));
+        info!("Listening for the hotkey");
+
+        // Returns once the backend is stopped
+        CFRunLoop::run_current();
+
+        unsafe { run_loop.remove_source(&source, kCFRunLoopCommonModes) };
+        TAP_PORT.with(|port| port.set(ptr::null_mut()));
+        // Releasing the port alone doesn't remove the tap from the system
+        unsafe { CFMachPortInvalidate(tap.mach_port.as_concrete_TypeRef()) };
+        debug!("Hotkey event tap removed");
+    });
+
+    match started.recv() {
+        Ok(Ok(run_loop)) => Ok(Box::new(HotkeyBackend {
+            run_loop,
+            tap_thread: Some(tap_thread),
+            capture_thread: Some(capture_thread),
+        })),
+        Ok(Err(err)) => {
+            let _ = tap_thread.join();
+            let _ = capture_thread.join();
+            Err(err)
+        }
+        Err(_) => Err(SelectionError::Other(
+            "Hotkey thread exited unexpectedly".to_string(),
+        )),
+    }
+}
+
+impl WatcherBackend for HotkeyBackend {
+    fn stop(mut self: Box<Self>) {
+        self.run_loop.stop();
+        if let Some(handle) = self.tap_thread.take() {
+            let _ = handle.join();
+        }
+        // The capture thread ends once the tap and its trigger are gone
+        if let Some(handle) = self.capture_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn modifier_flags(modifiers: Modifiers) -> u64 {
+    [
+        (modifiers.control, CGEventFlags::CGEventFlagControl),
+        (modifiers.alt, CGEventFlags::CGEventFlagAlternate),
+        (modifiers.shift, CGEventFlags::CGEventFlagShift),
+        (modifiers.meta, CGEventFlags::CGEventFlagCommand),
+    ]
+    .into_iter()
+    .filter(|(held, _)| *held)
+    .fold(0, |flags, (_, flag)| flags | flag.bits())
+}
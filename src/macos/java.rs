@@ -0,0 +1,34 @@
+//! Detection of Java apps, whose Swing/AWT UI is only visible to the
+//! accessibility API through the Java Access Bridge
+
+use std::path::Path;
+
+use crate::AppInfo;
+
+/// Launcher executables used by bundled Java apps
+const JAVA_LAUNCHERS: [&str; 3] = ["java", "JavaAppLauncher", "JavaApplicationStub"];
+
+/// Directories of a bundle's `Contents` holding an embedded Java runtime
+const RUNTIME_DIRS: [&str; 4] = ["jbr", "runtime", "Home/lib/jli", "PlugIns/Java.runtime"];
+
+/// Check whether an app runs on the JVM
+pub(crate) fn is_java_app(app: &AppInfo) -> bool {
+    let executable = match app.executable.as_deref() {
+        Some(executable) => executable,
+        None => return false,
+    };
+
+    if executable
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| JAVA_LAUNCHERS.contains(&name))
+    {
+        return true;
+    }
+
+    // The executable lives in `Foo.app/Contents/MacOS`
+    executable
+        .parent()
+        .and_then(Path::parent)
+        .is_some_and(|contents| RUNTIME_DIRS.iter().any(|dir| contents.join(dir).exists()))
+}
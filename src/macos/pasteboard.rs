@@ -0,0 +1,518 @@
+//! Native pasteboard access and synthetic Cmd+C/Cmd+V for the clipboard
+//! fallbacks
+
+use core_graphics::event::{CGEvent, CGEventFlags, CGEventTapLocation, CGKeyCode};
+use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+use log::{debug, info, warn};
+use objc2::rc::Retained;
+use objc2::runtime::ProtocolObject;
+use objc2_app_kit::{
+    NSBitmapImageFileType, NSBitmapImageRep, NSPasteboard, NSPasteboardItem, NSPasteboardType,
+    NSPasteboardTypeFileURL, NSPasteboardTypeHTML, NSPasteboardTypePNG, NSPasteboardTypeRTF,
+    NSPasteboardTypeString, NSPasteboardTypeTIFF, NSPasteboardWriting,
+};
+use objc2_foundation::{NSArray, NSData, NSDictionary, NSRange, NSString, NSURL};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::privacy::redact;
+use crate::rtf;
+use crate::uri::file_url_to_path;
+use crate::{ContentType, OversizePolicy, Selection, SelectionError, SelectionOptions};
+
+/// Virtual key code of the C key (kVK_ANSI_C)
+const KEY_C: CGKeyCode = 8;
+
+/// Virtual key code of the V key (kVK_ANSI_V)
+const KEY_V: CGKeyCode = 9;
+
+/// Marks pasteboard contents that only exist for a moment, so clipboard
+/// managers don't record them (see nspasteboard.org)
+const TRANSIENT_TYPE: &str = "org.nspasteboard.TransientType";
+
+/// Marks pasteboard contents that are sensitive, such as passwords copied
+/// from a password manager, so clipboard managers never record them
+const CONCEALED_TYPE: &str = "org.nspasteboard.ConcealedType";
+
+/// Contents of the general pasteboard saved before a capture
+///
+/// Holds the data of every type of every item, in order, so multiple copied
+/// files, rich text and images survive the round trip, not just the string.
+pub(crate) struct PasteboardSnapshot {
+    items: Vec<Vec<(Retained<NSPasteboardType>, Retained<NSData>)>>,
+    /// Whether the saved contents were marked concealed
+    concealed: bool,
+}
+
+fn general() -> Retained<NSPasteboard> {
+    NSPasteboard::generalPasteboard()
+}
+
+/// Get the change count of the general pasteboard
+pub(crate) fn change_count() -> isize {
+    general().changeCount()
+}
+
+/// Save the current contents of the general pasteboard
+pub(crate) fn snapshot() -> PasteboardSnapshot {
+    let items: Vec<Vec<_>> = general()
+        .pasteboardItems()
+        .map(|items| {
+            items
+                .iter()
+                .map(|item| {
+                    item.types()
+                        .iter()
+                        .filter_map(|ty| item.dataForType(&ty).map(|data| (ty, data)))
+                        .collect()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let concealed = items
+        .iter()
+        .flatten()
+        .any(|(ty, _)| ty.to_string() == CONCEALED_TYPE);
+
+    PasteboardSnapshot { items, concealed }
+}
+
+/// Put a snapshot back on the general pasteboard
+///
+/// With `mark_transient` the restored contents are marked transient, since
+/// clipboard managers already recorded them when they were first copied,
+/// and concealed again if they were concealed before.
+fn restore(snapshot: &PasteboardSnapshot, mark_transient: bool) {
+    let pasteboard = general();
+    pasteboard.clearContents();
+
+    if snapshot.items.is_empty() {
+        return;
+    }
+
+    let items: Vec<Retained<ProtocolObject<dyn NSPasteboardWriting>>> = snapshot
+        .items
+        .iter()
+        .enumerate()
+        .map(|(index, flavors)| {
+            let item = NSPasteboardItem::new();
+            for (ty, data) in flavors {
+                if !item.setData_forType(data, ty) {
+                    warn!("Failed to restore pasteboard type {}", ty);
+                }
+            }
+            // Clipboard managers look for the markers on the first item
+            if mark_transient && index == 0 {
+                set_markers(&item, snapshot.concealed);
+            }
+            ProtocolObject::from_retained(item)
+        })
+        .collect();
+
+    if !pasteboard.writeObjects(&NSArray::from_retained_slice(&items)) {
+        warn!("Failed to restore the pasteboard items");
+    }
+}
+
+/// Put a snapshot back unless the pasteboard changed since `expected_count`
+///
+/// A newer change is a copy the user (or another app) made during the
+/// capture, which must not be clobbered by the older snapshot. Returns
+/// whether the snapshot was restored.
+pub(crate) fn restore_unless_changed(
+    snapshot: &PasteboardSnapshot,
+    expected_count: isize,
+    mark_transient: bool,
+) -> bool {
+    let current = change_count();
+    if current != expected_count {
+        info!(
+            "Pasteboard changed during the capture (change count {} -> {}), not restoring it",
+            expected_count, current
+        );
+        return false;
+    }
+
+    restore(snapshot, mark_transient);
+    true
+}
+
+fn set_markers(item: &NSPasteboardItem, concealed: bool) {
+    let empty = NSData::new();
+    item.setData_forType(&empty, &NSString::from_str(TRANSIENT_TYPE));
+    if concealed {
+        item.setData_forType(&empty, &NSString::from_str(CONCEALED_TYPE));
+    }
+}
+
+/// Replace the contents of the general pasteboard with a string
+///
+/// With `mark_transient` the string is marked transient, as it's only
+/// there to be pasted and is replaced by the restored snapshot right after.
+pub(crate) fn write_string(string: &str, mark_transient: bool) {
+    let pasteboard = general();
+    pasteboard.clearContents();
+
+    let string = NSString::from_str(string);
+    pasteboard.setString_forType(&string, unsafe { NSPasteboardTypeString });
+    if mark_transient {
+        pasteboard.setData_forType(Some(&NSData::new()), &NSString::from_str(TRANSIENT_TYPE));
+    }
+}
+
+/// Pasteboard types that can be negotiated, richest first
+///
+/// TIFF is converted to PNG, so it's listed as a PNG image after the native
+/// PNG type. Likewise RTF is listed as text after the string type, for
+/// editors that occasionally copy an empty string next to the RTF.
+fn known_types() -> [(ContentType, &'static NSPasteboardType); 7] {
+    let png = ContentType::Image("png".to_string());
+
+    unsafe {
+        [
+            (ContentType::Files, NSPasteboardTypeFileURL),
+            (ContentType::Html, NSPasteboardTypeHTML),
+            (ContentType::Rtf, NSPasteboardTypeRTF),
+            (png.clone(), NSPasteboardTypePNG),
+            (png, NSPasteboardTypeTIFF),
+            (ContentType::Text, NSPasteboardTypeString),
+            (ContentType::Text, NSPasteboardTypeRTF),
+        ]
+    }
+}
+
+/// Read the general pasteboard in the first type of a MIME preference list
+/// it holds
+///
+/// Nothing larger than `SelectionOptions::max_bytes` is copied out of the
+/// pasteboard: text and HTML are cut short, and images and RTF are refused.
+pub(crate) fn negotiate(
+    preferred: &[&str],
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    negotiate_on(&general(), preferred, options)
+}
+
+fn negotiate_on(
+    pasteboard: &NSPasteboard,
+    preferred: &[&str],
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let available: Vec<String> = pasteboard
+        .types()
+        .map(|types| types.iter().map(|ty| ty.to_string()).collect())
+        .unwrap_or_default();
+
+    for mime in preferred {
+        for (content_type, pasteboard_type) in known_types() {
+            if !content_type.matches_mime(mime) || !available.contains(&pasteboard_type.to_string())
+            {
+                continue;
+            }
+            debug!("Pasteboard type {} matches {}", pasteboard_type, mime);
+
+            if let Some(selection) = read_type(pasteboard, &content_type, pasteboard_type, options)?
+            {
+                return Ok(selection);
+            }
+        }
+    }
+
+    Err(SelectionError::InvalidContentType {
+        expected: preferred.join(", "),
+        received: "no matching pasteboard type".to_string(),
+    })
+}
+
+/// Read the general pasteboard in every known type it holds, richest first
+///
+/// Types converted to a content type already read (TIFF after PNG, RTF as
+/// text after the string) are skipped. A type that can't be read, e.g. one
+/// over `SelectionOptions::max_bytes`, is left out, and its error only
+/// returned if nothing else could be read.
+pub(crate) fn read_all(options: &SelectionOptions) -> Result<Vec<Selection>, SelectionError> {
+    let pasteboard = general();
+    let available: Vec<String> = pasteboard
+        .types()
+        .map(|types| types.iter().map(|ty| ty.to_string()).collect())
+        .unwrap_or_default();
+
+    let mut selections: Vec<Selection> = Vec::new();
+    let mut first_error = None;
+    for (content_type, pasteboard_type) in known_types() {
+        if !available.contains(&pasteboard_type.to_string())
+            || selections
+                .iter()
+                .any(|selection| selection.content_type == content_type)
+        {
+            continue;
+        }
+
+        match read_type(&pasteboard, &content_type, pasteboard_type, options) {
+            Ok(Some(selection)) => selections.push(selection),
+            Ok(None) => {}
+            Err(err) => {
+                debug!(
+                    "Failed to read pasteboard type {}: {}",
+                    pasteboard_type, err
+                );
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    match first_error {
+        Some(err) if selections.is_empty() => Err(err),
+        _ => Ok(selections),
+    }
+}
+
+/// Read one pasteboard type, or `None` if it turned out to be empty
+fn read_type(
+    pasteboard: &NSPasteboard,
+    content_type: &ContentType,
+    pasteboard_type: &NSPasteboardType,
+    options: &SelectionOptions,
+) -> Result<Option<Selection>, SelectionError> {
+    let mut selection = match content_type {
+        ContentType::Files => Some(Selection::new_files(read_file_urls(pasteboard))),
+        ContentType::Image(_) => read_png(pasteboard, pasteboard_type, options)?
+            .filter(|png| !png.is_empty())
+            .map(Selection::new_image_detect)
+            .transpose()?,
+        ContentType::Rtf => {
+            read_data(pasteboard, pasteboard_type, options)?.map(Selection::new_rtf)
+        }
+        ContentType::Html => {
+            read_utf8(pasteboard, pasteboard_type, options)?.map(|(html, truncated)| {
+                let mut selection = Selection::new_html(html);
+                selection.metadata.truncated = truncated;
+                selection
+            })
+        }
+        ContentType::Text if pasteboard_type == unsafe { NSPasteboardTypeRTF } => {
+            read_data(pasteboard, pasteboard_type, options)?.map(rtf_to_text)
+        }
+        _ => read_utf8(pasteboard, pasteboard_type, options)?.map(|(text, truncated)| {
+            let mut selection = Selection::new_text(text);
+            selection.metadata.truncated = truncated;
+            selection
+        }),
+    };
+
+    // Text converted from RTF is only cut short once converted
+    if let (Some(selection), Some(max_bytes)) = (&mut selection, options.max_bytes) {
+        selection.truncate_text(max_bytes);
+    }
+    Ok(selection.filter(|selection| !selection.is_empty()))
+}
+
+/// Read a pasteboard type as it is, refusing it if it's larger than
+/// `max_bytes` before it's copied
+fn read_data(
+    pasteboard: &NSPasteboard,
+    pasteboard_type: &NSPasteboardType,
+    options: &SelectionOptions,
+) -> Result<Option<Vec<u8>>, SelectionError> {
+    let Some(data) = pasteboard.dataForType(pasteboard_type) else {
+        return Ok(None);
+    };
+    if options.max_bytes.is_some_and(|max| data.length() > max) {
+        return Err(options.too_large());
+    }
+    Ok(Some(data.to_vec()))
+}
+
+/// Read a pasteboard type holding UTF-8, and whether it was cut short
+///
+/// Only the first `max_bytes` are copied out of the pasteboard, cut at a
+/// character boundary, unless `oversize` refuses larger data.
+fn read_utf8(
+    pasteboard: &NSPasteboard,
+    pasteboard_type: &NSPasteboardType,
+    options: &SelectionOptions,
+) -> Result<Option<(String, bool)>, SelectionError> {
+    let Some(data) = pasteboard.dataForType(pasteboard_type) else {
+        return Ok(None);
+    };
+    let max = match options.max_bytes {
+        Some(max) if data.length() > max => max,
+        _ => {
+            return Ok(Some((
+                String::from_utf8_lossy(&data.to_vec()).into_owned(),
+                false,
+            )))
+        }
+    };
+    if options.oversize == OversizePolicy::Error {
+        return Err(options.too_large());
+    }
+
+    let mut bytes = data.subdataWithRange(NSRange::new(0, max)).to_vec();
+    // Drop the start of a character straddling the limit
+    if let Err(err) = std::str::from_utf8(&bytes) {
+        if err.error_len().is_none() {
+            bytes.truncate(err.valid_up_to());
+        }
+    }
+    Ok(Some((String::from_utf8_lossy(&bytes).into_owned(), true)))
+}
+
+/// Build a text selection from RTF, keeping the RTF in the metadata
+fn rtf_to_text(data: Vec<u8>) -> Selection {
+    debug!(
+        "Converting {} bytes of RTF to plain text",
+        redact(data.len())
+    );
+    let mut selection = Selection::new_text(rtf::to_plain_text(&data));
+    selection.metadata.converted_from = Some(ContentType::Rtf);
+    selection.metadata.original = Some(data);
+    selection
+}
+
+/// Read an image type from the pasteboard as PNG bytes
+///
+/// Images above `SelectionOptions::max_bytes` are refused before being
+/// copied out of the pasteboard or decoded.
+fn read_png(
+    pasteboard: &NSPasteboard,
+    pasteboard_type: &NSPasteboardType,
+    options: &SelectionOptions,
+) -> Result<Option<Vec<u8>>, SelectionError> {
+    let data = match pasteboard.dataForType(pasteboard_type) {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+
+    if options.max_bytes.is_some_and(|max| data.length() > max) {
+        return Err(options.too_large());
+    }
+
+    if pasteboard_type == unsafe { NSPasteboardTypePNG } {
+        return Ok(Some(data.to_vec()));
+    }
+
+    // Screenshots and images copied from Preview usually only come as TIFF
+    let png = NSBitmapImageRep::imageRepWithData(&data).and_then(|image| unsafe {
+        image.representationUsingType_properties(NSBitmapImageFileType::PNG, &NSDictionary::new())
+    });
+
+    match png {
+        Some(png) => Ok(Some(png.to_vec())),
+        None => Err(SelectionError::Other(
+            "Failed to convert the pasteboard image to PNG".to_string(),
+        )),
+    }
+}
+
+/// Read the file URLs of all pasteboard items as paths
+fn read_file_urls(pasteboard: &NSPasteboard) -> Vec<PathBuf> {
+    let items = match pasteboard.pasteboardItems() {
+        Some(items) => items,
+        None => return Vec::new(),
+    };
+
+    items
+        .iter()
+        .filter_map(|item| item.stringForType(unsafe { NSPasteboardTypeFileURL }))
+        .filter_map(|url| file_url_path(&url))
+        .collect()
+}
+
+fn file_url_path(url: &NSString) -> Option<PathBuf> {
+    let string = url.to_string();
+    if !string.contains("/.file/id=") {
+        return file_url_to_path(&string);
+    }
+
+    // File reference URLs can only be resolved by Foundation
+    let path = NSURL::URLWithString(url)?.filePathURL()?.path()?;
+    Some(PathBuf::from(path.to_string()))
+}
+
+/// Post a Cmd+C keystroke to the focused application
+pub(crate) fn post_copy() -> Result<(), SelectionError> {
+    post_command_shortcut(KEY_C)
+}
+
+/// Post a Cmd+V keystroke to the focused application
+pub(crate) fn post_paste() -> Result<(), SelectionError> {
+    post_command_shortcut(KEY_V)
+}
+
+fn post_command_shortcut(key: CGKeyCode) -> Result<(), SelectionError> {
+    let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+        .map_err(|_| SelectionError::Other("Failed to create CGEventSource".to_string()))?;
+
+    for key_down in [true, false] {
+        let event = CGEvent::new_keyboard_event(source.clone(), key, key_down)
+            .map_err(|_| SelectionError::Other("Failed to create keyboard event".to_string()))?;
+        event.set_flags(CGEventFlags::CGEventFlagCommand);
+        event.post(CGEventTapLocation::HID);
+    }
+
+    Ok(())
+}
+
+/// Wait until the pasteboard change count moves past `initial`, checking
+/// every `interval`
+///
+/// Returns false if the deadline expired first.
+pub(crate) fn wait_for_change(initial: isize, deadline: Duration, interval: Duration) -> bool {
+    let start = Instant::now();
+
+    loop {
+        if change_count() != initial {
+            return true;
+        }
+        if start.elapsed() >= deadline {
+            return false;
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_html() {
+        let pasteboard = NSPasteboard::pasteboardWithUniqueName();
+        pasteboard.clearContents();
+        let html = NSString::from_str("<b>bold</b>");
+        assert!(pasteboard.setString_forType(&html, unsafe { NSPasteboardTypeHTML }));
+        let text = NSString::from_str("bold");
+        assert!(pasteboard.setString_forType(&text, unsafe { NSPasteboardTypeString }));
+
+        let options = SelectionOptions::default();
+        let selection = negotiate_on(&pasteboard, &["text/html"], &options).unwrap();
+        assert_eq!(selection.content_type, ContentType::Html);
+        assert_eq!(selection.data, b"<b>bold</b>");
+        assert!(!selection.metadata.truncated);
+
+        let selection = negotiate_on(&pasteboard, &["text/html"], &options.max_bytes(3)).unwrap();
+        assert_eq!(selection.data, b"<b>");
+        assert!(selection.metadata.truncated);
+    }
+
+    #[test]
+    fn test_negotiate_text_within_max_bytes() {
+        let pasteboard = NSPasteboard::pasteboardWithUniqueName();
+        pasteboard.clearContents();
+        let text = NSString::from_str("h\u{e9}llo");
+        assert!(pasteboard.setString_forType(&text, unsafe { NSPasteboardTypeString }));
+
+        let options = SelectionOptions::default().max_bytes(2);
+        let selection = negotiate_on(&pasteboard, &["text/plain"], &options).unwrap();
+        assert_eq!(selection.as_text().as_deref(), Some("h"));
+        assert!(selection.metadata.truncated);
+
+        let options = options.oversize(OversizePolicy::Error);
+        assert!(matches!(
+            negotiate_on(&pasteboard, &["text/plain"], &options),
+            Err(SelectionError::TooLarge { limit: 2 })
+        ));
+    }
+}
@@ -0,0 +1,294 @@
+//! Selection watcher driven by accessibility notifications
+//!
+//! An `AXObserver` on the frontmost application receives selected-text and
+//! focus changes on a dedicated run loop thread. NSWorkspace activation
+//! notifications are only delivered on the main run loop, which library
+//! callers don't necessarily run, so the frontmost application is instead
+//! re-checked by a cheap timer on the same run loop.
+//!
+//! While VoiceOver or Switch Control is running, reads are debounced more
+//! heavily so they don't slow it down.
+
+use accessibility_ng::{AXObserver, AXUIElement};
+use accessibility_sys_ng::{
+    kAXFocusedUIElementChangedNotification, kAXSelectedTextChangedNotification,
+    AXObserverAddNotification, AXObserverRef, AXUIElementRef,
+};
+use core_foundation::base::TCFType;
+use core_foundation::date::CFAbsoluteTimeGetCurrent;
+use core_foundation::runloop::{
+    kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopTimer, CFRunLoopTimerRef,
+    CFRunLoopTimerSetNextFireDate,
+};
+use core_foundation::string::{CFString, CFStringRef};
+use log::{debug, warn};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{app, get_selection_by_accessibility};
+use crate::watcher::{Debouncer, WatchSource, WatcherBackend, WatcherOptions};
+use crate::{SelectionError, SelectionEvent, SelectionOptions};
+
+/// How often the frontmost application is checked for changes
+const FRONTMOST_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Shortest debounce while assistive technology is running, so a selection
+/// being made doesn't cause a read for every notification it produces
+const ASSISTIVE_DEBOUNCE: Duration = Duration::from_millis(750);
+
+/// Fire date, in seconds since 2001, of a timer that isn't scheduled; also
+/// used as the interval so a fired timer goes back to sleep
+const NEVER: f64 = 1.0e10;
+
+/// Notifications that may mean the selection changed
+const NOTIFICATIONS: [&str; 2] = [
+    kAXSelectedTextChangedNotification,
+    kAXFocusedUIElementChangedNotification,
+];
+
+/// Run loop thread observing the frontmost application
+pub(crate) struct ObserverBackend {
+    run_loop: CFRunLoop,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// State of the observer thread
+struct ObserverState {
+    sender: Sender<SelectionEvent>,
+    debounce: Duration,
+    debouncer: Debouncer,
+    /// Whether to back off while assistive technology is running
+    accommodate_assistive_technology: bool,
+    /// Whether assistive technology was running at the last check
+    assistive_technology: bool,
+    /// Observer of the application currently being watched, with its pid
+    observer: Option<(i32, AXObserver)>,
+    /// One-shot timer reading the selection once notifications settle down
+    read_timer: CFRunLoopTimer,
+}
+
+thread_local! {
+    static OBSERVER: RefCell<Option<ObserverState>> = const { RefCell::new(None) };
+}
+
+/// Start the watcher backends for macOS
+///
+/// Selection changes are read through the accessibility API, which never
+/// touches the clipboard.
+pub(crate) fn start_watcher(
+    options: &WatcherOptions,
+    sender: Sender<SelectionEvent>,
+) -> Result<Vec<Box<dyn WatcherBackend>>, SelectionError> {
+    let mut backends: Vec<Box<dyn WatcherBackend>> = Vec::new();
+
+    if options.watch_selection {
+        backends.push(Box::new(ObserverBackend::spawn(options, sender)?));
+    }
+
+    Ok(backends)
+}
+
+impl ObserverBackend {
+    pub(crate) fn spawn(
+        options: &WatcherOptions,
+        sender: Sender<SelectionEvent>,
+    ) -> Result<Self, SelectionError> {
+        let debounce = options.debounce;
+        let accommodate_assistive_technology = options.accommodate_assistive_technology;
+        let (ready, started) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let run_loop = CFRunLoop::get_current();
+
+            // The read timer stays on the run loop with a far-away fire date
+            // until a notification schedules it
+            let read_timer =
+                CFRunLoopTimer::new(NEVER, NEVER, 0, 0, read_selection, ptr::null_mut());
+            let check_timer = CFRunLoopTimer::new(
+                unsafe { CFAbsoluteTimeGetCurrent() },
+                FRONTMOST_CHECK_INTERVAL.as_secs_f64(),
+                0,
+                0,
+                check_frontmost,
+                ptr::null_mut(),
+            );
+            unsafe {
+                run_loop.add_timer(&read_timer, kCFRunLoopDefaultMode);
+                run_loop.add_timer(&check_timer, kCFRunLoopDefaultMode);
+            }
+
+            OBSERVER.with(|observer| {
+                *observer.borrow_mut() = Some(ObserverState {
+                    sender,
+                    debounce,
+                    // Duplicates are filtered here; the timer does the debouncing
+                    debouncer: Debouncer::new(Duration::ZERO),
+                    accommodate_assistive_technology,
+                    assistive_technology: false,
+                    observer: None,
+                    read_timer,
+                })
+            });
+            let _ = ready.send(run_loop);
+
+            // Returns once the backend is stopped
+            CFRunLoop::run_current();
+
+            OBSERVER.with(|observer| {
+                if let Some(state) = observer.borrow_mut().take() {
+                    if let Some((_, observer)) = state.observer {
+                        observer.stop();
+                    }
+                }
+            });
+            debug!("Accessibility observer stopped");
+        });
+
+        match started.recv() {
+            Ok(run_loop) => Ok(Self {
+                run_loop,
+                handle: Some(handle),
+            }),
+            Err(_) => Err(SelectionError::Other(
+                "Accessibility observer thread exited unexpectedly".to_string(),
+            )),
+        }
+    }
+}
+
+impl WatcherBackend for ObserverBackend {
+    fn stop(mut self: Box<Self>) {
+        self.run_loop.stop();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Move the observer over to the frontmost application if it changed, and
+/// note whether assistive technology is running
+extern "C" fn check_frontmost(_timer: CFRunLoopTimerRef, _info: *mut c_void) {
+    let pid = match app::frontmost_pid() {
+        Some(pid) => pid,
+        None => return,
+    };
+
+    OBSERVER.with(|observer| {
+        let mut observer = observer.borrow_mut();
+        let Some(state) = observer.as_mut() else {
+            return;
+        };
+
+        let assistive_technology =
+            state.accommodate_assistive_technology && app::assistive_technology_active();
+        if assistive_technology != state.assistive_technology {
+            debug!("Assistive technology running: {}", assistive_technology);
+            state.assistive_technology = assistive_technology;
+        }
+
+        if state
+            .observer
+            .as_ref()
+            .is_some_and(|(observed, _)| *observed == pid)
+        {
+            return;
+        }
+
+        if let Some((_, previous)) = state.observer.take() {
+            previous.stop();
+        }
+        state.observer = observe_application(pid).map(|observer| (pid, observer));
+
+        // Selecting in the newly activated app may not notify until it changes
+        schedule_read(state);
+    });
+}
+
+/// Create an observer for the notifications of an application
+fn observe_application(pid: i32) -> Option<AXObserver> {
+    let observer = match AXObserver::new(pid, on_notification) {
+        Ok(observer) => observer,
+        Err(err) => {
+            warn!(
+                "Failed to create accessibility observer for {}: {}",
+                pid, err
+            );
+            return None;
+        }
+    };
+
+    let application = AXUIElement::application(pid);
+    for notification in NOTIFICATIONS {
+        let name = CFString::from_static_string(notification);
+        // The refcon is unused; the state lives in a thread local
+        let result = unsafe {
+            AXObserverAddNotification(
+                observer.as_concrete_TypeRef(),
+                application.as_concrete_TypeRef(),
+                name.as_concrete_TypeRef(),
+                ptr::null_mut(),
+            )
+        };
+        if result != 0 {
+            debug!("Failed to observe {} for {}: {}", notification, pid, result);
+        }
+    }
+
+    observer.start();
+    Some(observer)
+}
+
+unsafe extern "C" fn on_notification(
+    _observer: AXObserverRef,
+    _element: AXUIElementRef,
+    _notification: CFStringRef,
+    _refcon: *mut c_void,
+) {
+    // Drag-selections notify for every character, so wait until they stop
+    OBSERVER.with(|observer| {
+        if let Some(state) = observer.borrow_mut().as_mut() {
+            schedule_read(state);
+        }
+    });
+}
+
+fn schedule_read(state: &ObserverState) {
+    let debounce = if state.assistive_technology {
+        state.debounce.max(ASSISTIVE_DEBOUNCE)
+    } else {
+        state.debounce
+    };
+    let fire_date = unsafe { CFAbsoluteTimeGetCurrent() } + debounce.as_secs_f64();
+    unsafe { CFRunLoopTimerSetNextFireDate(state.read_timer.as_concrete_TypeRef(), fire_date) };
+}
+
+extern "C" fn read_selection(_timer: CFRunLoopTimerRef, _info: *mut c_void) {
+    let current = get_selection_by_accessibility(&SelectionOptions::default())
+        .ok()
+        .map(|selection| {
+            let app = selection.metadata.source_app.clone();
+            (selection, app)
+        });
+
+    OBSERVER.with(|observer| {
+        let mut observer = observer.borrow_mut();
+        let Some(state) = observer.as_mut() else {
+            return;
+        };
+
+        if let Some((selection, app)) = state.debouncer.observe(current, Instant::now()) {
+            let event = SelectionEvent {
+                selection,
+                source: WatchSource::Selection,
+                app,
+            };
+            if state.sender.send(event).is_err() {
+                CFRunLoop::get_current().stop();
+            }
+        }
+    });
+}
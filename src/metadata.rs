@@ -0,0 +1,67 @@
+//! Metadata describing where a selection came from
+
+use std::path::PathBuf;
+
+use crate::{ContentType, SelectionMethod};
+
+/// Additional information captured together with a selection
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct SelectionMetadata {
+    /// The application the selection was read from, if known
+    pub source_app: Option<AppInfo>,
+    /// How the selection was read; `None` when it came from neither method,
+    /// e.g. Finder's selected files asked for by script
+    pub method: Option<SelectionMethod>,
+    /// Text of each range of a discontiguous selection, in the order the
+    /// application reports them; empty for an ordinary single-range selection
+    pub ranges: Vec<String>,
+    /// Format the content was converted from, e.g. RTF for plain text
+    /// extracted from a copy that only produced RTF
+    pub converted_from: Option<ContentType>,
+    /// The data before conversion, in the `converted_from` format
+    pub original: Option<Vec<u8>>,
+    /// Platform format the content was read as, e.g. the MIME type picked
+    /// from those a Wayland selection owner offered
+    pub source_format: Option<String>,
+    /// Whether the content was read from the regular clipboard in place of
+    /// an unreadable primary selection, so may be something copied earlier
+    /// rather than what is selected
+    pub possibly_stale: bool,
+    /// Whether an input method composition was still open in the focused
+    /// widget when the selection was read, so the text may be the
+    /// unfinished composition rather than what is selected, see
+    /// `SelectionOptions::composition_wait`
+    pub possibly_composing: bool,
+    /// Whether the content was cut short because it exceeded
+    /// `SelectionOptions::max_bytes`
+    pub truncated: bool,
+    /// How many times the owner was asked again after timing out, see
+    /// `SelectionOptions::selection_retries`
+    pub retries: u32,
+    /// Whether the selection was copied and left on the clipboard instead
+    /// of the previous contents being restored, see
+    /// `SelectionOptions::restore_clipboard`
+    pub left_on_clipboard: bool,
+    /// The URL the text consists of, when parsed for
+    /// `SelectionOptions::classify_urls`
+    #[cfg(feature = "url")]
+    pub url: Option<url::Url>,
+}
+
+/// Information about the application that owns a selection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppInfo {
+    /// Display name of the application
+    pub name: Option<String>,
+    /// Platform-specific application identifier, e.g. the bundle identifier
+    /// on macOS, the `WM_CLASS` class on X11 or the executable's file name
+    /// on Windows
+    pub identifier: Option<String>,
+    /// Process id of the application
+    pub pid: Option<u32>,
+    /// Path of the application's executable
+    pub executable: Option<PathBuf>,
+    /// Title of the window the content came from, where the platform tells
+    pub window_title: Option<String>,
+}
@@ -0,0 +1,241 @@
+//! Hooks reporting each capture attempt, e.g. for metrics
+//!
+//! A `SelectionObserver` installed with `SelectionOptions::observer`, or
+//! for the whole process with `set_observer`, is told which methods a
+//! capture tries, how each one went and how long it took. It's never given
+//! the content itself, only its type and size.
+
+use log::warn;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::{ContentType, ErrorKind, Selection, SelectionError, SelectionMethod};
+
+/// How one capture method went
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttemptOutcome {
+    /// The method read a selection
+    Captured {
+        content_type: ContentType,
+        /// Size of the content in bytes
+        bytes: usize,
+        /// Whether it was cut off, see `SelectionMetadata::truncated`
+        truncated: bool,
+        /// Whether the copy it was read with was left on the clipboard, see
+        /// `SelectionMetadata::left_on_clipboard`
+        left_on_clipboard: bool,
+    },
+    /// The method failed, or found nothing selected
+    Failed(ErrorKind),
+}
+
+/// Receives reports on capture attempts
+///
+/// The methods are called on the capturing thread, so they should return
+/// quickly. A panicking observer is logged and otherwise ignored.
+pub trait SelectionObserver: Send + Sync {
+    /// A method is about to be tried
+    fn on_attempt(&self, method: SelectionMethod) {
+        let _ = method;
+    }
+
+    /// A method finished after `duration`
+    fn on_result(&self, method: SelectionMethod, outcome: &AttemptOutcome, duration: Duration) {
+        let _ = (method, outcome, duration);
+    }
+
+    /// Method `from` failed with `kind`, so `to` is tried next
+    fn on_fallback(&self, from: SelectionMethod, to: SelectionMethod, kind: ErrorKind) {
+        let _ = (from, to, kind);
+    }
+}
+
+impl fmt::Debug for dyn SelectionObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SelectionObserver")
+    }
+}
+
+static GLOBAL: RwLock<Option<Arc<dyn SelectionObserver>>> = RwLock::new(None);
+/// Whether `GLOBAL` is set, so captures without an observer don't lock it
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Install an observer for every capture whose options don't have one, or
+/// remove it with `None`
+pub fn set_observer(observer: Option<Arc<dyn SelectionObserver>>) {
+    let mut global = GLOBAL.write().unwrap_or_else(PoisonError::into_inner);
+    INSTALLED.store(observer.is_some(), Ordering::Release);
+    *global = observer;
+}
+
+/// The observer of a capture: the one of its options, or the global one
+pub(crate) fn resolve(
+    local: Option<&Arc<dyn SelectionObserver>>,
+) -> Option<Arc<dyn SelectionObserver>> {
+    if let Some(local) = local {
+        return Some(local.clone());
+    }
+    if !INSTALLED.load(Ordering::Acquire) {
+        return None;
+    }
+    GLOBAL
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// What a capture method reads: one selection, or every representation of
+/// it, richest first
+pub(crate) trait Captured {
+    fn is_empty(&self) -> bool;
+
+    /// The outcome reported for it, which describes the richest
+    /// representation
+    fn outcome(&self) -> AttemptOutcome;
+}
+
+impl Captured for Selection {
+    fn is_empty(&self) -> bool {
+        Selection::is_empty(self)
+    }
+
+    fn outcome(&self) -> AttemptOutcome {
+        AttemptOutcome::Captured {
+            content_type: self.content_type.clone(),
+            bytes: self.data.len(),
+            truncated: self.metadata.truncated,
+            left_on_clipboard: self.metadata.left_on_clipboard,
+        }
+    }
+}
+
+impl Captured for Vec<Selection> {
+    fn is_empty(&self) -> bool {
+        self.iter().all(Selection::is_empty)
+    }
+
+    fn outcome(&self) -> AttemptOutcome {
+        match self.first() {
+            Some(selection) => selection.outcome(),
+            None => AttemptOutcome::Failed(ErrorKind::NoSelection),
+        }
+    }
+}
+
+/// Try `method` through `read`, reporting it to `observer`
+///
+/// An empty selection is reported, and returned, as `NoSelectedContent`.
+pub(crate) fn attempt<T: Captured>(
+    observer: Option<&dyn SelectionObserver>,
+    method: SelectionMethod,
+    read: impl FnOnce() -> Result<T, SelectionError>,
+) -> Result<T, SelectionError> {
+    let Some(observer) = observer else {
+        return read().and_then(require_content);
+    };
+
+    notify(|| observer.on_attempt(method));
+    let started = Instant::now();
+    let result = read().and_then(require_content);
+    let duration = started.elapsed();
+
+    let outcome = match &result {
+        Ok(captured) => captured.outcome(),
+        Err(err) => AttemptOutcome::Failed(err.kind()),
+    };
+    notify(|| observer.on_result(method, &outcome, duration));
+    result
+}
+
+/// Report falling back from `from` to `to` after `err`
+pub(crate) fn fallback(
+    observer: Option<&dyn SelectionObserver>,
+    from: SelectionMethod,
+    to: SelectionMethod,
+    err: &SelectionError,
+) {
+    if let Some(observer) = observer {
+        notify(|| observer.on_fallback(from, to, err.kind()));
+    }
+}
+
+fn require_content<T: Captured>(captured: T) -> Result<T, SelectionError> {
+    if captured.is_empty() {
+        Err(SelectionError::NoSelectedContent)
+    } else {
+        Ok(captured)
+    }
+}
+
+/// Call an observer, keeping its panics away from the capture
+fn notify(call: impl FnOnce()) {
+    if panic::catch_unwind(AssertUnwindSafe(call)).is_err() {
+        warn!("A selection observer panicked");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl SelectionObserver for Recorder {
+        fn on_attempt(&self, method: SelectionMethod) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("attempt {}", method));
+        }
+
+        fn on_result(&self, method: SelectionMethod, outcome: &AttemptOutcome, _: Duration) {
+            let outcome = match outcome {
+                AttemptOutcome::Captured { bytes, .. } => format!("{} bytes", bytes),
+                AttemptOutcome::Failed(kind) => kind.to_string(),
+            };
+            let event = format!("{} {}", method, outcome);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    struct Panicking;
+
+    impl SelectionObserver for Panicking {
+        fn on_attempt(&self, _: SelectionMethod) {
+            panic!("observer bug");
+        }
+    }
+
+    #[test]
+    fn test_attempt() {
+        let recorder = Recorder::default();
+        let observer: Option<&dyn SelectionObserver> = Some(&recorder);
+        let method = SelectionMethod::Accessibility;
+
+        let read = attempt(observer, method, || Ok(Selection::new_text("hi".into())));
+        assert!(read.is_ok());
+        let empty = attempt(observer, method, || Ok(Selection::new_text(String::new())));
+        assert!(matches!(empty, Err(SelectionError::NoSelectedContent)));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            [
+                "attempt accessibility",
+                "accessibility 2 bytes",
+                "attempt accessibility",
+                "accessibility no_selection",
+            ]
+        );
+
+        let read = attempt(Some(&Panicking), method, || {
+            Ok(Selection::new_text("hi".into()))
+        });
+        assert!(read.is_ok());
+    }
+}
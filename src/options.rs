@@ -0,0 +1,498 @@
+//! Options controlling how selections are captured
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::app_rules::{default_app_rules, AppRule};
+use crate::observer;
+use crate::{Selection, SelectionError, SelectionObserver};
+
+/// Default for `SelectionOptions::max_bytes`, 64 MiB
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Ways of simulating keystrokes on Wayland
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum WaylandInjector {
+    /// The first available of the others, in the order listed
+    #[default]
+    Auto,
+    /// The compositor's virtual keyboard protocol, supported by wlroots
+    /// compositors (Sway, Hyprland) but not GNOME; needs the
+    /// `virtual-keyboard` feature
+    VirtualKeyboard,
+    /// The `wtype` command, which uses the same protocol
+    Wtype,
+    /// The `ydotool` command, which writes to uinput and works with any
+    /// compositor, given access to `/dev/uinput` and its daemon running
+    Ydotool,
+}
+
+/// Selection buffers of the Linux display servers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SelectionSource {
+    /// The primary selection, which holds whatever text is highlighted
+    #[default]
+    Primary,
+    /// The regular clipboard, which holds what was last copied
+    Clipboard,
+}
+
+/// What to do with a selection larger than `SelectionOptions::max_bytes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OversizePolicy {
+    /// Return the start of text, HTML and file lists, flagged as
+    /// `truncated` in the metadata; images are refused all the same
+    #[default]
+    Truncate,
+    /// Refuse anything larger with `SelectionError::TooLarge`
+    Error,
+}
+
+/// Options shared by the platform selectors
+///
+/// Options are set with the chainable builder methods, e.g.
+/// `SelectionOptions::new().trust_empty_accessibility(false)`.
+#[derive(Debug, Clone)]
+pub struct SelectionOptions {
+    /// Treat an accessibility API that definitively reports an empty
+    /// selection as `NoSelectedContent` instead of trying the clipboard
+    /// fallback, which would inject a copy shortcut into an application that
+    /// has nothing selected
+    ///
+    /// Disable this for applications whose accessibility data is unreliable.
+    pub trust_empty_accessibility: bool,
+    /// Only simulate the copy shortcut when the focused element can hold a
+    /// selection, such as a text field, web area or list
+    ///
+    /// Many applications beep when asked to copy from a button or an empty
+    /// canvas, and the beep can't be suppressed once the keystroke is sent.
+    /// Applications exposing no accessibility information at all never get
+    /// the shortcut with this enabled. Currently honoured on macOS.
+    pub require_text_focus: bool,
+    /// Whether keystrokes may be simulated, for the clipboard fallback and
+    /// for pasting replacement text
+    ///
+    /// Without it only the accessibility API is used. Defaults to false in
+    /// macOS builds with the `sandbox` feature, where posting events needs
+    /// extra entitlements.
+    pub synthetic_input: bool,
+    /// Whether to simulate the copy shortcut and read the clipboard when
+    /// the selection couldn't be read directly
+    ///
+    /// Only used where that isn't the default: on Linux the PRIMARY
+    /// selection normally holds the selected text, but some applications
+    /// (many Electron and Java programs) never set it. Off by default.
+    /// Currently honoured on Linux (X11).
+    pub copy_fallback: bool,
+    /// Whether to read the selection with `xclip` or `xsel`, where one is
+    /// installed, when reading it natively fails in an X11 session
+    ///
+    /// A last resort for setups such as Xpra or old VNC servers. Never used
+    /// in Wayland sessions. Off by default. Currently honoured on Linux.
+    pub x11_command_fallback: bool,
+    /// Whether to ask the terminal for the selection with the OSC 52 escape
+    /// sequence when there's no display server but stdin and stdout are a
+    /// terminal, e.g. over SSH
+    ///
+    /// Most terminals only answer once reading the clipboard is allowed in
+    /// their settings. Off by default. Currently honoured on Linux.
+    pub osc52: bool,
+    /// How the copy shortcut is simulated on Wayland, which has no
+    /// portable way to inject keystrokes
+    pub wayland_injector: WaylandInjector,
+    /// Name of the Wayland seat whose selections are read, any seat if
+    /// `None`
+    ///
+    /// Multi-seat systems and nested compositors may otherwise read the
+    /// empty selection of another seat. `linux::wayland_seats` lists the
+    /// names. Currently honoured on Linux.
+    pub wayland_seat: Option<String>,
+    /// Which selection buffer `get_selection` reads
+    ///
+    /// `get_selection_by_clipboard` always reads the regular clipboard.
+    /// Currently honoured on Linux.
+    pub selection_source: SelectionSource,
+    /// Most bytes of a selection to read, `None` for no limit
+    ///
+    /// The limit is enforced where the data enters the crate, so a
+    /// select-all in a huge document or a huge copied image is never read
+    /// in full. What happens to larger selections is up to `oversize`.
+    /// Defaults to `DEFAULT_MAX_BYTES`.
+    pub max_bytes: Option<usize>,
+    /// Whether selections larger than `max_bytes` are cut short or refused
+    pub oversize: OversizePolicy,
+    /// Whether whitespace around the selected text is trimmed off
+    ///
+    /// Selection owners often include the newline after a triple-clicked
+    /// line. Disable this to keep intentional leading and trailing spaces.
+    /// NULs that owners pad or sprinkle text with are dropped either way.
+    /// Currently honoured on Linux.
+    pub trim_whitespace: bool,
+    /// Separator placed between the ranges of a discontiguous selection
+    /// (e.g. several Cmd-clicked ranges) when joining them into one text
+    ///
+    /// The individual ranges are available in `SelectionMetadata::ranges`.
+    pub range_separator: String,
+    /// Whether text that is a single URL gets it parsed into
+    /// `SelectionMetadata::url`
+    ///
+    /// Multi-line text and prose are left alone. Off by default; needs the
+    /// `url` feature.
+    #[cfg(feature = "url")]
+    pub classify_urls: bool,
+    /// Schemes of the URLs `classify_urls` parses, lowercase; http, https,
+    /// file and mailto by default
+    #[cfg(feature = "url")]
+    pub url_schemes: Vec<String>,
+    /// How long the clipboard fallback waits for the application to handle
+    /// the simulated copy before concluding nothing was selected
+    ///
+    /// Slow applications (Word, remote desktop clients) may need more than
+    /// the default of 500 ms.
+    pub copy_timeout: Duration,
+    /// How often the clipboard is checked while waiting for the copy
+    pub copy_poll_interval: Duration,
+    /// How long the owner of an X11 or Wayland selection may take to send
+    /// its contents
+    ///
+    /// Owners convert the selection when it's requested, which takes busy
+    /// applications (LibreOffice with large selections) several hundred
+    /// milliseconds. Currently honoured on Linux.
+    pub selection_timeout: Duration,
+    /// How many more times a selection owner that timed out is asked
+    ///
+    /// Busy owners (a browser collecting garbage, an IDE indexing) often
+    /// miss one request and answer the next right away. Each attempt gets
+    /// the full `selection_timeout`. Currently honoured on Linux.
+    pub selection_retries: u32,
+    /// Pause before the first retry, doubled before each further one
+    pub selection_retry_backoff: Duration,
+    /// How long to wait for an input method composition open in the focused
+    /// widget to be committed before reading the selection
+    ///
+    /// Some input methods (ibus, fcitx) put the text being composed into
+    /// the primary selection. If the composition is still open then, the
+    /// selection is read anyway and flagged with
+    /// `SelectionMetadata::possibly_composing`. Compositions are found
+    /// through AT-SPI, so this needs the `atspi` feature. Currently
+    /// honoured on Linux.
+    pub composition_wait: Duration,
+    /// Whether clipboard writes made during a capture (the restore of the
+    /// previous contents, text put there to be pasted) are marked transient
+    /// so clipboard managers leave them out of their history
+    ///
+    /// Previous contents that were marked concealed are marked concealed
+    /// again. Currently honoured on macOS.
+    pub mark_transient: bool,
+    /// Whether the clipboard contents a simulated copy replaces are put
+    /// back once the selection is read
+    ///
+    /// Without it the copied selection stays on the clipboard, as if the
+    /// user had copied it, and the previous contents aren't read at all;
+    /// `SelectionMetadata::left_on_clipboard` records this. Pasting
+    /// replacement text restores the clipboard either way.
+    pub restore_clipboard: bool,
+    /// How long accessibility calls wait for an application to answer
+    ///
+    /// Calls block on the application's main thread, so without a limit a
+    /// hanging application hangs the capture with it.
+    pub accessibility_timeout: Duration,
+    /// How long a script querying another application (e.g. Finder's
+    /// selected files) may run before it's killed
+    ///
+    /// Scripts block while the target application hangs or while the user
+    /// is shown the Automation permission prompt.
+    pub script_timeout: Duration,
+    /// Per-application rules replacing the built-in ones, keyed by
+    /// application identifier
+    pub app_rules: HashMap<String, AppRule>,
+    /// Observer told about each capture attempt, instead of the one
+    /// installed with `set_observer`
+    pub observer: Option<Arc<dyn SelectionObserver>>,
+}
+
+impl Default for SelectionOptions {
+    fn default() -> Self {
+        Self {
+            trust_empty_accessibility: true,
+            require_text_focus: false,
+            synthetic_input: !cfg!(all(target_os = "macos", feature = "sandbox")),
+            copy_fallback: false,
+            x11_command_fallback: false,
+            osc52: false,
+            wayland_injector: WaylandInjector::Auto,
+            wayland_seat: None,
+            selection_source: SelectionSource::Primary,
+            max_bytes: Some(DEFAULT_MAX_BYTES),
+            oversize: OversizePolicy::Truncate,
+            trim_whitespace: true,
+            range_separator: "\n".to_string(),
+            #[cfg(feature = "url")]
+            classify_urls: false,
+            #[cfg(feature = "url")]
+            url_schemes: ["http", "https", "file", "mailto"]
+                .map(str::to_string)
+                .to_vec(),
+            copy_timeout: Duration::from_millis(500),
+            copy_poll_interval: Duration::from_millis(10),
+            selection_timeout: Duration::from_millis(100),
+            selection_retries: 2,
+            selection_retry_backoff: Duration::from_millis(20),
+            composition_wait: Duration::from_millis(300),
+            mark_transient: true,
+            restore_clipboard: true,
+            accessibility_timeout: Duration::from_secs(1),
+            script_timeout: Duration::from_secs(2),
+            app_rules: HashMap::new(),
+            observer: None,
+        }
+    }
+}
+
+impl SelectionOptions {
+    /// Create options with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether an empty accessibility result skips the clipboard fallback
+    pub fn trust_empty_accessibility(mut self, trust: bool) -> Self {
+        self.trust_empty_accessibility = trust;
+        self
+    }
+
+    /// Set whether the copy shortcut is only sent to elements holding text
+    pub fn require_text_focus(mut self, require: bool) -> Self {
+        self.require_text_focus = require;
+        self
+    }
+
+    /// Set whether keystrokes may be simulated
+    pub fn synthetic_input(mut self, allow: bool) -> Self {
+        self.synthetic_input = allow;
+        self
+    }
+
+    /// Set whether to fall back to simulating the copy shortcut where that
+    /// isn't the default
+    pub fn copy_fallback(mut self, enable: bool) -> Self {
+        self.copy_fallback = enable;
+        self
+    }
+
+    /// Set whether to fall back to `xclip` or `xsel` when reading an X11
+    /// selection natively fails
+    pub fn x11_command_fallback(mut self, enable: bool) -> Self {
+        self.x11_command_fallback = enable;
+        self
+    }
+
+    /// Set whether to ask the terminal for the selection through OSC 52
+    /// when there's no display server
+    pub fn osc52(mut self, enable: bool) -> Self {
+        self.osc52 = enable;
+        self
+    }
+
+    /// Set how the copy shortcut is simulated on Wayland
+    pub fn wayland_injector(mut self, injector: WaylandInjector) -> Self {
+        self.wayland_injector = injector;
+        self
+    }
+
+    /// Set the name of the Wayland seat whose selections are read
+    pub fn wayland_seat(mut self, seat: &str) -> Self {
+        self.wayland_seat = Some(seat.to_string());
+        self
+    }
+
+    /// Set which selection buffer `get_selection` reads
+    pub fn selection_source(mut self, source: SelectionSource) -> Self {
+        self.selection_source = source;
+        self
+    }
+
+    /// Set the most bytes of a selection to read
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Read selections of any size
+    pub fn no_max_bytes(mut self) -> Self {
+        self.max_bytes = None;
+        self
+    }
+
+    /// Set whether selections larger than `max_bytes` are cut short or
+    /// refused
+    pub fn oversize(mut self, policy: OversizePolicy) -> Self {
+        self.oversize = policy;
+        self
+    }
+
+    /// Set whether whitespace around the selected text is trimmed off
+    pub fn trim_whitespace(mut self, trim: bool) -> Self {
+        self.trim_whitespace = trim;
+        self
+    }
+
+    /// Set the separator joining the ranges of a discontiguous selection
+    pub fn range_separator(mut self, separator: &str) -> Self {
+        self.range_separator = separator.to_string();
+        self
+    }
+
+    /// Set whether text that is a single URL gets it parsed into the
+    /// metadata
+    #[cfg(feature = "url")]
+    pub fn classify_urls(mut self, classify: bool) -> Self {
+        self.classify_urls = classify;
+        self
+    }
+
+    /// Set the schemes of the URLs `classify_urls` parses
+    #[cfg(feature = "url")]
+    pub fn url_schemes<I, S>(mut self, schemes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.url_schemes = schemes
+            .into_iter()
+            .map(|scheme| scheme.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Set how long to wait for the simulated copy
+    pub fn copy_timeout(mut self, timeout: Duration) -> Self {
+        self.copy_timeout = timeout;
+        self
+    }
+
+    /// Set how often the clipboard is checked while waiting for the copy
+    pub fn copy_poll_interval(mut self, interval: Duration) -> Self {
+        self.copy_poll_interval = interval;
+        self
+    }
+
+    /// Set how long the owner of an X11 or Wayland selection may take to
+    /// send it
+    pub fn selection_timeout(mut self, timeout: Duration) -> Self {
+        self.selection_timeout = timeout;
+        self
+    }
+
+    /// Set how many more times a selection owner that timed out is asked
+    pub fn selection_retries(mut self, retries: u32) -> Self {
+        self.selection_retries = retries;
+        self
+    }
+
+    /// Set the pause before the first retry, doubled before each further one
+    pub fn selection_retry_backoff(mut self, backoff: Duration) -> Self {
+        self.selection_retry_backoff = backoff;
+        self
+    }
+
+    /// Set how long to wait for an open input method composition to be
+    /// committed before reading the selection
+    pub fn composition_wait(mut self, wait: Duration) -> Self {
+        self.composition_wait = wait;
+        self
+    }
+
+    /// Set whether clipboard writes made during a capture are marked transient
+    pub fn mark_transient(mut self, mark: bool) -> Self {
+        self.mark_transient = mark;
+        self
+    }
+
+    /// Set whether the clipboard is restored after a simulated copy
+    pub fn restore_clipboard(mut self, restore: bool) -> Self {
+        self.restore_clipboard = restore;
+        self
+    }
+
+    /// Set how long accessibility calls wait for an application to answer
+    pub fn accessibility_timeout(mut self, timeout: Duration) -> Self {
+        self.accessibility_timeout = timeout;
+        self
+    }
+
+    /// Set how long scripts querying other applications may run
+    pub fn script_timeout(mut self, timeout: Duration) -> Self {
+        self.script_timeout = timeout;
+        self
+    }
+
+    /// Set the rule for an application, replacing any built-in rule for it
+    pub fn app_rule(mut self, identifier: &str, rule: AppRule) -> Self {
+        self.app_rules.insert(identifier.to_string(), rule);
+        self
+    }
+
+    /// Get the rule that applies to an application
+    pub fn rule_for_app(&self, identifier: Option<&str>) -> AppRule {
+        let identifier = match identifier {
+            Some(identifier) => identifier,
+            None => return AppRule::default(),
+        };
+
+        self.app_rules
+            .get(identifier)
+            .cloned()
+            .or_else(|| default_app_rules().remove(identifier))
+            .unwrap_or_default()
+    }
+
+    /// Get all rules in effect, built-in ones merged with the overrides
+    pub fn app_rules(&self) -> HashMap<String, AppRule> {
+        let mut rules = default_app_rules();
+        rules.extend(self.app_rules.clone());
+        rules
+    }
+
+    /// Set the observer told about each capture attempt
+    pub fn observer(mut self, observer: Arc<dyn SelectionObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// The observer captures with these options report to, if any
+    pub(crate) fn active_observer(&self) -> Option<Arc<dyn SelectionObserver>> {
+        observer::resolve(self.observer.as_ref())
+    }
+
+    /// Apply the options acting on a selection once it's read: refuse one
+    /// that was cut short at `max_bytes`, if `oversize` says so, and parse
+    /// a URL for `classify_urls`
+    #[cfg_attr(not(feature = "url"), allow(unused_mut))]
+    pub(crate) fn finish(&self, mut selection: Selection) -> Result<Selection, SelectionError> {
+        if let Some(limit) = self.max_bytes {
+            if selection.metadata.truncated && self.oversize == OversizePolicy::Error {
+                return Err(SelectionError::TooLarge { limit });
+            }
+        }
+
+        #[cfg(feature = "url")]
+        if self.classify_urls && !selection.metadata.truncated {
+            selection.metadata.url = selection
+                .as_text()
+                .filter(|text| crate::classify_text(text) == crate::TextClass::Url)
+                .and_then(|text| url::Url::parse(text.trim()).ok())
+                .filter(|url| self.url_schemes.iter().any(|scheme| scheme == url.scheme()));
+        }
+        Ok(selection)
+    }
+
+    /// The error for data that can't be cut short, such as an image, over
+    /// `max_bytes`
+    pub(crate) fn too_large(&self) -> SelectionError {
+        SelectionError::TooLarge {
+            limit: self.max_bytes.unwrap_or(usize::MAX),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Running AppleScript through `osascript`
+
+#[cfg(target_os = "macos")]
+use log::debug;
+use std::io::Read;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "macos")]
+use crate::privacy::redact;
+use crate::SelectionError;
+
+/// How often a running script is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Apple Event error returned when the user denied the Automation permission
+#[cfg(target_os = "macos")]
+const ERR_AE_EVENT_NOT_PERMITTED: &str = "-1743";
+
+/// Text printed by a script
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ScriptOutput {
+    pub text: String,
+    /// Whether invalid UTF-8 was replaced while decoding
+    pub lossy: bool,
+}
+
+/// Decode what `osascript` printed to stdout
+///
+/// `osascript` terminates the result with a newline, which isn't part of it;
+/// any newlines belonging to the result itself are kept.
+pub(crate) fn decode_output(stdout: &[u8]) -> ScriptOutput {
+    let stdout = stdout.strip_suffix(b"\n").unwrap_or(stdout);
+
+    match std::str::from_utf8(stdout) {
+        Ok(text) => ScriptOutput {
+            text: text.to_string(),
+            lossy: false,
+        },
+        Err(_) => ScriptOutput {
+            text: String::from_utf8_lossy(stdout).into_owned(),
+            lossy: true,
+        },
+    }
+}
+
+/// Run a script given as one argument per line
+///
+/// `target` names the scripted application in the error returned when the
+/// Automation permission was denied. A script still running after `timeout`,
+/// e.g. because the target hangs or the user hasn't answered the permission
+/// prompt yet, is killed.
+#[cfg(target_os = "macos")]
+pub(crate) fn run(
+    lines: &[&str],
+    target: &str,
+    timeout: Duration,
+) -> Result<ScriptOutput, SelectionError> {
+    let mut command = Command::new("osascript");
+    for line in lines {
+        command.arg("-e").arg(line);
+    }
+
+    let output = match output_with_deadline(command, timeout, "running an AppleScript") {
+        Err(err @ SelectionError::Timeout { .. }) => {
+            debug!("{} didn't answer the script in time", redact(target));
+            return Err(err);
+        }
+        output => output?,
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains(ERR_AE_EVENT_NOT_PERMITTED) {
+            return Err(SelectionError::PermissionDenied(format!(
+                "Automation permission for {} has not been granted",
+                redact(target)
+            )));
+        }
+        return Err(SelectionError::AppleScriptError(stderr.trim().to_string()));
+    }
+
+    Ok(decode_output(&output.stdout))
+}
+
+/// Run a command to completion, killing it once `timeout` has passed
+///
+/// Like `Command::output`, stdout and stderr are collected; they're drained
+/// on their own threads so a chatty child can't block on a full pipe.
+fn output_with_deadline(
+    mut command: Command,
+    timeout: Duration,
+    phase: &'static str,
+) -> Result<Output, SelectionError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            kill(&mut child);
+            return Err(SelectionError::Timeout {
+                phase,
+                waited: started.elapsed(),
+            });
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    })
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> Option<JoinHandle<Vec<u8>>> {
+    pipe.map(|mut pipe| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let _ = pipe.read_to_end(&mut bytes);
+            bytes
+        })
+    })
+}
+
+fn collect(reader: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default()
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    // Reap the process so it doesn't linger as a zombie
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_output() {
+        let output = decode_output("héllo 👋\n\n".as_bytes());
+        assert_eq!(output.text, "héllo 👋\n");
+        assert!(!output.lossy);
+
+        assert_eq!(decode_output(b"").text, "");
+
+        let output = decode_output(b"caf\xe9\n");
+        assert_eq!(output.text, "caf\u{fffd}");
+        assert!(output.lossy);
+    }
+
+    #[test]
+    fn test_output_with_deadline() {
+        let mut echo = Command::new("echo");
+        echo.arg("done");
+        let output = output_with_deadline(echo, Duration::from_secs(5), "echoing").unwrap();
+        assert_eq!(decode_output(&output.stdout).text, "done");
+
+        let mut sleep = Command::new("sleep");
+        sleep.arg("5");
+        let started = Instant::now();
+        match output_with_deadline(sleep, Duration::from_millis(50), "sleeping") {
+            Err(SelectionError::Timeout { phase, waited }) => {
+                assert_eq!(phase, "sleeping");
+                assert!(waited >= Duration::from_millis(50));
+            }
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
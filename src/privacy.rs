@@ -0,0 +1,113 @@
+//! Keeping what the user selected out of logs and error messages
+//!
+//! Log output and error messages are useful to diagnose failing captures,
+//! but can tell a lot about the user: how much they selected, which files,
+//! which application. With `Privacy::Strict`, set through `set_privacy` or
+//! `SELECTIC_PRIVACY=strict`, they replace all of that by a placeholder.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Environment variable setting the privacy mode, unless `set_privacy` was
+/// called: `strict` or `standard`
+pub const PRIVACY_ENV: &str = "SELECTIC_PRIVACY";
+
+/// What log output and error messages may tell about selections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Privacy {
+    /// Include sizes, paths and application names, to help diagnose
+    /// failing captures; the selected text itself is never included
+    #[default]
+    Standard,
+    /// Replace sizes, selected content, paths, window titles and
+    /// application names by a placeholder
+    Strict,
+}
+
+/// Placeholder replacing what `Privacy::Strict` hides
+pub(crate) const REDACTED: &str = "<redacted>";
+
+const UNSET: u8 = 0;
+const STANDARD: u8 = 1;
+const STRICT: u8 = 2;
+
+static PRIVACY: AtomicU8 = AtomicU8::new(UNSET);
+
+/// Set the privacy mode for the whole process, overriding `SELECTIC_PRIVACY`
+pub fn set_privacy(privacy: Privacy) {
+    PRIVACY.store(encode(privacy), Ordering::Relaxed);
+}
+
+/// The privacy mode in effect
+pub fn privacy() -> Privacy {
+    match PRIVACY.load(Ordering::Relaxed) {
+        STANDARD => Privacy::Standard,
+        STRICT => Privacy::Strict,
+        _ => {
+            let privacy = from_env(std::env::var(PRIVACY_ENV).ok().as_deref());
+            // Lose to a concurrent `set_privacy`
+            let _ = PRIVACY.compare_exchange(
+                UNSET,
+                encode(privacy),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+            self::privacy()
+        }
+    }
+}
+
+fn encode(privacy: Privacy) -> u8 {
+    match privacy {
+        Privacy::Standard => STANDARD,
+        Privacy::Strict => STRICT,
+    }
+}
+
+fn from_env(value: Option<&str>) -> Privacy {
+    match value.map(str::trim) {
+        Some(value) if value.eq_ignore_ascii_case("strict") => Privacy::Strict,
+        _ => Privacy::Standard,
+    }
+}
+
+/// Something `Privacy::Strict` hides, formatted as `REDACTED` then
+pub(crate) struct Redacted<T>(T);
+
+/// Wrap a size, path, application name or piece of content for logging or
+/// an error message
+pub(crate) fn redact<T>(value: T) -> Redacted<T> {
+    Redacted(value)
+}
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match privacy() {
+            Privacy::Standard => self.0.fmt(f),
+            Privacy::Strict => f.write_str(REDACTED),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match privacy() {
+            Privacy::Standard => self.0.fmt(f),
+            Privacy::Strict => f.write_str(REDACTED),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_env() {
+        assert_eq!(from_env(Some("strict")), Privacy::Strict);
+        assert_eq!(from_env(Some(" STRICT ")), Privacy::Strict);
+        assert_eq!(from_env(Some("standard")), Privacy::Standard);
+        assert_eq!(from_env(Some("")), Privacy::Standard);
+        assert_eq!(from_env(None), Privacy::Standard);
+    }
+}
@@ -0,0 +1,272 @@
+//! Conversion of RTF to plain text
+//!
+//! Only what's needed to recover the text of copied selections is handled:
+//! groups, destinations that don't hold body text, escapes and Unicode
+//! characters. Formatting is dropped.
+
+/// Destinations whose content isn't part of the document text
+const IGNORED_DESTINATIONS: [&str; 22] = [
+    "fonttbl",
+    "colortbl",
+    "expandedcolortbl",
+    "stylesheet",
+    "info",
+    "pict",
+    "object",
+    "header",
+    "headerl",
+    "headerr",
+    "footer",
+    "footerl",
+    "footerr",
+    "footnote",
+    "listtable",
+    "listoverridetable",
+    "listtext",
+    "generator",
+    "themedata",
+    "colorschememapping",
+    "latentstyles",
+    "datastore",
+];
+
+/// Characters 0x80..0xA0 of Windows-1252, the usual `\ansicpg` on both
+/// Windows and macOS; the rest of the range matches Latin-1
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// State saved when a group is entered
+#[derive(Clone, Copy)]
+struct Group {
+    /// Whether the group's text is discarded
+    skip: bool,
+    /// Number of fallback characters following each `\u`
+    unicode_skip: usize,
+}
+
+/// Extract the plain text of an RTF document
+pub(crate) fn to_plain_text(rtf: &[u8]) -> String {
+    let mut text = String::new();
+    let mut stack: Vec<Group> = Vec::new();
+    let mut group = Group {
+        skip: false,
+        unicode_skip: 1,
+    };
+    // Fallback characters of the last `\u` still to be dropped
+    let mut pending_skip = 0;
+    // UTF-16 high surrogate waiting for its low half
+    let mut high_surrogate: Option<u16> = None;
+
+    let mut i = 0;
+    while i < rtf.len() {
+        let byte = rtf[i];
+        match byte {
+            b'{' => {
+                stack.push(group);
+                pending_skip = 0;
+                i += 1;
+            }
+            b'}' => {
+                group = stack.pop().unwrap_or(group);
+                pending_skip = 0;
+                i += 1;
+            }
+            b'\\' => {
+                let (control, next) = parse_control(rtf, i + 1);
+                i = next;
+
+                // Fallback text of a `\u` may itself be written as escapes
+                if pending_skip > 0 && !matches!(control, Control::Word("u", _)) {
+                    if let Control::Hex(_) | Control::Symbol(_) = control {
+                        pending_skip -= 1;
+                        continue;
+                    }
+                }
+
+                match control {
+                    Control::Word(word, parameter) => {
+                        // Binary data is never text
+                        if word == "bin" {
+                            i += parameter.map_or(0, |len| len.max(0) as usize);
+                            continue;
+                        }
+                        // A destination at the start of a group replaces it
+                        if IGNORED_DESTINATIONS.contains(&word) {
+                            group.skip = true;
+                        }
+                        if group.skip {
+                            continue;
+                        }
+                        match word {
+                            "uc" => group.unicode_skip = parameter.map_or(1, |n| n.max(0) as usize),
+                            "u" => {
+                                // Negative values encode code units above 0x7fff
+                                let unit = parameter.unwrap_or(0) as u16;
+                                push_utf16(&mut text, &mut high_surrogate, unit);
+                                pending_skip = group.unicode_skip;
+                            }
+                            "par" | "line" | "sect" | "row" => text.push('\n'),
+                            "tab" | "cell" => text.push('\t'),
+                            "emdash" => text.push('—'),
+                            "endash" => text.push('–'),
+                            "bullet" => text.push('•'),
+                            "lquote" => text.push('‘'),
+                            "rquote" => text.push('’'),
+                            "ldblquote" => text.push('“'),
+                            "rdblquote" => text.push('”'),
+                            _ => {}
+                        }
+                    }
+                    Control::Symbol(b'*') => group.skip = true,
+                    Control::Symbol(symbol) => {
+                        if group.skip {
+                            continue;
+                        }
+                        match symbol {
+                            b'~' => text.push('\u{a0}'),
+                            b'_' => text.push('\u{2011}'),
+                            // Equivalent to `\par`, written by some generators
+                            b'\n' | b'\r' => text.push('\n'),
+                            b'-' => {}
+                            literal => text.push(char::from(literal)),
+                        }
+                    }
+                    Control::Hex(value) => {
+                        if !group.skip {
+                            text.push(decode_cp1252(value));
+                        }
+                    }
+                }
+            }
+            // Line breaks in the source are insignificant
+            b'\r' | b'\n' => i += 1,
+            _ => {
+                let start = i;
+                while i < rtf.len() && !matches!(rtf[i], b'{' | b'}' | b'\\' | b'\r' | b'\n') {
+                    i += 1;
+                }
+                if group.skip {
+                    continue;
+                }
+
+                let mut run = &rtf[start..i];
+                let dropped = pending_skip.min(run.len());
+                run = &run[dropped..];
+                pending_skip -= dropped;
+
+                text.push_str(&String::from_utf8_lossy(run));
+            }
+        }
+    }
+
+    text
+}
+
+enum Control<'a> {
+    /// A control word with its optional numeric parameter
+    Word(&'a str, Option<i32>),
+    /// A control symbol such as `\{` or `\~`
+    Symbol(u8),
+    /// A `\'hh` escape
+    Hex(u8),
+}
+
+/// Parse the control word or symbol following a backslash at `start`,
+/// returning it with the index after it
+fn parse_control(rtf: &[u8], start: usize) -> (Control<'_>, usize) {
+    let Some(&first) = rtf.get(start) else {
+        return (Control::Symbol(b'\\'), start);
+    };
+
+    if first == b'\'' {
+        let value = rtf
+            .get(start + 1..start + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        return match value {
+            Some(value) => (Control::Hex(value), start + 3),
+            None => (Control::Symbol(b'\''), start + 1),
+        };
+    }
+
+    if !first.is_ascii_alphabetic() {
+        return (Control::Symbol(first), start + 1);
+    }
+
+    let mut end = start;
+    while end < rtf.len() && rtf[end].is_ascii_alphabetic() {
+        end += 1;
+    }
+    // Never fails: the word is ASCII letters only
+    let word = std::str::from_utf8(&rtf[start..end]).unwrap_or_default();
+
+    let number_start = end;
+    if rtf.get(end) == Some(&b'-') {
+        end += 1;
+    }
+    while end < rtf.len() && rtf[end].is_ascii_digit() {
+        end += 1;
+    }
+    let parameter = std::str::from_utf8(&rtf[number_start..end])
+        .ok()
+        .and_then(|number| number.parse().ok());
+
+    // A single space delimits the control word and belongs to it
+    if rtf.get(end) == Some(&b' ') {
+        end += 1;
+    }
+
+    (Control::Word(word, parameter), end)
+}
+
+fn decode_cp1252(value: u8) -> char {
+    match value {
+        0x80..=0x9f => CP1252_HIGH[usize::from(value - 0x80)],
+        _ => char::from(value),
+    }
+}
+
+fn push_utf16(text: &mut String, high_surrogate: &mut Option<u16>, unit: u16) {
+    match (high_surrogate.take(), unit) {
+        (None, 0xd800..=0xdbff) => *high_surrogate = Some(unit),
+        (Some(high), 0xdc00..=0xdfff) => {
+            let code_point =
+                0x10000 + ((u32::from(high) - 0xd800) << 10) + (u32::from(unit) - 0xdc00);
+            text.push(char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        (high, _) => {
+            if high.is_some() {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+            text.push(char::from_u32(u32::from(unit)).unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_plain_text() {
+        // As written by TextEdit
+        let rtf = br"{\rtf1\ansi\ansicpg1252\cocoartf2761
+\cocoatextscaling0\cocoaplatform0{\fonttbl\f0\fswiss\fcharset0 Helvetica;}
+{\colortbl;\red255\green255\blue255;}
+{\*\expandedcolortbl;;}
+\pard\tx560\pardirnatural\partightenfactor0
+
+\f0\fs24 \cf0 Caf\'e9 \{braces\}\
+second line\tab \u8364 ? and \u-10179?\u-8694?}";
+
+        assert_eq!(to_plain_text(rtf), "Café {braces}\nsecond line\t€ and 😊");
+    }
+
+    #[test]
+    fn test_to_plain_text_skips_ignored_groups() {
+        let rtf = br"{\rtf1{\info{\title Secret}}{\*\generator Word;}Hello\par World\emdash !{\pict\bin3 abc}}";
+        assert_eq!(to_plain_text(rtf), "Hello\nWorld—!");
+    }
+}
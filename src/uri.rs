@@ -0,0 +1,163 @@
+//! Parsing of `file://` URLs into paths
+
+use std::path::PathBuf;
+
+use crate::path_from_bytes;
+
+/// Convert a `file://` URL into a path
+///
+/// Accepts an empty host or `localhost`; URLs naming another host or using
+/// any other scheme return `None`.
+pub(crate) fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let url = url.trim();
+    let rest = strip_prefix_ignore_case(url, "file://")?;
+
+    // The path starts at the first slash after the host
+    let path = match rest.find('/') {
+        Some(0) => rest,
+        Some(index) if rest[..index].eq_ignore_ascii_case("localhost") => &rest[index..],
+        _ => return None,
+    };
+
+    // Drop any query or fragment, which never belong to the path
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+
+    Some(path_from_bytes(&percent_decode(path)))
+}
+
+/// MIME type of Nautilus's and Caja's file lists, which start with whether
+/// the files were cut or copied
+#[cfg(any(not(target_os = "macos"), test))]
+const GNOME_COPIED_FILES_MIME: &str = "x-special/gnome-copied-files";
+
+/// MIME types of file lists, by preference
+#[cfg(not(any(target_os = "macos", windows)))]
+pub(crate) const FILE_LIST_TYPES: [&str; 2] = ["text/uri-list", GNOME_COPIED_FILES_MIME];
+
+/// Parse a file list offered as one of `FILE_LIST_TYPES`
+#[cfg(any(not(target_os = "macos"), test))]
+pub(crate) fn parse_file_list(mime: &str, list: &str) -> Vec<PathBuf> {
+    if mime == GNOME_COPIED_FILES_MIME {
+        parse_uri_list(strip_gnome_operation(list))
+    } else {
+        parse_uri_list(list)
+    }
+}
+
+/// Drop the `copy` or `cut` line heading a GNOME file list
+#[cfg(any(not(target_os = "macos"), test))]
+fn strip_gnome_operation(list: &str) -> &str {
+    match list.split_once('\n') {
+        Some((operation, rest)) if matches!(operation.trim(), "copy" | "cut") => rest,
+        _ => list,
+    }
+}
+
+/// Parse a `text/uri-list` into the paths of its `file://` URLs
+///
+/// Lines starting with `#` are comments; URLs that aren't local files are
+/// skipped.
+#[cfg(any(not(target_os = "macos"), test))]
+fn parse_uri_list(list: &str) -> Vec<PathBuf> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(file_url_to_path)
+        .collect()
+}
+
+/// Decode `%XX` escapes, leaving malformed escapes as they are
+pub(crate) fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            if let (Some(high), Some(low)) =
+                (hex_value(bytes[index + 1]), hex_value(bytes[index + 2]))
+            {
+                decoded.push(high << 4 | low);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+
+    decoded
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    if text.len() >= prefix.len()
+        && text.is_char_boundary(prefix.len())
+        && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        Some(&text[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_url_to_path() {
+        assert_eq!(
+            file_url_to_path("file:///Users/me/My%20File%22quoted%22%0A.txt"),
+            Some(PathBuf::from("/Users/me/My File\"quoted\"\n.txt"))
+        );
+        assert_eq!(
+            file_url_to_path("file://localhost/tmp/%E4%B8%AD%E6%96%87/"),
+            Some(PathBuf::from("/tmp/中文/"))
+        );
+        assert_eq!(file_url_to_path("file://server/share"), None);
+        assert_eq!(file_url_to_path("https://example.com/a"), None);
+    }
+
+    #[test]
+    fn test_parse_uri_list() {
+        let list = "# copied from Dolphin\r\nfile:///home/me/My%20Report.pdf\r\n\r\n\
+                    file://localhost/home/me/%C3%A9t%C3%A9.png\r\nhttps://example.com/\r\n";
+        assert_eq!(
+            parse_uri_list(list),
+            [
+                PathBuf::from("/home/me/My Report.pdf"),
+                PathBuf::from("/home/me/été.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_gnome_file_list() {
+        assert_eq!(
+            parse_file_list(
+                GNOME_COPIED_FILES_MIME,
+                "cut\nfile:///tmp/a%20b\nfile:///tmp/c"
+            ),
+            [PathBuf::from("/tmp/a b"), PathBuf::from("/tmp/c")]
+        );
+        assert_eq!(
+            parse_file_list(GNOME_COPIED_FILES_MIME, "file:///tmp/a\n"),
+            [PathBuf::from("/tmp/a")]
+        );
+    }
+
+    #[test]
+    fn test_percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz%41"), b"%zzA");
+    }
+}
@@ -0,0 +1,362 @@
+//! Watching for selection and clipboard changes
+//!
+//! A `SelectionWatcher` runs platform backends on background threads and
+//! delivers their events through a channel. Backends never simulate
+//! keystrokes: they only use passive reads such as accessibility queries,
+//! the X11/Wayland primary selection or clipboard notifications.
+
+use crate::{AppInfo, Selection, SelectionError};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What kind of change produced a watcher event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchSource {
+    /// The user's selection changed
+    Selection,
+    /// Something was copied to the clipboard
+    Clipboard,
+}
+
+/// An event delivered by a `SelectionWatcher`
+#[derive(Debug, Clone)]
+pub struct SelectionEvent {
+    /// The new content
+    pub selection: Selection,
+    /// What kind of change produced the event
+    pub source: WatchSource,
+    /// The application the content came from, if known
+    pub app: Option<AppInfo>,
+}
+
+/// Options for a `SelectionWatcher`
+#[derive(Debug, Clone)]
+pub struct WatcherOptions {
+    /// How often polling backends check for changes
+    pub poll_interval: Duration,
+    /// How long a change has to stay stable before it's reported, so that
+    /// e.g. a drag-selection produces one event instead of dozens
+    pub debounce: Duration,
+    /// Report changes of the user's selection
+    pub watch_selection: bool,
+    /// Report clipboard changes (currently supported on Windows, X11 and
+    /// Wayland compositors with the data control protocol)
+    pub watch_clipboard: bool,
+    /// MIME preference list used when reading clipboard contents
+    pub preferred: Vec<String>,
+    /// Back off while a screen reader or other assistive technology is
+    /// running, so the watcher doesn't compete with it for the accessibility
+    /// API (currently detected on macOS)
+    pub accommodate_assistive_technology: bool,
+}
+
+impl Default for WatcherOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(250),
+            debounce: Duration::from_millis(100),
+            watch_selection: true,
+            watch_clipboard: false,
+            preferred: vec!["text/plain".to_string()],
+            accommodate_assistive_technology: true,
+        }
+    }
+}
+
+impl WatcherOptions {
+    /// Create watcher options with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how often polling backends check for changes
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Set how long a change has to stay stable before it's reported
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Set whether selection changes are reported
+    pub fn watch_selection(mut self, watch: bool) -> Self {
+        self.watch_selection = watch;
+        self
+    }
+
+    /// Set whether clipboard changes are reported
+    pub fn watch_clipboard(mut self, watch: bool) -> Self {
+        self.watch_clipboard = watch;
+        self
+    }
+
+    /// Set the MIME preference list used when reading clipboard contents
+    pub fn preferred(mut self, preferred: &[&str]) -> Self {
+        self.preferred = preferred.iter().map(|mime| mime.to_string()).collect();
+        self
+    }
+
+    /// Set whether watching backs off while assistive technology is running
+    pub fn accommodate_assistive_technology(mut self, accommodate: bool) -> Self {
+        self.accommodate_assistive_technology = accommodate;
+        self
+    }
+}
+
+/// Watches for selection and clipboard changes in the background
+///
+/// The backends are stopped when the watcher is dropped.
+pub struct SelectionWatcher {
+    receiver: Receiver<SelectionEvent>,
+    backends: Vec<Box<dyn WatcherBackend>>,
+}
+
+impl SelectionWatcher {
+    /// Start watching with the given options
+    pub fn new(options: WatcherOptions) -> Result<Self, SelectionError> {
+        let (sender, receiver) = mpsc::channel();
+        let backends = start_backends(&options, sender)?;
+
+        Ok(Self { receiver, backends })
+    }
+
+    /// Block until the next event arrives
+    pub fn recv(&self) -> Option<SelectionEvent> {
+        self.receiver.recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<SelectionEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Get the next event if one is already waiting
+    pub fn try_recv(&self) -> Option<SelectionEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Iterate over events as they arrive
+    pub fn iter(&self) -> impl Iterator<Item = SelectionEvent> + '_ {
+        self.receiver.iter()
+    }
+}
+
+impl Drop for SelectionWatcher {
+    fn drop(&mut self) {
+        for backend in self.backends.drain(..) {
+            backend.stop();
+        }
+    }
+}
+
+/// A running watcher backend
+pub(crate) trait WatcherBackend: Send {
+    /// Stop the backend and wait for its thread to finish
+    fn stop(self: Box<Self>);
+}
+
+fn start_backends(
+    options: &WatcherOptions,
+    sender: Sender<SelectionEvent>,
+) -> Result<Vec<Box<dyn WatcherBackend>>, SelectionError> {
+    #[cfg(feature = "fake-platform")]
+    {
+        crate::fake::start_watcher(options, sender)
+    }
+
+    #[cfg(all(not(feature = "fake-platform"), target_os = "macos"))]
+    {
+        crate::macos::start_watcher(options, sender)
+    }
+
+    #[cfg(all(not(feature = "fake-platform"), target_os = "windows"))]
+    {
+        crate::windows::start_watcher(options, sender)
+    }
+
+    #[cfg(all(
+        not(feature = "fake-platform"),
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    ))]
+    {
+        crate::linux::start_watcher(options, sender)
+    }
+
+    #[cfg(not(any(
+        feature = "fake-platform",
+        target_os = "macos",
+        target_os = "windows",
+        all(unix, not(any(target_os = "ios", target_os = "android")))
+    )))]
+    {
+        let _ = (options, sender);
+        Err(SelectionError::UnsupportedPlatform)
+    }
+}
+
+/// A backend that periodically calls a passive read function
+///
+/// The macOS backend is notification driven and doesn't use it.
+#[cfg_attr(
+    all(target_os = "macos", not(feature = "fake-platform")),
+    allow(dead_code)
+)]
+pub(crate) struct PollingBackend {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[cfg_attr(
+    all(target_os = "macos", not(feature = "fake-platform")),
+    allow(dead_code)
+)]
+impl PollingBackend {
+    /// Spawn a polling thread
+    ///
+    /// `read` returns the current content, or `None` when nothing is selected.
+    pub(crate) fn spawn<F>(
+        options: &WatcherOptions,
+        source: WatchSource,
+        sender: Sender<SelectionEvent>,
+        mut read: F,
+    ) -> Self
+    where
+        F: FnMut() -> Option<(Selection, Option<AppInfo>)> + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let interval = options.poll_interval;
+        let mut debouncer = Debouncer::new(options.debounce);
+
+        let handle = thread::spawn(move || {
+            // Dropping the stop sender ends the loop
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Some((selection, app)) = debouncer.observe(read(), Instant::now()) {
+                    let event = SelectionEvent {
+                        selection,
+                        source,
+                        app,
+                    };
+                    if sender.send(event).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl WatcherBackend for PollingBackend {
+    fn stop(mut self: Box<Self>) {
+        // Waking the thread up immediately instead of after the interval
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Coalesces rapid changes and suppresses repeated reports of the same content
+pub(crate) struct Debouncer {
+    debounce: Duration,
+    pending: Option<(Selection, Option<AppInfo>, Instant)>,
+    last_reported: Option<Selection>,
+}
+
+impl Debouncer {
+    pub(crate) fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            pending: None,
+            last_reported: None,
+        }
+    }
+
+    /// Feed the latest observed value, returning it once it's been stable for
+    /// the debounce period and differs from what was last reported
+    pub(crate) fn observe(
+        &mut self,
+        current: Option<(Selection, Option<AppInfo>)>,
+        now: Instant,
+    ) -> Option<(Selection, Option<AppInfo>)> {
+        let Some((selection, app)) = current.filter(|(selection, _)| !selection.is_empty()) else {
+            // Selecting the same content again after clearing it is a new event
+            self.pending = None;
+            self.last_reported = None;
+            return None;
+        };
+
+        let changed = match &self.pending {
+            Some((pending, _, _)) => !same_content(pending, &selection),
+            None => true,
+        };
+        if changed {
+            self.pending = Some((selection, app, now));
+        }
+
+        let (pending, _, since) = self.pending.as_ref()?;
+        let stable = now.duration_since(*since) >= self.debounce;
+        let new = self
+            .last_reported
+            .as_ref()
+            .is_none_or(|last| !same_content(last, pending));
+
+        if stable && new {
+            let (selection, app, _) = self.pending.clone()?;
+            self.last_reported = Some(selection.clone());
+            Some((selection, app))
+        } else {
+            None
+        }
+    }
+}
+
+fn same_content(a: &Selection, b: &Selection) -> bool {
+    a.content_type == b.content_type && a.data == b.data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Option<(Selection, Option<AppInfo>)> {
+        Some((Selection::new_text(value.to_string()), None))
+    }
+
+    #[test]
+    fn test_debouncer_coalesces_changes() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+
+        // A drag-selection growing quickly produces no events
+        assert!(debouncer.observe(text("h"), ms(0)).is_none());
+        assert!(debouncer.observe(text("he"), ms(50)).is_none());
+        assert!(debouncer.observe(text("hello"), ms(100)).is_none());
+
+        // Once stable, it's reported exactly once
+        let (selection, _) = debouncer.observe(text("hello"), ms(200)).unwrap();
+        assert_eq!(selection.as_text().as_deref(), Some("hello"));
+        assert!(debouncer.observe(text("hello"), ms(300)).is_none());
+    }
+
+    #[test]
+    fn test_debouncer_reports_reselection() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+
+        assert!(debouncer.observe(text("a"), ms(0)).is_some());
+        assert!(debouncer.observe(None, ms(10)).is_none());
+        assert!(debouncer.observe(text("a"), ms(20)).is_some());
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{Selection, SelectionError, Selector};
+use crate::{
+    capture, pick_preferred, sort_by_richness, AccessibilityRole, AppInfo, AppRule, Capabilities,
+    ContentType, ErrorKind, Selection, SelectionError, SelectionMethod, SelectionObserver,
+    SelectionOptions, Selector,
+};
 use arboard::Clipboard;
 use enigo::{
     self,
@@ -7,34 +11,133 @@ use enigo::{
 };
 use log::{debug, error, info, warn};
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Once;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
 use std::time::Duration;
+use windows::core::w;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HGLOBAL, HWND};
 use windows::Win32::System::Com::{
     CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_APARTMENTTHREADED,
 };
-use windows::Win32::System::DataExchange::GetClipboardSequenceNumber;
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, GetClipboardData, GetClipboardSequenceNumber, IsClipboardFormatAvailable,
+    OpenClipboard, RegisterClipboardFormatW,
+};
+use windows::Win32::System::Memory::{GlobalLock, GlobalSize, GlobalUnlock};
+#[cfg(feature = "image")]
+use windows::Win32::System::Ole::CF_DIB;
+use windows::Win32::System::Ole::{CF_HDROP, CF_UNICODETEXT};
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
 use windows::Win32::UI::Accessibility::{
     CUIAutomation, IUIAutomation, IUIAutomationTextPattern, UIA_TextPatternId,
 };
+use windows::Win32::UI::Shell::{DragQueryFileW, HDROP};
+use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+#[cfg_attr(feature = "fake-platform", allow(dead_code))]
+mod watcher;
+#[cfg_attr(feature = "fake-platform", allow(unused_imports))]
+pub(crate) use watcher::start_watcher;
+
+const TEXT_MIME: &str = "text/plain";
+// 模拟 Ctrl+C 后等待剪贴板更新的时间
+const COPY_SETTLE_TIME: Duration = Duration::from_millis(150);
 
 // 确保COM只初始化一次
 static COM_INIT: Once = Once::new();
 static COM_INIT_FAILED: AtomicBool = AtomicBool::new(false);
 
-pub struct WindowsSelector {}
+// 正在进行的模拟复制数, 以及本crate最后一次改写剪贴板后的序列号;
+// 剪贴板监听器据此忽略复制和恢复产生的更新
+static COPIES_IN_PROGRESS: AtomicUsize = AtomicUsize::new(0);
+static OWN_SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+pub struct WindowsSelector {
+    options: SelectionOptions,
+}
 
 impl WindowsSelector {
     pub fn new() -> Self {
+        Self::with_options(SelectionOptions::default())
+    }
+
+    pub fn with_options(options: SelectionOptions) -> Self {
         // 在创建选择器时尝试初始化COM
         init_com();
-        WindowsSelector {}
+        WindowsSelector { options }
     }
 }
 
 impl Selector for WindowsSelector {
-    fn get_selection(&self) -> Result<Selection, SelectionError> {
-        get_windows_selection()
+    // 前台窗口所属程序的规则, 以可执行文件名为标识
+    fn capture_rule(&self) -> Result<AppRule, SelectionError> {
+        let app = app_info_for_window(unsafe { GetForegroundWindow() });
+        Ok(self
+            .options
+            .rule_for_app(app.as_ref().and_then(|app| app.identifier.as_deref())))
+    }
+
+    fn observer(&self) -> Option<Arc<dyn SelectionObserver>> {
+        self.options.active_observer()
+    }
+
+    fn trusts_empty_accessibility(&self) -> bool {
+        self.options.trust_empty_accessibility
+    }
+
+    fn get_selection_raw(&self, preferred: &[&str]) -> Result<Selection, SelectionError> {
+        // UI自动化只能提供纯文本, 所以只有纯文本排在首位时才尝试它
+        let accessibility = match preferred.first() {
+            None => return self.get_selection(),
+            Some(mime) if ContentType::Text.matches_mime(mime) => AccessibilityRole::Answer,
+            Some(_) => AccessibilityRole::Skip,
+        };
+        capture(self, accessibility, |method| match method {
+            SelectionMethod::Accessibility => {
+                pick_preferred(vec![self.get_selection_by_accessibility()?], preferred)
+            }
+            SelectionMethod::Clipboard => self
+                .options
+                .finish(get_selection_by_clipboard(&self.options, preferred)?),
+        })
+    }
+
+    // 各格式来自一次复制, UI自动化读到的纯文本在复制失败时作为后备
+    fn get_selection_all(&self) -> Result<Vec<Selection>, SelectionError> {
+        capture(self, AccessibilityRole::StandIn, |method| match method {
+            SelectionMethod::Accessibility => Ok(vec![self.get_selection_by_accessibility()?]),
+            SelectionMethod::Clipboard => get_windows_selection_all(&self.options)?
+                .into_iter()
+                .map(|selection| self.options.finish(selection))
+                .collect(),
+        })
+    }
+
+    fn get_selection_by_accessibility(&self) -> Result<Selection, SelectionError> {
+        if COM_INIT_FAILED.load(Ordering::SeqCst) {
+            return Err(SelectionError::MethodUnavailable(
+                "COM initialization failed".to_string(),
+            ));
+        }
+        match get_text_by_automation(self.options.max_bytes) {
+            Ok(AutomationText::Text(text)) => self
+                .options
+                .finish(automation_selection(text, &self.options)),
+            Ok(AutomationText::Empty) => Err(SelectionError::NoSelectedContent),
+            Ok(AutomationText::Unavailable) => Err(SelectionError::MethodUnavailable(
+                "focused element does not support TextPattern".to_string(),
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn get_selection_by_clipboard(&self) -> Result<Selection, SelectionError> {
+        let selection = get_selection_by_clipboard(&self.options, &[TEXT_MIME])?;
+        self.options.finish(selection)
     }
 }
 
@@ -57,67 +160,57 @@ fn init_com() {
     });
 }
 
-fn get_windows_selection() -> Result<Selection, SelectionError> {
-    debug!("Getting Windows selection...");
-
-    let result = get_text_internal()?;
-
-    if result.is_empty() {
-        return Err(SelectionError::NoSelectedContent);
+pub(crate) fn capabilities() -> Capabilities {
+    Capabilities {
+        platform: "windows",
+        // 选中文本靠轮询UI自动化获取
+        selection_events: Some(false),
+        focused_app: Some(true),
+        global_hotkeys: None,
+        ..Default::default()
     }
+}
 
-    Ok(Selection::new_text(result))
+// UI自动化的读取结果
+enum AutomationText {
+    // 焦点元素返回了选中的文本
+    Text(String),
+    // 焦点元素支持TextPattern, 并且明确报告没有选中内容
+    Empty,
+    // 焦点元素不支持TextPattern, 无法判断是否有选中内容
+    Unavailable,
 }
 
-fn get_text_internal() -> Result<String, SelectionError> {
-    // 首先尝试UI自动化方法
-    if !COM_INIT_FAILED.load(Ordering::SeqCst) {
-        match get_text_by_automation() {
-            Ok(text) if !text.is_empty() => {
-                debug!(
-                    "Successfully retrieved text via UI Automation: {} chars",
-                    text.len()
-                );
-                return Ok(text);
-            }
-            Ok(_) => info!("UI Automation returned empty text"),
-            Err(err) => error!("UI Automation error: {}", err),
-        }
-    } else {
-        debug!("Skipping UI Automation due to COM initialization failure");
-    }
-
-    // 回退到剪贴板方法
-    info!("Falling back to clipboard method");
-    match get_text_by_clipboard() {
-        Ok(text) if !text.is_empty() => {
-            debug!(
-                "Successfully retrieved text via clipboard: {} chars",
-                text.len()
-            );
-            return Ok(text);
-        }
-        Ok(_) => info!("Clipboard method returned empty text"),
-        Err(err) => {
-            error!("Clipboard method error: {}", err);
-            return Err(SelectionError::ClipboardError(err.to_string()));
-        }
+// UI自动化的错误, 保留HRESULT和原始错误
+fn automation_error(message: &str, err: windows::core::Error) -> SelectionError {
+    SelectionError::AccessibilityError {
+        message: format!("{}: {}", message, err),
+        code: Some(err.code().0),
+        source: Some(Box::new(err)),
     }
+}
 
-    Err(SelectionError::NoSelectedContent)
+// UI自动化读到的文本, 截断到max_bytes
+fn automation_selection(text: String, options: &SelectionOptions) -> Selection {
+    let mut selection = Selection::new_text(text).with_method(SelectionMethod::Accessibility);
+    if let Some(max_bytes) = options.max_bytes {
+        selection.truncate_text(max_bytes);
+    }
+    selection
 }
 
-fn get_text_by_automation() -> Result<String, Box<dyn Error>> {
+// max_bytes限制读取的字符数, 每个UTF-16单元至少占一个UTF-8字节
+fn get_text_by_automation(max_bytes: Option<usize>) -> Result<AutomationText, SelectionError> {
     debug!("Attempting to get text via UI Automation");
 
     // 创建IUIAutomation实例
     let auto: IUIAutomation = unsafe { CoCreateInstance(&CUIAutomation, None, CLSCTX_ALL) }
-        .map_err(|e| Box::new(e) as Box<dyn Error>)?;
+        .map_err(|e| automation_error("Failed to create the UI Automation client", e))?;
 
     // 获取焦点元素
     let el = unsafe { auto.GetFocusedElement() }.map_err(|e| {
         debug!("Failed to get focused element: {:?}", e);
-        Box::new(SelectionError::NoFocusedElement) as Box<dyn Error>
+        SelectionError::NoFocusedElement
     })?;
 
     // 尝试获取TextPattern
@@ -128,224 +221,650 @@ fn get_text_by_automation() -> Result<String, Box<dyn Error>> {
         Ok(pattern) => pattern,
         Err(e) => {
             debug!("No text pattern available: {:?}", e);
-            return Ok(String::new());
+            return Ok(AutomationText::Unavailable);
         }
     };
 
     // 获取TextRange数组
-    let text_array = unsafe { text_pattern.GetSelection() }.map_err(|e| {
-        debug!("Failed to get selection: {:?}", e);
-        Box::new(e) as Box<dyn Error>
-    })?;
+    let text_array = unsafe { text_pattern.GetSelection() }
+        .map_err(|e| automation_error("Failed to get the selection", e))?;
 
-    let length = unsafe { text_array.Length() }.map_err(|e| {
-        debug!("Failed to get text array length: {:?}", e);
-        Box::new(e) as Box<dyn Error>
-    })?;
+    let length = unsafe { text_array.Length() }
+        .map_err(|e| automation_error("Failed to get the number of selected ranges", e))?;
 
     if length == 0 {
         debug!("No text ranges in selection");
-        return Ok(String::new());
+        return Ok(AutomationText::Empty);
     }
 
     // 迭代TextRange数组
     let mut target = String::with_capacity(256); // 预分配合理的容量
     for i in 0..length {
-        let text_range = unsafe { text_array.GetElement(i) }.map_err(|e| {
-            debug!("Failed to get text range element {}: {:?}", i, e);
-            Box::new(e) as Box<dyn Error>
-        })?;
-
-        // 指定合理的字符数量限制，-1表示获取所有
-        let text = unsafe { text_range.GetText(1024) }.map_err(|e| {
-            debug!("Failed to get text from range {}: {:?}", i, e);
-            Box::new(e) as Box<dyn Error>
-        })?;
+        let text_range = unsafe { text_array.GetElement(i) }
+            .map_err(|e| automation_error("Failed to get a selected range", e))?;
+
+        // 多读一个字符以判断是否被截断, -1表示获取所有
+        let max_length = match max_bytes {
+            Some(max) if target.len() > max => break,
+            Some(max) => i32::try_from(max - target.len() + 1).unwrap_or(i32::MAX),
+            None => -1,
+        };
+        let text = unsafe { text_range.GetText(max_length) }
+            .map_err(|e| automation_error("Failed to get the text of a selected range", e))?;
 
         target.push_str(&text.to_string());
     }
 
-    Ok(target.trim().to_string())
+    let target = target.trim();
+    if target.is_empty() {
+        Ok(AutomationText::Empty)
+    } else {
+        Ok(AutomationText::Text(target.to_string()))
+    }
+}
+
+fn get_selection_by_clipboard(
+    options: &SelectionOptions,
+    preferred: &[&str],
+) -> Result<Selection, SelectionError> {
+    debug!("Attempting to get selection via clipboard");
+
+    // 按调用方的优先级读取新的剪贴板内容
+    let selection = copy_and_read(options, || negotiate_clipboard(preferred, options))?;
+
+    // 返回新获取的内容
+    if !selection.is_empty() {
+        Ok(selection
+            .with_method(SelectionMethod::Clipboard)
+            .left_on_clipboard(!options.restore_clipboard))
+    } else {
+        Err(SelectionError::NoSelectedContent)
+    }
 }
 
-fn get_text_by_clipboard() -> Result<String, Box<dyn Error>> {
-    debug!("Attempting to get text via clipboard");
+// 模拟Ctrl+C, 用read读取复制的内容, 再恢复原来的剪贴板内容;
+// restore_clipboard关闭时既不保存也不恢复, 复制的内容留在剪贴板上
+fn copy_and_read<T>(
+    options: &SelectionOptions,
+    read: impl FnOnce() -> Result<T, SelectionError>,
+) -> Result<T, SelectionError> {
+    if !options.synthetic_input {
+        return Err(synthetic_input_disabled());
+    }
+    let _copying = CopyInProgress::start();
 
     // 读取旧的剪贴板内容
-    let mut clipboard = Clipboard::new().map_err(|e| {
-        Box::new(SelectionError::ClipboardError(format!(
-            "Failed to open clipboard: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
+    let previous = if options.restore_clipboard {
+        let mut clipboard =
+            Clipboard::new().map_err(clipboard_error("Failed to open clipboard"))?;
+        Some((clipboard.get_text().ok(), clipboard.get_image().ok()))
+    } else {
+        None
+    };
+
+    // 尝试复制选中内容到剪贴板, copy()已经等待过剪贴板更新
+    copy()?;
+    let copied_sequence = unsafe { GetClipboardSequenceNumber() };
+    OWN_SEQUENCE.store(copied_sequence, Ordering::SeqCst);
+
+    let read = read();
+    let Some((old_text, old_image)) = previous else {
+        debug!("Leaving the copied content on the clipboard");
+        return read;
+    };
 
-    let old_text = clipboard.get_text().ok();
-    let old_image = clipboard.get_image().ok();
+    // 恢复原来的剪贴板内容, 除非复制之后剪贴板又被用户或其他程序改写
+    let current_sequence = unsafe { GetClipboardSequenceNumber() };
+    if current_sequence == copied_sequence {
+        let restored = restore_clipboard(old_text, old_image);
+        OWN_SEQUENCE.store(unsafe { GetClipboardSequenceNumber() }, Ordering::SeqCst);
+        restored?;
+    } else {
+        info!(
+            "Clipboard changed during the capture (sequence {} -> {}), not restoring it",
+            copied_sequence, current_sequence
+        );
+    }
+
+    read
+}
+
+// 模拟复制期间存在, 期间的剪贴板更新都算作自己的
+struct CopyInProgress;
 
-    // 尝试复制选中内容到剪贴板
-    if !copy() {
-        return Err(Box::new(SelectionError::ClipboardError(
-            "Copy operation failed".into(),
-        )));
+impl CopyInProgress {
+    fn start() -> Self {
+        COPIES_IN_PROGRESS.fetch_add(1, Ordering::SeqCst);
+        CopyInProgress
     }
+}
 
-    // 给系统一点时间处理剪贴板
-    std::thread::sleep(Duration::from_millis(150));
+impl Drop for CopyInProgress {
+    fn drop(&mut self) {
+        COPIES_IN_PROGRESS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-    // 读取新的剪贴板内容
-    let mut new_clipboard = Clipboard::new().map_err(|e| {
-        Box::new(SelectionError::ClipboardError(format!(
-            "Failed to open clipboard after copy: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
+// 当前的剪贴板内容是否来自本crate的模拟复制或恢复, 而不是用户的复制
+pub(crate) fn is_own_clipboard_update() -> bool {
+    if COPIES_IN_PROGRESS.load(Ordering::SeqCst) > 0 {
+        return true;
+    }
+    let own = OWN_SEQUENCE.load(Ordering::SeqCst);
+    own != 0 && own == unsafe { GetClipboardSequenceNumber() }
+}
+
+// 读取剪贴板上所有可读的格式, 按丰富程度排列; 读取失败的格式被跳过,
+// 只有什么都读不到时才返回第一个错误
+fn read_all_clipboard_formats(
+    options: &SelectionOptions,
+) -> Result<Vec<Selection>, SelectionError> {
+    let mut selections: Vec<Selection> = Vec::new();
+    let mut first_error = None;
+    for (content_type, format) in known_clipboard_formats() {
+        if format == 0 || unsafe { IsClipboardFormatAvailable(format) }.is_err() {
+            continue;
+        }
+        match read_clipboard_format(format, &content_type, options) {
+            Ok(selection) if !selection.is_empty() => {
+                selections.push(selection.with_method(SelectionMethod::Clipboard))
+            }
+            Ok(_) => {}
+            Err(err) => {
+                debug!("Failed to read clipboard format {}: {}", content_type, err);
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    match first_error {
+        Some(err) if selections.is_empty() => Err(err),
+        _ => Ok(selections),
+    }
+}
+
+// 模拟一次复制, 读取剪贴板上的所有格式
+fn get_windows_selection_all(options: &SelectionOptions) -> Result<Vec<Selection>, SelectionError> {
+    let selections = copy_and_read(options, || read_all_clipboard_formats(options))?;
+    let left = !options.restore_clipboard;
+    let mut selections: Vec<Selection> = selections
+        .into_iter()
+        .map(|selection| selection.left_on_clipboard(left))
+        .collect();
+    sort_by_richness(&mut selections);
+    Ok(selections)
+}
+
+/// Read the current clipboard contents without simulating a copy
+///
+/// Formats are negotiated in the order of the MIME preference list, the same
+/// way the clipboard fallback of `get_selection_raw` does it, within the
+/// default `SelectionOptions::max_bytes`.
+pub fn read_clipboard(preferred: &[&str]) -> Result<Selection, SelectionError> {
+    let options = SelectionOptions::default();
+    if preferred.is_empty() {
+        return negotiate_clipboard(&[TEXT_MIME], &options);
+    }
+    negotiate_clipboard(preferred, &options)
+}
+
+// 按丰富程度排列的可协商剪贴板格式
+fn known_clipboard_formats() -> Vec<(ContentType, u32)> {
+    let html = unsafe { RegisterClipboardFormatW(w!("HTML Format")) };
+    let rtf = unsafe { RegisterClipboardFormatW(w!("Rich Text Format")) };
+
+    #[allow(unused_mut)]
+    let mut formats = vec![
+        (ContentType::Files, CF_HDROP.0 as u32),
+        (ContentType::Html, html),
+        (ContentType::Rtf, rtf),
+        (ContentType::Text, CF_UNICODETEXT.0 as u32),
+    ];
+    // 位图由 arboard 读取, 再编码为 PNG
+    #[cfg(feature = "image")]
+    formats.insert(1, (ContentType::Image("png".to_string()), CF_DIB.0 as u32));
+    formats
+}
+
+fn negotiate_clipboard(
+    preferred: &[&str],
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    let formats = known_clipboard_formats();
+
+    // 按优先级检查剪贴板上是否有对应格式, 返回第一个匹配项
+    for mime in preferred {
+        for (content_type, format) in formats.iter() {
+            if *format == 0 || !content_type.matches_mime(mime) {
+                continue;
+            }
+            if unsafe { IsClipboardFormatAvailable(*format) }.is_ok() {
+                debug!("Clipboard format {} matches {}", content_type, mime);
+                return read_clipboard_format(*format, content_type, options);
+            }
+        }
+    }
+
+    Err(SelectionError::InvalidContentType {
+        expected: preferred.join(", "),
+        received: "no matching clipboard format".to_string(),
+    })
+}
+
+// 打开剪贴板, 并在离开作用域时关闭
+struct ClipboardGuard;
+
+impl ClipboardGuard {
+    fn open() -> Result<Self, SelectionError> {
+        // 其他程序可能短暂占用剪贴板, 重试几次
+        let mut last_error = None;
+        for attempt in 0..5 {
+            match unsafe { OpenClipboard(None) } {
+                Ok(()) => return Ok(ClipboardGuard),
+                Err(err) => last_error = Some(err),
+            }
+            debug!("OpenClipboard failed, attempt {}", attempt + 1);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Err(SelectionError::platform(
+            ErrorKind::ClipboardBusy,
+            "Failed to open clipboard",
+            last_error.unwrap_or_else(windows::core::Error::from_win32),
+        ))
+    }
+}
+
+impl Drop for ClipboardGuard {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseClipboard() };
+    }
+}
+
+// 只复制max_bytes以内的数据: 文本和HTML被截断, RTF和位图过大时报错
+fn read_clipboard_format(
+    format: u32,
+    content_type: &ContentType,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    // arboard 自己打开剪贴板, 须在 ClipboardGuard 之前读取
+    #[cfg(feature = "image")]
+    if let ContentType::Image(_) = content_type {
+        return read_clipboard_image(format, options);
+    }
 
-    let new_text = new_clipboard.get_text().map_err(|e| {
-        Box::new(SelectionError::ClipboardError(format!(
-            "Failed to get text from clipboard: {}",
-            e
-        ))) as Box<dyn Error>
+    let _guard = ClipboardGuard::open()?;
+
+    let handle = unsafe { GetClipboardData(format) }.map_err(|e| {
+        SelectionError::platform(ErrorKind::Clipboard, "Failed to get clipboard data", e)
     })?;
 
-    // 恢复原来的剪贴板内容
-    restore_clipboard(old_text, old_image)?;
+    // 多读一个字节以判断是否超出限制
+    let limit = options.max_bytes.map(|max| max + 1);
+    let over_limit = |len: usize| options.max_bytes.is_some_and(|max| len > max);
+
+    match content_type {
+        ContentType::Files => Ok(Selection::new_files(read_hdrop(handle))),
+        // 偏移量针对整个缓冲区, 先找到片段再截断, 不复制头部和整个文档
+        ContentType::Html => with_global_bytes(handle, |bytes| {
+            html_selection(trim_nul(bytes), options.max_bytes)
+        }),
+        ContentType::Rtf => {
+            let bytes = read_global_bytes(handle, limit)?;
+            let rtf = trim_nul(&bytes);
+            if over_limit(rtf.len()) {
+                return Err(options.too_large());
+            }
+            Ok(Selection::new_rtf(rtf.to_vec()))
+        }
+        _ => {
+            // UTF-16每个单元至少对应一个UTF-8字节
+            let bytes = read_global_bytes(handle, limit.map(|units| units * 2))?;
+            let wide: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .take_while(|unit| *unit != 0)
+                .collect();
+            let mut selection =
+                Selection::new_text(String::from_utf16_lossy(&wide).trim().to_string());
+            if let Some(max_bytes) = options.max_bytes {
+                selection.truncate_text(max_bytes);
+            }
+            Ok(selection)
+        }
+    }
+}
 
-    // 返回新获取的文本
-    if !new_text.is_empty() {
-        Ok(new_text.trim().to_string())
-    } else {
-        Err(Box::new(SelectionError::NoSelectedContent))
+// 读取剪贴板位图, 编码为 PNG; 先检查位图大小, 过大的不交给 arboard 解码
+#[cfg(feature = "image")]
+fn read_clipboard_image(
+    format: u32,
+    options: &SelectionOptions,
+) -> Result<Selection, SelectionError> {
+    if let Some(max_bytes) = options.max_bytes {
+        let _guard = ClipboardGuard::open()?;
+        let handle = unsafe { GetClipboardData(format) }.map_err(|e| {
+            SelectionError::platform(ErrorKind::Clipboard, "Failed to get clipboard data", e)
+        })?;
+        if unsafe { GlobalSize(HGLOBAL(handle.0)) } > max_bytes {
+            return Err(options.too_large());
+        }
+    }
+
+    let image = Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_image())
+        .map_err(clipboard_error("Failed to read clipboard image"))?;
+    Ok(Selection::new_image(
+        "png",
+        crate::image::image_data_to_png(&image)?,
+    ))
+}
+
+// 复制剪贴板内存, 至多limit个字节
+fn read_global_bytes(handle: HANDLE, limit: Option<usize>) -> Result<Vec<u8>, SelectionError> {
+    with_global_bytes(handle, |bytes| {
+        bytes[..bytes.len().min(limit.unwrap_or(usize::MAX))].to_vec()
+    })
+}
+
+// 锁定剪贴板内存, 在不复制的情况下交给read处理
+fn with_global_bytes<T>(
+    handle: HANDLE,
+    read: impl FnOnce(&[u8]) -> T,
+) -> Result<T, SelectionError> {
+    let hglobal = HGLOBAL(handle.0);
+    unsafe {
+        let ptr = GlobalLock(hglobal) as *const u8;
+        if ptr.is_null() {
+            return Err(SelectionError::ClipboardError(
+                "Failed to lock clipboard memory".to_string(),
+            ));
+        }
+        let result = read(std::slice::from_raw_parts(ptr, GlobalSize(hglobal)));
+        let _ = GlobalUnlock(hglobal);
+        Ok(result)
+    }
+}
+
+fn read_hdrop(handle: HANDLE) -> Vec<PathBuf> {
+    let hdrop = HDROP(handle.0);
+    let count = unsafe { DragQueryFileW(hdrop, u32::MAX, None) };
+
+    (0..count)
+        .filter_map(|i| {
+            let len = unsafe { DragQueryFileW(hdrop, i, None) } as usize;
+            if len == 0 {
+                return None;
+            }
+            let mut buffer = vec![0u16; len + 1];
+            let written = unsafe { DragQueryFileW(hdrop, i, Some(&mut buffer)) } as usize;
+            Some(PathBuf::from(String::from_utf16_lossy(&buffer[..written])))
+        })
+        .collect()
+}
+
+// 根据窗口句柄获取所属进程的信息
+pub(crate) fn app_info_for_window(hwnd: HWND) -> Option<AppInfo> {
+    if hwnd.is_invalid() {
+        return None;
+    }
+
+    let mut pid = 0u32;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+    if pid == 0 {
+        return None;
+    }
+
+    let executable = process_executable(pid);
+    let name = executable
+        .as_ref()
+        .and_then(|path| path.file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned());
+
+    let identifier = executable
+        .as_ref()
+        .and_then(|path| path.file_name())
+        .map(|file_name| file_name.to_string_lossy().into_owned());
+
+    Some(AppInfo {
+        name,
+        identifier,
+        pid: Some(pid),
+        executable,
+        ..Default::default()
+    })
+}
+
+fn process_executable(pid: u32) -> Option<PathBuf> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+
+    let mut buffer = [0u16; 1024];
+    let mut size = buffer.len() as u32;
+    let result = unsafe {
+        QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut size,
+        )
+    };
+    let _ = unsafe { CloseHandle(process) };
+
+    result.ok()?;
+    Some(PathBuf::from(String::from_utf16_lossy(
+        &buffer[..size as usize],
+    )))
+}
+
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+// 从CF_HTML中取出被复制的片段, 超过max_bytes时在字符边界处截断
+fn html_selection(raw: &[u8], max_bytes: Option<usize>) -> Selection {
+    let mut fragment = extract_html_fragment(raw);
+    let truncated = max_bytes.is_some_and(|max| fragment.len() > max);
+    if let Some(max) = max_bytes.filter(|_| truncated) {
+        // 退回到跨越限制的字符的起始位置
+        let mut end = max;
+        while end > 0 && (fragment[end] & 0xc0) == 0x80 {
+            end -= 1;
+        }
+        fragment = &fragment[..end];
+    }
+    let mut selection = Selection::new_html(String::from_utf8_lossy(fragment).into_owned());
+    selection.metadata.truncated = truncated;
+    selection
+}
+
+// CF_HTML带有描述片段偏移量的头部, 只返回被复制的片段;
+// 偏移量是字节偏移, 所以在解码之前按字节切片
+fn extract_html_fragment(raw: &[u8]) -> &[u8] {
+    let header_len = raw.iter().position(|b| *b == b'<').unwrap_or(raw.len());
+    let header = String::from_utf8_lossy(&raw[..header_len]);
+    let offset = |key: &str| -> Option<usize> {
+        header
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|value| value.trim().parse::<usize>().ok())
+    };
+
+    let range = match (offset("StartFragment:"), offset("EndFragment:")) {
+        (Some(start), Some(end)) => Some((start, end)),
+        _ => offset("StartHTML:").zip(offset("EndHTML:")),
+    };
+
+    match range {
+        Some((start, end)) if start <= end && end <= raw.len() => raw[start..end].trim_ascii(),
+        _ => raw,
     }
 }
 
 fn restore_clipboard(
     old_text: Option<String>,
     old_image: Option<arboard::ImageData>,
-) -> Result<(), Box<dyn Error>> {
-    let mut restore_clipboard = Clipboard::new().map_err(|e| {
-        Box::new(SelectionError::ClipboardError(format!(
-            "Failed to open clipboard for restoration: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
+) -> Result<(), SelectionError> {
+    let mut restore_clipboard =
+        Clipboard::new().map_err(clipboard_error("Failed to open clipboard for restoration"))?;
 
     if let Some(text) = old_text {
-        restore_clipboard.set_text(text).map_err(|e| {
-            Box::new(SelectionError::ClipboardError(format!(
-                "Failed to restore text to clipboard: {}",
-                e
-            ))) as Box<dyn Error>
-        })?;
+        restore_clipboard
+            .set_text(text)
+            .map_err(clipboard_error("Failed to restore text to clipboard"))?;
     } else if let Some(image) = old_image {
-        restore_clipboard.set_image(image).map_err(|e| {
-            Box::new(SelectionError::ClipboardError(format!(
-                "Failed to restore image to clipboard: {}",
-                e
-            ))) as Box<dyn Error>
-        })?;
+        restore_clipboard
+            .set_image(image)
+            .map_err(clipboard_error("Failed to restore image to clipboard"))?;
     } else {
-        restore_clipboard.clear().map_err(|e| {
-            Box::new(SelectionError::ClipboardError(format!(
-                "Failed to clear clipboard: {}",
-                e
-            ))) as Box<dyn Error>
-        })?;
+        restore_clipboard
+            .clear()
+            .map_err(clipboard_error("Failed to clear clipboard"))?;
     }
 
     Ok(())
 }
 
-// 确保所有修饰键处于释放状态
-fn release_keys() -> Result<(), Box<dyn Error>> {
-    let mut enigo = Enigo::new(&Settings::default()).map_err(|e| {
-        Box::new(SelectionError::Other(format!(
-            "Failed to create Enigo instance: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
+fn synthetic_input_disabled() -> SelectionError {
+    SelectionError::MethodUnavailable("simulated keystrokes are disabled".to_string())
+}
 
-    enigo.key(Key::Control, Release).map_err(|e| {
-        Box::new(SelectionError::Other(format!(
-            "Failed to release Control key: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
-    enigo.key(Key::Alt, Release).map_err(|e| {
-        Box::new(SelectionError::Other(format!(
-            "Failed to release Alt key: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
-    enigo.key(Key::Shift, Release).map_err(|e| {
-        Box::new(SelectionError::Other(format!(
-            "Failed to release Shift key: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
-    enigo.key(Key::Meta, Release).map_err(|e| {
-        Box::new(SelectionError::Other(format!(
-            "Failed to release Meta key: {}",
-            e
-        ))) as Box<dyn Error>
-    })?;
+// 剪贴板的错误, 保留arboard的原始错误; 剪贴板被其他程序占用时可以重试
+fn clipboard_error(message: &'static str) -> impl Fn(arboard::Error) -> SelectionError {
+    move |e| {
+        let kind = match e {
+            arboard::Error::ClipboardOccupied => ErrorKind::ClipboardBusy,
+            _ => ErrorKind::Clipboard,
+        };
+        SelectionError::platform(kind, message, e)
+    }
+}
+
+// 模拟按键的错误, 保留enigo的原始错误
+fn input_error<E>(message: &'static str) -> impl Fn(E) -> SelectionError
+where
+    E: Error + Send + Sync + 'static,
+{
+    move |e| SelectionError::platform(ErrorKind::Other, message, e)
+}
+
+// 确保所有修饰键处于释放状态
+fn release_keys(enigo: &mut Enigo) -> Result<(), SelectionError> {
+    enigo
+        .key(Key::Control, Release)
+        .map_err(input_error("Failed to release Control key"))?;
+    enigo
+        .key(Key::Alt, Release)
+        .map_err(input_error("Failed to release Alt key"))?;
+    enigo
+        .key(Key::Shift, Release)
+        .map_err(input_error("Failed to release Shift key"))?;
+    enigo
+        .key(Key::Meta, Release)
+        .map_err(input_error("Failed to release Meta key"))?;
 
     Ok(())
 }
 
-fn copy() -> bool {
+fn copy() -> Result<(), SelectionError> {
     debug!("Executing copy command");
 
     // 记录复制前的剪贴板序列号
     let num_before = unsafe { GetClipboardSequenceNumber() };
 
     // 创建自动化引擎
-    let mut enigo = match Enigo::new(&Settings::default()) {
-        Ok(e) => e,
-        Err(e) => {
-            error!("Failed to create Enigo instance: {:?}", e);
-            return false;
-        }
-    };
-
-    if let Err(e) = release_keys() {
-        error!("Failed to release modifier keys: {:?}", e);
-        return false;
-    }
+    let mut enigo =
+        Enigo::new(&Settings::default()).map_err(input_error("Failed to create Enigo instance"))?;
+    release_keys(&mut enigo)?;
 
     // 执行Ctrl+C
-    let copy_result = (|| -> Result<(), Box<dyn Error>> {
-        enigo
-            .key(Key::Control, Press)
-            .map_err(|e| Box::new(e) as Box<dyn Error>)?;
-
-        enigo.key(Key::C, Click).map_err(|e| {
-            // 确保释放Ctrl键
-            let _ = enigo.key(Key::Control, Release);
-            Box::new(e) as Box<dyn Error>
-        })?;
-
-        enigo
-            .key(Key::Control, Release)
-            .map_err(|e| Box::new(e) as Box<dyn Error>)?;
+    enigo
+        .key(Key::Control, Press)
+        .map_err(input_error("Failed to press Control key"))?;
+    let clicked = enigo.key(Key::C, Click);
+    // 确保释放Ctrl键
+    let released = enigo.key(Key::Control, Release);
+    clicked.map_err(input_error("Failed to press C key"))?;
+    released.map_err(input_error("Failed to release Control key"))?;
 
-        Ok(())
-    })();
+    // 等待剪贴板更新
+    std::thread::sleep(COPY_SETTLE_TIME);
 
-    if let Err(e) = copy_result {
-        error!("Copy operation failed: {:?}", e);
-        return false;
+    // 检查剪贴板是否变化, 没变化说明没有选中内容
+    let num_after = unsafe { GetClipboardSequenceNumber() };
+    if num_after == num_before {
+        warn!("Clipboard did not change after Ctrl+C, assuming nothing is selected");
+        return Err(SelectionError::NoSelectedContent);
     }
 
-    // 等待剪贴板更新
-    std::thread::sleep(Duration::from_millis(150));
+    Ok(())
+}
 
-    // 检查剪贴板是否变化
-    let num_after = unsafe { GetClipboardSequenceNumber() };
-    let result = num_after != num_before;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_sources() {
+        let err = clipboard_error("Failed to open clipboard")(arboard::Error::ClipboardOccupied);
+        assert_eq!(err.kind(), ErrorKind::ClipboardBusy);
+        assert!(err.is_retryable());
+        let source = err.source().unwrap().downcast_ref::<arboard::Error>();
+        assert!(matches!(source, Some(arboard::Error::ClipboardOccupied)));
+
+        let hresult = windows::core::Error::from(windows::Win32::Foundation::E_FAIL);
+        let err = automation_error("Failed to get the selection", hresult);
+        assert!(matches!(
+            err,
+            SelectionError::AccessibilityError { code: Some(_), .. }
+        ));
+        assert!(err.source().unwrap().is::<windows::core::Error>());
+    }
 
-    if !result {
-        warn!("Clipboard sequence number did not change after copy attempt");
+    #[test]
+    fn test_html_selection_cuts_the_fragment() {
+        let fragment = "<b>h\u{e9}llo</b>";
+        let prefix = "<html><body><!--StartFragment-->";
+        let suffix = "<!--EndFragment--></body></html>";
+        let header_len = "Version:0.9\r\nStartHTML:0000000000\r\nEndHTML:0000000000\r\n\
+            StartFragment:0000000000\r\nEndFragment:0000000000\r\n"
+            .len();
+        let start = header_len + prefix.len();
+        let end = start + fragment.len();
+        let raw = format!(
+            "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\n\
+             StartFragment:{:010}\r\nEndFragment:{:010}\r\n{}{}{}",
+            header_len,
+            end + suffix.len(),
+            start,
+            end,
+            prefix,
+            fragment,
+            suffix
+        );
+
+        let selection = html_selection(raw.as_bytes(), None);
+        assert_eq!(selection.data, fragment.as_bytes());
+        assert!(!selection.metadata.truncated);
+
+        // 限制落在é的中间, 截断后不应带有头部或文档的其余部分
+        let selection = html_selection(raw.as_bytes(), Some(5));
+        assert_eq!(selection.data, b"<b>h");
+        assert!(selection.metadata.truncated);
+        assert!(!String::from_utf8_lossy(&selection.data).contains("Version:"));
     }
 
-    result
+    #[test]
+    fn test_own_clipboard_update() {
+        OWN_SEQUENCE.store(0, Ordering::SeqCst);
+        let copying = CopyInProgress::start();
+        assert!(is_own_clipboard_update());
+        drop(copying);
+        assert!(!is_own_clipboard_update());
+
+        OWN_SEQUENCE.store(unsafe { GetClipboardSequenceNumber() }, Ordering::SeqCst);
+        assert_eq!(
+            is_own_clipboard_update(),
+            unsafe { GetClipboardSequenceNumber() } != 0
+        );
+    }
 }
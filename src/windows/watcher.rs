@@ -0,0 +1,249 @@
+use super::{
+    app_info_for_window, get_text_by_automation, is_own_clipboard_update, read_clipboard,
+    AutomationText,
+};
+use crate::watcher::{PollingBackend, WatchSource, WatcherBackend, WatcherOptions};
+use crate::{AppInfo, ErrorKind, Selection, SelectionError, SelectionEvent, DEFAULT_MAX_BYTES};
+use log::{debug, error};
+use std::cell::RefCell;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use windows::core::w;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::Com::{CoInitializeEx, COINIT_APARTMENTTHREADED};
+use windows::Win32::System::DataExchange::{
+    AddClipboardFormatListener, GetClipboardOwner, RemoveClipboardFormatListener,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetForegroundWindow,
+    GetMessageW, KillTimer, PostThreadMessageW, RegisterClassW, SetTimer, HWND_MESSAGE, MSG,
+    WINDOW_EX_STYLE, WINDOW_STYLE, WM_CLIPBOARDUPDATE, WM_QUIT, WM_TIMER, WNDCLASSW,
+};
+
+const DEBOUNCE_TIMER_ID: usize = 1;
+
+pub(crate) fn start_watcher(
+    options: &WatcherOptions,
+    sender: Sender<SelectionEvent>,
+) -> Result<Vec<Box<dyn WatcherBackend>>, SelectionError> {
+    let mut backends: Vec<Box<dyn WatcherBackend>> = Vec::new();
+
+    if options.watch_clipboard {
+        backends.push(Box::new(ClipboardListener::spawn(options, sender.clone())?));
+    }
+
+    if options.watch_selection {
+        // UI自动化是被动读取, 不会模拟按键; COM需要在轮询线程里初始化
+        let mut com_ready = false;
+        backends.push(Box::new(PollingBackend::spawn(
+            options,
+            WatchSource::Selection,
+            sender,
+            move || {
+                if !com_ready {
+                    let _ = unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) };
+                    com_ready = true;
+                }
+                match get_text_by_automation(Some(DEFAULT_MAX_BYTES)) {
+                    Ok(AutomationText::Text(text)) => {
+                        let app = app_info_for_window(unsafe { GetForegroundWindow() });
+                        let mut selection = Selection::new_text(text);
+                        selection.truncate_text(DEFAULT_MAX_BYTES);
+                        Some((selection, app))
+                    }
+                    _ => None,
+                }
+            },
+        )));
+    }
+
+    Ok(backends)
+}
+
+// 监听WM_CLIPBOARDUPDATE的消息窗口线程
+struct ClipboardListener {
+    thread_id: u32,
+    handle: Option<JoinHandle<()>>,
+}
+
+// 窗口过程所在线程的监听状态
+struct ListenerState {
+    sender: Sender<SelectionEvent>,
+    preferred: Vec<String>,
+    debounce_ms: u32,
+    owner: Option<AppInfo>,
+}
+
+thread_local! {
+    static LISTENER: RefCell<Option<ListenerState>> = const { RefCell::new(None) };
+}
+
+impl ClipboardListener {
+    fn spawn(
+        options: &WatcherOptions,
+        sender: Sender<SelectionEvent>,
+    ) -> Result<Self, SelectionError> {
+        let state = ListenerState {
+            sender,
+            preferred: options.preferred.clone(),
+            debounce_ms: options.debounce.as_millis().clamp(1, u32::MAX as u128) as u32,
+            owner: None,
+        };
+        let (ready, started) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let hwnd = match create_listener_window() {
+                Ok(hwnd) => hwnd,
+                Err(err) => {
+                    let _ = ready.send(Err(err));
+                    return;
+                }
+            };
+
+            LISTENER.with(|listener| *listener.borrow_mut() = Some(state));
+            let _ = ready.send(Ok(unsafe { GetCurrentThreadId() }));
+
+            // 消息循环, 收到WM_QUIT时退出
+            let mut msg = MSG::default();
+            while unsafe { GetMessageW(&mut msg, None, 0, 0) }.as_bool() {
+                unsafe { DispatchMessageW(&msg) };
+            }
+
+            unsafe {
+                let _ = RemoveClipboardFormatListener(hwnd);
+                let _ = DestroyWindow(hwnd);
+            }
+            LISTENER.with(|listener| listener.borrow_mut().take());
+            debug!("Clipboard listener stopped");
+        });
+
+        match started.recv() {
+            Ok(Ok(thread_id)) => Ok(Self {
+                thread_id,
+                handle: Some(handle),
+            }),
+            Ok(Err(err)) => {
+                let _ = handle.join();
+                Err(err)
+            }
+            Err(_) => Err(SelectionError::Other(
+                "Clipboard listener thread exited unexpectedly".to_string(),
+            )),
+        }
+    }
+}
+
+impl WatcherBackend for ClipboardListener {
+    fn stop(mut self: Box<Self>) {
+        let _ = unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) };
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn create_listener_window() -> Result<HWND, SelectionError> {
+    unsafe {
+        let instance = GetModuleHandleW(None).map_err(|e| {
+            SelectionError::platform(ErrorKind::Other, "Failed to get module handle", e)
+        })?;
+        let class_name = w!("SelecticClipboardListener");
+
+        // 重复注册会失败, 已注册的类可以直接使用
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(listener_wndproc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        RegisterClassW(&class);
+
+        // 仅用于接收消息的窗口
+        let hwnd = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            w!(""),
+            WINDOW_STYLE::default(),
+            0,
+            0,
+            0,
+            0,
+            HWND_MESSAGE,
+            None,
+            instance,
+            None,
+        )
+        .map_err(|e| {
+            SelectionError::platform(ErrorKind::Other, "Failed to create listener window", e)
+        })?;
+
+        if let Err(e) = AddClipboardFormatListener(hwnd) {
+            let _ = DestroyWindow(hwnd);
+            return Err(SelectionError::platform(
+                ErrorKind::Clipboard,
+                "Failed to add clipboard format listener",
+                e,
+            ));
+        }
+
+        Ok(hwnd)
+    }
+}
+
+unsafe extern "system" fn listener_wndproc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        // 捕获时模拟的复制和恢复都会触发更新, 不算作用户的复制
+        WM_CLIPBOARDUPDATE if is_own_clipboard_update() => {
+            debug!("Ignoring a clipboard update made by a capture");
+            LRESULT(0)
+        }
+        WM_CLIPBOARDUPDATE => {
+            // 程序写入多个格式时会连续触发, 用计时器合并成一个事件
+            LISTENER.with(|listener| {
+                if let Some(state) = listener.borrow_mut().as_mut() {
+                    state.owner = GetClipboardOwner().ok().and_then(app_info_for_window);
+                    SetTimer(hwnd, DEBOUNCE_TIMER_ID, state.debounce_ms, None);
+                }
+            });
+            LRESULT(0)
+        }
+        WM_TIMER if wparam.0 == DEBOUNCE_TIMER_ID => {
+            let _ = KillTimer(hwnd, DEBOUNCE_TIMER_ID);
+            LISTENER.with(|listener| {
+                if let Some(state) = listener.borrow_mut().as_mut() {
+                    emit_clipboard_event(state);
+                }
+            });
+            LRESULT(0)
+        }
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+fn emit_clipboard_event(state: &mut ListenerState) {
+    // 计时期间可能开始了一次捕获
+    if is_own_clipboard_update() {
+        return;
+    }
+    let preferred: Vec<&str> = state.preferred.iter().map(String::as_str).collect();
+
+    match read_clipboard(&preferred) {
+        Ok(selection) if !selection.is_empty() => {
+            let event = SelectionEvent {
+                selection,
+                source: WatchSource::Clipboard,
+                app: state.owner.take(),
+            };
+            let _ = state.sender.send(event);
+        }
+        Ok(_) => debug!("Clipboard update contained no matching content"),
+        Err(err) => error!("Failed to read clipboard after update: {}", err),
+    }
+}
@@ -0,0 +1,262 @@
+//! The fallback chain, options and watcher, run against the fake desktop
+#![cfg(feature = "fake-platform")]
+
+use selectic::fake;
+use selectic::{
+    AppInfo, AppRule, AttemptOutcome, ContentType, ErrorKind, Selection, SelectionError,
+    SelectionMethod, SelectionObserver, SelectionOptions, SelectionWatcher, Selector, WatchSource,
+    WatcherOptions,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn app(identifier: &str) -> Option<AppInfo> {
+    Some(AppInfo {
+        identifier: Some(identifier.to_string()),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_reads_through_accessibility() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("hello");
+
+    let selection = selectic::get_selection().unwrap();
+    assert_eq!(selection.as_text().as_deref(), Some("hello"));
+    assert_eq!(
+        selection.metadata.method,
+        Some(SelectionMethod::Accessibility)
+    );
+    assert_eq!(fake::copy_count(), 0);
+}
+
+#[test]
+fn test_falls_back_to_copying() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("hello");
+    fake::set_accessible(false);
+    fake::set_clipboard(Selection::new_text("copied earlier".to_string()));
+
+    let selection = selectic::get_selection().unwrap();
+    assert_eq!(selection.as_text().as_deref(), Some("hello"));
+    assert_eq!(selection.metadata.method, Some(SelectionMethod::Clipboard));
+    assert_eq!(fake::copy_count(), 1);
+    // The copy didn't clobber the user's clipboard
+    assert_eq!(
+        fake::clipboard()
+            .and_then(|clipboard| clipboard.as_text())
+            .as_deref(),
+        Some("copied earlier")
+    );
+}
+
+#[test]
+fn test_leaves_copy_on_clipboard() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("hello");
+    fake::set_accessible(false);
+    fake::set_clipboard(Selection::new_text("copied earlier".to_string()));
+
+    let recorder = Arc::new(Recorder::default());
+    let selector = fake::FakeSelector::with_options(
+        SelectionOptions::new()
+            .synthetic_input(true)
+            .restore_clipboard(false)
+            .observer(recorder.clone()),
+    );
+    let selection = selector.get_selection().unwrap();
+    assert_eq!(selection.as_text().as_deref(), Some("hello"));
+    assert!(selection.metadata.left_on_clipboard);
+    assert_eq!(
+        recorder.events.lock().unwrap().last().map(String::as_str),
+        Some("clipboard 5 bytes left on the clipboard")
+    );
+    assert_eq!(
+        fake::clipboard()
+            .and_then(|clipboard| clipboard.as_text())
+            .as_deref(),
+        Some("hello")
+    );
+
+    let selection = selectic::get_selection().unwrap();
+    assert!(!selection.metadata.left_on_clipboard);
+}
+
+#[test]
+fn test_empty_selection() {
+    let _desktop = fake::reset();
+    assert!(matches!(
+        selectic::get_selection(),
+        Err(SelectionError::NoSelectedContent)
+    ));
+    assert_eq!(fake::copy_count(), 0);
+
+    // Without trusting the accessibility API, a copy is tried as well
+    let selector = fake::FakeSelector::with_options(
+        SelectionOptions::new()
+            .synthetic_input(true)
+            .trust_empty_accessibility(false),
+    );
+    assert!(matches!(
+        selector.get_selection(),
+        Err(SelectionError::NoSelectedContent)
+    ));
+    assert_eq!(fake::copy_count(), 1);
+}
+
+#[test]
+fn test_synthetic_input_disabled() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("hello");
+    fake::set_accessible(false);
+
+    let selector = fake::FakeSelector::with_options(SelectionOptions::new().synthetic_input(false));
+    assert!(selector.get_selection().is_err());
+    assert_eq!(fake::copy_count(), 0);
+}
+
+#[test]
+fn test_app_rules() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("secret");
+    fake::set_focused_app(app("com.example.vault"));
+
+    let options = SelectionOptions::new()
+        .synthetic_input(true)
+        .app_rule("com.example.vault", AppRule::new().allow_capture(false))
+        .app_rule(
+            "com.example.editor",
+            AppRule::new().methods(&[SelectionMethod::Clipboard]),
+        );
+    let selector = fake::FakeSelector::with_options(options);
+    assert!(matches!(
+        selector.get_selection(),
+        Err(SelectionError::PermissionDenied(_))
+    ));
+
+    fake::set_focused_app(app("com.example.editor"));
+    let selection = selector.get_selection().unwrap();
+    assert_eq!(selection.metadata.method, Some(SelectionMethod::Clipboard));
+    assert_eq!(
+        selection
+            .metadata
+            .source_app
+            .and_then(|app| app.identifier)
+            .as_deref(),
+        Some("com.example.editor")
+    );
+}
+
+#[test]
+fn test_max_bytes() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("hello world");
+
+    let selector = fake::FakeSelector::with_options(
+        SelectionOptions::new().synthetic_input(true).max_bytes(5),
+    );
+    let selection = selector.get_selection().unwrap();
+    assert_eq!(selection.as_text().as_deref(), Some("hello"));
+    assert!(selection.metadata.truncated);
+}
+
+#[test]
+fn test_all_representations() {
+    let _desktop = fake::reset();
+    fake::set_selected(vec![
+        Selection::new_text("hello".to_string()),
+        Selection::new_html("<b>hello</b>".to_string()),
+    ]);
+
+    let selections = selectic::get_selection_all().unwrap();
+    let types: Vec<_> = selections.iter().map(|s| s.content_type.clone()).collect();
+    assert_eq!(types, [ContentType::Html, ContentType::Text]);
+
+    let selection = selectic::get_selection_raw(&["text/html"]).unwrap();
+    assert_eq!(selection.content_type, ContentType::Html);
+}
+
+#[test]
+fn test_watcher() {
+    let _desktop = fake::reset();
+    let watcher = SelectionWatcher::new(
+        WatcherOptions::new()
+            .poll_interval(Duration::from_millis(10))
+            .debounce(Duration::from_millis(0))
+            .watch_clipboard(true),
+    )
+    .unwrap();
+
+    fake::set_selected_text("selected");
+    let event = watcher.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.source, WatchSource::Selection);
+    assert_eq!(event.selection.as_text().as_deref(), Some("selected"));
+
+    fake::set_clipboard(Selection::new_text("copied".to_string()));
+    let event = loop {
+        let event = watcher.recv_timeout(Duration::from_secs(5)).unwrap();
+        if event.source == WatchSource::Clipboard {
+            break event;
+        }
+    };
+    assert_eq!(event.selection.as_text().as_deref(), Some("copied"));
+}
+
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<String>>,
+}
+
+impl SelectionObserver for Recorder {
+    fn on_attempt(&self, method: SelectionMethod) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("attempt {}", method));
+    }
+
+    fn on_result(&self, method: SelectionMethod, outcome: &AttemptOutcome, _: Duration) {
+        let outcome = match outcome {
+            AttemptOutcome::Captured {
+                bytes,
+                left_on_clipboard: true,
+                ..
+            } => format!("{} bytes left on the clipboard", bytes),
+            AttemptOutcome::Captured { bytes, .. } => format!("{} bytes", bytes),
+            AttemptOutcome::Failed(kind) => kind.to_string(),
+        };
+        let event = format!("{} {}", method, outcome);
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn on_fallback(&self, from: SelectionMethod, to: SelectionMethod, kind: ErrorKind) {
+        let event = format!("fallback {} -> {} after {}", from, to, kind);
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[test]
+fn test_observer() {
+    let _desktop = fake::reset();
+    fake::set_selected_text("hello");
+    fake::set_accessible(false);
+
+    let recorder = Arc::new(Recorder::default());
+    let selector = fake::FakeSelector::with_options(
+        SelectionOptions::new()
+            .synthetic_input(true)
+            .observer(recorder.clone()),
+    );
+    selector.get_selection().unwrap();
+    assert_eq!(
+        *recorder.events.lock().unwrap(),
+        [
+            "attempt accessibility",
+            "accessibility unavailable",
+            "fallback accessibility -> clipboard after unavailable",
+            "attempt clipboard",
+            "clipboard 5 bytes",
+        ]
+    );
+}
@@ -0,0 +1,83 @@
+//! Strict privacy keeps selections out of log output and error messages
+#![cfg(feature = "fake-platform")]
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use selectic::fake;
+use selectic::{AppInfo, OversizePolicy, Privacy, SelectionError, SelectionOptions, Selector};
+use std::sync::Mutex;
+
+/// Text selected in the tests, found in nothing strict privacy lets through
+const SENTINEL: &str = "sentinel-4c1f9e";
+const APP_NAME: &str = "Appname-b83d";
+
+static CAPTURED: Mutex<String> = Mutex::new(String::new());
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Trace
+    }
+
+    fn log(&self, record: &Record) {
+        let mut captured = CAPTURED.lock().unwrap();
+        captured.push_str(&record.args().to_string());
+        captured.push('\n');
+    }
+
+    fn flush(&self) {}
+}
+
+/// Capture the selection in every way the fake desktop allows, returning
+/// the log output and error messages produced
+fn capture_everything() -> String {
+    CAPTURED.lock().unwrap().clear();
+    let _desktop = fake::reset();
+    // A length that can't turn up by chance
+    let text = SENTINEL.repeat(611);
+    fake::set_selected_text(&text);
+    fake::set_focused_app(Some(AppInfo {
+        name: Some(APP_NAME.to_string()),
+        ..Default::default()
+    }));
+
+    let mut messages = Vec::new();
+    assert!(selectic::get_selection().is_ok());
+
+    fake::set_accessible(false);
+    assert!(selectic::get_selection().is_ok());
+
+    let selector = fake::FakeSelector::with_options(
+        SelectionOptions::new()
+            .synthetic_input(true)
+            .max_bytes(10)
+            .oversize(OversizePolicy::Error),
+    );
+    messages.push(selector.get_selection().unwrap_err().to_string());
+    messages.push(SelectionError::JavaAccessibilityUnavailable(APP_NAME.to_string()).to_string());
+    messages.push(SelectionError::QtAccessibilityUnavailable(APP_NAME.to_string()).to_string());
+
+    let mut output = CAPTURED.lock().unwrap().clone();
+    output.push_str(&messages.join("\n"));
+    output
+}
+
+#[test]
+fn test_strict_privacy() {
+    log::set_logger(&CaptureLogger).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+    let length = (SENTINEL.len() * 611).to_string();
+
+    // Make sure the sentinels would be noticed
+    selectic::set_privacy(Privacy::Standard);
+    let output = capture_everything();
+    assert!(output.contains(&length), "{}", output);
+    assert!(output.contains(APP_NAME), "{}", output);
+
+    selectic::set_privacy(Privacy::Strict);
+    let output = capture_everything();
+    for sentinel in [SENTINEL, APP_NAME, &length] {
+        assert!(!output.contains(sentinel), "{} in {}", sentinel, output);
+    }
+    assert!(output.contains("<redacted>"), "{}", output);
+}